@@ -0,0 +1,410 @@
+use chrono::{DateTime, Datelike, Duration, LocalResult, NaiveDateTime, NaiveTime, TimeZone, Utc, Weekday};
+use core::fmt;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AlarmError {
+	InvalidDuration,
+	InvalidBrightness,
+	InvalidTemperature,
+}
+
+impl fmt::Display for AlarmError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			AlarmError::InvalidDuration => write!(f, "Sunrise duration must be positive."),
+			AlarmError::InvalidBrightness => write!(f, "Brightness must not be negative."),
+			AlarmError::InvalidTemperature => write!(f, "Color temperature must be between 1000 and 25000 K."),
+		}
+	}
+}
+
+/// Alarm time for each day of the week. Days without a time have no alarm.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct WeekdayTimes {
+	#[serde(default)]
+	pub mon: Option<NaiveTime>,
+	#[serde(default)]
+	pub tue: Option<NaiveTime>,
+	#[serde(default)]
+	pub wed: Option<NaiveTime>,
+	#[serde(default)]
+	pub thu: Option<NaiveTime>,
+	#[serde(default)]
+	pub fri: Option<NaiveTime>,
+	#[serde(default)]
+	pub sat: Option<NaiveTime>,
+	#[serde(default)]
+	pub sun: Option<NaiveTime>,
+}
+
+impl WeekdayTimes {
+	pub fn get(&self, day: Weekday) -> Option<NaiveTime> {
+		return match day {
+			Weekday::Mon => self.mon,
+			Weekday::Tue => self.tue,
+			Weekday::Wed => self.wed,
+			Weekday::Thu => self.thu,
+			Weekday::Fri => self.fri,
+			Weekday::Sat => self.sat,
+			Weekday::Sun => self.sun,
+		};
+	}
+}
+
+/// Configuration of the sunrise alarm clock. All durations are in seconds.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AlarmConfig {
+	#[serde(default)]
+	pub enabled: bool,
+	/// Local wall clock times at which the sunrise is complete.
+	#[serde(default)]
+	pub times: WeekdayTimes,
+	/// Length of the sunrise. It starts this long before the alarm time.
+	#[serde(default = "default_duration")]
+	pub duration: u32,
+	/// How long the light goes dark when the alarm is snoozed.
+	#[serde(default = "default_snooze")]
+	pub snooze: u32,
+	/// How long the light stays at full brightness after the alarm time,
+	/// before the alarm releases control over the lamp.
+	#[serde(default = "default_hold")]
+	pub hold: u32,
+	/// Brightness at the end of the sunrise.
+	#[serde(default = "default_brightness")]
+	pub brightness: f32,
+	/// Color temperature in K at the end of the sunrise.
+	#[serde(default = "default_temperature")]
+	pub temperature: f32,
+}
+
+fn default_duration() -> u32 { 30 * 60 }
+fn default_snooze() -> u32 { 9 * 60 }
+fn default_hold() -> u32 { 30 * 60 }
+fn default_brightness() -> f32 { 20.0 }
+fn default_temperature() -> f32 { 5500.0 }
+
+impl Default for AlarmConfig {
+	fn default() -> Self {
+		return Self {
+			enabled: false,
+			times: WeekdayTimes::default(),
+			duration: default_duration(),
+			snooze: default_snooze(),
+			hold: default_hold(),
+			brightness: default_brightness(),
+			temperature: default_temperature(),
+		};
+	}
+}
+
+impl AlarmConfig {
+	pub fn validate(&self) -> Result<(), AlarmError> {
+		if self.duration == 0 {
+			return Err(AlarmError::InvalidDuration);
+		}
+		if !self.brightness.is_finite() || self.brightness < 0.0 {
+			return Err(AlarmError::InvalidBrightness);
+		}
+		if !(SUNRISE_START_TEMPERATURE..=25000.0).contains(&self.temperature) {
+			return Err(AlarmError::InvalidTemperature);
+		}
+		return Ok(());
+	}
+}
+
+/// Color temperature of the very first light of the sunrise.
+const SUNRISE_START_TEMPERATURE: f32 = 1000.0;
+
+/// After a snooze, the light comes back within this time instead of doing a whole sunrise again.
+const SNOOZE_RISE_SECONDS: i64 = 60;
+
+/// Keyframes of the sunrise as (progress, share of the color change, share of the brightness).
+/// The color change is interpolated in mired, so that the red and orange phases at the
+/// beginning take as long as they would in a real sunrise.
+const SUNRISE_KEYFRAMES: [(f32, f32, f32); 5] = [
+	(0.0, 0.0, 0.0),
+	(0.2, 0.35, 0.01),
+	(0.45, 0.6, 0.08),
+	(0.7, 0.82, 0.35),
+	(1.0, 1.0, 1.0),
+];
+
+/// Light that the alarm wants the lamp to show.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AlarmOutput {
+	pub temperature: f32,
+	pub brightness: f32,
+}
+
+/// Computes the light of a sunrise that is `progress` (from 0.0 to 1.0) done and
+/// ends at the given color temperature and brightness.
+pub fn sunrise_color(progress: f32, temperature: f32, brightness: f32) -> AlarmOutput {
+	let progress = progress.clamp(0.0, 1.0);
+	let mut color_share = 1.0;
+	let mut brightness_share = 1.0;
+	for pair in SUNRISE_KEYFRAMES.windows(2) {
+		let (p0, c0, b0) = pair[0];
+		let (p1, c1, b1) = pair[1];
+		if progress <= p1 {
+			let a = (progress - p0) / (p1 - p0);
+			color_share = c0 + (c1 - c0) * a;
+			brightness_share = b0 + (b1 - b0) * a;
+			break;
+		}
+	}
+
+	let start_mired = 1_000_000.0 / SUNRISE_START_TEMPERATURE;
+	let end_mired = 1_000_000.0 / temperature;
+	let mired = start_mired + (end_mired - start_mired) * color_share;
+	return AlarmOutput {
+		temperature: 1_000_000.0 / mired,
+		brightness: brightness * brightness_share,
+	};
+}
+
+/// Converts a local wall clock time into an absolute time. Ambiguous times
+/// (when the clock is turned back) resolve to the earlier one, and times
+/// that are skipped (when the clock is turned forward) to the first valid
+/// time after the gap.
+fn local_to_utc<Tz: TimeZone>(tz: &Tz, local: NaiveDateTime) -> Option<DateTime<Utc>> {
+	let mut local = local;
+	// DST gaps are at most an hour long, but be generous.
+	for _ in 0..=120 {
+		match tz.from_local_datetime(&local) {
+			LocalResult::Single(time) => return Some(time.with_timezone(&Utc)),
+			LocalResult::Ambiguous(earliest, _) => return Some(earliest.with_timezone(&Utc)),
+			LocalResult::None => local += Duration::minutes(1),
+		}
+	}
+	return None;
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum AlarmState {
+	Idle,
+	Rising { start: DateTime<Utc>, end: DateTime<Utc> },
+	Holding { until: DateTime<Utc> },
+	Snoozed { until: DateTime<Utc> },
+}
+
+/// A sunrise alarm clock that fades the light in over a configurable time,
+/// reaching full brightness at the alarm time.
+///
+/// All times are handled as absolute times, so a sunrise always takes the
+/// configured duration, even if the clock is changed for daylight saving
+/// time in the middle of it.
+pub struct SunriseAlarm {
+	config: AlarmConfig,
+	state: AlarmState,
+	/// End of the last alarm that was stopped or has finished, so that it is not started again.
+	dismissed: Option<DateTime<Utc>>,
+}
+
+impl SunriseAlarm {
+	pub fn new(config: AlarmConfig) -> Self {
+		return Self { config, state: AlarmState::Idle, dismissed: None };
+	}
+
+	pub fn config(&self) -> &AlarmConfig {
+		return &self.config;
+	}
+
+	/// Replaces the configuration. A running alarm is stopped.
+	pub fn set_config(&mut self, config: AlarmConfig) {
+		self.config = config;
+		self.stop();
+	}
+
+	/// Returns true while the alarm controls the light, i.e. button presses should snooze or stop it.
+	pub fn is_active(&self) -> bool {
+		return self.state != AlarmState::Idle;
+	}
+
+	/// Returns the end of the next sunrise that has not ended yet at `now`.
+	pub fn next_alarm<Tz: TimeZone>(&self, now: DateTime<Utc>, tz: &Tz) -> Option<DateTime<Utc>> {
+		if !self.config.enabled {
+			return None;
+		}
+		let mut date = now.with_timezone(tz).date_naive().pred_opt()?;
+		for _ in 0..9 {
+			if let Some(time) = self.config.times.get(date.weekday()) {
+				if let Some(end) = local_to_utc(tz, date.and_time(time)) {
+					if end > now {
+						return Some(end);
+					}
+				}
+			}
+			date = date.succ_opt()?;
+		}
+		return None;
+	}
+
+	/// Advances the alarm to `now` and returns the light it wants to show, if any.
+	/// Should be called regularly, e.g. once per second.
+	pub fn update<Tz: TimeZone>(&mut self, now: DateTime<Utc>, tz: &Tz) -> Option<AlarmOutput> {
+		if self.state == AlarmState::Idle {
+			if let Some(end) = self.next_alarm(now, tz) {
+				let start = end - Duration::seconds(self.config.duration as i64);
+				if start <= now && self.dismissed != Some(end) {
+					self.state = AlarmState::Rising { start, end };
+				}
+			}
+		}
+
+		match self.state.clone() {
+			AlarmState::Idle => return None,
+			AlarmState::Rising { start, end } => {
+				if now >= end {
+					self.dismissed = Some(end);
+					self.state = AlarmState::Holding { until: end + Duration::seconds(self.config.hold as i64) };
+					return self.update(now, tz);
+				}
+				let total = (end - start).num_milliseconds() as f32;
+				let progress = (now - start).num_milliseconds() as f32 / total;
+				return Some(sunrise_color(progress, self.config.temperature, self.config.brightness));
+			},
+			AlarmState::Holding { until } => {
+				if now >= until {
+					self.state = AlarmState::Idle;
+					return None;
+				}
+				return Some(sunrise_color(1.0, self.config.temperature, self.config.brightness));
+			},
+			AlarmState::Snoozed { until } => {
+				if now >= until {
+					self.state = AlarmState::Rising { start: now, end: now + Duration::seconds(SNOOZE_RISE_SECONDS) };
+					return self.update(now, tz);
+				}
+				return Some(sunrise_color(0.0, self.config.temperature, self.config.brightness));
+			},
+		}
+	}
+
+	/// Turns the light down for the configured snooze time, if the alarm is active.
+	pub fn snooze(&mut self, now: DateTime<Utc>) {
+		if let AlarmState::Rising { end, .. } = self.state {
+			self.dismissed = Some(end);
+		}
+		if self.is_active() {
+			self.state = AlarmState::Snoozed { until: now + Duration::seconds(self.config.snooze as i64) };
+		}
+	}
+
+	/// Stops the alarm. The light stays as it is, but is no longer controlled by the alarm.
+	pub fn stop(&mut self) {
+		if let AlarmState::Rising { end, .. } = self.state {
+			self.dismissed = Some(end);
+		}
+		self.state = AlarmState::Idle;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use chrono_tz::Europe::Berlin;
+
+	fn utc(day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+		return Utc.with_ymd_and_hms(2024, 3, day, hour, minute, 0).unwrap();
+	}
+
+	fn config() -> AlarmConfig {
+		let mut config = AlarmConfig { enabled: true, ..Default::default() };
+		config.times.sat = NaiveTime::from_hms_opt(7, 0, 0);
+		config.times.sun = NaiveTime::from_hms_opt(7, 0, 0);
+		return config;
+	}
+
+	#[test]
+	fn test_validate() {
+		assert_eq!(config().validate(), Ok(()));
+		assert_eq!(AlarmConfig { duration: 0, ..config() }.validate(), Err(AlarmError::InvalidDuration));
+		assert_eq!(AlarmConfig { brightness: f32::NAN, ..config() }.validate(), Err(AlarmError::InvalidBrightness));
+		assert_eq!(AlarmConfig { brightness: -1.0, ..config() }.validate(), Err(AlarmError::InvalidBrightness));
+		assert_eq!(AlarmConfig { temperature: 0.0, ..config() }.validate(), Err(AlarmError::InvalidTemperature));
+		assert_eq!(AlarmConfig { temperature: f32::NAN, ..config() }.validate(), Err(AlarmError::InvalidTemperature));
+	}
+
+	#[test]
+	fn test_sunrise_color() {
+		let start = sunrise_color(0.0, 5500.0, 20.0);
+		assert_eq!(start.brightness, 0.0);
+		assert!((start.temperature - 1000.0).abs() < 0.1);
+
+		let middle = sunrise_color(0.5, 5500.0, 20.0);
+		assert!(middle.temperature > 1800.0 && middle.temperature < 3000.0);
+		assert!(middle.brightness > 0.5 && middle.brightness < 10.0);
+
+		let end = sunrise_color(1.0, 5500.0, 20.0);
+		assert_eq!(end.brightness, 20.0);
+		assert!((end.temperature - 5500.0).abs() < 0.1);
+	}
+
+	#[test]
+	fn test_next_alarm_respects_dst() {
+		let alarm = SunriseAlarm::new(config());
+		// Saturday 2024-03-30, still CET (UTC+1)
+		assert_eq!(alarm.next_alarm(utc(30, 0, 0), &Berlin), Some(utc(30, 6, 0)));
+		// Sunday 2024-03-31, already CEST (UTC+2)
+		assert_eq!(alarm.next_alarm(utc(30, 6, 0), &Berlin), Some(utc(31, 5, 0)));
+	}
+
+	#[test]
+	fn test_alarm_in_dst_gap() {
+		let mut config = config();
+		config.times.sun = NaiveTime::from_hms_opt(2, 30, 0);
+		let alarm = SunriseAlarm::new(config);
+		// 02:30 does not exist on 2024-03-31, the alarm is at 03:00 CEST instead
+		assert_eq!(alarm.next_alarm(utc(30, 23, 0), &Berlin), Some(utc(31, 1, 0)));
+	}
+
+	#[test]
+	fn test_ramp_across_dst_change() {
+		let mut config = config();
+		config.times.sun = NaiveTime::from_hms_opt(3, 15, 0);
+		let mut alarm = SunriseAlarm::new(config);
+
+		// Sunrise ends at 03:15 CEST = 01:15 UTC, so it starts at 00:45 UTC = 01:45 CET
+		assert_eq!(alarm.update(utc(31, 0, 44), &Berlin), None);
+		let first = alarm.update(utc(31, 0, 45), &Berlin).unwrap();
+		assert_eq!(first.brightness, 0.0);
+		let middle = alarm.update(utc(31, 1, 0), &Berlin).unwrap();
+		assert_eq!(middle, sunrise_color(0.5, 5500.0, 20.0));
+		let end = alarm.update(utc(31, 1, 15), &Berlin).unwrap();
+		assert_eq!(end.brightness, 20.0);
+
+		// Holds the light for 30 minutes, then releases control
+		assert!(alarm.update(utc(31, 1, 44), &Berlin).is_some());
+		assert_eq!(alarm.update(utc(31, 1, 45), &Berlin), None);
+		assert_eq!(alarm.update(utc(31, 1, 46), &Berlin), None);
+	}
+
+	#[test]
+	fn test_starts_late_after_reboot() {
+		let mut alarm = SunriseAlarm::new(config());
+		// 06:45 CET is in the middle of the sunrise that ends at 07:00
+		let output = alarm.update(utc(30, 5, 45), &Berlin).unwrap();
+		assert_eq!(output, sunrise_color(0.5, 5500.0, 20.0));
+	}
+
+	#[test]
+	fn test_snooze_and_stop() {
+		let mut alarm = SunriseAlarm::new(config());
+		alarm.update(utc(30, 5, 50), &Berlin).unwrap();
+		assert!(alarm.is_active());
+
+		alarm.snooze(utc(30, 5, 50));
+		assert_eq!(alarm.update(utc(30, 5, 55), &Berlin).unwrap().brightness, 0.0);
+		// Comes back after 9 minutes, and rises within one minute
+		let back = alarm.update(utc(30, 5, 59), &Berlin).unwrap();
+		assert_eq!(back.brightness, 0.0);
+		assert_eq!(alarm.update(utc(30, 6, 0), &Berlin).unwrap().brightness, 20.0);
+
+		alarm.stop();
+		assert!(!alarm.is_active());
+		assert_eq!(alarm.update(utc(30, 6, 1), &Berlin), None);
+		// The next day's alarm starts normally
+		assert!(alarm.update(utc(31, 4, 40), &Berlin).is_some());
+	}
+}
@@ -0,0 +1,282 @@
+use core::fmt;
+use serde::{Deserialize, Serialize};
+#[cfg(not(feature = "std"))]
+use crate::float::Float;
+
+/// Below this illuminance, the color channels are too noisy to estimate a color temperature.
+const MIN_LUX_FOR_CCT: f32 = 5.0;
+
+/// Coefficients of the CCT estimate from the VEML6040 application note:
+/// `CCT = A * ((R - B) / G + OFFSET) ^ EXPONENT`
+const CCT_A: f32 = 4278.6;
+const CCT_OFFSET: f32 = 0.5;
+const CCT_EXPONENT: f32 = -1.2455;
+
+/// A reading of the red, green and blue channels of an ambient light sensor like
+/// the VEML6040, scaled so that the green channel is the illuminance in lux.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AmbientReading {
+	pub red: f32,
+	pub green: f32,
+	pub blue: f32,
+}
+
+impl AmbientReading {
+	/// The reading that a light source of the given illuminance and color temperature
+	/// would cause. Red and blue are split evenly around green, which is all the CCT
+	/// estimate can tell apart.
+	pub fn of_light(lux: f32, temperature: f32) -> Self {
+		let index = (temperature / CCT_A).powf(1.0 / CCT_EXPONENT) - CCT_OFFSET;
+		let difference = lux * index;
+		return Self {
+			red: lux + difference / 2.0,
+			green: lux,
+			blue: lux - difference / 2.0,
+		};
+	}
+
+	pub fn lux(&self) -> f32 {
+		return self.green;
+	}
+
+	/// Estimate of the correlated color temperature in K, or `None` if it is too dark.
+	pub fn cct(&self) -> Option<f32> {
+		if self.green < MIN_LUX_FOR_CCT {
+			return None;
+		}
+		let index = (self.red - self.blue) / self.green + CCT_OFFSET;
+		if index <= 0.0 {
+			return None;
+		}
+		return Some((CCT_A * index.powf(CCT_EXPONENT)).clamp(1000.0, 25000.0));
+	}
+
+	/// Removes the light of another source from this reading, e.g. the lamp's own light.
+	pub fn without(&self, other: &AmbientReading) -> AmbientReading {
+		return AmbientReading {
+			red: self.red - other.red,
+			green: (self.green - other.green).max(0.0),
+			blue: self.blue - other.blue,
+		};
+	}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ColorMatchingError {
+	InvalidTemperatureRange,
+	InvalidLux,
+	InvalidSmoothing,
+}
+
+impl fmt::Display for ColorMatchingError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			ColorMatchingError::InvalidTemperatureRange => write!(f, "Color temperatures must be between 1000 and 25000 K, and the range must not be empty."),
+			ColorMatchingError::InvalidLux => write!(f, "Illuminance and sensor gain must not be negative."),
+			ColorMatchingError::InvalidSmoothing => write!(f, "Smoothing must not be negative."),
+		}
+	}
+}
+
+/// Configuration of the ambient color matching mode. Illuminances are in lux,
+/// color temperatures in K and durations in seconds.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ColorMatchingConfig {
+	#[serde(default)]
+	pub enabled: bool,
+	/// Below this much daylight, the lamp fully falls back to the warm schedule.
+	/// Between this and twice as much, it blends.
+	#[serde(default = "default_min_lux")]
+	pub min_lux: f32,
+	/// Illuminance that the lamp adds at its own sensor per unit of brightness.
+	#[serde(default = "default_sensor_gain")]
+	pub sensor_gain: f32,
+	/// Time constant of the tracking. Daylight changes slowly, and so should the lamp.
+	#[serde(default = "default_smoothing")]
+	pub smoothing: f32,
+	#[serde(default = "default_min_temperature")]
+	pub min_temperature: f32,
+	#[serde(default = "default_max_temperature")]
+	pub max_temperature: f32,
+	/// Color temperature when it's dark and no other mode provides one.
+	#[serde(default = "default_fallback_temperature")]
+	pub fallback_temperature: f32,
+}
+
+fn default_min_lux() -> f32 { 50.0 }
+fn default_sensor_gain() -> f32 { 5.0 }
+fn default_smoothing() -> f32 { 300.0 }
+fn default_min_temperature() -> f32 { 2200.0 }
+fn default_max_temperature() -> f32 { 6500.0 }
+fn default_fallback_temperature() -> f32 { 2700.0 }
+
+impl Default for ColorMatchingConfig {
+	fn default() -> Self {
+		return Self {
+			enabled: false,
+			min_lux: default_min_lux(),
+			sensor_gain: default_sensor_gain(),
+			smoothing: default_smoothing(),
+			min_temperature: default_min_temperature(),
+			max_temperature: default_max_temperature(),
+			fallback_temperature: default_fallback_temperature(),
+		};
+	}
+}
+
+impl ColorMatchingConfig {
+	pub fn validate(&self) -> Result<(), ColorMatchingError> {
+		let valid = |t: f32| (1000.0..=25000.0).contains(&t);
+		if !valid(self.min_temperature) || !valid(self.max_temperature) || !valid(self.fallback_temperature)
+			|| self.min_temperature > self.max_temperature {
+			return Err(ColorMatchingError::InvalidTemperatureRange);
+		}
+		if self.min_lux.is_nan() || self.min_lux < 0.0 || self.sensor_gain.is_nan() || self.sensor_gain < 0.0 {
+			return Err(ColorMatchingError::InvalidLux);
+		}
+		if !self.smoothing.is_finite() || self.smoothing < 0.0 {
+			return Err(ColorMatchingError::InvalidSmoothing);
+		}
+		return Ok(());
+	}
+}
+
+fn mix_mired(a: f32, b: f32, factor: f32) -> f32 {
+	let mired_a = 1_000_000.0 / a;
+	let mired_b = 1_000_000.0 / b;
+	return 1_000_000.0 / (mired_a + (mired_b - mired_a) * factor);
+}
+
+/// Lets the color temperature of the lamp slowly follow the daylight.
+pub struct ColorMatcher {
+	config: ColorMatchingConfig,
+	/// Smoothed color temperature of the daylight
+	tracked: Option<f32>,
+	/// Smoothed share of the daylight in the output, from 0.0 to 1.0
+	weight: f32,
+}
+
+impl ColorMatcher {
+	pub fn new(config: ColorMatchingConfig) -> Self {
+		return Self { config, tracked: None, weight: 0.0 };
+	}
+
+	pub fn config(&self) -> &ColorMatchingConfig {
+		return &self.config;
+	}
+
+	pub fn set_config(&mut self, config: ColorMatchingConfig) {
+		self.config = config;
+	}
+
+	/// Smoothed color temperature of the daylight, if there was enough of it yet.
+	pub fn tracked(&self) -> Option<f32> {
+		return self.tracked;
+	}
+
+	/// Feeds a sensor reading, taken while the lamp was at `lamp_brightness` and
+	/// `lamp_temperature`, `elapsed` seconds after the previous one. Returns the color
+	/// temperature the lamp should have, blending from `fallback` (e.g. from the natural
+	/// light curve) to the daylight. Returns `None` if the mode is disabled.
+	pub fn update(&mut self, reading: &AmbientReading, lamp_brightness: f32, lamp_temperature: f32, elapsed: f32, fallback: Option<f32>) -> Option<f32> {
+		let lamp = AmbientReading::of_light(self.config.sensor_gain * lamp_brightness, lamp_temperature);
+		let daylight = reading.without(&lamp);
+
+		let alpha = if self.config.smoothing > 0.0 { 1.0 - (-elapsed / self.config.smoothing).exp() } else { 1.0 };
+		let target_weight = ((daylight.lux() - self.config.min_lux) / self.config.min_lux.max(1.0)).clamp(0.0, 1.0);
+		self.weight += (target_weight - self.weight) * alpha;
+
+		if let Some(cct) = daylight.cct() {
+			let cct = cct.clamp(self.config.min_temperature, self.config.max_temperature);
+			self.tracked = Some(match self.tracked {
+				Some(tracked) => mix_mired(tracked, cct, alpha),
+				None => cct,
+			});
+		}
+
+		if !self.config.enabled {
+			return None;
+		}
+		let fallback = fallback.unwrap_or(self.config.fallback_temperature);
+		return match self.tracked {
+			Some(tracked) => Some(mix_mired(fallback, tracked, self.weight)),
+			None => Some(fallback),
+		};
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn config() -> ColorMatchingConfig {
+		return ColorMatchingConfig { enabled: true, smoothing: 10.0, ..Default::default() };
+	}
+
+	#[test]
+	fn test_cct_estimate() {
+		for temperature in [2700.0, 4000.0, 5000.0, 6500.0] {
+			let reading = AmbientReading::of_light(300.0, temperature);
+			let cct = reading.cct().unwrap();
+			assert!((cct - temperature).abs() < 1.0, "{} != {}", cct, temperature);
+		}
+		assert_eq!(AmbientReading::of_light(1.0, 5000.0).cct(), None);
+	}
+
+	#[test]
+	fn test_own_light_is_subtracted() {
+		// Cool daylight, plus the lamp shining warm light onto the sensor
+		let daylight = AmbientReading::of_light(200.0, 6000.0);
+		let lamp = AmbientReading::of_light(5.0 * 20.0, 2700.0);
+		let reading = AmbientReading {
+			red: daylight.red + lamp.red,
+			green: daylight.green + lamp.green,
+			blue: daylight.blue + lamp.blue,
+		};
+		assert!(reading.cct().unwrap() < 5000.0);
+
+		let mut matcher = ColorMatcher::new(config());
+		let mut output = 0.0;
+		for _ in 0..100 {
+			output = matcher.update(&reading, 20.0, 2700.0, 1.0, None).unwrap();
+		}
+		assert!((matcher.tracked().unwrap() - 6000.0).abs() < 1.0, "{:?}", matcher.tracked());
+		assert!((output - 6000.0).abs() < 1.0, "{}", output);
+	}
+
+	#[test]
+	fn test_falls_back_when_daylight_fades() {
+		let mut matcher = ColorMatcher::new(config());
+		let day = AmbientReading::of_light(500.0, 5000.0);
+		for _ in 0..100 {
+			matcher.update(&day, 0.0, 3000.0, 1.0, Some(2200.0));
+		}
+
+		// Dusk: the output slowly blends to the fallback instead of jumping
+		let dusk = AmbientReading::of_light(10.0, 3500.0);
+		let first = matcher.update(&dusk, 0.0, 3000.0, 1.0, Some(2200.0)).unwrap();
+		assert!(first > 4000.0, "{}", first);
+		let mut output = first;
+		for _ in 0..100 {
+			output = matcher.update(&dusk, 0.0, 3000.0, 1.0, Some(2200.0)).unwrap();
+		}
+		assert!((output - 2200.0).abs() < 1.0, "{}", output);
+
+		// Without a schedule, the configured fallback is used
+		let output = matcher.update(&dusk, 0.0, 3000.0, 1.0, None).unwrap();
+		assert!((output - 2700.0).abs() < 1.0, "{}", output);
+	}
+
+	#[test]
+	fn test_clamped_and_disabled() {
+		let mut matcher = ColorMatcher::new(config());
+		let output = matcher.update(&AmbientReading::of_light(1000.0, 12000.0), 0.0, 3000.0, 1.0, None).unwrap();
+		assert!(output <= 6500.0 + 0.1, "{}", output);
+
+		let mut matcher = ColorMatcher::new(ColorMatchingConfig::default());
+		assert_eq!(matcher.update(&AmbientReading::of_light(1000.0, 5000.0), 0.0, 3000.0, 1.0, None), None);
+		assert_eq!(ColorMatchingConfig { min_temperature: 7000.0, ..config() }.validate(), Err(ColorMatchingError::InvalidTemperatureRange));
+		assert_eq!(ColorMatchingConfig { smoothing: f32::NAN, ..config() }.validate(), Err(ColorMatchingError::InvalidSmoothing));
+		assert_eq!(ColorMatchingConfig { smoothing: -1.0, ..config() }.validate(), Err(ColorMatchingError::InvalidSmoothing));
+	}
+}
@@ -0,0 +1,399 @@
+//! Version 1 of the lamp's JSON API. It does not depend on the HTTP server, so that
+//! it can be tested on the host: the firmware passes every request below `PREFIX`
+//! to `handle`, and sends back the `Response`.
+//!
+//! Resources:
+//! - `/light`: GET, PUT and PATCH the light state. Reads and writes all zones,
+//!   unless a zone or group is selected with `?zone=name` or `?group=name`.
+//! - `/sensors`: GET readings of the ambient light and presence sensors.
+//! - `/telemetry`: GET temperature and power consumption.
+//! - `/device`: GET information about the lamp itself.
+//!
+//! Errors are returned as `{"error": {"code": ..., "field": ..., "message": ...}}`.
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use serde::{Deserialize, Serialize};
+
+use crate::color::XyColor;
+use crate::corridor::CorridorDetection;
+use crate::occupancy::OccupancyState;
+use crate::scene::{dim_speed, transition, ColorTarget};
+use crate::zone::{LightState, ZoneSelector, Zones};
+
+pub const PREFIX: &str = "/api/v1";
+
+pub const VERSION: u32 = 1;
+
+/// Brightness when the lamp is switched on without giving a brightness, and it
+/// was off before.
+pub const DEFAULT_ON_BRIGHTNESS: f32 = 1.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Method {
+	Get,
+	Put,
+	Patch,
+	Post,
+	Delete,
+}
+
+/// A response with a JSON body.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Response {
+	pub status: u16,
+	pub body: String,
+}
+
+impl Response {
+	fn json<T: Serialize>(status: u16, value: &T) -> Self {
+		return match serde_json::to_string(value) {
+			Ok(body) => Self { status, body },
+			Err(err) => ApiError::new(500, "internal", err.to_string()).into(),
+		};
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ApiError {
+	#[serde(skip)]
+	pub status: u16,
+	/// Kind of the error, which clients can rely on.
+	pub code: &'static str,
+	/// The invalid field of the request body, if there is one.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub field: Option<&'static str>,
+	/// Description of the error for humans.
+	pub message: String,
+}
+
+impl ApiError {
+	pub fn new(status: u16, code: &'static str, message: impl ToString) -> Self {
+		return Self { status, code, field: None, message: message.to_string() };
+	}
+
+	fn invalid(field: &'static str, message: &str) -> Self {
+		return Self { status: 422, code: "invalid_value", field: Some(field), message: message.to_string() };
+	}
+}
+
+impl From<ApiError> for Response {
+	fn from(error: ApiError) -> Self {
+		#[derive(Serialize)]
+		struct Body<'a> {
+			error: &'a ApiError,
+		}
+		return Response::json(error.status, &Body { error: &error });
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LightResource {
+	/// Whether the lamp is on, i.e. whether its brightness is above 0.
+	pub on: bool,
+	pub brightness: f32,
+	/// Color temperature in K, if the color was set as a color temperature.
+	pub temperature: Option<f32>,
+	/// Chromaticity on the CIE 1931 xy diagram, if the color was set as such.
+	pub xy: Option<XyColor>,
+	/// Time in seconds in which the lamp fades to a new state.
+	pub transition: f32,
+}
+
+impl LightResource {
+	pub fn new(state: &LightState, tick: f32) -> Self {
+		return Self {
+			on: state.brightness > 0.0,
+			brightness: state.brightness,
+			temperature: if state.xy.is_none() { Some(state.temperature) } else { None },
+			xy: state.xy.clone(),
+			transition: transition(state.dim_speed, tick),
+		};
+	}
+}
+
+/// Body of PUT and PATCH requests to `/light`. PUT needs `on`, `brightness` and a
+/// color, PATCH only changes the given fields. Without a transition, the lamp
+/// fades as fast as it did before.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LightUpdate {
+	pub on: Option<bool>,
+	pub brightness: Option<f32>,
+	pub temperature: Option<f32>,
+	pub xy: Option<XyColor>,
+	pub transition: Option<f32>,
+}
+
+impl LightUpdate {
+	pub fn validate(&self, complete: bool) -> Result<(), ApiError> {
+		if complete {
+			if self.on.is_none() {
+				return Err(ApiError::invalid("on", "PUT needs all of on, brightness and a color, use PATCH to change only some."));
+			}
+			if self.brightness.is_none() {
+				return Err(ApiError::invalid("brightness", "PUT needs all of on, brightness and a color, use PATCH to change only some."));
+			}
+			if self.temperature.is_none() && self.xy.is_none() {
+				return Err(ApiError::invalid("temperature", "PUT needs all of on, brightness and a color, use PATCH to change only some."));
+			}
+		}
+		if let Some(brightness) = self.brightness {
+			if !brightness.is_finite() || brightness < 0.0 {
+				return Err(ApiError::invalid("brightness", "Brightness must not be negative."));
+			}
+			if self.on == Some(false) && brightness > 0.0 {
+				return Err(ApiError::invalid("brightness", "A lamp which is off has a brightness of 0."));
+			}
+			if self.on == Some(true) && brightness == 0.0 {
+				return Err(ApiError::invalid("brightness", "A lamp which is on needs a brightness above 0."));
+			}
+		}
+		if let Some(temperature) = self.temperature {
+			if self.xy.is_some() {
+				return Err(ApiError::invalid("xy", "Set either temperature or xy, not both."));
+			}
+			if !ColorTarget::Temperature(temperature).is_valid() {
+				return Err(ApiError::invalid("temperature", "Color temperature must be between 1000 and 25000 K."));
+			}
+		}
+		if let Some(xy) = &self.xy {
+			if !ColorTarget::Xy(xy.clone()).is_valid() {
+				return Err(ApiError::invalid("xy", "Color is outside of the CIE 1931 xy diagram."));
+			}
+		}
+		if self.transition.is_some_and(|transition| !transition.is_finite() || transition < 0.0) {
+			return Err(ApiError::invalid("transition", "Transition time must not be negative."));
+		}
+		return Ok(());
+	}
+
+	pub(crate) fn apply(&self, state: &mut LightState, tick: f32) {
+		if let Some(transition) = self.transition {
+			state.dim_speed = dim_speed(transition, tick);
+		}
+		if let Some(temperature) = self.temperature {
+			state.set_temperature(temperature);
+		}
+		if let Some(xy) = &self.xy {
+			state.xy = Some(xy.clone());
+		}
+		if let Some(brightness) = self.brightness {
+			state.brightness = brightness;
+		}
+		match self.on {
+			Some(false) => state.brightness = 0.0,
+			Some(true) if state.brightness <= 0.0 => state.brightness = DEFAULT_ON_BRIGHTNESS,
+			_ => {},
+		}
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SensorsResource {
+	/// Illuminance of the ambient light in lx, if the light sensor works.
+	pub illuminance: Option<f32>,
+	/// Color temperature of the ambient light in K, if it's bright enough to tell.
+	pub ambient_temperature: Option<f32>,
+	pub occupancy: OccupancyState,
+	/// What this lamp's own sensor detects as part of a corridor, in the format
+	/// which the other lamps accept. `None` while nobody is there, or if the
+	/// corridor is disabled.
+	#[serde(default)]
+	pub corridor: Option<CorridorDetection>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TelemetryResource {
+	/// Temperature of the LED board in °C.
+	pub temperature: f32,
+	/// Supply voltage in V.
+	pub voltage: f32,
+	/// Supply current in A.
+	pub current: f32,
+	/// Power consumption in W.
+	pub power: f32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DeviceResource {
+	pub name: String,
+	pub firmware_version: String,
+	pub api_version: u32,
+	/// Time since the lamp started, in s.
+	pub uptime: u64,
+	pub zones: Vec<String>,
+}
+
+/// What the API needs from the lamp.
+pub trait Lamp {
+	/// Runs `f` with exclusive access to the zones.
+	fn with_zones<R>(&self, f: impl FnOnce(&mut Zones) -> R) -> R;
+
+	/// Called after the light was changed through the API, e.g. to pause automatic
+	/// adjustments.
+	fn light_changed(&self);
+
+	fn sensors(&self) -> SensorsResource;
+
+	fn telemetry(&self) -> TelemetryResource;
+
+	fn device(&self) -> DeviceResource;
+
+	/// Interval in seconds in which the lamp fades, to convert transition times.
+	fn tick(&self) -> f32;
+}
+
+/// Handles a request to `path`, which may start with `PREFIX` and may have a query.
+pub fn handle(lamp: &impl Lamp, method: Method, path: &str, body: &[u8]) -> Response {
+	let path = path.strip_prefix(PREFIX).unwrap_or(path);
+	let (path, query) = path.split_once('?').unwrap_or((path, ""));
+	let result = match (path.trim_end_matches('/'), method) {
+		("/light", Method::Get) => selector(query).and_then(|selector| get_light(lamp, &selector)),
+		("/light", Method::Put) => selector(query).and_then(|selector| update_light(lamp, &selector, body, true)),
+		("/light", Method::Patch) => selector(query).and_then(|selector| update_light(lamp, &selector, body, false)),
+		("/sensors", Method::Get) => Ok(Response::json(200, &lamp.sensors())),
+		("/telemetry", Method::Get) => Ok(Response::json(200, &lamp.telemetry())),
+		("/device", Method::Get) => Ok(Response::json(200, &lamp.device())),
+		("/light" | "/sensors" | "/telemetry" | "/device", _) => Err(ApiError::new(405, "method_not_allowed", "Method not allowed for this resource.")),
+		_ => Err(ApiError::new(404, "not_found", "No such resource.")),
+	};
+	return result.unwrap_or_else(Response::from);
+}
+
+/// Parses `zone=name` or `group=name` from a query.
+fn selector(query: &str) -> Result<ZoneSelector, ApiError> {
+	let mut selector = ZoneSelector::All;
+	for parameter in query.split('&').filter(|parameter| !parameter.is_empty()) {
+		selector = match parameter.split_once('=') {
+			Some(("zone", name)) => ZoneSelector::Zone(name.to_string()),
+			Some(("group", name)) => ZoneSelector::Group(name.to_string()),
+			_ => return Err(ApiError::new(400, "invalid_query", "Only zone=name or group=name can be queried.")),
+		};
+	}
+	return Ok(selector);
+}
+
+/// The state of the first selected zone. Zones which are selected together
+/// usually share their state.
+fn get_light(lamp: &impl Lamp, selector: &ZoneSelector) -> Result<Response, ApiError> {
+	let tick = lamp.tick();
+	let light = lamp.with_zones(|zones| {
+		let index = zones.config().resolve(selector)?[0];
+		return Ok(LightResource::new(&zones.states()[index], tick));
+	}).map_err(|err: crate::zone::ZoneError| ApiError::new(404, "unknown_zone", err))?;
+	return Ok(Response::json(200, &light));
+}
+
+fn update_light(lamp: &impl Lamp, selector: &ZoneSelector, body: &[u8], complete: bool) -> Result<Response, ApiError> {
+	let update: LightUpdate = serde_json::from_slice(body).map_err(|err| ApiError::new(400, "invalid_json", err))?;
+	update.validate(complete)?;
+	let tick = lamp.tick();
+	lamp.with_zones(|zones| zones.update(selector, |state| update.apply(state, tick)))
+		.map_err(|err| ApiError::new(404, "unknown_zone", err))?;
+	lamp.light_changed();
+	return get_light(lamp, selector);
+}
+
+#[cfg(test)]
+mod tests {
+	use core::cell::{Cell, RefCell};
+	use crate::zone::test_zones;
+	use super::*;
+
+	struct FakeLamp {
+		zones: RefCell<Zones>,
+		changes: Cell<usize>,
+	}
+
+	impl FakeLamp {
+		fn new() -> Self {
+			return Self { zones: RefCell::new(Zones::new(test_zones())), changes: Cell::new(0) };
+		}
+	}
+
+	impl Lamp for FakeLamp {
+		fn with_zones<R>(&self, f: impl FnOnce(&mut Zones) -> R) -> R {
+			return f(&mut self.zones.borrow_mut());
+		}
+
+		fn light_changed(&self) {
+			self.changes.set(self.changes.get() + 1);
+		}
+
+		fn sensors(&self) -> SensorsResource {
+			return SensorsResource { illuminance: Some(120.0), ambient_temperature: None, occupancy: OccupancyState::Active, corridor: None };
+		}
+
+		fn telemetry(&self) -> TelemetryResource {
+			return TelemetryResource { temperature: 41.5, voltage: 12.0, current: 0.5, power: 6.0 };
+		}
+
+		fn device(&self) -> DeviceResource {
+			return DeviceResource { name: "lamp".to_string(), firmware_version: "0.1.0".to_string(), api_version: VERSION, uptime: 60, zones: vec!["main".to_string(), "shelf".to_string()] };
+		}
+
+		fn tick(&self) -> f32 {
+			return 0.01;
+		}
+	}
+
+	fn light(response: &Response) -> LightResource {
+		assert_eq!(response.status, 200, "{}", response.body);
+		return serde_json::from_str(&response.body).unwrap();
+	}
+
+	#[test]
+	fn test_put_and_patch_light() {
+		let lamp = FakeLamp::new();
+		let response = handle(&lamp, Method::Put, "/api/v1/light", br#"{"on": true, "brightness": 2.0, "temperature": 2700, "transition": 1.0}"#);
+		let expected = LightResource { on: true, brightness: 2.0, temperature: Some(2700.0), xy: None, transition: light(&response).transition };
+		assert_eq!(light(&response), expected);
+		assert!((expected.transition - 1.0).abs() < 0.01);
+		assert_eq!(light(&handle(&lamp, Method::Get, "/api/v1/light?zone=shelf", b"")), expected);
+
+		let response = handle(&lamp, Method::Patch, "/api/v1/light?zone=shelf", br#"{"xy": {"x": 0.5, "y": 0.4}}"#);
+		assert_eq!(light(&response).xy, Some(XyColor::new(0.5, 0.4)));
+		assert_eq!(light(&response).temperature, None);
+		assert_eq!(light(&response).brightness, 2.0);
+		assert_eq!(light(&handle(&lamp, Method::Get, "/api/v1/light", b"")).temperature, Some(2700.0));
+
+		// Switching off and on again without a brightness
+		assert!(!light(&handle(&lamp, Method::Patch, "/api/v1/light", br#"{"on": false}"#)).on);
+		assert_eq!(light(&handle(&lamp, Method::Patch, "/api/v1/light", br#"{"on": true}"#)).brightness, DEFAULT_ON_BRIGHTNESS);
+		assert_eq!(lamp.changes.get(), 4);
+	}
+
+	#[test]
+	fn test_errors() {
+		let lamp = FakeLamp::new();
+		let response = handle(&lamp, Method::Patch, "/api/v1/light", br#"{"brightness": -1}"#);
+		assert_eq!(response.status, 422);
+		assert_eq!(response.body, r#"{"error":{"code":"invalid_value","field":"brightness","message":"Brightness must not be negative."}}"#);
+
+		assert_eq!(handle(&lamp, Method::Put, "/api/v1/light", br#"{"on": true, "brightness": 1}"#).status, 422);
+		assert_eq!(handle(&lamp, Method::Patch, "/api/v1/light", br#"{"temperature": 2700, "xy": {"x": 0.5, "y": 0.4}}"#).status, 422);
+		assert_eq!(handle(&lamp, Method::Patch, "/api/v1/light", br#"{"on": false, "brightness": 1}"#).status, 422);
+		assert_eq!(handle(&lamp, Method::Patch, "/api/v1/light", br#"{"colour": 2700}"#).status, 400);
+		assert_eq!(handle(&lamp, Method::Patch, "/api/v1/light", b"{").status, 400);
+		assert_eq!(handle(&lamp, Method::Patch, "/api/v1/light?zone=attic", br#"{"on": true}"#).status, 404);
+		assert_eq!(handle(&lamp, Method::Get, "/api/v1/light?color=red", b"").status, 400);
+		assert_eq!(handle(&lamp, Method::Delete, "/api/v1/light", b"").status, 405);
+		assert_eq!(handle(&lamp, Method::Get, "/api/v1/lights", b"").status, 404);
+		// Nothing was changed by any of these
+		assert_eq!(lamp.changes.get(), 0);
+		assert_eq!(lamp.zones.borrow().states()[0], LightState::default());
+	}
+
+	#[test]
+	fn test_read_only_resources() {
+		let lamp = FakeLamp::new();
+		let response = handle(&lamp, Method::Get, "/api/v1/sensors", b"");
+		assert_eq!(response, Response { status: 200, body: r#"{"illuminance":120.0,"ambient_temperature":null,"occupancy":{"state":"active"},"corridor":null}"#.to_string() });
+		let response = handle(&lamp, Method::Get, "/api/v1/telemetry/", b"");
+		assert_eq!(serde_json::from_str::<TelemetryResource>(&response.body).unwrap(), lamp.telemetry());
+		let response = handle(&lamp, Method::Get, "/api/v1/device", b"");
+		assert_eq!(serde_json::from_str::<DeviceResource>(&response.body).unwrap(), lamp.device());
+		assert_eq!(handle(&lamp, Method::Put, "/api/v1/device", b"{}").status, 405);
+	}
+}
@@ -0,0 +1,187 @@
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::fmt::Write;
+
+/// Device whose serial data was recorded. The radar models have different
+/// protocols, so a capture has to tell them apart.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CaptureSource {
+	Mr24hpc1,
+	Ld2410,
+	Gps,
+}
+
+impl CaptureSource {
+	pub fn name(&self) -> &'static str {
+		return match self {
+			CaptureSource::Mr24hpc1 => "radar-mr24hpc1",
+			CaptureSource::Ld2410 => "radar-ld2410",
+			CaptureSource::Gps => "gps",
+		};
+	}
+
+	pub fn from_name(name: &str) -> Option<Self> {
+		return match name {
+			// Captures from before the model was recorded only had the MR24HPC1
+			"radar" | "radar-mr24hpc1" => Some(CaptureSource::Mr24hpc1),
+			"radar-ld2410" => Some(CaptureSource::Ld2410),
+			"gps" => Some(CaptureSource::Gps),
+			_ => None,
+		};
+	}
+}
+
+/// Bytes which were received at once.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CaptureChunk {
+	/// Time since the start of the device, in ms.
+	pub time_ms: u64,
+	pub source: CaptureSource,
+	pub bytes: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CaptureError {
+	/// The line with the given number (starting at 1) is not a valid chunk.
+	InvalidLine(usize),
+}
+
+impl fmt::Display for CaptureError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			CaptureError::InvalidLine(line) => write!(f, "Line {} must contain a time in ms, a source and hex bytes.", line),
+		}
+	}
+}
+
+/// Ring buffer of the most recently received serial data. When it is full, the
+/// oldest chunks are dropped.
+#[derive(Clone, Debug)]
+pub struct CaptureBuffer {
+	chunks: VecDeque<CaptureChunk>,
+	/// Total number of bytes in all chunks
+	len: usize,
+	capacity: usize,
+}
+
+impl CaptureBuffer {
+	/// Creates a buffer for up to `capacity` bytes of serial data.
+	pub fn new(capacity: usize) -> Self {
+		return Self { chunks: VecDeque::new(), len: 0, capacity };
+	}
+
+	pub fn record(&mut self, time_ms: u64, source: CaptureSource, bytes: &[u8]) {
+		if bytes.is_empty() {
+			return;
+		}
+		let bytes = &bytes[bytes.len().saturating_sub(self.capacity)..];
+		while self.len + bytes.len() > self.capacity {
+			let Some(oldest) = self.chunks.pop_front() else { break };
+			self.len -= oldest.bytes.len();
+		}
+		self.len += bytes.len();
+		self.chunks.push_back(CaptureChunk { time_ms, source, bytes: bytes.to_vec() });
+	}
+
+	pub fn chunks(&self) -> impl Iterator<Item = &CaptureChunk> {
+		return self.chunks.iter();
+	}
+
+	/// Number of bytes in the buffer.
+	pub fn len(&self) -> usize {
+		return self.len;
+	}
+
+	pub fn is_empty(&self) -> bool {
+		return self.len == 0;
+	}
+
+	pub fn clear(&mut self) {
+		self.chunks.clear();
+		self.len = 0;
+	}
+
+	/// Writes all chunks in the text format that `parse_capture` reads.
+	pub fn to_text(&self) -> String {
+		let mut text = String::new();
+		for chunk in &self.chunks {
+			let _ = write!(text, "{} {} ", chunk.time_ms, chunk.source.name());
+			for byte in &chunk.bytes {
+				let _ = write!(text, "{:02x}", byte);
+			}
+			text.push('\n');
+		}
+		return text;
+	}
+}
+
+fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+	return hex.as_bytes().chunks(2)
+		.map(|pair| match pair {
+			[high, low] => Some(((*high as char).to_digit(16)? * 16 + (*low as char).to_digit(16)?) as u8),
+			_ => None,
+		})
+		.collect();
+}
+
+/// Reads a capture with one chunk per line, like `1234 radar-mr24hpc1 5359800300010636 5443`.
+/// Spaces within the hex bytes are allowed. Empty lines and lines starting with
+/// `#` are ignored, so that captures can be annotated.
+pub fn parse_capture(text: &str) -> Result<Vec<CaptureChunk>, CaptureError> {
+	let mut chunks = Vec::new();
+	for (index, line) in text.lines().enumerate() {
+		let line = line.trim();
+		if line.is_empty() || line.starts_with('#') {
+			continue;
+		}
+		let error = CaptureError::InvalidLine(index + 1);
+		let mut parts = line.splitn(3, ' ');
+		let time_ms = parts.next().and_then(|time| time.parse().ok()).ok_or(error.clone())?;
+		let source = parts.next().and_then(CaptureSource::from_name).ok_or(error.clone())?;
+		let hex: String = parts.next().unwrap_or_default().split_whitespace().collect();
+		let bytes = parse_hex(&hex).ok_or(error)?;
+		chunks.push(CaptureChunk { time_ms, source, bytes });
+	}
+	return Ok(chunks);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_ring_buffer() {
+		let mut buffer = CaptureBuffer::new(10);
+		buffer.record(1, CaptureSource::Mr24hpc1, &[1, 2, 3, 4]);
+		buffer.record(2, CaptureSource::Gps, &[5, 6, 7, 8]);
+		assert_eq!(buffer.len(), 8);
+		// The first chunk is dropped completely
+		buffer.record(3, CaptureSource::Mr24hpc1, &[9, 10, 11]);
+		assert_eq!(buffer.len(), 7);
+		assert_eq!(buffer.chunks().map(|chunk| chunk.time_ms).collect::<Vec<_>>(), vec![2, 3]);
+		// A chunk larger than the buffer keeps its end
+		buffer.record(4, CaptureSource::Mr24hpc1, &[0; 12]);
+		assert_eq!(buffer.len(), 10);
+		assert_eq!(buffer.chunks().count(), 1);
+		buffer.clear();
+		assert!(buffer.is_empty());
+	}
+
+	#[test]
+	fn test_text_format() {
+		let mut buffer = CaptureBuffer::new(100);
+		buffer.record(1500, CaptureSource::Mr24hpc1, &[0x53, 0x59, 0x80]);
+		buffer.record(1520, CaptureSource::Gps, b"$GP");
+		buffer.record(1540, CaptureSource::Ld2410, &[0xfd]);
+		let text = buffer.to_text();
+		assert_eq!(text, "1500 radar-mr24hpc1 535980\n1520 gps 244750\n1540 radar-ld2410 fd\n");
+		assert_eq!(parse_capture(&text).unwrap(), buffer.chunks().cloned().collect::<Vec<_>>());
+
+		let annotated = "# Someone enters\n\n1500 radar 53 59 80\n";
+		assert_eq!(parse_capture(annotated).unwrap()[0], CaptureChunk { time_ms: 1500, source: CaptureSource::Mr24hpc1, bytes: vec![0x53, 0x59, 0x80] });
+		assert_eq!(parse_capture("1500 lidar 00"), Err(CaptureError::InvalidLine(1)));
+		assert_eq!(parse_capture("\n1500 radar 5"), Err(CaptureError::InvalidLine(2)));
+	}
+}
@@ -0,0 +1,240 @@
+//! Raw control of the output channels, which bypasses the color pipeline, e.g. to
+//! calibrate or debug LED boards. Raw duties are still subject to the output
+//! limits, and the lamp returns to normal mode after a timeout.
+
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use serde::{Deserialize, Serialize};
+
+use crate::clock::Clock;
+use crate::zone::ZoneConfig;
+
+/// Maximum time that raw mode can be active without being renewed, in s.
+pub const MAX_TIMEOUT: f32 = 3600.0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChannelError {
+	UnknownChannel(String),
+	AmbiguousChannel(String),
+	InvalidDuty(String),
+	InvalidTimeout,
+}
+
+impl fmt::Display for ChannelError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			ChannelError::UnknownChannel(name) => write!(f, "No LED named '{}'.", name),
+			ChannelError::AmbiguousChannel(name) => write!(f, "Several zones have an LED named '{}', use 'zone/{}' instead.", name, name),
+			ChannelError::InvalidDuty(name) => write!(f, "Duty of '{}' must be between 0 and 1.", name),
+			ChannelError::InvalidTimeout => write!(f, "Timeout must be above 0 and at most {} s.", MAX_TIMEOUT),
+		}
+	}
+}
+
+/// Limits which apply to all outputs, to protect the LED board.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OutputLimits {
+	/// Maximum sum of the raw duties of all channels, as a stand-in for the
+	/// power. 1.0 is one channel at full duty.
+	pub max_total_duty: f32,
+	/// Board temperature in °C above which all outputs are dimmed...
+	pub derating_temperature: f32,
+	/// ... until they are off at this temperature.
+	pub shutdown_temperature: f32,
+}
+
+impl Default for OutputLimits {
+	fn default() -> Self {
+		return Self { max_total_duty: 3.0, derating_temperature: 60.0, shutdown_temperature: 80.0 };
+	}
+}
+
+impl OutputLimits {
+	/// Factor for all outputs at the given board temperature.
+	pub fn thermal_factor(&self, temperature: f32) -> f32 {
+		if temperature <= self.derating_temperature {
+			return 1.0;
+		}
+		if temperature >= self.shutdown_temperature {
+			return 0.0;
+		}
+		return (self.shutdown_temperature - temperature) / (self.shutdown_temperature - self.derating_temperature);
+	}
+
+	/// Scales raw duties down, so that they stay within the limits.
+	pub fn limit(&self, duties: &[f32], temperature: f32) -> Vec<f32> {
+		let total: f32 = duties.iter().sum();
+		let power_factor = if total > self.max_total_duty { self.max_total_duty / total } else { 1.0 };
+		let factor = power_factor * self.thermal_factor(temperature);
+		return duties.iter().map(|duty| duty * factor).collect();
+	}
+}
+
+/// Request to switch to raw mode. Channels are named like the LEDs of the zones,
+/// or as `zone/led` if several zones have an LED of that name. Channels which are
+/// not listed are off.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RawRequest {
+	/// Relative duty of each channel, from 0 to 1.
+	pub channels: BTreeMap<String, f32>,
+	/// Time after which the lamp returns to normal mode, in s.
+	#[serde(default = "default_timeout")]
+	pub timeout: f32,
+}
+
+fn default_timeout() -> f32 {
+	return 60.0;
+}
+
+impl RawRequest {
+	/// Duties indexed by output channel.
+	pub fn resolve(&self, config: &ZoneConfig) -> Result<Vec<f32>, ChannelError> {
+		if self.timeout.is_nan() || self.timeout <= 0.0 || self.timeout > MAX_TIMEOUT {
+			return Err(ChannelError::InvalidTimeout);
+		}
+		let channel_count = config.zones.iter().flat_map(|zone| &zone.leds).map(|led| led.channel + 1).max().unwrap_or(0);
+		let mut duties = vec![0.0; channel_count];
+		for (name, duty) in &self.channels {
+			if !(0.0..=1.0).contains(duty) {
+				return Err(ChannelError::InvalidDuty(name.clone()));
+			}
+			duties[channel(config, name)?] = *duty;
+		}
+		return Ok(duties);
+	}
+}
+
+/// Output channel of the LED named `name` or `zone/name`.
+fn channel(config: &ZoneConfig, name: &str) -> Result<usize, ChannelError> {
+	let (zone_name, led_name) = match name.split_once('/') {
+		Some((zone, led)) => (Some(zone), led),
+		None => (None, name),
+	};
+	let mut channels = config.zones.iter()
+		.filter(|zone| zone_name.is_none() || zone_name == Some(zone.name.as_str()))
+		.flat_map(|zone| &zone.leds)
+		.filter(|led| led.name == led_name)
+		.map(|led| led.channel);
+	let channel = channels.next().ok_or_else(|| ChannelError::UnknownChannel(name.to_string()))?;
+	if channels.next().is_some() {
+		return Err(ChannelError::AmbiguousChannel(name.to_string()));
+	}
+	return Ok(channel);
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RawStatus {
+	pub active: bool,
+	/// The duties as requested, before the output limits.
+	pub channels: BTreeMap<String, f32>,
+	/// Time until the lamp returns to normal mode, in s.
+	pub remaining: Option<f32>,
+}
+
+/// Whether raw mode is active, and with which duties. While it is, the color
+/// pipeline must not drive the outputs.
+pub struct RawMode<C> {
+	clock: C,
+	request: Option<RawRequest>,
+	duties: Vec<f32>,
+	/// Time at which raw mode ends, in ms.
+	until: u64,
+}
+
+impl<C: Clock> RawMode<C> {
+	pub fn new(clock: C) -> Self {
+		return Self { clock, request: None, duties: Vec::new(), until: 0 };
+	}
+
+	/// Starts raw mode, or replaces its duties and restarts its timeout.
+	pub fn start(&mut self, request: RawRequest, config: &ZoneConfig) -> Result<(), ChannelError> {
+		self.duties = request.resolve(config)?;
+		self.until = self.clock.now_ms() + (request.timeout * 1000.0) as u64;
+		self.request = Some(request);
+		return Ok(());
+	}
+
+	/// Returns to normal mode.
+	pub fn stop(&mut self) {
+		self.request = None;
+	}
+
+	/// Duties indexed by output channel, or `None` in normal mode.
+	pub fn duties(&self) -> Option<&[f32]> {
+		if self.request.is_none() || self.clock.now_ms() >= self.until {
+			return None;
+		}
+		return Some(&self.duties);
+	}
+
+	pub fn status(&self) -> RawStatus {
+		let now = self.clock.now_ms();
+		return match &self.request {
+			Some(request) if now < self.until => RawStatus {
+				active: true,
+				channels: request.channels.clone(),
+				remaining: Some((self.until - now) as f32 / 1000.0),
+			},
+			_ => RawStatus { active: false, channels: BTreeMap::new(), remaining: None },
+		};
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::clock::ManualClock;
+	use crate::zone::test_zones;
+	use super::*;
+
+	fn request(json: &str) -> RawRequest {
+		return serde_json::from_str(json).unwrap();
+	}
+
+	#[test]
+	fn test_resolve_names() {
+		let config = test_zones();
+		let duties = request(r#"{"channels": {"R": 0.5, "shelf/WW": 1.0, "A": 0.25}}"#).resolve(&config).unwrap();
+		assert_eq!(duties, vec![0.5, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.25]);
+
+		assert_eq!(request(r#"{"channels": {"WW": 1.0}}"#).resolve(&config), Err(ChannelError::AmbiguousChannel("WW".to_string())));
+		assert_eq!(request(r#"{"channels": {"main/A": 1.0}}"#).resolve(&config), Err(ChannelError::UnknownChannel("main/A".to_string())));
+		assert_eq!(request(r#"{"channels": {"R": 1.5}}"#).resolve(&config), Err(ChannelError::InvalidDuty("R".to_string())));
+		assert_eq!(request(r#"{"channels": {}, "timeout": 0}"#).resolve(&config), Err(ChannelError::InvalidTimeout));
+	}
+
+	#[test]
+	fn test_timeout_and_stop() {
+		let clock = ManualClock::new(0);
+		let mut raw = RawMode::new(&clock);
+		assert_eq!(raw.duties(), None);
+		raw.start(request(r#"{"channels": {"G": 0.5}, "timeout": 10}"#), &ZoneConfig::default()).unwrap();
+		assert_eq!(raw.duties(), Some(&[0.0, 0.5, 0.0, 0.0, 0.0, 0.0][..]));
+		clock.advance(4_000);
+		assert_eq!(raw.status().remaining, Some(6.0));
+		clock.advance(6_000);
+		assert_eq!(raw.duties(), None);
+		assert!(!raw.status().active);
+
+		raw.start(request(r#"{"channels": {"G": 0.5}}"#), &ZoneConfig::default()).unwrap();
+		assert!(raw.status().active);
+		raw.stop();
+		assert_eq!(raw.duties(), None);
+
+		// A rejected request keeps the previous state
+		assert!(raw.start(request(r#"{"channels": {"X": 0.5}}"#), &ZoneConfig::default()).is_err());
+		assert_eq!(raw.duties(), None);
+	}
+
+	#[test]
+	fn test_limits() {
+		let limits = OutputLimits::default();
+		assert_eq!(limits.limit(&[1.0, 0.5, 0.5], 25.0), vec![1.0, 0.5, 0.5]);
+		assert_eq!(limits.limit(&[1.0, 1.0, 1.0, 1.0, 1.0, 1.0], 25.0), vec![0.5; 6]);
+		assert_eq!(limits.limit(&[1.0, 0.5], 70.0), vec![0.5, 0.25]);
+		assert_eq!(limits.limit(&[1.0], 90.0), vec![0.0]);
+		assert_eq!(limits.thermal_factor(60.0), 1.0);
+	}
+}
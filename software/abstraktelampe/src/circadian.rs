@@ -0,0 +1,315 @@
+use chrono::{DateTime, Duration, NaiveTime, TimeZone, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+use core::fmt;
+
+use alloc::{vec, vec::Vec};
+
+use crate::sun::{solar_elevation, Location};
+
+const SECONDS_PER_DAY: u32 = 24 * 60 * 60;
+
+/// Color temperature and brightness at a local wall clock time.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TimeKeyframe {
+	pub time: NaiveTime,
+	pub temperature: f32,
+	pub brightness: f32,
+}
+
+/// Color temperature and brightness at an elevation of the sun in degrees.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ElevationKeyframe {
+	pub elevation: f32,
+	pub temperature: f32,
+	pub brightness: f32,
+}
+
+/// Course of the light over the day. The keyframes don't need to be sorted.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircadianCurve {
+	/// Keyed to the clock. Wraps around at midnight, so the last keyframe of
+	/// the day is interpolated towards the first one of the next day.
+	Time(Vec<TimeKeyframe>),
+	/// Keyed to the elevation of the sun, which follows the seasons by itself.
+	/// Mornings and evenings are symmetric. Below the lowest and above the
+	/// highest keyframe, the light stays constant.
+	Elevation(Vec<ElevationKeyframe>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum CurveError {
+	Empty,
+	InvalidTemperature,
+	InvalidBrightness,
+}
+
+impl fmt::Display for CurveError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		return match self {
+			CurveError::Empty => write!(f, "The curve needs at least one keyframe."),
+			CurveError::InvalidTemperature => write!(f, "Color temperature must be between 1000 and 25000 K."),
+			CurveError::InvalidBrightness => write!(f, "Brightness must not be negative."),
+		};
+	}
+}
+
+/// Light that the curve wants the lamp to show.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CircadianOutput {
+	pub temperature: f32,
+	pub brightness: f32,
+}
+
+impl CircadianOutput {
+	/// Interpolates the color temperature in mired, which matches the perceived
+	/// difference better than interpolating in K.
+	fn lerp(&self, other: &CircadianOutput, factor: f32) -> CircadianOutput {
+		let mired = 1_000_000.0 / self.temperature;
+		let other_mired = 1_000_000.0 / other.temperature;
+		return CircadianOutput {
+			temperature: 1_000_000.0 / (mired + (other_mired - mired) * factor),
+			brightness: self.brightness + (other.brightness - self.brightness) * factor,
+		};
+	}
+}
+
+fn validate_values(temperature: f32, brightness: f32) -> Result<(), CurveError> {
+	if !(1000.0..=25000.0).contains(&temperature) {
+		return Err(CurveError::InvalidTemperature);
+	}
+	if brightness.is_nan() || brightness < 0.0 {
+		return Err(CurveError::InvalidBrightness);
+	}
+	return Ok(());
+}
+
+impl CircadianCurve {
+	pub fn validate(&self) -> Result<(), CurveError> {
+		let values: Vec<(f32, f32)> = match self {
+			CircadianCurve::Time(keyframes) => keyframes.iter().map(|k| (k.temperature, k.brightness)).collect(),
+			CircadianCurve::Elevation(keyframes) => keyframes.iter().map(|k| (k.temperature, k.brightness)).collect(),
+		};
+		if values.is_empty() {
+			return Err(CurveError::Empty);
+		}
+		for (temperature, brightness) in values {
+			validate_values(temperature, brightness)?;
+		}
+		return Ok(());
+	}
+
+	/// Evaluates the curve at a local wall clock time. Returns `None` for an
+	/// elevation curve if the location is unknown.
+	pub fn evaluate(&self, local_time: NaiveTime, now: DateTime<Utc>, location: Option<&Location>) -> Option<CircadianOutput> {
+		match self {
+			CircadianCurve::Time(keyframes) => return evaluate_time(keyframes, local_time),
+			CircadianCurve::Elevation(keyframes) => {
+				let elevation = solar_elevation(now, location?) as f32;
+				return evaluate_elevation(keyframes, elevation);
+			},
+		}
+	}
+}
+
+fn evaluate_time(keyframes: &[TimeKeyframe], time: NaiveTime) -> Option<CircadianOutput> {
+	let seconds = time.num_seconds_from_midnight();
+	// Seconds from the previous keyframe to `time`, and from `time` to the next keyframe
+	let since = |k: &TimeKeyframe| (seconds + SECONDS_PER_DAY - k.time.num_seconds_from_midnight()) % SECONDS_PER_DAY;
+	let until = |k: &TimeKeyframe| (k.time.num_seconds_from_midnight() + SECONDS_PER_DAY - seconds) % SECONDS_PER_DAY;
+
+	let previous = keyframes.iter().min_by_key(|k| since(k))?;
+	let next = keyframes.iter().min_by_key(|k| until(k))?;
+	let span = since(previous) + until(next);
+	let factor = if span == 0 { 0.0 } else { since(previous) as f32 / span as f32 };
+
+	let from = CircadianOutput { temperature: previous.temperature, brightness: previous.brightness };
+	let to = CircadianOutput { temperature: next.temperature, brightness: next.brightness };
+	return Some(from.lerp(&to, factor));
+}
+
+fn evaluate_elevation(keyframes: &[ElevationKeyframe], elevation: f32) -> Option<CircadianOutput> {
+	let below = keyframes.iter().filter(|k| k.elevation <= elevation).max_by(|a, b| a.elevation.total_cmp(&b.elevation));
+	let above = keyframes.iter().filter(|k| k.elevation > elevation).min_by(|a, b| a.elevation.total_cmp(&b.elevation));
+
+	let output = |k: &ElevationKeyframe| CircadianOutput { temperature: k.temperature, brightness: k.brightness };
+	return match (below, above) {
+		(Some(below), Some(above)) => {
+			let factor = (elevation - below.elevation) / (above.elevation - below.elevation);
+			Some(output(below).lerp(&output(above), factor))
+		},
+		(Some(keyframe), None) | (None, Some(keyframe)) => Some(output(keyframe)),
+		(None, None) => None,
+	};
+}
+
+/// Configuration of the natural light mode. All durations are in seconds.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CircadianConfig {
+	#[serde(default)]
+	pub enabled: bool,
+	#[serde(default = "default_curve")]
+	pub curve: CircadianCurve,
+	/// How long the curve pauses after the light was changed manually.
+	#[serde(default = "default_override_duration")]
+	pub override_duration: u32,
+}
+
+fn default_override_duration() -> u32 { 2 * 60 * 60 }
+
+/// Warm and dim in the morning and evening, bright and cool around noon.
+fn default_curve() -> CircadianCurve {
+	let keyframe = |hour, temperature, brightness| TimeKeyframe {
+		time: NaiveTime::from_hms_opt(hour, 0, 0).unwrap(),
+		temperature,
+		brightness,
+	};
+	return CircadianCurve::Time(vec![
+		keyframe(6, 2700.0, 5.0),
+		keyframe(9, 4500.0, 30.0),
+		keyframe(13, 5500.0, 40.0),
+		keyframe(18, 4000.0, 25.0),
+		keyframe(21, 2700.0, 8.0),
+		keyframe(23, 2200.0, 2.0),
+	]);
+}
+
+impl Default for CircadianConfig {
+	fn default() -> Self {
+		return Self {
+			enabled: false,
+			curve: default_curve(),
+			override_duration: default_override_duration(),
+		};
+	}
+}
+
+/// Lets the light follow a curve over the day, until somebody changes it manually.
+pub struct NaturalLight {
+	config: CircadianConfig,
+	overridden_until: Option<DateTime<Utc>>,
+}
+
+impl NaturalLight {
+	pub fn new(config: CircadianConfig) -> Self {
+		return Self { config, overridden_until: None };
+	}
+
+	pub fn config(&self) -> &CircadianConfig {
+		return &self.config;
+	}
+
+	/// Replaces the configuration and resumes the curve.
+	pub fn set_config(&mut self, config: CircadianConfig) {
+		self.config = config;
+		self.overridden_until = None;
+	}
+
+	/// Pauses the curve for the configured time, because the light was changed manually.
+	pub fn override_manually(&mut self, now: DateTime<Utc>) {
+		self.overridden_until = Some(now + Duration::seconds(self.config.override_duration as i64));
+	}
+
+	/// Lets the curve control the light again before the override has expired.
+	pub fn resume(&mut self) {
+		self.overridden_until = None;
+	}
+
+	pub fn is_overridden(&self, now: DateTime<Utc>) -> bool {
+		return self.overridden_until.is_some_and(|until| now < until);
+	}
+
+	/// Returns the light the curve wants to show at `now`, if the mode is
+	/// enabled and not overridden. Should be called regularly, e.g. once per second.
+	pub fn update<Tz: TimeZone>(&mut self, now: DateTime<Utc>, tz: &Tz, location: Option<&Location>) -> Option<CircadianOutput> {
+		if !self.config.enabled {
+			return None;
+		}
+		if self.is_overridden(now) {
+			return None;
+		}
+		self.overridden_until = None;
+		let local_time = now.with_timezone(tz).time();
+		return self.config.curve.evaluate(local_time, now, location);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use chrono_tz::Europe::Berlin;
+
+	fn time(hour: u32, minute: u32) -> NaiveTime {
+		return NaiveTime::from_hms_opt(hour, minute, 0).unwrap();
+	}
+
+	fn utc(day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+		return Utc.with_ymd_and_hms(2024, 6, day, hour, minute, 0).unwrap();
+	}
+
+	fn assert_output(output: Option<CircadianOutput>, temperature: f32, brightness: f32) {
+		let output = output.unwrap();
+		assert!((output.temperature - temperature).abs() < 1.0, "Expected {} K, got {:?}", temperature, output);
+		assert!((output.brightness - brightness).abs() < 0.01, "Expected brightness {}, got {:?}", brightness, output);
+	}
+
+	#[test]
+	fn test_time_curve() {
+		let curve: CircadianCurve = serde_json::from_str(r#"{"time": [
+			{"time": "20:00:00", "temperature": 2000.0, "brightness": 2.0},
+			{"time": "08:00:00", "temperature": 4000.0, "brightness": 10.0}
+		]}"#).unwrap();
+		assert_eq!(curve.validate(), Ok(()));
+
+		assert_output(curve.evaluate(time(8, 0), utc(1, 0, 0), None), 4000.0, 10.0);
+		assert_output(curve.evaluate(time(20, 0), utc(1, 0, 0), None), 2000.0, 2.0);
+		// 250 and 500 mired, so half way is 375 mired
+		assert_output(curve.evaluate(time(14, 0), utc(1, 0, 0), None), 2666.67, 6.0);
+		// Wraps around midnight
+		assert_output(curve.evaluate(time(2, 0), utc(1, 0, 0), None), 2666.67, 6.0);
+	}
+
+	#[test]
+	fn test_elevation_curve() {
+		let curve = CircadianCurve::Elevation(vec![
+			ElevationKeyframe { elevation: 30.0, temperature: 5000.0, brightness: 30.0 },
+			ElevationKeyframe { elevation: -6.0, temperature: 2000.0, brightness: 3.0 },
+		]);
+		let berlin = Location::new(52.52, 13.405);
+
+		assert_eq!(curve.evaluate(time(12, 0), utc(21, 11, 6), None), None);
+		// Sun at 60.9°, above the highest keyframe
+		assert_output(curve.evaluate(time(13, 6), utc(21, 11, 6), Some(&berlin)), 5000.0, 30.0);
+		// Sun at -14°, below the lowest keyframe
+		assert_output(curve.evaluate(time(1, 8), utc(21, 23, 8), Some(&berlin)), 2000.0, 3.0);
+		// In the evening, between the keyframes
+		let evening = utc(21, 18, 0);
+		let factor = (solar_elevation(evening, &berlin) as f32 + 6.0) / 36.0;
+		assert!(factor > 0.0 && factor < 1.0);
+		let output = curve.evaluate(time(20, 0), evening, Some(&berlin)).unwrap();
+		assert!((output.brightness - (3.0 + 27.0 * factor)).abs() < 0.01, "{:?}", output);
+	}
+
+	#[test]
+	fn test_validate() {
+		assert_eq!(CircadianCurve::Time(vec![]).validate(), Err(CurveError::Empty));
+		let keyframe = TimeKeyframe { time: time(12, 0), temperature: 500.0, brightness: 1.0 };
+		assert_eq!(CircadianCurve::Time(vec![keyframe]).validate(), Err(CurveError::InvalidTemperature));
+		assert_eq!(CircadianConfig::default().curve.validate(), Ok(()));
+	}
+
+	#[test]
+	fn test_manual_override() {
+		let mut light = NaturalLight::new(CircadianConfig { enabled: true, override_duration: 3600, ..Default::default() });
+		// 13:00 local time
+		assert_output(light.update(utc(1, 11, 0), &Berlin, None), 5500.0, 40.0);
+
+		light.override_manually(utc(1, 11, 0));
+		assert_eq!(light.update(utc(1, 11, 59), &Berlin, None), None);
+		assert!(light.update(utc(1, 12, 0), &Berlin, None).is_some());
+
+		light.override_manually(utc(1, 12, 0));
+		light.resume();
+		assert!(light.update(utc(1, 12, 1), &Berlin, None).is_some());
+	}
+}
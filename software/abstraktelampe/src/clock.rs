@@ -0,0 +1,37 @@
+//! Time source for logic which measures durations, so that it can be tested
+//! deterministically on the host.
+
+use core::cell::Cell;
+
+/// The same trait that the sensor drivers use, so that one clock serves both.
+pub use uptime_clock::Clock;
+
+/// Clock which only advances when it is told to.
+#[derive(Debug, Default)]
+pub struct ManualClock {
+	now: Cell<u64>,
+}
+
+impl ManualClock {
+	pub fn new(now_ms: u64) -> Self {
+		return Self { now: Cell::new(now_ms) };
+	}
+
+	pub fn set(&self, now_ms: u64) {
+		self.now.set(now_ms);
+	}
+
+	pub fn advance(&self, ms: u64) {
+		self.now.set(self.now.get() + ms);
+	}
+}
+
+impl Clock for &ManualClock {
+	fn now_ms(&self) -> u64 {
+		return self.now.get();
+	}
+
+	fn sleep_ms(&self, ms: u64) {
+		self.advance(ms);
+	}
+}
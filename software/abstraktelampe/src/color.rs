@@ -1,5 +1,7 @@
 use core::fmt;
+#[cfg(feature = "alloc")]
 use delaunator::Point;
+use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone)]
 pub struct ColorBoundsError;
@@ -10,7 +12,7 @@ impl fmt::Display for ColorBoundsError {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct XyColor {
 	pub x: f32,
 	pub y: f32
@@ -22,7 +24,7 @@ impl XyColor {
 	}
 }
 
-pub fn temperature_to_xy(mut t: f32) -> Result<XyColor, ColorBoundsError> {
+pub fn temperature_to_xy(t: f32) -> Result<XyColor, ColorBoundsError> {
 	let x: f32;
 	let y: f32;
 
@@ -67,6 +69,7 @@ pub fn temperature_to_xy(mut t: f32) -> Result<XyColor, ColorBoundsError> {
 //     }
 // }
 
+#[cfg(feature = "alloc")]
 impl From<XyColor> for Point {
     fn from(value: XyColor) -> Self {
         Point { x: value.x as f64, y: value.y as f64 }
@@ -0,0 +1,567 @@
+//! "Follow me" lighting for lamps along a corridor or an access balcony. When
+//! somebody is detected walking in one direction, the lamps ahead of them are
+//! switched on, and the ones behind them dim after a delay.
+//!
+//! Every lamp can run the same `Corridor` with the detections of all lamps, and
+//! use its own brightness from it. Each lamp feeds its own sensor, and the
+//! detections of the others have to be forwarded to it, e.g. by home automation.
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use serde::{Deserialize, Serialize};
+
+use crate::clock::{Clock, ManualClock};
+#[cfg(not(feature = "std"))]
+use crate::float::Float;
+use crate::presence::{Direction, MotionLevel, PresenceReading};
+use crate::scene::MAX_NAME_LEN;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CorridorError {
+	InvalidName,
+	DuplicateName(String),
+	NoLamps,
+	InvalidDistance,
+	InvalidDuration,
+	InvalidBrightness,
+	UnknownLamp(String),
+	/// The walk of a simulation never ends, or the simulation would take too many steps.
+	InvalidSimulation,
+}
+
+impl fmt::Display for CorridorError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			CorridorError::InvalidName => write!(f, "Lamp names must be between 1 and {} bytes long.", MAX_NAME_LEN),
+			CorridorError::DuplicateName(name) => write!(f, "The name '{}' is used more than once.", name),
+			CorridorError::NoLamps => write!(f, "There must be at least one lamp."),
+			CorridorError::InvalidDistance => write!(f, "Positions must be finite, and the lookahead must not be negative."),
+			CorridorError::InvalidDuration => write!(f, "The dim delay must not be negative."),
+			CorridorError::InvalidBrightness => write!(f, "Idle brightness must be between 0 and 1."),
+			CorridorError::UnknownLamp(name) => write!(f, "No other lamp named '{}'.", name),
+			CorridorError::InvalidSimulation => write!(f, "Walk positions, speed, sensor range and step must be finite, and speed and step positive."),
+		}
+	}
+}
+
+/// The side of the lamp which its presence sensor watches.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Facing {
+	/// Towards lamps with greater positions.
+	#[default]
+	Increasing,
+	/// Towards lamps with smaller positions.
+	Decreasing,
+}
+
+impl Facing {
+	fn sign(self) -> f32 {
+		return match self {
+			Facing::Increasing => 1.0,
+			Facing::Decreasing => -1.0,
+		};
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CorridorLamp {
+	pub name: String,
+	/// Position along the corridor, in m.
+	pub position: f32,
+	#[serde(default)]
+	pub facing: Facing,
+	/// Lamps to which the light propagates from this one. If unset, these are
+	/// the lamps next to it on either side. Setting it allows to separate parts of
+	/// the corridor, e.g. at a door. Neighbours are mutual, so it's enough to list
+	/// them on one of the two lamps.
+	#[serde(default)]
+	pub neighbours: Option<Vec<String>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CorridorConfig {
+	/// Whether this lamp follows the corridor. If not, it's not dimmed by it.
+	#[serde(default)]
+	pub enabled: bool,
+	/// Name of this lamp among `lamps`.
+	#[serde(default)]
+	pub this_lamp: String,
+	#[serde(default)]
+	pub lamps: Vec<CorridorLamp>,
+	/// How far ahead of somebody lamps are switched on, in m. If it's not known in
+	/// which direction they walk, lamps this far away on both sides are switched on.
+	#[serde(default = "default_lookahead")]
+	pub lookahead: f32,
+	/// How long a lamp stays on after it was last switched on, in s. This is the
+	/// delay after which the lamps behind somebody dim.
+	#[serde(default = "default_dim_delay")]
+	pub dim_delay: f32,
+	/// Brightness of lamps which nobody is near, relative to the brightness of
+	/// the lamps which are on. Can be used as an orientation light.
+	#[serde(default)]
+	pub idle_brightness: f32,
+}
+
+fn default_lookahead() -> f32 {
+	return 6.0;
+}
+
+fn default_dim_delay() -> f32 {
+	return 10.0;
+}
+
+impl Default for CorridorConfig {
+	/// Disabled, without any lamps.
+	fn default() -> Self {
+		return Self {
+			enabled: false,
+			this_lamp: String::new(),
+			lamps: Vec::new(),
+			lookahead: default_lookahead(),
+			dim_delay: default_dim_delay(),
+			idle_brightness: 0.0,
+		};
+	}
+}
+
+impl CorridorConfig {
+	pub fn validate(&self) -> Result<(), CorridorError> {
+		if !self.enabled && self.lamps.is_empty() {
+			return Ok(());
+		}
+		if self.lamps.is_empty() {
+			return Err(CorridorError::NoLamps);
+		}
+		let mut names: Vec<&str> = Vec::new();
+		for lamp in &self.lamps {
+			if lamp.name.is_empty() || lamp.name.len() > MAX_NAME_LEN {
+				return Err(CorridorError::InvalidName);
+			}
+			if names.contains(&lamp.name.as_str()) {
+				return Err(CorridorError::DuplicateName(lamp.name.clone()));
+			}
+			names.push(&lamp.name);
+			if !lamp.position.is_finite() {
+				return Err(CorridorError::InvalidDistance);
+			}
+		}
+		for lamp in &self.lamps {
+			for neighbour in lamp.neighbours.iter().flatten() {
+				if *neighbour == lamp.name || !names.contains(&neighbour.as_str()) {
+					return Err(CorridorError::UnknownLamp(neighbour.clone()));
+				}
+			}
+		}
+		if self.lookahead.is_nan() || self.lookahead < 0.0 {
+			return Err(CorridorError::InvalidDistance);
+		}
+		if self.dim_delay.is_nan() || self.dim_delay < 0.0 {
+			return Err(CorridorError::InvalidDuration);
+		}
+		if !(0.0..=1.0).contains(&self.idle_brightness) {
+			return Err(CorridorError::InvalidBrightness);
+		}
+		if self.enabled && self.lamp_index(&self.this_lamp).is_none() {
+			return Err(CorridorError::UnknownLamp(self.this_lamp.clone()));
+		}
+		return Ok(());
+	}
+
+	pub fn lamp_index(&self, name: &str) -> Option<usize> {
+		return self.lamps.iter().position(|lamp| lamp.name == name);
+	}
+
+	/// Neighbours of each lamp, as indices into `lamps`.
+	fn neighbours(&self) -> Vec<Vec<usize>> {
+		let mut neighbours = vec![Vec::new(); self.lamps.len()];
+		let mut connect = |a: usize, b: usize| {
+			if a != b && !neighbours[a].contains(&b) {
+				neighbours[a].push(b);
+				neighbours[b].push(a);
+			}
+		};
+		for (index, lamp) in self.lamps.iter().enumerate() {
+			match &lamp.neighbours {
+				Some(names) => {
+					for other in names.iter().filter_map(|name| self.lamp_index(name)) {
+						connect(index, other);
+					}
+				}
+				None => {
+					let before = self.nearest(index, |offset| offset < 0.0);
+					let after = self.nearest(index, |offset| offset > 0.0);
+					for other in before.into_iter().chain(after) {
+						connect(index, other);
+					}
+				}
+			}
+		}
+		return neighbours;
+	}
+
+	/// The nearest lamp whose offset from the lamp at `index` matches.
+	fn nearest(&self, index: usize, side: impl Fn(f32) -> bool) -> Option<usize> {
+		let position = self.lamps[index].position;
+		return self.lamps.iter()
+			.enumerate()
+			.filter(|(_, lamp)| side(lamp.position - position))
+			.min_by(|(_, a), (_, b)| (a.position - position).abs().total_cmp(&(b.position - position).abs()))
+			.map(|(other, _)| other);
+	}
+}
+
+/// Somebody was detected by the sensor of another lamp.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CorridorDetection {
+	pub lamp: String,
+	/// Relative to the sensor, as reported by it.
+	#[serde(default)]
+	pub direction: Option<Direction>,
+}
+
+/// Switches lamps on and off as somebody walks along the corridor.
+pub struct Corridor<C> {
+	config: CorridorConfig,
+	clock: C,
+	neighbours: Vec<Vec<usize>>,
+	/// For each lamp, the time until which it stays on, in ms.
+	on_until: Vec<Option<u64>>,
+	/// Direction of somebody detected by this lamp's own sensor, while there is somebody.
+	own: Option<Option<Direction>>,
+}
+
+impl<C: Clock> Corridor<C> {
+	pub fn new(config: CorridorConfig, clock: C) -> Self {
+		let neighbours = config.neighbours();
+		let on_until = vec![None; config.lamps.len()];
+		return Self { config, clock, neighbours, on_until, own: None };
+	}
+
+	pub fn config(&self) -> &CorridorConfig {
+		return &self.config;
+	}
+
+	/// Replaces the configuration. All lamps are switched off if the lamps changed.
+	pub fn set_config(&mut self, config: CorridorConfig) {
+		if config.lamps != self.config.lamps {
+			self.neighbours = config.neighbours();
+			self.on_until = vec![None; config.lamps.len()];
+		}
+		self.config = config;
+	}
+
+	/// Feeds a reading of the sensor of the lamp at `lamp`.
+	pub fn update(&mut self, lamp: usize, reading: &PresenceReading) {
+		if reading.presence {
+			self.detect(lamp, reading.direction);
+		}
+	}
+
+	/// Somebody was detected by the sensor of the lamp at `lamp`. The direction is
+	/// relative to the sensor, as reported by it.
+	pub fn detect(&mut self, lamp: usize, direction: Option<Direction>) {
+		let Some(origin) = self.config.lamps.get(lamp) else {
+			return;
+		};
+		// Approaching the sensor means walking against the direction it faces
+		let walking = direction.map(|direction| match direction {
+			Direction::Approaching => -origin.facing.sign(),
+			Direction::MovingAway => origin.facing.sign(),
+		});
+		let origin = origin.position;
+		let until = self.clock.now_ms() + (self.config.dim_delay * 1000.0) as u64;
+
+		// Propagate from lamp to lamp, away from the origin and within the lookahead
+		let mut pending = vec![lamp];
+		let mut visited = vec![lamp];
+		while let Some(current) = pending.pop() {
+			self.on_until[current] = Some(until);
+			let distance = (self.config.lamps[current].position - origin).abs();
+			for &next in &self.neighbours[current] {
+				let offset = self.config.lamps[next].position - origin;
+				let ahead = match walking {
+					Some(sign) => offset * sign >= 0.0,
+					None => true,
+				};
+				if ahead && offset.abs() > distance && offset.abs() <= self.config.lookahead && !visited.contains(&next) {
+					visited.push(next);
+					pending.push(next);
+				}
+			}
+		}
+	}
+
+	/// Current brightness of the lamp at `lamp`, relative to the brightness while it's on.
+	pub fn brightness(&self, lamp: usize) -> f32 {
+		let now = self.clock.now_ms();
+		return match self.on_until.get(lamp) {
+			Some(Some(until)) if now < *until => 1.0,
+			_ => self.config.idle_brightness,
+		};
+	}
+
+	pub fn brightnesses(&self) -> Vec<f32> {
+		return (0..self.config.lamps.len()).map(|lamp| self.brightness(lamp)).collect();
+	}
+
+	/// Feeds a reading of this lamp's own sensor. Does nothing while disabled.
+	pub fn update_own(&mut self, reading: &PresenceReading) {
+		if !self.config.enabled {
+			self.own = None;
+			return;
+		}
+		self.own = reading.presence.then_some(reading.direction);
+		if let Some(lamp) = self.config.lamp_index(&self.config.this_lamp) {
+			self.update(lamp, reading);
+		}
+	}
+
+	/// What this lamp's own sensor detects while somebody is there, so that it can
+	/// be forwarded to the other lamps. `None` while disabled.
+	pub fn own_detection(&self) -> Option<CorridorDetection> {
+		if !self.config.enabled {
+			return None;
+		}
+		return self.own.map(|direction| CorridorDetection { lamp: self.config.this_lamp.clone(), direction });
+	}
+
+	/// Feeds a detection by the sensor of another lamp.
+	pub fn detect_named(&mut self, detection: &CorridorDetection) -> Result<(), CorridorError> {
+		let Some(lamp) = self.config.lamp_index(&detection.lamp) else {
+			return Err(CorridorError::UnknownLamp(detection.lamp.clone()));
+		};
+		self.detect(lamp, detection.direction);
+		return Ok(());
+	}
+
+	/// Brightness of this lamp, relative to the brightness while it's on. Always
+	/// full while disabled.
+	pub fn own_brightness(&self) -> f32 {
+		if !self.config.enabled {
+			return 1.0;
+		}
+		return match self.config.lamp_index(&self.config.this_lamp) {
+			Some(lamp) => self.brightness(lamp),
+			None => 1.0,
+		};
+	}
+}
+
+/// Somebody walking along the corridor at a constant speed, for simulations.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Walk {
+	/// Positions in m, like those of the lamps.
+	pub from: f32,
+	pub to: f32,
+	/// In m/s.
+	pub speed: f32,
+}
+
+impl Walk {
+	pub fn duration(&self) -> f32 {
+		return (self.to - self.from).abs() / self.speed;
+	}
+
+	/// Position `time` seconds after the start, or `None` once they arrived and left the corridor.
+	pub fn position(&self, time: f32) -> Option<f32> {
+		if time < 0.0 || time > self.duration() {
+			return None;
+		}
+		return Some(self.from + (self.to - self.from).signum() * self.speed * time);
+	}
+
+	/// What the sensor of `lamp` would report at `time`, if it can see `range` m
+	/// ahead of the lamp on the side which it faces.
+	pub fn reading(&self, lamp: &CorridorLamp, range: f32, time: f32, step: f32) -> PresenceReading {
+		let distance = |time: f32| {
+			return self.position(time)
+				.map(|position| (position - lamp.position) * lamp.facing.sign())
+				.filter(|distance| *distance >= 0.0 && *distance <= range);
+		};
+		let Some(now) = distance(time) else {
+			return PresenceReading::default();
+		};
+		let direction = match distance(time - step) {
+			Some(before) if before > now => Some(Direction::Approaching),
+			Some(before) if before < now => Some(Direction::MovingAway),
+			_ => None,
+		};
+		return PresenceReading { presence: true, motion: MotionLevel::Active, distance: Some(now), direction };
+	}
+}
+
+/// Upper limit for the steps of a simulation, so that a tiny step can't exhaust the memory.
+const MAX_SIMULATION_STEPS: f32 = 100_000.0;
+
+/// Runs a corridor through a scripted walk on the host. Sensors see `range` m
+/// ahead, and are read every `step` seconds. Returns the time and the brightness
+/// of every lamp after each step, until all lamps went back to idle.
+pub fn simulate(config: &CorridorConfig, walk: &Walk, range: f32, step: f32) -> Result<Vec<(f32, Vec<f32>)>, CorridorError> {
+	config.validate()?;
+	if !walk.from.is_finite() || !walk.to.is_finite() || !walk.speed.is_finite() || walk.speed <= 0.0
+		|| !range.is_finite() || range < 0.0 || !step.is_finite() || step <= 0.0 {
+		return Err(CorridorError::InvalidSimulation);
+	}
+	let end = walk.duration() + config.dim_delay + step;
+	let steps = (end / step).floor();
+	if steps > MAX_SIMULATION_STEPS {
+		return Err(CorridorError::InvalidSimulation);
+	}
+
+	let clock = ManualClock::new(0);
+	let mut corridor = Corridor::new(config.clone(), &clock);
+	let mut timeline = Vec::new();
+	for index in 0..=(steps as usize) {
+		let time = index as f32 * step;
+		clock.set((time * 1000.0) as u64);
+		for (index, lamp) in config.lamps.iter().enumerate() {
+			corridor.update(index, &walk.reading(lamp, range, time, step));
+		}
+		timeline.push((time, corridor.brightnesses()));
+	}
+	return Ok(timeline);
+}
+
+#[cfg(test)]
+mod tests {
+	use alloc::string::ToString;
+	use super::*;
+
+	fn lamp(name: &str, position: f32) -> CorridorLamp {
+		return CorridorLamp { name: name.to_string(), position, facing: Facing::Increasing, neighbours: None };
+	}
+
+	/// Five lamps, 3 m apart.
+	fn config() -> CorridorConfig {
+		let lamps = (0..5).map(|index| lamp(&index.to_string(), index as f32 * 3.0)).collect();
+		return CorridorConfig { enabled: true, this_lamp: "2".to_string(), lamps, lookahead: 6.0, dim_delay: 10.0, idle_brightness: 0.1 };
+	}
+
+	fn frame(timeline: &[(f32, Vec<f32>)], time: f32) -> &[f32] {
+		return &timeline.iter().find(|(t, _)| *t >= time).unwrap().1;
+	}
+
+	#[test]
+	fn test_propagation() {
+		let clock = ManualClock::new(0);
+		let mut corridor = Corridor::new(config(), &clock);
+		assert_eq!(corridor.brightnesses(), vec![0.1; 5]);
+
+		// Somebody walks away from the sensor of lamp 1, i.e. towards lamp 4
+		corridor.detect(1, Some(Direction::MovingAway));
+		assert_eq!(corridor.brightnesses(), vec![0.1, 1.0, 1.0, 1.0, 0.1]);
+		clock.advance(10_000);
+		assert_eq!(corridor.brightnesses(), vec![0.1; 5]);
+
+		// Towards the sensor of lamp 3, i.e. towards lamp 0
+		corridor.detect(3, Some(Direction::Approaching));
+		assert_eq!(corridor.brightnesses(), vec![0.1, 1.0, 1.0, 1.0, 0.1]);
+		clock.advance(10_000);
+
+		// In an unknown direction
+		corridor.detect(2, None);
+		assert_eq!(corridor.brightnesses(), vec![1.0; 5]);
+		clock.advance(10_000);
+
+		// A sensor facing the other way reverses the direction
+		let mut config = config();
+		config.lamps[1].facing = Facing::Decreasing;
+		corridor.set_config(config);
+		corridor.detect(1, Some(Direction::MovingAway));
+		assert_eq!(corridor.brightnesses(), vec![1.0, 1.0, 0.1, 0.1, 0.1]);
+	}
+
+	#[test]
+	fn test_neighbours() {
+		let mut config = config();
+		// A door between lamps 2 and 3
+		config.lamps[2].neighbours = Some(vec!["1".to_string()]);
+		config.lamps[3].neighbours = Some(vec!["4".to_string()]);
+		assert_eq!(config.validate(), Ok(()));
+		assert_eq!(config.neighbours(), vec![vec![1], vec![0, 2], vec![1], vec![4], vec![3]]);
+
+		let clock = ManualClock::new(0);
+		let mut corridor = Corridor::new(config, &clock);
+		corridor.detect(1, Some(Direction::MovingAway));
+		assert_eq!(corridor.brightnesses(), vec![0.1, 1.0, 1.0, 0.1, 0.1]);
+	}
+
+	#[test]
+	fn test_simulated_walk() {
+		let config = config();
+		let walk = Walk { from: 0.0, to: 12.0, speed: 1.0 };
+		let timeline = simulate(&config, &walk, 2.0, 0.5).unwrap();
+
+		// The lamps ahead are on before somebody reaches them
+		assert_eq!(frame(&timeline, 0.5), [1.0, 1.0, 1.0, 0.1, 0.1]);
+		assert_eq!(frame(&timeline, 4.0), [1.0, 1.0, 1.0, 1.0, 0.1]);
+		// The ones behind dim after the delay. Each sensor first sees somebody
+		// without knowing their direction, which also keeps the lamps behind on.
+		assert_eq!(frame(&timeline, 16.0), [0.1, 1.0, 1.0, 1.0, 1.0]);
+		assert_eq!(frame(&timeline, 19.0), [0.1, 0.1, 1.0, 1.0, 1.0]);
+		assert_eq!(timeline.last().unwrap().1, vec![0.1; 5]);
+
+		// And the same way back
+		let walk = Walk { from: 12.0, to: 0.0, speed: 1.0 };
+		let timeline = simulate(&config, &walk, 2.0, 0.5).unwrap();
+		assert_eq!(frame(&timeline, 0.5), [0.1, 0.1, 1.0, 1.0, 1.0]);
+		assert_eq!(frame(&timeline, 4.0), [1.0, 1.0, 1.0, 1.0, 1.0]);
+		assert_eq!(frame(&timeline, 14.0), [1.0, 1.0, 1.0, 1.0, 0.1]);
+		assert_eq!(timeline.last().unwrap().1, vec![0.1; 5]);
+	}
+
+	#[test]
+	fn test_validate() {
+		assert_eq!(config().validate(), Ok(()));
+		let mut config = config();
+		config.lamps[0].neighbours = Some(vec!["7".to_string()]);
+		assert_eq!(config.validate(), Err(CorridorError::UnknownLamp("7".to_string())));
+		config.lamps[0].name = "1".to_string();
+		assert_eq!(config.validate(), Err(CorridorError::DuplicateName("1".to_string())));
+
+		let config: CorridorConfig = serde_json::from_str(r#"{"lamps": [{"name": "door", "position": 0, "facing": "decreasing"}]}"#).unwrap();
+		assert_eq!(config.lookahead, default_lookahead());
+		assert_eq!(config.lamps[0].facing, Facing::Decreasing);
+		assert_eq!(config.validate(), Ok(()));
+		assert_eq!(CorridorConfig { enabled: true, ..config }.validate(), Err(CorridorError::UnknownLamp(String::new())));
+		assert_eq!(CorridorConfig::default().validate(), Ok(()));
+	}
+
+	#[test]
+	fn test_own_lamp() {
+		let clock = ManualClock::new(0);
+		let mut corridor = Corridor::new(CorridorConfig { enabled: false, ..config() }, &clock);
+		let reading = PresenceReading { presence: true, motion: MotionLevel::Active, distance: None, direction: None };
+		corridor.update_own(&reading);
+		assert_eq!(corridor.own_brightness(), 1.0);
+		assert_eq!(corridor.own_detection(), None);
+		assert_eq!(corridor.brightnesses(), vec![0.1; 5]);
+
+		corridor.set_config(config());
+		assert_eq!(corridor.own_brightness(), 0.1);
+		corridor.detect_named(&CorridorDetection { lamp: "0".to_string(), direction: Some(Direction::MovingAway) }).unwrap();
+		assert_eq!(corridor.own_brightness(), 1.0);
+		clock.advance(10_000);
+		corridor.update_own(&reading);
+		assert_eq!(corridor.brightnesses(), vec![1.0; 5]);
+		assert_eq!(corridor.own_detection(), Some(CorridorDetection { lamp: "2".to_string(), direction: None }));
+		corridor.update_own(&PresenceReading { direction: Some(Direction::Approaching), ..reading.clone() });
+		assert_eq!(corridor.own_detection(), Some(CorridorDetection { lamp: "2".to_string(), direction: Some(Direction::Approaching) }));
+		corridor.update_own(&PresenceReading::default());
+		assert_eq!(corridor.own_detection(), None);
+		assert_eq!(corridor.detect_named(&CorridorDetection { lamp: "9".to_string(), direction: None }), Err(CorridorError::UnknownLamp("9".to_string())));
+	}
+
+	#[test]
+	fn test_invalid_simulation() {
+		let walk = Walk { from: 0.0, to: 12.0, speed: 1.0 };
+		assert_eq!(simulate(&config(), &walk, 2.0, 0.0), Err(CorridorError::InvalidSimulation));
+		assert_eq!(simulate(&config(), &walk, 2.0, f32::NAN), Err(CorridorError::InvalidSimulation));
+		assert_eq!(simulate(&config(), &walk, f32::INFINITY, 0.5), Err(CorridorError::InvalidSimulation));
+		assert_eq!(simulate(&config(), &Walk { speed: 0.0, ..walk.clone() }, 2.0, 0.5), Err(CorridorError::InvalidSimulation));
+		assert_eq!(simulate(&config(), &walk, 2.0, 1e-6), Err(CorridorError::InvalidSimulation));
+	}
+}
@@ -0,0 +1,250 @@
+use core::fmt;
+use serde::{Deserialize, Serialize};
+#[cfg(not(feature = "std"))]
+use crate::float::Float;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DaylightError {
+	InvalidTarget,
+	InvalidGain,
+	InvalidBrightnessRange,
+	InvalidSmoothing,
+	InvalidHysteresis,
+}
+
+impl fmt::Display for DaylightError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			DaylightError::InvalidTarget => write!(f, "Target illuminance must not be negative."),
+			DaylightError::InvalidGain => write!(f, "Lamp gain must be positive, sensor gain must not be negative."),
+			DaylightError::InvalidBrightnessRange => write!(f, "Brightness range must not be empty or negative."),
+			DaylightError::InvalidSmoothing => write!(f, "Smoothing must not be negative."),
+			DaylightError::InvalidHysteresis => write!(f, "Hysteresis must not be negative."),
+		}
+	}
+}
+
+/// Configuration of daylight harvesting. Illuminances are in lux, durations in seconds.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DaylightConfig {
+	#[serde(default)]
+	pub enabled: bool,
+	/// Illuminance that daylight and lamp should reach together on the work surface.
+	#[serde(default = "default_target")]
+	pub target: f32,
+	/// Illuminance that the lamp adds on the work surface per unit of brightness.
+	#[serde(default = "default_lamp_gain")]
+	pub lamp_gain: f32,
+	/// Illuminance that the lamp adds at its own sensor per unit of brightness.
+	/// Without subtracting this, the lamp would dim itself down when it turns up.
+	#[serde(default = "default_sensor_gain")]
+	pub sensor_gain: f32,
+	/// Time constant of the low-pass filter on the ambient light, so that a passing
+	/// cloud doesn't make the lamp pump.
+	#[serde(default = "default_smoothing")]
+	pub smoothing: f32,
+	/// The brightness only changes when the illuminance it should add has changed
+	/// by more than this.
+	#[serde(default = "default_hysteresis")]
+	pub hysteresis: f32,
+	#[serde(default)]
+	pub min_brightness: f32,
+	#[serde(default = "default_max_brightness")]
+	pub max_brightness: f32,
+}
+
+fn default_target() -> f32 { 500.0 }
+fn default_lamp_gain() -> f32 { 20.0 }
+fn default_sensor_gain() -> f32 { 5.0 }
+fn default_smoothing() -> f32 { 60.0 }
+fn default_hysteresis() -> f32 { 25.0 }
+fn default_max_brightness() -> f32 { 30.0 }
+
+impl Default for DaylightConfig {
+	fn default() -> Self {
+		return Self {
+			enabled: false,
+			target: default_target(),
+			lamp_gain: default_lamp_gain(),
+			sensor_gain: default_sensor_gain(),
+			smoothing: default_smoothing(),
+			hysteresis: default_hysteresis(),
+			min_brightness: 0.0,
+			max_brightness: default_max_brightness(),
+		};
+	}
+}
+
+impl DaylightConfig {
+	pub fn validate(&self) -> Result<(), DaylightError> {
+		if self.target.is_nan() || self.target < 0.0 {
+			return Err(DaylightError::InvalidTarget);
+		}
+		if self.lamp_gain.is_nan() || self.lamp_gain <= 0.0 || self.sensor_gain.is_nan() || self.sensor_gain < 0.0 {
+			return Err(DaylightError::InvalidGain);
+		}
+		if self.min_brightness.is_nan() || self.min_brightness < 0.0 || self.max_brightness.is_nan() || self.max_brightness < self.min_brightness {
+			return Err(DaylightError::InvalidBrightnessRange);
+		}
+		if !self.smoothing.is_finite() || self.smoothing < 0.0 {
+			return Err(DaylightError::InvalidSmoothing);
+		}
+		if !self.hysteresis.is_finite() || self.hysteresis < 0.0 {
+			return Err(DaylightError::InvalidHysteresis);
+		}
+		return Ok(());
+	}
+}
+
+/// Closed-loop control of the brightness, so that the lamp only adds the light
+/// that is missing from the daylight.
+pub struct DaylightHarvester {
+	config: DaylightConfig,
+	/// Low-pass filtered ambient light at the sensor, without the lamp's own light
+	ambient: Option<f32>,
+	brightness: Option<f32>,
+}
+
+impl DaylightHarvester {
+	pub fn new(config: DaylightConfig) -> Self {
+		return Self { config, ambient: None, brightness: None };
+	}
+
+	pub fn config(&self) -> &DaylightConfig {
+		return &self.config;
+	}
+
+	pub fn set_config(&mut self, config: DaylightConfig) {
+		self.config = config;
+		self.brightness = None;
+	}
+
+	/// Filtered ambient light in lux, if there was any measurement yet.
+	pub fn ambient(&self) -> Option<f32> {
+		return self.ambient;
+	}
+
+	/// Feeds a sensor reading in lux, taken while the lamp was at `lamp_brightness`,
+	/// `elapsed` seconds after the previous one. Returns the brightness the lamp should
+	/// have, or `None` if harvesting is disabled.
+	pub fn update(&mut self, measured: f32, lamp_brightness: f32, elapsed: f32) -> Option<f32> {
+		let ambient = (measured - self.config.sensor_gain * lamp_brightness).max(0.0);
+		self.ambient = Some(match self.ambient {
+			Some(previous) if self.config.smoothing > 0.0 => {
+				let alpha = 1.0 - (-elapsed / self.config.smoothing).exp();
+				previous + (ambient - previous) * alpha
+			},
+			_ => ambient,
+		});
+		if !self.config.enabled {
+			self.brightness = None;
+			return None;
+		}
+
+		let missing = (self.config.target - self.ambient.unwrap_or(ambient)).max(0.0);
+		let wanted = (missing / self.config.lamp_gain).clamp(self.config.min_brightness, self.config.max_brightness);
+		// The limits are always reached, otherwise the hysteresis could keep the lamp slightly on in bright daylight
+		let at_limit = wanted == self.config.min_brightness || wanted == self.config.max_brightness;
+		let brightness = match self.brightness {
+			Some(current) if !at_limit && (wanted - current).abs() * self.config.lamp_gain <= self.config.hysteresis => current,
+			_ => wanted,
+		};
+		self.brightness = Some(brightness);
+		return Some(brightness);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn config() -> DaylightConfig {
+		return DaylightConfig {
+			enabled: true,
+			target: 500.0,
+			lamp_gain: 10.0,
+			sensor_gain: 4.0,
+			smoothing: 10.0,
+			hysteresis: 20.0,
+			min_brightness: 0.0,
+			max_brightness: 50.0,
+		};
+	}
+
+	/// Runs the closed loop for some seconds. `brightness` ends at the final brightness,
+	/// and the brightness after each second is returned.
+	fn simulate(harvester: &mut DaylightHarvester, ambient: f32, brightness: &mut f32, seconds: u32) -> Vec<f32> {
+		let mut history = Vec::new();
+		for _ in 0..seconds {
+			let measured = ambient + 4.0 * *brightness;
+			*brightness = harvester.update(measured, *brightness, 1.0).unwrap();
+			history.push(*brightness);
+		}
+		return history;
+	}
+
+	#[test]
+	fn test_adds_missing_light() {
+		let mut harvester = DaylightHarvester::new(config());
+		let mut brightness = 0.0;
+		simulate(&mut harvester, 200.0, &mut brightness, 100);
+		assert!((brightness - 30.0).abs() < 0.01, "{}", brightness);
+		assert!((harvester.ambient().unwrap() - 200.0).abs() < 0.01);
+
+		// Lots of daylight
+		simulate(&mut harvester, 2000.0, &mut brightness, 100);
+		assert_eq!(brightness, 0.0);
+
+		// Night, limited by the maximum brightness
+		simulate(&mut harvester, 0.0, &mut brightness, 100);
+		assert!((50.0 - brightness) * 10.0 <= 20.0, "{}", brightness);
+		let mut harvester = DaylightHarvester::new(config());
+		simulate(&mut harvester, 0.0, &mut brightness, 1);
+		assert_eq!(brightness, 50.0);
+	}
+
+	#[test]
+	fn test_own_light_does_not_cause_oscillation() {
+		let mut harvester = DaylightHarvester::new(config());
+		let mut brightness = 0.0;
+		let history = simulate(&mut harvester, 100.0, &mut brightness, 100);
+		// Without compensation, the lamp's own light would make it dim down again
+		for pair in history.windows(2) {
+			assert!(pair[1] >= pair[0], "{:?}", history);
+		}
+		assert!((brightness - 40.0).abs() < 0.01, "{}", brightness);
+	}
+
+	#[test]
+	fn test_smoothing_and_hysteresis() {
+		let mut harvester = DaylightHarvester::new(config());
+		let mut brightness = 0.0;
+		simulate(&mut harvester, 300.0, &mut brightness, 100);
+		let settled = brightness;
+
+		// Noise within the hysteresis does not change anything
+		for noise in [10.0, -12.0, 8.0, -15.0, 5.0] {
+			brightness = harvester.update(300.0 + noise + 4.0 * brightness, brightness, 1.0).unwrap();
+			assert_eq!(brightness, settled);
+		}
+
+		// A short cloud only changes the brightness by a single step, if at all
+		let history = simulate(&mut harvester, 100.0, &mut brightness, 3);
+		assert!(history.iter().all(|b| (b - settled).abs() < 8.0), "{:?}", history);
+		simulate(&mut harvester, 300.0, &mut brightness, 100);
+		assert!((brightness - settled).abs() * 10.0 <= 20.0);
+	}
+
+	#[test]
+	fn test_disabled_and_validation() {
+		let mut harvester = DaylightHarvester::new(DaylightConfig::default());
+		assert_eq!(harvester.update(100.0, 0.0, 1.0), None);
+		assert_eq!(harvester.ambient(), Some(100.0));
+
+		assert_eq!(DaylightConfig::default().validate(), Ok(()));
+		assert_eq!(DaylightConfig { lamp_gain: 0.0, ..config() }.validate(), Err(DaylightError::InvalidGain));
+		assert_eq!(DaylightConfig { max_brightness: -1.0, ..config() }.validate(), Err(DaylightError::InvalidBrightnessRange));
+		assert_eq!(DaylightConfig { smoothing: f32::NAN, ..config() }.validate(), Err(DaylightError::InvalidSmoothing));
+		assert_eq!(DaylightConfig { hysteresis: -1.0, ..config() }.validate(), Err(DaylightError::InvalidHysteresis));
+	}
+}
@@ -0,0 +1,753 @@
+//! DMX input over IP, for live control on stage. The firmware receives Art-Net and
+//! sACN (E1.31) packets over UDP and passes them to a `DmxReceiver`, which merges
+//! the sources and decodes the lamp's slots according to its `Personality`.
+//!
+//! Sources with the highest sACN priority win, and sources of equal priority are
+//! merged by taking the highest value of each slot. Art-Net has no priority, so
+//! its sources get a configurable one. When all sources are lost, the last look
+//! is held and then faded out, after which the lamp returns to normal mode.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use serde::{Deserialize, Serialize};
+
+use crate::clock::Clock;
+use crate::color::XyColor;
+use crate::scene::ColorTarget;
+use crate::zone::{LightState, ZoneConfig};
+
+pub const ARTNET_PORT: u16 = 6454;
+pub const SACN_PORT: u16 = 5568;
+
+/// Number of slots in a universe.
+pub const SLOTS: usize = 512;
+
+/// Highest universe that sACN allows. Art-Net only goes up to 32767.
+pub const MAX_UNIVERSE: u16 = 63999;
+
+/// Priority of sACN sources which don't ask for one.
+const DEFAULT_PRIORITY: u8 = 100;
+
+const MAX_PRIORITY: u8 = 200;
+
+/// Sources beyond this are ignored, so that a misconfigured network can't use up the heap.
+const MAX_SOURCES: usize = 4;
+
+/// Packets which are at most this much older than the last one of their source
+/// arrived out of order and are dropped.
+const SEQUENCE_WINDOW: i8 = 20;
+
+const ARTNET_ID: &[u8; 8] = b"Art-Net\0";
+const ARTNET_OP_DMX: u16 = 0x5000;
+const ARTNET_MIN_VERSION: u16 = 14;
+const ARTNET_HEADER: usize = 18;
+
+const SACN_ID: &[u8; 12] = b"ASC-E1.17\0\0\0";
+const SACN_VECTOR_ROOT_DATA: u32 = 0x0000_0004;
+const SACN_VECTOR_FRAMING_DATA: u32 = 0x0000_0002;
+const SACN_VECTOR_DMP_SET_PROPERTY: u8 = 0x02;
+const SACN_OPTION_PREVIEW: u8 = 0x80;
+const SACN_OPTION_TERMINATED: u8 = 0x40;
+const SACN_HEADER: usize = 125;
+
+/// White for colors which the lamp can't show, e.g. when all slots are 0.
+const D65: XyColor = XyColor { x: 0.3127, y: 0.3290 };
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DmxError {
+	TooShort,
+	UnknownProtocol,
+	/// A valid packet which carries no DMX levels, like an ArtPoll or sACN discovery.
+	UnsupportedPacket,
+	InvalidPacket,
+	InvalidUniverse,
+	InvalidAddress,
+	InvalidPriority,
+	InvalidTime,
+	InvalidBrightness,
+	InvalidTemperature,
+}
+
+impl fmt::Display for DmxError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			DmxError::TooShort => write!(f, "Packet is too short."),
+			DmxError::UnknownProtocol => write!(f, "Packet is neither Art-Net nor sACN."),
+			DmxError::UnsupportedPacket => write!(f, "Packet does not contain DMX levels."),
+			DmxError::InvalidPacket => write!(f, "Packet is malformed."),
+			DmxError::InvalidUniverse => write!(f, "Universe must be at most {}.", MAX_UNIVERSE),
+			DmxError::InvalidAddress => write!(f, "All slots of the personality must be within 1 to {}.", SLOTS),
+			DmxError::InvalidPriority => write!(f, "Priority must be at most {}.", MAX_PRIORITY),
+			DmxError::InvalidTime => write!(f, "Timeout must be above 0, hold and fade-out must not be negative."),
+			DmxError::InvalidBrightness => write!(f, "Full brightness must be above 0."),
+			DmxError::InvalidTemperature => write!(f, "Color temperatures must be between 1000 and 25000 K, the minimum below the maximum."),
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Protocol {
+	ArtNet,
+	Sacn,
+}
+
+/// Levels of a universe from an Art-Net or sACN packet.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DmxPacket<'a> {
+	pub protocol: Protocol,
+	pub universe: u16,
+	/// Only sACN has a priority.
+	pub priority: Option<u8>,
+	/// 0 if the source does not number its packets.
+	pub sequence: u8,
+	/// Component identifier of an sACN source.
+	pub cid: Option<[u8; 16]>,
+	/// The source stops sending, and its levels should be dropped at once.
+	pub terminated: bool,
+	/// Levels for visualizers, not for lamps.
+	pub preview: bool,
+	/// Levels of the slots, starting with slot 1.
+	pub slots: &'a [u8],
+}
+
+/// Parses an ArtDmx or sACN data packet.
+pub fn parse(bytes: &[u8]) -> Result<DmxPacket<'_>, DmxError> {
+	if bytes.starts_with(ARTNET_ID) {
+		return parse_artnet(bytes);
+	}
+	if bytes.len() >= 16 && &bytes[4..16] == SACN_ID {
+		return parse_sacn(bytes);
+	}
+	return Err(DmxError::UnknownProtocol);
+}
+
+fn u16_be(bytes: &[u8], at: usize) -> u16 {
+	return u16::from_be_bytes([bytes[at], bytes[at + 1]]);
+}
+
+fn u32_be(bytes: &[u8], at: usize) -> u32 {
+	return u32::from_be_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]]);
+}
+
+fn parse_artnet(bytes: &[u8]) -> Result<DmxPacket<'_>, DmxError> {
+	if bytes.len() < 10 {
+		return Err(DmxError::TooShort);
+	}
+	if u16::from_le_bytes([bytes[8], bytes[9]]) != ARTNET_OP_DMX {
+		return Err(DmxError::UnsupportedPacket);
+	}
+	if bytes.len() < ARTNET_HEADER {
+		return Err(DmxError::TooShort);
+	}
+	if u16_be(bytes, 10) < ARTNET_MIN_VERSION {
+		return Err(DmxError::InvalidPacket);
+	}
+	let length = u16_be(bytes, 16) as usize;
+	if length > SLOTS || bytes.len() < ARTNET_HEADER + length {
+		return Err(DmxError::InvalidPacket);
+	}
+	return Ok(DmxPacket {
+		protocol: Protocol::ArtNet,
+		// 7 bits of net, 4 bits of sub-net and 4 bits of universe
+		universe: u16::from_le_bytes([bytes[14], bytes[15] & 0x7f]),
+		priority: None,
+		sequence: bytes[12],
+		cid: None,
+		terminated: false,
+		preview: false,
+		slots: &bytes[ARTNET_HEADER..ARTNET_HEADER + length],
+	});
+}
+
+fn parse_sacn(bytes: &[u8]) -> Result<DmxPacket<'_>, DmxError> {
+	if bytes.len() < 22 {
+		return Err(DmxError::TooShort);
+	}
+	if u32_be(bytes, 18) != SACN_VECTOR_ROOT_DATA {
+		return Err(DmxError::UnsupportedPacket);
+	}
+	if bytes.len() < SACN_HEADER + 1 {
+		return Err(DmxError::TooShort);
+	}
+	if u32_be(bytes, 40) != SACN_VECTOR_FRAMING_DATA || bytes[117] != SACN_VECTOR_DMP_SET_PROPERTY {
+		return Err(DmxError::InvalidPacket);
+	}
+	// The first value is the start code, and only 0 carries levels
+	if bytes[SACN_HEADER] != 0 {
+		return Err(DmxError::UnsupportedPacket);
+	}
+	let count = u16_be(bytes, 123) as usize;
+	if count == 0 || count > SLOTS + 1 || bytes.len() < SACN_HEADER + count {
+		return Err(DmxError::InvalidPacket);
+	}
+	let mut cid = [0; 16];
+	cid.copy_from_slice(&bytes[22..38]);
+	let options = bytes[112];
+	return Ok(DmxPacket {
+		protocol: Protocol::Sacn,
+		universe: u16_be(bytes, 113),
+		priority: Some(bytes[108]),
+		sequence: bytes[111],
+		cid: Some(cid),
+		terminated: options & SACN_OPTION_TERMINATED != 0,
+		preview: options & SACN_OPTION_PREVIEW != 0,
+		slots: &bytes[SACN_HEADER + 1..SACN_HEADER + count],
+	});
+}
+
+/// How the lamp's slots, starting at its address, are used.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Personality {
+	/// One slot per output channel, like raw mode.
+	Raw,
+	/// Per zone: dimmer and color temperature.
+	#[default]
+	CctDimmer,
+	/// Per zone: dimmer, red, green and blue.
+	RgbDimmer,
+	/// Per zone: dimmer, x and y, each with a coarse and a fine slot.
+	XyDimmer16,
+}
+
+impl Personality {
+	/// Number of slots which the lamp uses.
+	pub fn footprint(&self, zones: &ZoneConfig) -> usize {
+		return match self {
+			Personality::Raw => channel_count(zones),
+			Personality::CctDimmer => 2 * zones.zones.len(),
+			Personality::RgbDimmer => 4 * zones.zones.len(),
+			Personality::XyDimmer16 => 6 * zones.zones.len(),
+		};
+	}
+}
+
+fn channel_count(zones: &ZoneConfig) -> usize {
+	return zones.zones.iter().flat_map(|zone| &zone.leds).map(|led| led.channel + 1).max().unwrap_or(0);
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DmxConfig {
+	#[serde(default)]
+	pub enabled: bool,
+	/// Universe of both Art-Net and sACN packets.
+	#[serde(default = "default_universe")]
+	pub universe: u16,
+	/// First slot of the lamp, from 1.
+	#[serde(default = "default_address")]
+	pub address: u16,
+	#[serde(default)]
+	pub personality: Personality,
+	/// Priority of Art-Net sources, compared to sACN sources.
+	#[serde(default = "default_artnet_priority")]
+	pub artnet_priority: u8,
+	/// Time without packets after which a source counts as lost, in s.
+	#[serde(default = "default_timeout")]
+	pub timeout: f32,
+	/// Time for which the last look is held when all sources are lost, in s, or
+	/// `None` to hold it until a source returns.
+	#[serde(default = "default_hold")]
+	pub hold: Option<f32>,
+	/// Time in which the held look fades out, in s.
+	#[serde(default = "default_fade_out")]
+	pub fade_out: f32,
+	/// Brightness of the lamp at full dimmer.
+	#[serde(default = "default_full_brightness")]
+	pub full_brightness: f32,
+	/// Color temperatures at 0 and 255 of the color temperature slot, in K.
+	#[serde(default = "default_min_temperature")]
+	pub min_temperature: f32,
+	#[serde(default = "default_max_temperature")]
+	pub max_temperature: f32,
+}
+
+fn default_universe() -> u16 {
+	return 1;
+}
+
+fn default_address() -> u16 {
+	return 1;
+}
+
+fn default_artnet_priority() -> u8 {
+	return DEFAULT_PRIORITY;
+}
+
+fn default_timeout() -> f32 {
+	// The network data loss timeout of E1.31
+	return 2.5;
+}
+
+fn default_hold() -> Option<f32> {
+	return Some(5.0);
+}
+
+fn default_fade_out() -> f32 {
+	return 2.0;
+}
+
+fn default_full_brightness() -> f32 {
+	return 20.0;
+}
+
+fn default_min_temperature() -> f32 {
+	return 2000.0;
+}
+
+fn default_max_temperature() -> f32 {
+	return 6500.0;
+}
+
+impl Default for DmxConfig {
+	fn default() -> Self {
+		return Self {
+			enabled: false,
+			universe: default_universe(),
+			address: default_address(),
+			personality: Personality::default(),
+			artnet_priority: default_artnet_priority(),
+			timeout: default_timeout(),
+			hold: default_hold(),
+			fade_out: default_fade_out(),
+			full_brightness: default_full_brightness(),
+			min_temperature: default_min_temperature(),
+			max_temperature: default_max_temperature(),
+		};
+	}
+}
+
+impl DmxConfig {
+	/// Checks the config, and whether the personality fits into the universe with the given zones.
+	pub fn validate(&self, zones: &ZoneConfig) -> Result<(), DmxError> {
+		if self.universe > MAX_UNIVERSE {
+			return Err(DmxError::InvalidUniverse);
+		}
+		if self.address == 0 || self.address as usize - 1 + self.personality.footprint(zones) > SLOTS {
+			return Err(DmxError::InvalidAddress);
+		}
+		if self.artnet_priority > MAX_PRIORITY {
+			return Err(DmxError::InvalidPriority);
+		}
+		let time = |t: f32| t.is_finite() && t >= 0.0;
+		if !time(self.timeout) || self.timeout == 0.0 || self.hold.is_some_and(|hold| !time(hold)) || !time(self.fade_out) {
+			return Err(DmxError::InvalidTime);
+		}
+		if !self.full_brightness.is_finite() || self.full_brightness <= 0.0 {
+			return Err(DmxError::InvalidBrightness);
+		}
+		if !ColorTarget::Temperature(self.min_temperature).is_valid() || !ColorTarget::Temperature(self.max_temperature).is_valid() || self.min_temperature >= self.max_temperature {
+			return Err(DmxError::InvalidTemperature);
+		}
+		return Ok(());
+	}
+
+	/// Decodes the lamp's slots of a universe. Colors which the lamp can't show are white.
+	pub fn decode(&self, slots: &[u8], zones: &ZoneConfig) -> DmxOutput {
+		// Configs are validated, but decoding must not panic on one which isn't
+		let start = (self.address as usize).saturating_sub(1);
+		let slot = |index: usize| slots.get(start + index).copied().unwrap_or(0) as f32 / 255.0;
+		let fine = |index: usize| (slot(index) * 255.0 * 256.0 + slot(index + 1) * 255.0) / 65535.0;
+		if self.personality == Personality::Raw {
+			return DmxOutput::Raw((0..channel_count(zones)).map(slot).collect());
+		}
+		let footprint = self.personality.footprint(zones) / zones.zones.len().max(1);
+		let states = (0..zones.zones.len()).map(|zone| {
+			let base = zone * footprint;
+			let mut state = LightState { dim_speed: 1.0, ..LightState::default() };
+			match self.personality {
+				Personality::Raw => {},
+				Personality::CctDimmer => {
+					state.brightness = slot(base) * self.full_brightness;
+					state.set_temperature(self.min_temperature + slot(base + 1) * (self.max_temperature - self.min_temperature));
+				},
+				Personality::RgbDimmer => {
+					let (red, green, blue) = (slot(base + 1), slot(base + 2), slot(base + 3));
+					state.brightness = slot(base) * red.max(green).max(blue) * self.full_brightness;
+					state.xy = Some(rgb_to_xy(red, green, blue));
+				},
+				Personality::XyDimmer16 => {
+					state.brightness = fine(base) * self.full_brightness;
+					let xy = XyColor::new(fine(base + 2), fine(base + 4));
+					state.xy = Some(if ColorTarget::Xy(xy.clone()).is_valid() { xy } else { D65 });
+				},
+			}
+			return state;
+		}).collect();
+		return DmxOutput::Zones(states);
+	}
+}
+
+/// Chromaticity of linear sRGB.
+fn rgb_to_xy(red: f32, green: f32, blue: f32) -> XyColor {
+	let x = 0.4124 * red + 0.3576 * green + 0.1805 * blue;
+	let y = 0.2126 * red + 0.7152 * green + 0.0722 * blue;
+	let z = 0.0193 * red + 0.1192 * green + 0.9505 * blue;
+	let sum = x + y + z;
+	if sum <= 0.0 {
+		return D65;
+	}
+	return XyColor::new(x / sum, y / sum);
+}
+
+/// What the lamp shows while it is controlled by DMX.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DmxOutput {
+	/// Duty of each output channel.
+	Raw(Vec<f32>),
+	/// State of each zone, which the LEDs take on immediately.
+	Zones(Vec<LightState>),
+}
+
+impl DmxOutput {
+	fn scale(&mut self, factor: f32) {
+		match self {
+			DmxOutput::Raw(duties) => duties.iter_mut().for_each(|duty| *duty *= factor),
+			DmxOutput::Zones(states) => states.iter_mut().for_each(|state| state.brightness *= factor),
+		}
+	}
+}
+
+struct Source {
+	protocol: Protocol,
+	/// The CID of sACN sources, or the IPv4 address of Art-Net sources.
+	id: [u8; 16],
+	priority: u8,
+	sequence: u8,
+	slots: Vec<u8>,
+	/// Time at which the source counts as lost, in ms.
+	until: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DmxStatus {
+	/// Whether DMX controls the lamp, including while the last look is held or faded out.
+	pub active: bool,
+	pub sources: usize,
+	/// Time since all sources were lost, in s.
+	pub lost: Option<f32>,
+}
+
+/// Keeps the sources of the configured universe and merges their levels.
+pub struct DmxReceiver<C> {
+	config: DmxConfig,
+	clock: C,
+	sources: Vec<Source>,
+	/// The merged levels when the last source was lost, and when that happened in ms.
+	held: Option<(Vec<u8>, u64)>,
+}
+
+impl<C: Clock> DmxReceiver<C> {
+	pub fn new(config: DmxConfig, clock: C) -> Self {
+		return Self { config, clock, sources: Vec::new(), held: None };
+	}
+
+	pub fn config(&self) -> &DmxConfig {
+		return &self.config;
+	}
+
+	/// Replaces the config, and drops all sources.
+	pub fn set_config(&mut self, config: DmxConfig) {
+		self.config = config;
+		self.sources.clear();
+		self.held = None;
+	}
+
+	/// Takes the levels of a packet which was sent from `sender`. Returns whether
+	/// the packet was used.
+	pub fn receive(&mut self, packet: &DmxPacket, sender: [u8; 4]) -> bool {
+		if !self.config.enabled || packet.universe != self.config.universe || packet.preview {
+			return false;
+		}
+		let now = self.clock.now_ms();
+		let id = match packet.cid {
+			Some(cid) => cid,
+			None => {
+				let mut id = [0; 16];
+				id[..4].copy_from_slice(&sender);
+				id
+			},
+		};
+		let index = self.sources.iter().position(|source| source.protocol == packet.protocol && source.id == id);
+		if packet.terminated {
+			if let Some(index) = index {
+				// Treated as if the source was lost just now
+				self.sources[index].until = now;
+				self.expire(now);
+			}
+			return false;
+		}
+		let index = match index {
+			Some(index) => {
+				let difference = packet.sequence.wrapping_sub(self.sources[index].sequence) as i8;
+				// Art-Net sources which don't number their packets send 0. In sACN, 0
+				// is just the number after 255.
+				let numbered = packet.protocol != Protocol::ArtNet || packet.sequence != 0;
+				if numbered && difference <= 0 && difference > -SEQUENCE_WINDOW {
+					return false;
+				}
+				index
+			},
+			None if self.sources.len() < MAX_SOURCES => {
+				self.sources.push(Source { protocol: packet.protocol, id, priority: 0, sequence: 0, slots: vec![0; SLOTS], until: 0 });
+				self.sources.len() - 1
+			},
+			None => return false,
+		};
+		let source = &mut self.sources[index];
+		source.priority = packet.priority.unwrap_or(self.config.artnet_priority);
+		source.sequence = packet.sequence;
+		source.slots.fill(0);
+		source.slots[..packet.slots.len()].copy_from_slice(packet.slots);
+		source.until = now + (self.config.timeout * 1000.0) as u64;
+		self.held = None;
+		return true;
+	}
+
+	/// Drops lost sources, and keeps the look of the last ones.
+	fn expire(&mut self, now: u64) {
+		if self.sources.is_empty() || self.sources.iter().any(|source| source.until > now) {
+			self.sources.retain(|source| source.until > now);
+			return;
+		}
+		let lost = self.sources.iter().map(|source| source.until).max().unwrap_or(now);
+		self.held = Some((merge(&self.sources), lost));
+		self.sources.clear();
+	}
+
+	/// What the lamp shows, or `None` if DMX does not control it.
+	pub fn output(&mut self, zones: &ZoneConfig) -> Option<DmxOutput> {
+		let now = self.clock.now_ms();
+		self.expire(now);
+		if !self.sources.is_empty() {
+			return Some(self.config.decode(&merge(&self.sources), zones));
+		}
+		let factor = self.fade(now)?;
+		let (slots, _) = self.held.as_ref()?;
+		let mut output = self.config.decode(slots, zones);
+		output.scale(factor);
+		return Some(output);
+	}
+
+	/// Factor for the held look, or `None` once it has faded out.
+	fn fade(&mut self, now: u64) -> Option<f32> {
+		let (_, lost) = self.held.as_ref()?;
+		let Some(hold) = self.config.hold else {
+			return Some(1.0);
+		};
+		let elapsed = now.saturating_sub(*lost) as f32 / 1000.0 - hold;
+		if elapsed < 0.0 {
+			return Some(1.0);
+		}
+		if elapsed >= self.config.fade_out {
+			self.held = None;
+			return None;
+		}
+		return Some(1.0 - elapsed / self.config.fade_out);
+	}
+
+	pub fn status(&mut self) -> DmxStatus {
+		let now = self.clock.now_ms();
+		self.expire(now);
+		let active = !self.sources.is_empty() || self.fade(now).is_some();
+		let lost = self.held.as_ref().map(|(_, lost)| now.saturating_sub(*lost) as f32 / 1000.0);
+		return DmxStatus { active, sources: self.sources.len(), lost };
+	}
+}
+
+/// Highest level of each slot among the sources with the highest priority.
+fn merge(sources: &[Source]) -> Vec<u8> {
+	let priority = sources.iter().map(|source| source.priority).max().unwrap_or(0);
+	let mut slots = vec![0; SLOTS];
+	for source in sources.iter().filter(|source| source.priority == priority) {
+		for (slot, level) in slots.iter_mut().zip(&source.slots) {
+			*slot = (*slot).max(*level);
+		}
+	}
+	return slots;
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::clock::ManualClock;
+	use crate::zone::test_zones;
+	use super::*;
+
+	const SENDER: [u8; 4] = [192, 168, 1, 10];
+
+	fn artnet(universe: u16, sequence: u8, slots: &[u8]) -> Vec<u8> {
+		let mut bytes = Vec::from(&ARTNET_ID[..]);
+		bytes.extend_from_slice(&ARTNET_OP_DMX.to_le_bytes());
+		bytes.extend_from_slice(&ARTNET_MIN_VERSION.to_be_bytes());
+		bytes.extend_from_slice(&[sequence, 0]);
+		bytes.extend_from_slice(&universe.to_le_bytes());
+		bytes.extend_from_slice(&(slots.len() as u16).to_be_bytes());
+		bytes.extend_from_slice(slots);
+		return bytes;
+	}
+
+	fn sacn(cid: u8, universe: u16, priority: u8, sequence: u8, options: u8, slots: &[u8]) -> Vec<u8> {
+		let mut bytes = vec![0; SACN_HEADER + 1];
+		bytes[0..2].copy_from_slice(&0x0010_u16.to_be_bytes());
+		bytes[4..16].copy_from_slice(SACN_ID);
+		bytes[18..22].copy_from_slice(&SACN_VECTOR_ROOT_DATA.to_be_bytes());
+		bytes[22..38].copy_from_slice(&[cid; 16]);
+		bytes[40..44].copy_from_slice(&SACN_VECTOR_FRAMING_DATA.to_be_bytes());
+		bytes[44..51].copy_from_slice(b"console");
+		bytes[108] = priority;
+		bytes[111] = sequence;
+		bytes[112] = options;
+		bytes[113..115].copy_from_slice(&universe.to_be_bytes());
+		bytes[117] = SACN_VECTOR_DMP_SET_PROPERTY;
+		bytes[118] = 0xa1;
+		bytes[121..123].copy_from_slice(&1_u16.to_be_bytes());
+		bytes[123..125].copy_from_slice(&(slots.len() as u16 + 1).to_be_bytes());
+		bytes.extend_from_slice(slots);
+		return bytes;
+	}
+
+	fn config(personality: Personality) -> DmxConfig {
+		return DmxConfig { enabled: true, address: 3, personality, ..DmxConfig::default() };
+	}
+
+	fn brightness(output: Option<DmxOutput>) -> Vec<f32> {
+		return match output {
+			Some(DmxOutput::Zones(states)) => states.iter().map(|state| state.brightness).collect(),
+			Some(DmxOutput::Raw(duties)) => duties,
+			None => Vec::new(),
+		};
+	}
+
+	#[test]
+	fn test_parse() {
+		let bytes = artnet(0x0123, 7, &[1, 2, 3, 4]);
+		let packet = parse(&bytes).unwrap();
+		assert_eq!(packet.protocol, Protocol::ArtNet);
+		assert_eq!(packet.universe, 0x0123);
+		assert_eq!(packet.sequence, 7);
+		assert_eq!(packet.slots, &[1, 2, 3, 4]);
+
+		let bytes = sacn(9, 1, 150, 3, SACN_OPTION_TERMINATED, &[10, 20]);
+		let packet = parse(&bytes).unwrap();
+		assert_eq!(packet.protocol, Protocol::Sacn);
+		assert_eq!((packet.universe, packet.priority, packet.sequence), (1, Some(150), 3));
+		assert_eq!(packet.cid, Some([9; 16]));
+		assert!(packet.terminated && !packet.preview);
+		assert_eq!(packet.slots, &[10, 20]);
+
+		let mut poll = artnet(0, 0, &[]);
+		poll[8..10].copy_from_slice(&0x2000_u16.to_le_bytes());
+		assert_eq!(parse(&poll), Err(DmxError::UnsupportedPacket));
+		let mut priorities = sacn(9, 1, 100, 0, 0, &[1]);
+		priorities[SACN_HEADER] = 0xdd;
+		assert_eq!(parse(&priorities), Err(DmxError::UnsupportedPacket));
+		assert_eq!(parse(&artnet(1, 0, &[1, 2])[..19]), Err(DmxError::InvalidPacket));
+		assert_eq!(parse(&sacn(9, 1, 100, 0, 0, &[])[..100]), Err(DmxError::TooShort));
+		assert_eq!(parse(b"GET / HTTP/1.1"), Err(DmxError::UnknownProtocol));
+	}
+
+	fn zone_states(output: DmxOutput) -> Vec<LightState> {
+		return match output {
+			DmxOutput::Zones(states) => states,
+			DmxOutput::Raw(_) => panic!("Expected zones"),
+		};
+	}
+
+	#[test]
+	fn test_personalities() {
+		let mut zones = test_zones();
+		let mut slots = vec![0; SLOTS];
+		slots[2..6].copy_from_slice(&[255, 0, 51, 255]);
+		let states = zone_states(config(Personality::CctDimmer).decode(&slots, &zones));
+		assert_eq!((states[0].brightness, states[0].temperature, states[0].xy.clone()), (20.0, 2000.0, None));
+		assert_eq!((states[1].brightness, states[1].temperature), (51.0 / 255.0 * 20.0, 6500.0));
+		assert_eq!(states[0].dim_speed, 1.0);
+
+		slots[2..10].copy_from_slice(&[255, 255, 0, 0, 0, 0, 0, 0]);
+		let states = zone_states(config(Personality::RgbDimmer).decode(&slots, &zones));
+		assert_eq!(states[0].brightness, 20.0);
+		let red = states[0].xy.clone().unwrap();
+		assert!((red.x - 0.64).abs() < 0.001 && (red.y - 0.33).abs() < 0.001);
+		// No color at all is white
+		assert_eq!(states[1].xy, Some(D65));
+
+		// Dimmer at half, x = 0.5, y = 0.4, and an invalid color
+		slots[2..14].copy_from_slice(&[0x80, 0x00, 0x80, 0x00, 0x66, 0x66, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+		let states = zone_states(config(Personality::XyDimmer16).decode(&slots, &zones));
+		assert!((states[0].brightness - 10.0).abs() < 0.001);
+		let xy = states[0].xy.clone().unwrap();
+		assert!((xy.x - 0.5).abs() < 0.001 && (xy.y - 0.4).abs() < 0.001);
+		assert_eq!(states[1].xy, Some(D65));
+
+		let half = 128.0 / 255.0;
+		assert_eq!(config(Personality::Raw).decode(&slots, &zones), DmxOutput::Raw(vec![half, 0.0, half, 0.0, 0.4, 0.4, 1.0, 1.0, 1.0]));
+
+		let unset = DmxConfig { address: 0, ..config(Personality::Raw) };
+		assert_eq!(unset.validate(&zones), Err(DmxError::InvalidAddress));
+		assert_eq!(unset.decode(&slots, &zones), DmxConfig { address: 1, ..config(Personality::Raw) }.decode(&slots, &zones));
+
+		assert_eq!(config(Personality::XyDimmer16).validate(&zones), Ok(()));
+		assert_eq!(DmxConfig { address: 503, ..config(Personality::XyDimmer16) }.validate(&zones), Err(DmxError::InvalidAddress));
+		zones.zones.truncate(1);
+		assert_eq!(DmxConfig { address: 507, ..config(Personality::XyDimmer16) }.validate(&zones), Ok(()));
+	}
+
+	#[test]
+	fn test_priority_and_merge() {
+		let clock = ManualClock::new(0);
+		let zones = test_zones();
+		let mut receiver = DmxReceiver::new(config(Personality::CctDimmer), &clock);
+		assert_eq!(receiver.output(&zones), None);
+
+		assert!(receiver.receive(&parse(&sacn(1, 1, 100, 1, 0, &[0, 0, 51, 0, 102])).unwrap(), SENDER));
+		assert!(receiver.receive(&parse(&artnet(1, 1, &[0, 0, 102, 0, 51])).unwrap(), SENDER));
+		assert_eq!(brightness(receiver.output(&zones)), vec![8.0, 8.0]);
+
+		// A higher priority takes over, until it stops
+		assert!(receiver.receive(&parse(&sacn(2, 1, 150, 1, 0, &[0, 0, 255])).unwrap(), SENDER));
+		assert_eq!(brightness(receiver.output(&zones)), vec![20.0, 0.0]);
+		assert!(!receiver.receive(&parse(&sacn(2, 1, 150, 2, SACN_OPTION_TERMINATED, &[])).unwrap(), SENDER));
+		assert_eq!(brightness(receiver.output(&zones)), vec![8.0, 8.0]);
+
+		// Out of order, preview, and other universes are ignored
+		assert!(!receiver.receive(&parse(&sacn(1, 1, 100, 0xff, 0, &[0, 0, 255])).unwrap(), SENDER));
+		assert!(!receiver.receive(&parse(&sacn(3, 1, 100, 1, SACN_OPTION_PREVIEW, &[0, 0, 255])).unwrap(), SENDER));
+		assert!(!receiver.receive(&parse(&artnet(2, 1, &[0, 0, 255])).unwrap(), SENDER));
+		assert!(receiver.receive(&parse(&sacn(1, 1, 100, 2, 0, &[0, 0, 51])).unwrap(), SENDER));
+		assert_eq!(brightness(receiver.output(&zones)), vec![8.0, 4.0]);
+		assert_eq!(receiver.status().sources, 2);
+
+		// Only Art-Net packets without a sequence number are taken in any order
+		assert!(!receiver.receive(&parse(&sacn(1, 1, 100, 0, 0, &[0, 0, 255])).unwrap(), SENDER));
+		assert!(receiver.receive(&parse(&artnet(1, 0, &[0, 0, 102, 0, 51])).unwrap(), SENDER));
+		assert!(receiver.receive(&parse(&artnet(1, 0, &[0, 0, 102, 0, 51])).unwrap(), SENDER));
+	}
+
+	#[test]
+	fn test_source_loss() {
+		let clock = ManualClock::new(0);
+		let zones = test_zones();
+		let mut receiver = DmxReceiver::new(DmxConfig { hold: Some(1.0), fade_out: 2.0, ..config(Personality::CctDimmer) }, &clock);
+		receiver.receive(&parse(&artnet(1, 0, &[0, 0, 255, 0, 255])).unwrap(), SENDER);
+		clock.advance(2_000);
+		assert_eq!(brightness(receiver.output(&zones)), vec![20.0, 20.0]);
+
+		// Lost after 2.5 s, held for 1 s, then faded out in 2 s
+		clock.advance(1_000);
+		assert_eq!(brightness(receiver.output(&zones)), vec![20.0, 20.0]);
+		assert_eq!(receiver.status().lost, Some(0.5));
+		clock.advance(1_500);
+		assert_eq!(brightness(receiver.output(&zones)), vec![10.0, 10.0]);
+		clock.advance(1_000);
+		assert_eq!(receiver.output(&zones), None);
+		assert!(!receiver.status().active);
+
+		// Holding without a time limit
+		receiver.set_config(DmxConfig { hold: None, ..config(Personality::CctDimmer) });
+		receiver.receive(&parse(&artnet(1, 0, &[0, 0, 255])).unwrap(), SENDER);
+		clock.advance(60_000);
+		assert_eq!(brightness(receiver.output(&zones)), vec![20.0, 0.0]);
+		assert_eq!(receiver.status(), DmxStatus { active: true, sources: 0, lost: Some(57.5) });
+	}
+}
@@ -0,0 +1,401 @@
+use core::f32::consts::PI;
+use core::fmt;
+use serde::{Deserialize, Serialize};
+
+use crate::color::XyColor;
+#[cfg(not(feature = "std"))]
+use crate::float::Float;
+
+/// Upper limit for flashes per second. Following WCAG 2.3.1, light that flashes
+/// more than three times per second may trigger photosensitive seizures.
+pub const MAX_FLASH_FREQUENCY: f32 = 3.0;
+
+/// Shortest period of effects that smoothly modulate the light, so that they
+/// can't be abused as a fast flicker.
+pub const MIN_PERIOD: f32 = 1.0;
+
+/// Shortest time between two lightning strikes. Together with the spacing of the
+/// flashes within a strike, this keeps lightning below `MAX_FLASH_FREQUENCY`.
+pub const MIN_LIGHTNING_INTERVAL: f32 = 2.0;
+
+/// Duration of a single lightning flash in seconds.
+const FLASH_DURATION: f32 = 0.1;
+
+/// Maximum number of flashes in one lightning strike.
+const MAX_FLASHES_PER_STRIKE: u32 = 3;
+
+/// White point around which the color cycle turns (D65).
+const WHITE: XyColor = XyColor { x: 0.3127, y: 0.3290 };
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EffectError {
+	InvalidPeriod,
+	InvalidAmount,
+	InvalidFrequency,
+	InvalidInterval,
+}
+
+impl fmt::Display for EffectError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			EffectError::InvalidPeriod => write!(f, "Effect period must be at least {} s.", MIN_PERIOD),
+			EffectError::InvalidAmount => write!(f, "Effect intensity, depth, duty cycle and saturation must be between 0 and 1."),
+			EffectError::InvalidFrequency => write!(f, "Strobe frequency must be positive and at most {} Hz.", MAX_FLASH_FREQUENCY),
+			EffectError::InvalidInterval => write!(f, "Lightning interval must be at least {} s.", MIN_LIGHTNING_INTERVAL),
+		}
+	}
+}
+
+/// What an effect does to the light at a given moment. Effects are composed with
+/// the current state of the lamp: they scale its brightness and may replace its color.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EffectOutput {
+	/// Factor for the base brightness, from 0.0 to 1.0. Effects never make the
+	/// lamp brighter than the user has set it.
+	pub brightness: f32,
+	/// Color to show instead of the base color, if any.
+	pub color: Option<XyColor>,
+}
+
+impl EffectOutput {
+	fn dimmed(factor: f32) -> Self {
+		return Self { brightness: factor.clamp(0.0, 1.0), color: None };
+	}
+}
+
+/// A light show that is computed from the time since it was started. Rendering
+/// must only depend on the elapsed time and the parameters of the effect
+/// (including its random seed), so that it can be rendered at any rate.
+pub trait Effect {
+	/// Output at `elapsed` seconds after the effect has started.
+	fn render(&self, elapsed: f32) -> EffectOutput;
+}
+
+/// Deterministic hash of a seed and a number, mapped to 0.0..=1.0.
+fn random(seed: u32, n: u32) -> f32 {
+	let mut x = seed ^ n.wrapping_mul(0x9E37_79B9);
+	x ^= x >> 16;
+	x = x.wrapping_mul(0x7FEB_352D);
+	x ^= x >> 15;
+	x = x.wrapping_mul(0x846C_A68B);
+	x ^= x >> 16;
+	return x as f32 / u32::MAX as f32;
+}
+
+/// Smooth random value between 0.0 and 1.0 that changes about once per unit of `t`.
+fn noise(seed: u32, t: f32) -> f32 {
+	let index = t.max(0.0).floor();
+	let fraction = t.max(0.0) - index;
+	let a = random(seed, index as u32);
+	let b = random(seed, index as u32 + 1);
+	let smooth = fraction * fraction * (3.0 - 2.0 * fraction);
+	return a + (b - a) * smooth;
+}
+
+/// Irregular flickering like the flame of a candle. Keeps the base color,
+/// so it looks best with a warm scene.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Candle {
+	/// How far the flame may dim the light, from 0.0 to 1.0.
+	#[serde(default = "default_candle_intensity")]
+	pub intensity: f32,
+	#[serde(default)]
+	pub seed: u32,
+}
+
+fn default_candle_intensity() -> f32 { 0.3 }
+
+impl Default for Candle {
+	fn default() -> Self {
+		return Self { intensity: default_candle_intensity(), seed: 0 };
+	}
+}
+
+impl Effect for Candle {
+	fn render(&self, elapsed: f32) -> EffectOutput {
+		// A slow wobble with some faster flicker on top
+		let flicker = 0.6 * noise(self.seed, elapsed * 3.0) + 0.4 * noise(self.seed.wrapping_add(1), elapsed * 11.0);
+		return EffectOutput::dimmed(1.0 - self.intensity.clamp(0.0, 1.0) * flicker);
+	}
+}
+
+/// Slowly fades the light down and up again.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Breathing {
+	/// Duration of one breath in seconds.
+	#[serde(default = "default_breathing_period")]
+	pub period: f32,
+	/// How far the light is dimmed at the bottom of a breath, from 0.0 to 1.0.
+	#[serde(default = "default_breathing_depth")]
+	pub depth: f32,
+}
+
+fn default_breathing_period() -> f32 { 4.0 }
+fn default_breathing_depth() -> f32 { 0.7 }
+
+impl Default for Breathing {
+	fn default() -> Self {
+		return Self { period: default_breathing_period(), depth: default_breathing_depth() };
+	}
+}
+
+impl Effect for Breathing {
+	fn render(&self, elapsed: f32) -> EffectOutput {
+		let phase = elapsed / self.period.max(MIN_PERIOD);
+		let dip = (1.0 - (2.0 * PI * phase).cos()) / 2.0;
+		return EffectOutput::dimmed(1.0 - self.depth.clamp(0.0, 1.0) * dip);
+	}
+}
+
+/// Cycles through all hues around the white point.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ColorCycle {
+	/// Duration of one cycle in seconds.
+	#[serde(default = "default_color_cycle_period")]
+	pub period: f32,
+	/// Distance from the white point on the xy diagram. At 1.0, some hues are
+	/// outside of the colors that the lamp can show.
+	#[serde(default = "default_color_cycle_saturation")]
+	pub saturation: f32,
+}
+
+fn default_color_cycle_period() -> f32 { 60.0 }
+fn default_color_cycle_saturation() -> f32 { 0.5 }
+
+/// Distance from the white point at a saturation of 1.0.
+const MAX_COLOR_CYCLE_RADIUS: f32 = 0.3;
+
+impl Default for ColorCycle {
+	fn default() -> Self {
+		return Self { period: default_color_cycle_period(), saturation: default_color_cycle_saturation() };
+	}
+}
+
+impl Effect for ColorCycle {
+	fn render(&self, elapsed: f32) -> EffectOutput {
+		let angle = 2.0 * PI * elapsed / self.period.max(MIN_PERIOD);
+		let radius = MAX_COLOR_CYCLE_RADIUS * self.saturation.clamp(0.0, 1.0);
+		return EffectOutput {
+			brightness: 1.0,
+			color: Some(XyColor::new(WHITE.x + radius * angle.cos(), WHITE.y + radius * angle.sin())),
+		};
+	}
+}
+
+/// A thunderstorm: the light is dimmed to a faint glow, with a strike of up to
+/// three flashes at a random moment in each interval.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Lightning {
+	/// Average time between two strikes in seconds.
+	#[serde(default = "default_lightning_interval")]
+	pub interval: f32,
+	/// Brightness between the flashes, from 0.0 to 1.0.
+	#[serde(default = "default_lightning_glow")]
+	pub glow: f32,
+	#[serde(default)]
+	pub seed: u32,
+}
+
+fn default_lightning_interval() -> f32 { 8.0 }
+fn default_lightning_glow() -> f32 { 0.1 }
+
+impl Default for Lightning {
+	fn default() -> Self {
+		return Self { interval: default_lightning_interval(), glow: default_lightning_glow(), seed: 0 };
+	}
+}
+
+impl Effect for Lightning {
+	fn render(&self, elapsed: f32) -> EffectOutput {
+		let interval = self.interval.max(MIN_LIGHTNING_INTERVAL);
+		let slot = (elapsed.max(0.0) / interval).floor();
+		let spacing = 1.0 / MAX_FLASH_FREQUENCY;
+		// Each strike starts early enough in its interval that it is over at least
+		// a second before the next interval begins, so no second has more than
+		// `MAX_FLASHES_PER_STRIKE` flashes in it.
+		let start = random(self.seed, slot as u32) * (interval - MIN_LIGHTNING_INTERVAL);
+		let flashes = 1 + (random(self.seed.wrapping_add(1), slot as u32) * MAX_FLASHES_PER_STRIKE as f32) as u32;
+		let since_start = elapsed - slot * interval - start;
+		if since_start >= 0.0 {
+			let flash = (since_start / spacing).floor() as u32;
+			if flash < flashes.min(MAX_FLASHES_PER_STRIKE) && since_start - flash as f32 * spacing < FLASH_DURATION {
+				return EffectOutput::dimmed(1.0);
+			}
+		}
+		return EffectOutput::dimmed(self.glow);
+	}
+}
+
+/// Switches the light fully on and off. The frequency is limited to
+/// `MAX_FLASH_FREQUENCY`, even if a higher one is given.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Strobe {
+	/// Flashes per second.
+	#[serde(default = "default_strobe_frequency")]
+	pub frequency: f32,
+	/// Share of each period in which the light is on, from 0.0 to 1.0.
+	#[serde(default = "default_strobe_duty")]
+	pub duty: f32,
+}
+
+fn default_strobe_frequency() -> f32 { 2.0 }
+fn default_strobe_duty() -> f32 { 0.5 }
+
+impl Default for Strobe {
+	fn default() -> Self {
+		return Self { frequency: default_strobe_frequency(), duty: default_strobe_duty() };
+	}
+}
+
+impl Effect for Strobe {
+	fn render(&self, elapsed: f32) -> EffectOutput {
+		let frequency = self.frequency.clamp(0.0, MAX_FLASH_FREQUENCY);
+		let phase = (elapsed * frequency).fract();
+		return EffectOutput::dimmed(if phase < self.duty { 1.0 } else { 0.0 });
+	}
+}
+
+/// One of the built-in effects together with its parameters, as it is
+/// selected via the API. Deserializing internally tagged enums needs a heap.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "alloc", derive(Serialize, Deserialize), serde(tag = "type", rename_all = "snake_case"))]
+pub enum EffectConfig {
+	Candle(Candle),
+	Breathing(Breathing),
+	ColorCycle(ColorCycle),
+	Lightning(Lightning),
+	Strobe(Strobe),
+}
+
+impl EffectConfig {
+	/// Names of all effects, as accepted by `from_name`.
+	pub const NAMES: [&'static str; 5] = ["candle", "breathing", "color_cycle", "lightning", "strobe"];
+
+	/// The effect with the given name and default parameters. This is how
+	/// scenes refer to effects.
+	pub fn from_name(name: &str) -> Option<Self> {
+		return match name {
+			"candle" => Some(EffectConfig::Candle(Candle::default())),
+			"breathing" => Some(EffectConfig::Breathing(Breathing::default())),
+			"color_cycle" => Some(EffectConfig::ColorCycle(ColorCycle::default())),
+			"lightning" => Some(EffectConfig::Lightning(Lightning::default())),
+			"strobe" => Some(EffectConfig::Strobe(Strobe::default())),
+			_ => None,
+		};
+	}
+
+	pub fn name(&self) -> &'static str {
+		return match self {
+			EffectConfig::Candle(_) => "candle",
+			EffectConfig::Breathing(_) => "breathing",
+			EffectConfig::ColorCycle(_) => "color_cycle",
+			EffectConfig::Lightning(_) => "lightning",
+			EffectConfig::Strobe(_) => "strobe",
+		};
+	}
+
+	pub fn validate(&self) -> Result<(), EffectError> {
+		let amount = |a: f32| (0.0..=1.0).contains(&a);
+		return match self {
+			EffectConfig::Candle(candle) if !amount(candle.intensity) => Err(EffectError::InvalidAmount),
+			EffectConfig::Breathing(breathing) if breathing.period.is_nan() || breathing.period < MIN_PERIOD => Err(EffectError::InvalidPeriod),
+			EffectConfig::Breathing(breathing) if !amount(breathing.depth) => Err(EffectError::InvalidAmount),
+			EffectConfig::ColorCycle(cycle) if cycle.period.is_nan() || cycle.period < MIN_PERIOD => Err(EffectError::InvalidPeriod),
+			EffectConfig::ColorCycle(cycle) if !amount(cycle.saturation) => Err(EffectError::InvalidAmount),
+			EffectConfig::Lightning(lightning) if lightning.interval.is_nan() || lightning.interval < MIN_LIGHTNING_INTERVAL => Err(EffectError::InvalidInterval),
+			EffectConfig::Lightning(lightning) if !amount(lightning.glow) => Err(EffectError::InvalidAmount),
+			EffectConfig::Strobe(strobe) if strobe.frequency.is_nan() || strobe.frequency <= 0.0 || strobe.frequency > MAX_FLASH_FREQUENCY => Err(EffectError::InvalidFrequency),
+			EffectConfig::Strobe(strobe) if !amount(strobe.duty) => Err(EffectError::InvalidAmount),
+			_ => Ok(()),
+		};
+	}
+}
+
+impl Effect for EffectConfig {
+	fn render(&self, elapsed: f32) -> EffectOutput {
+		return match self {
+			EffectConfig::Candle(effect) => effect.render(elapsed),
+			EffectConfig::Breathing(effect) => effect.render(elapsed),
+			EffectConfig::ColorCycle(effect) => effect.render(elapsed),
+			EffectConfig::Lightning(effect) => effect.render(elapsed),
+			EffectConfig::Strobe(effect) => effect.render(elapsed),
+		};
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Renders an effect at 200 Hz like the LED task, and returns the brightness factors.
+	fn render(effect: &dyn Effect, seconds: f32) -> Vec<f32> {
+		return (0..(seconds * 200.0) as u32).map(|i| effect.render(i as f32 * 0.005).brightness).collect();
+	}
+
+	/// Counts how often the light jumps up by more than half of its range.
+	fn flashes(samples: &[f32]) -> usize {
+		return samples.windows(2).filter(|pair| pair[1] - pair[0] > 0.5).count();
+	}
+
+	#[test]
+	fn test_candle_is_deterministic_and_bounded() {
+		let candle = Candle { intensity: 0.4, seed: 7 };
+		let samples = render(&candle, 20.0);
+		assert!(samples.iter().all(|b| (0.6..=1.0).contains(b)));
+		assert!(samples.iter().any(|b| *b < 0.8) && samples.iter().any(|b| *b > 0.9));
+		assert_eq!(samples, render(&candle.clone(), 20.0));
+		assert_ne!(samples, render(&Candle { seed: 8, ..candle }, 20.0));
+	}
+
+	#[test]
+	fn test_breathing() {
+		let breathing = Breathing { period: 4.0, depth: 0.5 };
+		assert!((breathing.render(0.0).brightness - 1.0).abs() < 1e-6);
+		assert!((breathing.render(2.0).brightness - 0.5).abs() < 1e-6);
+		assert!((breathing.render(4.0).brightness - 1.0).abs() < 1e-6);
+		assert_eq!(breathing.render(1.0).color, None);
+	}
+
+	#[test]
+	fn test_color_cycle() {
+		let cycle = ColorCycle { period: 10.0, saturation: 0.5 };
+		let start = cycle.render(0.0).color.unwrap();
+		let half = cycle.render(5.0).color.unwrap();
+		assert!((start.x - 0.4627).abs() < 1e-4 && (start.y - 0.3290).abs() < 1e-4, "{:?}", start);
+		assert!((half.x - 0.1627).abs() < 1e-4, "{:?}", half);
+		assert_eq!(cycle.render(3.0).brightness, 1.0);
+	}
+
+	#[test]
+	fn test_lightning_respects_flash_limit() {
+		for seed in 0..20 {
+			let lightning = Lightning { interval: MIN_LIGHTNING_INTERVAL, glow: 0.1, seed };
+			let samples = render(&lightning, 60.0);
+			assert!(flashes(&samples) >= 30, "{}", flashes(&samples));
+			for window in samples.windows(200) {
+				assert!(flashes(window) <= 3, "Seed {}", seed);
+			}
+		}
+	}
+
+	#[test]
+	fn test_strobe_is_limited() {
+		let strobe = Strobe { frequency: 20.0, duty: 0.5 };
+		assert_eq!(flashes(&render(&strobe, 10.0)), 29);
+		assert_eq!(EffectConfig::Strobe(strobe).validate(), Err(EffectError::InvalidFrequency));
+		assert_eq!(EffectConfig::Breathing(Breathing { period: 0.1, depth: 1.0 }).validate(), Err(EffectError::InvalidPeriod));
+		assert_eq!(EffectConfig::Lightning(Lightning { interval: 1.0, ..Default::default() }).validate(), Err(EffectError::InvalidInterval));
+	}
+
+	#[test]
+	fn test_config() {
+		let config: EffectConfig = serde_json::from_str(r#"{"type": "candle", "seed": 42}"#).unwrap();
+		assert_eq!(config, EffectConfig::Candle(Candle { intensity: 0.3, seed: 42 }));
+		assert_eq!(config.validate(), Ok(()));
+		assert_eq!(EffectConfig::from_name("color_cycle").unwrap().name(), "color_cycle");
+		assert_eq!(EffectConfig::from_name("disco"), None);
+		for name in ["candle", "breathing", "color_cycle", "lightning", "strobe"] {
+			assert_eq!(EffectConfig::from_name(name).unwrap().validate(), Ok(()));
+		}
+	}
+}
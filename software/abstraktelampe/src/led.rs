@@ -1,6 +1,7 @@
 use crate::color::XyColor;
-use std::{rc::Rc, error::Error};
-use std::cell::RefCell;
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::cell::RefCell;
 use delaunator::{Point, triangulate};
 
 pub struct Led<'p> {
@@ -14,6 +15,18 @@ impl<'p> Led<'p> {
 		let xy_color = XyColor {x, y};
 		return Self { name, xy_color, max_brightness };
 	}
+
+	pub fn name(&self) -> &'p str {
+		return self.name;
+	}
+
+	pub fn xy_color(&self) -> &XyColor {
+		return &self.xy_color;
+	}
+
+	pub fn max_brightness(&self) -> f32 {
+		return self.max_brightness;
+	}
 }
 
 
@@ -43,7 +56,7 @@ impl<'p> LedTriangle<'p> {
 
 
 pub struct LedGroup<'p> {
-	leds: Vec<Led<'p>>,
+	leds: Vec<Rc<RefCell<Led<'p>>>>,
 	triangles: Vec<LedTriangle<'p>>,
 }
 
@@ -56,18 +69,19 @@ impl<'p> LedGroup<'p> {
 	}
 
 	pub fn add_led(&mut self, led: Led<'p>) {
-		self.leds.push(led);
+		self.leds.push(Rc::new(RefCell::new(led)));
 		self.triangulate();
 	}
 
 	fn triangulate(&mut self) {
-		let points : Vec<Point>  = self.leds.iter().map(|led| Into::<Point>::into(led.xy_color.clone())).collect();
+		let points : Vec<Point>  = self.leds.iter().map(|led| Into::<Point>::into(led.borrow().xy_color.clone())).collect();
 		let triangulation = triangulate(&points);
+		self.triangles.clear();
 		for geo_triangle in triangulation.triangles.chunks(3) {
 			self.triangles.push(LedTriangle::new(
-				 Rc::new(RefCell::new(self.leds[geo_triangle[0]])),
-				 Rc::new(RefCell::new(self.leds[geo_triangle[1]])),
-				 Rc::new(RefCell::new(self.leds[geo_triangle[2]])),
+				 self.leds[geo_triangle[0]].clone(),
+				 self.leds[geo_triangle[1]].clone(),
+				 self.leds[geo_triangle[2]].clone(),
 			));
 		}
 	}
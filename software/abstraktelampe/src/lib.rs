@@ -1,2 +1,49 @@
-mod color;
-mod led;
\ No newline at end of file
+#![cfg_attr(not(feature = "std"), no_std)]
+#![allow(clippy::needless_return, clippy::manual_range_contains, clippy::excessive_precision)]
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+/// `core` lacks float functions like `powf` and `exp`, so without `std` they come
+/// from `libm`.
+#[cfg(not(feature = "std"))]
+mod float {
+	pub use num_traits::Float;
+}
+
+pub mod alarm;
+pub mod ambient;
+#[cfg(feature = "alloc")]
+pub mod api;
+#[cfg(feature = "alloc")]
+pub mod capture;
+#[cfg(feature = "alloc")]
+pub mod channels;
+#[cfg(feature = "alloc")]
+pub mod circadian;
+pub mod clock;
+pub mod color;
+#[cfg(feature = "alloc")]
+pub mod corridor;
+pub mod daylight;
+#[cfg(feature = "alloc")]
+pub mod dmx;
+pub mod effect;
+#[cfg(feature = "alloc")]
+pub mod led;
+#[cfg(feature = "alloc")]
+pub mod mqtt;
+#[cfg(feature = "alloc")]
+pub mod occupancy;
+pub mod presence;
+#[cfg(feature = "alloc")]
+pub mod push;
+#[cfg(feature = "alloc")]
+pub mod rules;
+#[cfg(feature = "alloc")]
+pub mod scene;
+#[cfg(feature = "alloc")]
+pub mod schedule;
+pub mod sun;
+#[cfg(feature = "alloc")]
+pub mod zone;
@@ -0,0 +1,654 @@
+//! Integration with home automation systems over MQTT, following the conventions
+//! of Home Assistant: the lamp announces its entities with retained discovery
+//! configs, publishes the state of each zone as a JSON light and its sensors as
+//! one JSON object, and takes commands in the JSON light schema. The MQTT client
+//! itself is part of the firmware.
+//!
+//! Topics, with `node` being the configured node id:
+//! - `node/status`: `online`, or `offline` as the last will.
+//! - `node/light/zone` and `node/light/zone/set`: state and commands of a zone.
+//! - `node/sensors`: temperatures, power, illuminance and presence.
+
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+use serde::{Deserialize, Serialize};
+
+use crate::api::{DeviceResource, LightUpdate, SensorsResource, TelemetryResource};
+use crate::clock::Clock;
+use crate::color::XyColor;
+use crate::corridor::CorridorDetection;
+use crate::effect::EffectConfig;
+#[cfg(not(feature = "std"))]
+use crate::float::Float;
+use crate::zone::{LightState, ZoneConfig};
+
+pub const ONLINE: &str = "online";
+pub const OFFLINE: &str = "offline";
+
+/// Home Assistant's scale for brightness.
+const BRIGHTNESS_SCALE: f32 = 255.0;
+
+/// Color temperatures which the lamp accepts, in mireds, i.e. 25000 to 1000 K.
+const MIN_MIREDS: u32 = 40;
+const MAX_MIREDS: u32 = 1000;
+
+/// Effect which stands for no effect.
+const NO_EFFECT: &str = "none";
+
+/// Minimum time between two sensor messages, in ms. Presence changes are sent immediately.
+const SENSORS_INTERVAL_MS: u64 = 10_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MqttError {
+	InvalidUrl,
+	InvalidNodeId,
+	InvalidDiscoveryPrefix,
+	InvalidBrightness,
+	UnknownZone(String),
+	DuplicateZoneId(String),
+	UnknownEffect(String),
+	InvalidCommand(String),
+}
+
+impl fmt::Display for MqttError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			MqttError::InvalidUrl => write!(f, "Broker URL must start with mqtt:// or mqtts://."),
+			MqttError::InvalidNodeId => write!(f, "Node id must only contain letters, digits, '-' and '_'."),
+			MqttError::InvalidDiscoveryPrefix => write!(f, "Discovery prefix must not be empty or contain '+' or '#'."),
+			MqttError::InvalidBrightness => write!(f, "Full brightness must be above 0."),
+			MqttError::UnknownZone(zone) => write!(f, "No zone with the id '{}'.", zone),
+			MqttError::DuplicateZoneId(id) => write!(f, "Several zones have the id '{}' in MQTT topics.", id),
+			MqttError::UnknownEffect(effect) => write!(f, "No effect named '{}'.", effect),
+			MqttError::InvalidCommand(message) => write!(f, "Invalid command: {}", message),
+		}
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MqttConfig {
+	#[serde(default)]
+	pub enabled: bool,
+	/// URL of the broker, like `mqtt://192.168.1.2:1883`.
+	#[serde(default)]
+	pub url: String,
+	#[serde(default)]
+	pub username: Option<String>,
+	#[serde(default)]
+	pub password: Option<String>,
+	/// Prefix of the lamp's own topics, and base of the ids of its entities.
+	/// Must be unique if there are several lamps.
+	#[serde(default = "default_node_id")]
+	pub node_id: String,
+	#[serde(default = "default_discovery_prefix")]
+	pub discovery_prefix: String,
+	/// Brightness of the lamp which Home Assistant shows as 100 %.
+	#[serde(default = "default_full_brightness")]
+	pub full_brightness: f32,
+}
+
+fn default_node_id() -> String {
+	return "bestelampe".to_string();
+}
+
+fn default_discovery_prefix() -> String {
+	return "homeassistant".to_string();
+}
+
+fn default_full_brightness() -> f32 {
+	return 20.0;
+}
+
+impl Default for MqttConfig {
+	fn default() -> Self {
+		return Self {
+			enabled: false,
+			url: String::new(),
+			username: None,
+			password: None,
+			node_id: default_node_id(),
+			discovery_prefix: default_discovery_prefix(),
+			full_brightness: default_full_brightness(),
+		};
+	}
+}
+
+impl MqttConfig {
+	pub fn validate(&self) -> Result<(), MqttError> {
+		if self.enabled && !self.url.starts_with("mqtt://") && !self.url.starts_with("mqtts://") {
+			return Err(MqttError::InvalidUrl);
+		}
+		if self.node_id.is_empty() || self.node_id.chars().any(|c| !c.is_ascii_alphanumeric() && c != '-' && c != '_') {
+			return Err(MqttError::InvalidNodeId);
+		}
+		if self.discovery_prefix.is_empty() || self.discovery_prefix.contains(['+', '#']) {
+			return Err(MqttError::InvalidDiscoveryPrefix);
+		}
+		if !self.full_brightness.is_finite() || self.full_brightness <= 0.0 {
+			return Err(MqttError::InvalidBrightness);
+		}
+		return Ok(());
+	}
+
+	/// Topic of the availability, which is also the last will.
+	pub fn availability_topic(&self) -> String {
+		return format!("{}/status", self.node_id);
+	}
+
+	pub fn light_state_topic(&self, zone: &str) -> String {
+		return format!("{}/light/{}", self.node_id, zone_id(zone));
+	}
+
+	pub fn light_command_topic(&self, zone: &str) -> String {
+		return format!("{}/light/{}/set", self.node_id, zone_id(zone));
+	}
+
+	/// Filter for the command topics of all zones, to subscribe to.
+	pub fn command_filter(&self) -> String {
+		return format!("{}/light/+/set", self.node_id);
+	}
+
+	pub fn sensors_topic(&self) -> String {
+		return format!("{}/sensors", self.node_id);
+	}
+
+	/// Name of the zone which a command was sent to.
+	pub fn command_zone<'a>(&self, topic: &str, zones: &'a ZoneConfig) -> Result<&'a str, MqttError> {
+		let id = topic.strip_prefix(self.node_id.as_str())
+			.and_then(|topic| topic.strip_prefix("/light/"))
+			.and_then(|topic| topic.strip_suffix("/set"))
+			.ok_or_else(|| MqttError::UnknownZone(topic.to_string()))?;
+		return zones.zones.iter().find(|zone| zone_id(&zone.name) == id)
+			.map(|zone| zone.name.as_str())
+			.ok_or_else(|| MqttError::UnknownZone(id.to_string()));
+	}
+
+	/// Parses a config sent by a client. The password is never sent to clients, so
+	/// a config without one keeps `stored_password`. An empty password or `null`
+	/// removes it.
+	pub fn from_update(json: &[u8], stored_password: Option<String>) -> Result<MqttConfig, serde_json::Error> {
+		let mut config: MqttConfig = serde_json::from_slice(json)?;
+		let update: PasswordUpdate = serde_json::from_slice(json)?;
+		config.password = match update.password {
+			None => stored_password,
+			Some(password) => password.filter(|password| !password.is_empty()),
+		};
+		return Ok(config);
+	}
+
+	fn lamp_brightness(&self, brightness: f32) -> f32 {
+		return brightness / BRIGHTNESS_SCALE * self.full_brightness;
+	}
+
+	fn scaled_brightness(&self, brightness: f32) -> u8 {
+		if brightness <= 0.0 {
+			return 0;
+		}
+		// A lamp which is on must not look off
+		return (brightness / self.full_brightness * BRIGHTNESS_SCALE).round().clamp(1.0, BRIGHTNESS_SCALE) as u8;
+	}
+}
+
+/// Tells a missing password apart from one which is `null`.
+#[derive(Deserialize)]
+struct PasswordUpdate {
+	#[serde(default, deserialize_with = "present")]
+	password: Option<Option<String>>,
+}
+
+fn present<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<Option<String>>, D::Error> {
+	return Option::<String>::deserialize(deserializer).map(Some);
+}
+
+/// Zone names can contain anything, but topics and ids should not.
+fn zone_id(zone: &str) -> String {
+	return zone.chars().map(|c| if c.is_ascii_alphanumeric() || c == '-' { c.to_ascii_lowercase() } else { '_' }).collect();
+}
+
+/// Checks that no two zones get the same id, which would mix up their topics.
+/// Ids are case insensitive, and all other characters than letters, digits and
+/// '-' become '_'.
+pub fn validate_zone_ids<'a>(zones: impl IntoIterator<Item = &'a str>) -> Result<(), MqttError> {
+	let mut ids: Vec<String> = Vec::new();
+	for zone in zones {
+		let id = zone_id(zone);
+		if ids.contains(&id) {
+			return Err(MqttError::DuplicateZoneId(id));
+		}
+		ids.push(id);
+	}
+	return Ok(());
+}
+
+/// A message to publish.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Publication {
+	pub topic: String,
+	pub payload: String,
+	pub retain: bool,
+}
+
+#[derive(Serialize)]
+struct Device<'a> {
+	identifiers: [&'a str; 1],
+	name: &'a str,
+	model: &'static str,
+	sw_version: &'a str,
+}
+
+#[derive(Serialize)]
+struct LightDiscovery<'a> {
+	name: &'a str,
+	unique_id: String,
+	schema: &'static str,
+	state_topic: String,
+	command_topic: String,
+	availability_topic: String,
+	brightness: bool,
+	brightness_scale: u8,
+	supported_color_modes: [&'static str; 2],
+	min_mireds: u32,
+	max_mireds: u32,
+	effect: bool,
+	effect_list: Vec<&'static str>,
+	device: &'a Device<'a>,
+}
+
+#[derive(Serialize)]
+struct SensorDiscovery<'a> {
+	name: &'static str,
+	unique_id: String,
+	state_topic: String,
+	availability_topic: String,
+	value_template: &'static str,
+	device_class: &'static str,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	unit_of_measurement: Option<&'static str>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	state_class: Option<&'static str>,
+	device: &'a Device<'a>,
+}
+
+/// An entity for a field of `SensorsState`.
+struct SensorEntity {
+	/// `sensor` or `binary_sensor`.
+	component: &'static str,
+	key: &'static str,
+	name: &'static str,
+	device_class: &'static str,
+	unit: Option<&'static str>,
+	value_template: &'static str,
+}
+
+const fn sensor(key: &'static str, name: &'static str, unit: &'static str, value_template: &'static str) -> SensorEntity {
+	return SensorEntity { component: "sensor", key, name, device_class: key, unit: Some(unit), value_template };
+}
+
+const SENSORS: [SensorEntity; 6] = [
+	sensor("temperature", "LED temperature", "°C", "{{ value_json.temperature }}"),
+	sensor("voltage", "Voltage", "V", "{{ value_json.voltage }}"),
+	sensor("current", "Current", "A", "{{ value_json.current }}"),
+	sensor("power", "Power", "W", "{{ value_json.power }}"),
+	sensor("illuminance", "Illuminance", "lx", "{{ value_json.illuminance }}"),
+	SensorEntity { component: "binary_sensor", key: "occupancy", name: "Occupancy", device_class: "occupancy", unit: None, value_template: "{{ 'ON' if value_json.occupied else 'OFF' }}" },
+];
+
+/// Retained discovery configs for a light per zone, and for the sensors. Fails
+/// if several zones would share an entity.
+pub fn discovery(config: &MqttConfig, device: &DeviceResource) -> Result<Vec<Publication>, MqttError> {
+	validate_zone_ids(device.zones.iter().map(String::as_str))?;
+	let info = Device { identifiers: [&config.node_id], name: &device.name, model: "besteLampe!", sw_version: &device.firmware_version };
+	let mut publications = Vec::new();
+	for zone in &device.zones {
+		let light = LightDiscovery {
+			name: zone,
+			unique_id: format!("{}_{}", config.node_id, zone_id(zone)),
+			schema: "json",
+			state_topic: config.light_state_topic(zone),
+			command_topic: config.light_command_topic(zone),
+			availability_topic: config.availability_topic(),
+			brightness: true,
+			brightness_scale: BRIGHTNESS_SCALE as u8,
+			supported_color_modes: ["color_temp", "xy"],
+			min_mireds: MIN_MIREDS,
+			max_mireds: MAX_MIREDS,
+			effect: true,
+			effect_list: [NO_EFFECT].into_iter().chain(EffectConfig::NAMES).collect(),
+			device: &info,
+		};
+		publications.push(Publication {
+			topic: format!("{}/light/{}/{}/config", config.discovery_prefix, config.node_id, zone_id(zone)),
+			payload: serde_json::to_string(&light).unwrap_or_default(),
+			retain: true,
+		});
+	}
+	for entity in &SENSORS {
+		let sensor = SensorDiscovery {
+			name: entity.name,
+			unique_id: format!("{}_{}", config.node_id, entity.key),
+			state_topic: config.sensors_topic(),
+			availability_topic: config.availability_topic(),
+			value_template: entity.value_template,
+			device_class: entity.device_class,
+			unit_of_measurement: entity.unit,
+			state_class: if entity.component == "sensor" { Some("measurement") } else { None },
+			device: &info,
+		};
+		publications.push(Publication {
+			topic: format!("{}/{}/{}/{}/config", config.discovery_prefix, entity.component, config.node_id, entity.key),
+			payload: serde_json::to_string(&sensor).unwrap_or_default(),
+			retain: true,
+		});
+	}
+	return Ok(publications);
+}
+
+/// State of a zone in Home Assistant's JSON light schema.
+#[derive(Serialize)]
+struct LightMessage {
+	state: &'static str,
+	brightness: u8,
+	color_mode: &'static str,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	color_temp: Option<u32>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	color: Option<XyColor>,
+	effect: &'static str,
+}
+
+pub fn light_state(config: &MqttConfig, state: &LightState) -> String {
+	let message = LightMessage {
+		state: if state.brightness > 0.0 { "ON" } else { "OFF" },
+		brightness: config.scaled_brightness(state.brightness),
+		color_mode: if state.xy.is_some() { "xy" } else { "color_temp" },
+		color_temp: if state.xy.is_none() { Some(mireds(state.temperature).clamp(MIN_MIREDS, MAX_MIREDS)) } else { None },
+		color: state.xy.clone(),
+		effect: state.effect.as_ref().map(|effect| effect.name()).unwrap_or(NO_EFFECT),
+	};
+	return serde_json::to_string(&message).unwrap_or_default();
+}
+
+fn mireds(temperature: f32) -> u32 {
+	return (1_000_000.0 / temperature).round() as u32;
+}
+
+/// Everything which the sensor entities read their values from.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SensorsState {
+	/// Temperature of the LED board in °C.
+	pub temperature: f32,
+	pub voltage: f32,
+	pub current: f32,
+	pub power: f32,
+	pub illuminance: Option<f32>,
+	pub occupied: bool,
+	/// What this lamp's own sensor detects as part of a corridor, so that home
+	/// automation can forward it to the other lamps.
+	pub corridor: Option<CorridorDetection>,
+}
+
+impl SensorsState {
+	pub fn new(sensors: &SensorsResource, telemetry: &TelemetryResource) -> Self {
+		return Self {
+			temperature: telemetry.temperature,
+			voltage: telemetry.voltage,
+			current: telemetry.current,
+			power: telemetry.power,
+			illuminance: sensors.illuminance,
+			occupied: sensors.occupancy.is_occupied(),
+			corridor: sensors.corridor.clone(),
+		};
+	}
+}
+
+/// A command in Home Assistant's JSON light schema.
+#[derive(Deserialize)]
+struct CommandMessage {
+	state: Option<String>,
+	/// From 0 to 255.
+	brightness: Option<f32>,
+	/// Color temperature in mireds.
+	color_temp: Option<f32>,
+	color: Option<XyColor>,
+	effect: Option<String>,
+	/// In s.
+	transition: Option<f32>,
+}
+
+/// A command for a zone, translated to the lamp's terms.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Command {
+	pub update: LightUpdate,
+	/// The effect to start, `Some(None)` to stop the effect, or `None` to keep it.
+	pub effect: Option<Option<EffectConfig>>,
+}
+
+impl Command {
+	pub fn parse(config: &MqttConfig, payload: &[u8]) -> Result<Self, MqttError> {
+		let message: CommandMessage = serde_json::from_slice(payload).map_err(|err| MqttError::InvalidCommand(err.to_string()))?;
+		let on = match message.state.as_deref() {
+			Some("ON") => Some(true),
+			Some("OFF") => Some(false),
+			Some(state) => return Err(MqttError::InvalidCommand(format!("Unknown state '{}'.", state))),
+			None => None,
+		};
+		let temperature = match message.color_temp {
+			Some(mireds) if mireds.is_finite() && mireds > 0.0 => Some(1_000_000.0 / mireds),
+			Some(_) => return Err(MqttError::InvalidCommand("Color temperature must be above 0 mireds.".to_string())),
+			None => None,
+		};
+		let update = LightUpdate {
+			on,
+			brightness: message.brightness.map(|brightness| config.lamp_brightness(brightness)),
+			temperature,
+			xy: message.color,
+			transition: message.transition,
+		};
+		update.validate(false).map_err(|err| MqttError::InvalidCommand(err.message))?;
+		let effect = match message.effect.as_deref() {
+			Some(NO_EFFECT) => Some(None),
+			Some(name) => Some(Some(EffectConfig::from_name(name).ok_or_else(|| MqttError::UnknownEffect(name.to_string()))?)),
+			None => None,
+		};
+		return Ok(Self { update, effect });
+	}
+
+	pub fn apply(&self, state: &mut LightState, tick: f32) {
+		self.update.apply(state, tick);
+		if let Some(effect) = &self.effect {
+			state.effect = effect.clone();
+		}
+	}
+}
+
+/// Decides which states to publish: lights whenever they change, sensors at
+/// most every `SENSORS_INTERVAL_MS`, unless the presence or the corridor detection
+/// changed.
+pub struct StatePublisher<C> {
+	clock: C,
+	/// Last payload published to each topic.
+	published: BTreeMap<String, String>,
+	occupied: Option<bool>,
+	corridor: Option<Option<CorridorDetection>>,
+	/// Time at which the sensors were last published, in ms.
+	sensors_sent: u64,
+}
+
+impl<C: Clock> StatePublisher<C> {
+	pub fn new(clock: C) -> Self {
+		return Self { clock, published: BTreeMap::new(), occupied: None, corridor: None, sensors_sent: 0 };
+	}
+
+	/// Forgets what was published, e.g. after reconnecting to the broker.
+	pub fn reset(&mut self) {
+		self.published.clear();
+		self.occupied = None;
+		self.corridor = None;
+	}
+
+	pub fn update(&mut self, config: &MqttConfig, zones: &ZoneConfig, states: &[LightState], sensors: &SensorsState) -> Vec<Publication> {
+		let now = self.clock.now_ms();
+		let mut publications = Vec::new();
+		for (zone, state) in zones.zones.iter().zip(states) {
+			self.publish(&mut publications, config.light_state_topic(&zone.name), light_state(config, state));
+		}
+		let urgent = self.occupied != Some(sensors.occupied) || self.corridor.as_ref() != Some(&sensors.corridor);
+		if urgent || now >= self.sensors_sent + SENSORS_INTERVAL_MS {
+			let payload = serde_json::to_string(sensors).unwrap_or_default();
+			if self.publish(&mut publications, config.sensors_topic(), payload) {
+				self.occupied = Some(sensors.occupied);
+				self.corridor = Some(sensors.corridor.clone());
+				self.sensors_sent = now;
+			}
+		}
+		return publications;
+	}
+
+	/// Adds a retained publication if the payload differs from the last one.
+	fn publish(&mut self, publications: &mut Vec<Publication>, topic: String, payload: String) -> bool {
+		if self.published.get(&topic) == Some(&payload) {
+			return false;
+		}
+		self.published.insert(topic.clone(), payload.clone());
+		publications.push(Publication { topic, payload, retain: true });
+		return true;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use alloc::vec;
+	use crate::clock::ManualClock;
+	use crate::occupancy::OccupancyState;
+	use crate::zone::test_zones;
+	use super::*;
+
+	fn zones() -> ZoneConfig {
+		let mut config = test_zones();
+		config.zones[1].name = "Book Shelf".to_string();
+		return config;
+	}
+
+	fn sensors(occupancy: OccupancyState, temperature: f32) -> SensorsState {
+		let sensors = SensorsResource { illuminance: Some(120.0), ambient_temperature: None, occupancy, corridor: None };
+		let telemetry = TelemetryResource { temperature, voltage: 12.0, current: 0.5, power: 6.0 };
+		return SensorsState::new(&sensors, &telemetry);
+	}
+
+	#[test]
+	fn test_config_and_topics() {
+		let mut config = MqttConfig::default();
+		assert_eq!(config.validate(), Ok(()));
+		config.enabled = true;
+		assert_eq!(config.validate(), Err(MqttError::InvalidUrl));
+		config.url = "mqtt://192.168.1.2:1883".to_string();
+		config.node_id = "lamp/1".to_string();
+		assert_eq!(config.validate(), Err(MqttError::InvalidNodeId));
+		config.node_id = "lamp1".to_string();
+		assert_eq!(config.validate(), Ok(()));
+
+		let zones = zones();
+		assert_eq!(config.light_command_topic("Book Shelf"), "lamp1/light/book_shelf/set");
+		assert_eq!(config.command_zone("lamp1/light/book_shelf/set", &zones), Ok("Book Shelf"));
+		assert_eq!(config.command_zone("lamp1/light/main/set", &zones), Ok("main"));
+		assert_eq!(config.command_zone("lamp1/light/attic/set", &zones), Err(MqttError::UnknownZone("attic".to_string())));
+
+		assert_eq!(validate_zone_ids(zones.zones.iter().map(|zone| zone.name.as_str())), Ok(()));
+		assert_eq!(validate_zone_ids(["Book Shelf", "main", "book_shelf"]), Err(MqttError::DuplicateZoneId("book_shelf".to_string())));
+	}
+
+	#[test]
+	fn test_password_update() {
+		let stored = Some("secret".to_string());
+		let config = MqttConfig::from_update(br#"{"enabled": false}"#, stored.clone()).unwrap();
+		assert_eq!(config.password, stored);
+		let config = MqttConfig::from_update(br#"{"password": "new"}"#, stored.clone()).unwrap();
+		assert_eq!(config.password, Some("new".to_string()));
+		let config = MqttConfig::from_update(br#"{"password": ""}"#, stored.clone()).unwrap();
+		assert_eq!(config.password, None);
+		let config = MqttConfig::from_update(br#"{"password": null}"#, stored.clone()).unwrap();
+		assert_eq!(config.password, None);
+		assert!(MqttConfig::from_update(br#"{"password": 1}"#, stored).is_err());
+	}
+
+	#[test]
+	fn test_discovery() {
+		let config = MqttConfig::default();
+		let device = DeviceResource { name: "besteLampe!".to_string(), firmware_version: "0.1.0".to_string(), api_version: 1, uptime: 60, zones: vec!["main".to_string()] };
+		let colliding = DeviceResource { zones: vec!["Book Shelf".to_string(), "book shelf".to_string()], ..device.clone() };
+		assert_eq!(discovery(&config, &colliding), Err(MqttError::DuplicateZoneId("book_shelf".to_string())));
+		let publications = discovery(&config, &device).unwrap();
+		assert_eq!(publications.len(), 1 + SENSORS.len());
+		assert!(publications.iter().all(|publication| publication.retain));
+
+		assert_eq!(publications[0].topic, "homeassistant/light/bestelampe/main/config");
+		assert_eq!(publications[0].payload, concat!(
+			r#"{"name":"main","unique_id":"bestelampe_main","schema":"json","state_topic":"bestelampe/light/main","#,
+			r#""command_topic":"bestelampe/light/main/set","availability_topic":"bestelampe/status","brightness":true,"#,
+			r#""brightness_scale":255,"supported_color_modes":["color_temp","xy"],"min_mireds":40,"max_mireds":1000,"#,
+			r#""effect":true,"effect_list":["none","candle","breathing","color_cycle","lightning","strobe"],"#,
+			r#""device":{"identifiers":["bestelampe"],"name":"besteLampe!","model":"besteLampe!","sw_version":"0.1.0"}}"#,
+		));
+		let occupancy = publications.last().unwrap();
+		assert_eq!(occupancy.topic, "homeassistant/binary_sensor/bestelampe/occupancy/config");
+		assert!(occupancy.payload.contains(r#""device_class":"occupancy""#));
+		assert!(!occupancy.payload.contains("unit_of_measurement"));
+	}
+
+	#[test]
+	fn test_commands_and_state() {
+		let config = MqttConfig::default();
+		let mut state = LightState::default();
+		Command::parse(&config, br#"{"state": "ON", "brightness": 255, "color_temp": 370, "effect": "candle"}"#).unwrap().apply(&mut state, 0.01);
+		assert_eq!(state.brightness, 20.0);
+		assert!((state.temperature - 2702.7).abs() < 0.1);
+		assert_eq!(light_state(&config, &state), r#"{"state":"ON","brightness":255,"color_mode":"color_temp","color_temp":370,"effect":"candle"}"#);
+
+		Command::parse(&config, br#"{"color": {"x": 0.5, "y": 0.4}, "effect": "none"}"#).unwrap().apply(&mut state, 0.01);
+		assert_eq!(light_state(&config, &state), r#"{"state":"ON","brightness":255,"color_mode":"xy","color":{"x":0.5,"y":0.4},"effect":"none"}"#);
+
+		Command::parse(&config, br#"{"state": "OFF", "transition": 2}"#).unwrap().apply(&mut state, 0.01);
+		assert_eq!(state.brightness, 0.0);
+		assert!(light_state(&config, &state).starts_with(r#"{"state":"OFF","brightness":0,"#));
+
+		// A dim lamp is still on
+		state.brightness = 0.001;
+		assert!(light_state(&config, &state).starts_with(r#"{"state":"ON","brightness":1,"#));
+
+		assert_eq!(Command::parse(&config, br#"{"effect": "disco"}"#), Err(MqttError::UnknownEffect("disco".to_string())));
+		assert!(matches!(Command::parse(&config, br#"{"state": "TOGGLE"}"#), Err(MqttError::InvalidCommand(_))));
+		assert!(matches!(Command::parse(&config, br#"{"color_temp": 10}"#), Err(MqttError::InvalidCommand(_))));
+		assert!(matches!(Command::parse(&config, b"{"), Err(MqttError::InvalidCommand(_))));
+	}
+
+	#[test]
+	fn test_only_changes_are_published() {
+		let clock = ManualClock::new(0);
+		let config = MqttConfig::default();
+		let zones = zones();
+		let mut states = vec![LightState::default(); 2];
+		let mut publisher = StatePublisher::new(&clock);
+		let topics = |publications: Vec<Publication>| publications.into_iter().map(|publication| publication.topic).collect::<Vec<_>>();
+
+		assert_eq!(topics(publisher.update(&config, &zones, &states, &sensors(OccupancyState::Vacant, 40.0))), ["bestelampe/light/main", "bestelampe/light/book_shelf", "bestelampe/sensors"]);
+		assert!(publisher.update(&config, &zones, &states, &sensors(OccupancyState::Vacant, 40.0)).is_empty());
+
+		// The light at once, the temperature at most every 10 s, presence at once
+		states[1].brightness = 5.0;
+		assert_eq!(topics(publisher.update(&config, &zones, &states, &sensors(OccupancyState::Vacant, 41.0))), ["bestelampe/light/book_shelf"]);
+		assert_eq!(topics(publisher.update(&config, &zones, &states, &sensors(OccupancyState::Active, 41.0))), ["bestelampe/sensors"]);
+		clock.advance(5_000);
+		assert!(publisher.update(&config, &zones, &states, &sensors(OccupancyState::Still, 42.0)).is_empty());
+		clock.advance(5_000);
+		assert_eq!(topics(publisher.update(&config, &zones, &states, &sensors(OccupancyState::Still, 42.0))), ["bestelampe/sensors"]);
+		// Corridor detections at once
+		let detected = SensorsState { corridor: Some(CorridorDetection { lamp: "hall".to_string(), direction: None }), ..sensors(OccupancyState::Still, 43.0) };
+		assert_eq!(topics(publisher.update(&config, &zones, &states, &detected)), ["bestelampe/sensors"]);
+
+		publisher.reset();
+		assert_eq!(publisher.update(&config, &zones, &states, &sensors(OccupancyState::Still, 42.0)).len(), 3);
+	}
+}
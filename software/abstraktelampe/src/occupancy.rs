@@ -0,0 +1,307 @@
+//! Turns presence readings into an occupancy state, which keeps the light on for
+//! a while after the last motion, and dims it down step by step when nobody is
+//! detected anymore.
+
+use core::fmt;
+use serde::{Deserialize, Serialize};
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::clock::Clock;
+use crate::presence::{FusionPolicy, MotionLevel, PresenceReading};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OccupancyError {
+	InvalidDuration,
+	InvalidBrightness,
+}
+
+impl fmt::Display for OccupancyError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			OccupancyError::InvalidDuration => write!(f, "Hold times and fade-down durations must not be negative."),
+			OccupancyError::InvalidBrightness => write!(f, "Fade-down brightness must be between 0 and 1."),
+		}
+	}
+}
+
+/// A step of the fade-down schedule.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FadeStep {
+	/// Relative to the brightness while the room is occupied.
+	pub brightness: f32,
+	/// How long the step lasts, in seconds.
+	pub duration: f32,
+}
+
+/// All durations are in seconds.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct OccupancyConfig {
+	/// How long the room counts as occupied after the last motion, even if the
+	/// sensors don't detect anybody anymore.
+	#[serde(default = "default_hold_time")]
+	pub hold_time: f32,
+	/// How long somebody who is detected, but does not move, keeps the room occupied.
+	/// Static targets are often false detections, e.g. of a fan or a curtain.
+	#[serde(default = "default_motionless_timeout")]
+	pub motionless_timeout: f32,
+	/// Steps through which the light dims down after the room became unoccupied.
+	/// The light is off after the last step.
+	#[serde(default = "default_fade_down")]
+	pub fade_down: Vec<FadeStep>,
+	/// How the readings of the presence sensors are combined, if there are several.
+	#[serde(default = "default_fusion")]
+	pub fusion: FusionPolicy,
+}
+
+fn default_hold_time() -> f32 {
+	return 60.0;
+}
+
+fn default_motionless_timeout() -> f32 {
+	return 1800.0;
+}
+
+fn default_fade_down() -> Vec<FadeStep> {
+	return vec![FadeStep { brightness: 0.3, duration: 60.0 }];
+}
+
+fn default_fusion() -> FusionPolicy {
+	return FusionPolicy::Any;
+}
+
+impl Default for OccupancyConfig {
+	fn default() -> Self {
+		return Self {
+			hold_time: default_hold_time(),
+			motionless_timeout: default_motionless_timeout(),
+			fade_down: default_fade_down(),
+			fusion: default_fusion(),
+		};
+	}
+}
+
+impl OccupancyConfig {
+	pub fn validate(&self) -> Result<(), OccupancyError> {
+		if self.hold_time < 0.0 || self.motionless_timeout < 0.0 || self.fade_down.iter().any(|step| step.duration < 0.0) {
+			return Err(OccupancyError::InvalidDuration);
+		}
+		if self.fade_down.iter().any(|step| !(0.0..=1.0).contains(&step.brightness)) {
+			return Err(OccupancyError::InvalidBrightness);
+		}
+		return Ok(());
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum OccupancyState {
+	/// Nobody there, and the fade-down has finished.
+	Vacant,
+	/// Somebody arrived or moved within the hold time.
+	Active,
+	/// Somebody is detected, but did not move within the hold time.
+	Still,
+	/// Nobody was detected for the hold time, or nobody moved for the motionless
+	/// timeout. The light is dimmed according to `step` of the fade-down schedule.
+	FadingDown { step: usize },
+}
+
+impl OccupancyState {
+	pub fn is_occupied(&self) -> bool {
+		return matches!(self, OccupancyState::Active | OccupancyState::Still);
+	}
+}
+
+/// State machine from presence readings to an `OccupancyState`. It only changes
+/// its state in `update` and `tick`, and returns the new state if it changed.
+pub struct Occupancy<C> {
+	config: OccupancyConfig,
+	clock: C,
+	state: OccupancyState,
+	/// Time at which the state was entered, in ms.
+	state_since: u64,
+	/// Time of the last motion or arrival, in ms.
+	last_activity: Option<u64>,
+	/// Whether the latest reading detected somebody.
+	present: bool,
+}
+
+impl<C: Clock> Occupancy<C> {
+	pub fn new(config: OccupancyConfig, clock: C) -> Self {
+		let now = clock.now_ms();
+		return Self { config, clock, state: OccupancyState::Vacant, state_since: now, last_activity: None, present: false };
+	}
+
+	pub fn config(&self) -> &OccupancyConfig {
+		return &self.config;
+	}
+
+	/// Replaces the configuration. It applies from the next `update` or `tick`.
+	pub fn set_config(&mut self, config: OccupancyConfig) {
+		self.config = config;
+	}
+
+	pub fn state(&self) -> OccupancyState {
+		return self.state;
+	}
+
+	/// Brightness relative to the brightness while the room is occupied.
+	pub fn brightness(&self) -> f32 {
+		return match self.state {
+			OccupancyState::Vacant => 0.0,
+			OccupancyState::Active | OccupancyState::Still => 1.0,
+			OccupancyState::FadingDown { step } => self.config.fade_down.get(step).map_or(0.0, |step| step.brightness),
+		};
+	}
+
+	/// Feeds a new reading, e.g. fused from several sensors.
+	pub fn update(&mut self, reading: &PresenceReading) -> Option<OccupancyState> {
+		let now = self.clock.now_ms();
+		// Somebody who is detected without having moved, e.g. because they came
+		// in while the sensor was off, has arrived nevertheless
+		let arrived = reading.presence && !self.present;
+		if reading.motion == MotionLevel::Active || arrived {
+			self.last_activity = Some(now);
+		}
+		self.present = reading.presence;
+		return self.tick();
+	}
+
+	/// Advances the state as time passes. Should be called regularly, e.g. every
+	/// 100 ms, if no readings arrive.
+	pub fn tick(&mut self) -> Option<OccupancyState> {
+		let now = self.clock.now_ms();
+		let idle = self.last_activity.map_or(f32::INFINITY, |last| now.saturating_sub(last) as f32 / 1000.0);
+
+		let mut state = self.state;
+		let mut since = self.state_since;
+		if idle < self.config.hold_time {
+			state = OccupancyState::Active;
+		} else if self.present && idle < self.config.motionless_timeout {
+			state = OccupancyState::Still;
+		} else {
+			if state.is_occupied() {
+				state = OccupancyState::FadingDown { step: 0 };
+				since = now;
+			}
+			// Several steps may have passed since the last call
+			while let OccupancyState::FadingDown { step } = state {
+				let Some(fade_step) = self.config.fade_down.get(step) else {
+					state = OccupancyState::Vacant;
+					break;
+				};
+				let end = since + (fade_step.duration * 1000.0) as u64;
+				if now < end {
+					break;
+				}
+				since = end;
+				state = OccupancyState::FadingDown { step: step + 1 };
+			}
+		}
+
+		if state == self.state {
+			return None;
+		}
+		self.state = state;
+		self.state_since = if state.is_occupied() { now } else { since };
+		return Some(state);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::clock::ManualClock;
+	use super::*;
+
+	fn reading(presence: bool, motion: MotionLevel) -> PresenceReading {
+		return PresenceReading { presence, motion, distance: None, direction: None };
+	}
+
+	fn config() -> OccupancyConfig {
+		return OccupancyConfig {
+			hold_time: 10.0,
+			motionless_timeout: 60.0,
+			fade_down: vec![FadeStep { brightness: 0.3, duration: 5.0 }, FadeStep { brightness: 0.1, duration: 5.0 }],
+			fusion: FusionPolicy::Any,
+		};
+	}
+
+	#[test]
+	fn test_hold_and_fade_down() {
+		let clock = ManualClock::new(0);
+		let mut occupancy = Occupancy::new(config(), &clock);
+		assert_eq!(occupancy.tick(), None);
+		assert_eq!(occupancy.brightness(), 0.0);
+
+		assert_eq!(occupancy.update(&reading(true, MotionLevel::Active)), Some(OccupancyState::Active));
+		assert_eq!(occupancy.brightness(), 1.0);
+		// Leaving is only noticed after the hold time
+		clock.advance(1000);
+		assert_eq!(occupancy.update(&reading(false, MotionLevel::None)), None);
+		clock.advance(8_999);
+		assert_eq!(occupancy.tick(), None);
+		clock.advance(1);
+		assert_eq!(occupancy.tick(), Some(OccupancyState::FadingDown { step: 0 }));
+		assert_eq!(occupancy.brightness(), 0.3);
+		clock.advance(5_000);
+		assert_eq!(occupancy.tick(), Some(OccupancyState::FadingDown { step: 1 }));
+		assert_eq!(occupancy.brightness(), 0.1);
+		clock.advance(5_000);
+		assert_eq!(occupancy.tick(), Some(OccupancyState::Vacant));
+		assert_eq!(occupancy.brightness(), 0.0);
+	}
+
+	#[test]
+	fn test_motionless_timeout() {
+		let clock = ManualClock::new(0);
+		let mut occupancy = Occupancy::new(config(), &clock);
+		assert_eq!(occupancy.update(&reading(true, MotionLevel::Active)), Some(OccupancyState::Active));
+		clock.advance(10_000);
+		assert_eq!(occupancy.update(&reading(true, MotionLevel::Motionless)), Some(OccupancyState::Still));
+		assert_eq!(occupancy.brightness(), 1.0);
+		clock.advance(49_999);
+		assert_eq!(occupancy.tick(), None);
+		clock.advance(1);
+		assert_eq!(occupancy.tick(), Some(OccupancyState::FadingDown { step: 0 }));
+
+		// Still being detected without moving does not count as arriving again
+		clock.advance(1_000);
+		assert_eq!(occupancy.update(&reading(true, MotionLevel::Motionless)), None);
+		// But moving does
+		assert_eq!(occupancy.update(&reading(true, MotionLevel::Active)), Some(OccupancyState::Active));
+	}
+
+	#[test]
+	fn test_long_pause_and_return() {
+		let clock = ManualClock::new(0);
+		let mut occupancy = Occupancy::new(config(), &clock);
+		occupancy.update(&reading(true, MotionLevel::Active));
+		occupancy.update(&reading(false, MotionLevel::None));
+		// The fade-down starts when it is noticed, but without ticks for a long
+		// time, all of its steps pass at once
+		clock.advance(1_000_000);
+		assert_eq!(occupancy.tick(), Some(OccupancyState::FadingDown { step: 0 }));
+		clock.advance(1_000_000);
+		assert_eq!(occupancy.tick(), Some(OccupancyState::Vacant));
+
+		// Somebody is detected without having moved
+		assert_eq!(occupancy.update(&reading(true, MotionLevel::Motionless)), Some(OccupancyState::Active));
+	}
+
+	#[test]
+	fn test_validate() {
+		assert_eq!(OccupancyConfig::default().validate(), Ok(()));
+		let mut config = config();
+		config.fade_down[1].brightness = 1.5;
+		assert_eq!(config.validate(), Err(OccupancyError::InvalidBrightness));
+		config.hold_time = -1.0;
+		assert_eq!(config.validate(), Err(OccupancyError::InvalidDuration));
+
+		let config: OccupancyConfig = serde_json::from_str(r#"{"hold_time": 30}"#).unwrap();
+		assert_eq!(config.fade_down, default_fade_down());
+		let config: OccupancyConfig = serde_json::from_str(r#"{"fusion": "majority"}"#).unwrap();
+		assert_eq!(config.fusion, FusionPolicy::Majority);
+		assert_eq!(serde_json::to_string(&OccupancyState::FadingDown { step: 1 }).unwrap(), r#"{"state":"fading_down","step":1}"#);
+	}
+}
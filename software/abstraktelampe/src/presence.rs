@@ -0,0 +1,281 @@
+//! Presence detection which does not depend on the kind of sensor. Each sensor
+//! implements `PresenceSensor`, and reports a normalized `PresenceReading`.
+
+use serde::{Deserialize, Serialize};
+
+mod ld2410_sensor;
+mod mr24hpc1_sensor;
+pub use ld2410_sensor::Ld2410Sensor;
+pub use mr24hpc1_sensor::Mr24hpc1Sensor;
+
+/// Speed in m/s above which a target counts as approaching or moving away.
+const DIRECTION_SPEED: f32 = 0.2;
+
+/// Time constant of the smoothing of the speed, in s.
+const DIRECTION_SMOOTHING: f32 = 1.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MotionLevel {
+	None,
+	Motionless,
+	Active,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+	Approaching,
+	MovingAway,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PresenceReading {
+	pub presence: bool,
+	pub motion: MotionLevel,
+	/// Distance of the nearest target in m, if the sensor measures it.
+	pub distance: Option<f32>,
+	/// Whether the target approaches the sensor or moves away, if it does either.
+	pub direction: Option<Direction>,
+}
+
+impl Default for PresenceReading {
+	/// Nobody there.
+	fn default() -> Self {
+		return Self { presence: false, motion: MotionLevel::None, distance: None, direction: None };
+	}
+}
+
+pub trait PresenceSensor {
+	type Error;
+
+	/// Processes everything that the sensor sent since the last call, without
+	/// blocking. Returns the new reading if it changed.
+	fn poll(&mut self) -> Result<Option<PresenceReading>, Self::Error>;
+
+	/// Whether the sensor stopped sending data.
+	fn is_lost(&self) -> bool;
+}
+
+/// Kinds of presence sensors which fit into the sensor slots of the lamp.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SensorModel {
+	Mr24hpc1,
+	Ld2410,
+}
+
+/// The sensors which are installed in the left and the right slot, or `None`
+/// if a slot is empty. The right slot shares its pins with the GPS module, so
+/// the GPS is only used while that slot has no sensor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SensorSlots {
+	#[serde(default = "default_left")]
+	pub left: Option<SensorModel>,
+	#[serde(default)]
+	pub right: Option<SensorModel>,
+}
+
+fn default_left() -> Option<SensorModel> { Some(SensorModel::Mr24hpc1) }
+
+impl Default for SensorSlots {
+	/// A MR24HPC1 in the left slot, and the GPS module in the right one.
+	fn default() -> Self {
+		return Self { left: default_left(), right: None };
+	}
+}
+
+/// How the readings of several sensors are combined.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FusionPolicy {
+	/// Somebody is there if any sensor detects them.
+	Any,
+	/// Somebody is there if all sensors detect them.
+	All,
+	/// Somebody is there if more than half of the sensors detect them. With two
+	/// sensors, this is the same as `All`.
+	Majority,
+}
+
+/// Combines the readings of several sensors. Sensors without a reading, e.g.
+/// because they are lost, are left out. Motion is combined like presence, and
+/// the distance and direction are those of the nearest target.
+pub fn fuse(policy: FusionPolicy, readings: &[Option<PresenceReading>]) -> PresenceReading {
+	let available = readings.iter().flatten().count();
+	let vote = |count: usize| match policy {
+		FusionPolicy::Any => count > 0,
+		FusionPolicy::All => count == available,
+		FusionPolicy::Majority => 2 * count > available,
+	};
+	if available == 0 || !vote(readings.iter().flatten().filter(|reading| reading.presence).count()) {
+		return PresenceReading::default();
+	}
+
+	let moving = readings.iter().flatten().filter(|reading| reading.motion == MotionLevel::Active).count();
+	let nearest = readings.iter().flatten()
+		.filter(|reading| reading.presence && reading.distance.is_some())
+		.min_by(|a, b| a.distance.partial_cmp(&b.distance).unwrap_or(core::cmp::Ordering::Equal));
+	return PresenceReading {
+		presence: true,
+		motion: if vote(moving) { MotionLevel::Active } else { MotionLevel::Motionless },
+		distance: nearest.and_then(|reading| reading.distance),
+		direction: nearest.and_then(|reading| reading.direction),
+	};
+}
+
+/// Derives the direction of a target from its distance, for sensors which don't
+/// report it themselves.
+#[derive(Clone, Debug, Default)]
+pub struct DirectionEstimator {
+	distance: Option<f32>,
+	/// Smoothed speed in m/s. Positive when the target moves away.
+	speed: f32,
+}
+
+impl DirectionEstimator {
+	pub fn new() -> Self {
+		return Self::default();
+	}
+
+	/// Feeds a distance, measured `elapsed` seconds after the previous one.
+	pub fn update(&mut self, distance: Option<f32>, elapsed: f32) -> Option<Direction> {
+		let (Some(previous), Some(current)) = (self.distance, distance) else {
+			// The target appeared or disappeared, so its speed is unknown
+			self.distance = distance;
+			self.speed = 0.0;
+			return None;
+		};
+		if elapsed > 0.0 {
+			// A simple low pass, like `mr24hpc1::RawDetector` uses
+			let alpha = elapsed / (DIRECTION_SMOOTHING + elapsed);
+			self.speed += ((current - previous) / elapsed - self.speed) * alpha;
+			self.distance = Some(current);
+		}
+		return if self.speed > DIRECTION_SPEED {
+			Some(Direction::MovingAway)
+		} else if self.speed < -DIRECTION_SPEED {
+			Some(Direction::Approaching)
+		} else {
+			None
+		};
+	}
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+	use std::cell::{Cell, RefCell};
+	use std::collections::VecDeque;
+	use std::rc::Rc;
+
+	use super::*;
+
+	/// Plays a sensor on a serial port. Everything the driver writes is ignored.
+	#[derive(Clone, Default)]
+	pub(crate) struct FakeSensor {
+		rx: Rc<RefCell<VecDeque<u8>>>,
+		time: Rc<Cell<u64>>,
+	}
+
+	impl FakeSensor {
+		pub fn send(&self, bytes: &[u8]) {
+			self.rx.borrow_mut().extend(bytes);
+		}
+
+		pub fn set_time(&self, ms: u64) {
+			self.time.set(ms);
+		}
+
+		pub fn clock(&self) -> impl Fn() -> u64 {
+			let time = self.time.clone();
+			return move || time.get();
+		}
+	}
+
+	impl embedded_io::ErrorType for FakeSensor {
+		type Error = embedded_io::ErrorKind;
+	}
+
+	impl embedded_io::Read for FakeSensor {
+		fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+			let mut rx = self.rx.borrow_mut();
+			let len = buf.len().min(rx.len());
+			for byte in buf.iter_mut().take(len) {
+				*byte = rx.pop_front().unwrap();
+			}
+			return Ok(len);
+		}
+	}
+
+	impl embedded_io::ReadReady for FakeSensor {
+		fn read_ready(&mut self) -> Result<bool, Self::Error> {
+			return Ok(!self.rx.borrow().is_empty());
+		}
+	}
+
+	impl embedded_io::Write for FakeSensor {
+		fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+			return Ok(buf.len());
+		}
+
+		fn flush(&mut self) -> Result<(), Self::Error> {
+			return Ok(());
+		}
+	}
+
+	#[test]
+	fn test_sensor_slots() {
+		assert_eq!(serde_json::from_str::<SensorSlots>("{}").unwrap(), SensorSlots::default());
+		assert_eq!(serde_json::from_str::<SensorSlots>(r#"{"left": null, "right": "ld2410"}"#).unwrap(), SensorSlots {
+			left: None,
+			right: Some(SensorModel::Ld2410),
+		});
+		assert_eq!(serde_json::to_string(&SensorSlots::default()).unwrap(), r#"{"left":"mr24hpc1","right":null}"#);
+	}
+
+	#[test]
+	fn test_fuse() {
+		let nobody = Some(PresenceReading::default());
+		let still = Some(PresenceReading { presence: true, motion: MotionLevel::Motionless, distance: Some(3.0), direction: None });
+		let moving = Some(PresenceReading { presence: true, motion: MotionLevel::Active, distance: Some(1.5), direction: Some(Direction::Approaching) });
+
+		assert_eq!(fuse(FusionPolicy::Any, &[nobody.clone(), still.clone()]), still.clone().unwrap());
+		assert_eq!(fuse(FusionPolicy::All, &[nobody.clone(), still.clone()]), PresenceReading::default());
+		assert_eq!(fuse(FusionPolicy::Majority, &[nobody.clone(), still.clone()]), PresenceReading::default());
+		assert_eq!(fuse(FusionPolicy::Majority, &[nobody.clone(), still.clone(), moving.clone()]), PresenceReading {
+			presence: true,
+			motion: MotionLevel::Motionless,
+			distance: Some(1.5),
+			direction: Some(Direction::Approaching),
+		});
+		assert_eq!(fuse(FusionPolicy::Any, &[still.clone(), moving.clone()]), moving.clone().unwrap());
+		// A lost sensor does not count
+		assert_eq!(fuse(FusionPolicy::All, &[None, still.clone()]), still.clone().unwrap());
+		assert_eq!(fuse(FusionPolicy::Any, &[None, None]), PresenceReading::default());
+	}
+
+	#[test]
+	fn test_direction() {
+		let mut estimator = DirectionEstimator::new();
+		assert_eq!(estimator.update(Some(4.0), 0.1), None);
+		// Walking towards the sensor at 1 m/s
+		let mut direction = None;
+		for step in 1..=10 {
+			direction = estimator.update(Some(4.0 - step as f32 * 0.1), 0.1);
+		}
+		assert_eq!(direction, Some(Direction::Approaching));
+		// Standing still, with some noise
+		for step in 0..30 {
+			direction = estimator.update(Some(3.0 + (step % 2) as f32 * 0.05), 0.1);
+		}
+		assert_eq!(direction, None);
+		// Walking away
+		for step in 1..=10 {
+			direction = estimator.update(Some(3.0 + step as f32 * 0.1), 0.1);
+		}
+		assert_eq!(direction, Some(Direction::MovingAway));
+		// Gone
+		assert_eq!(estimator.update(None, 0.1), None);
+		assert_eq!(estimator.update(Some(1.0), 0.1), None);
+	}
+}
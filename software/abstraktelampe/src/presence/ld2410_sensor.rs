@@ -0,0 +1,115 @@
+use embedded_io::{Read, ReadReady, Write};
+use ld2410::{DriverError, Ld2410, SensorEvent};
+
+use super::*;
+use crate::clock::Clock;
+
+/// The LD2410 as a `PresenceSensor`.
+pub struct Ld2410Sensor<U, C> {
+	driver: Ld2410<U, C>,
+	direction: DirectionEstimator,
+	/// Time of the latest report, in ms.
+	last_report: u64,
+	reading: PresenceReading,
+}
+
+impl<U: Read + ReadReady + Write, C: Clock> Ld2410Sensor<U, C> {
+	pub fn new(driver: Ld2410<U, C>) -> Self {
+		let last_report = driver.clock().now_ms();
+		return Self { driver, direction: DirectionEstimator::new(), last_report, reading: PresenceReading::default() };
+	}
+
+	/// The driver, e.g. to send commands.
+	pub fn driver(&mut self) -> &mut Ld2410<U, C> {
+		return &mut self.driver;
+	}
+}
+
+impl<U: Read + ReadReady + Write, C: Clock> PresenceSensor for Ld2410Sensor<U, C> {
+	type Error = DriverError<U::Error>;
+
+	fn poll(&mut self) -> Result<Option<PresenceReading>, Self::Error> {
+		let now = self.driver.clock().now_ms();
+		let mut reading = self.reading.clone();
+		for event in self.driver.events() {
+			let SensorEvent::Report(report) = event? else {
+				continue;
+			};
+			let elapsed = now.saturating_sub(self.last_report) as f32 / 1000.0;
+			self.last_report = now;
+			let state = report.target.state;
+			reading.presence = state.is_moving() || state.is_stationary();
+			reading.motion = if state.is_moving() {
+				MotionLevel::Active
+			} else if state.is_stationary() {
+				MotionLevel::Motionless
+			} else {
+				MotionLevel::None
+			};
+			reading.distance = reading.presence.then(|| report.target.detection_distance_m());
+			reading.direction = self.direction.update(reading.distance, elapsed);
+		}
+
+		if reading == self.reading {
+			return Ok(None);
+		}
+		self.reading = reading.clone();
+		return Ok(Some(reading));
+	}
+
+	fn is_lost(&self) -> bool {
+		return self.driver.is_lost();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use ld2410::{Frame, Report, Target, TargetState};
+
+	use super::*;
+	use crate::presence::tests::FakeSensor;
+
+	fn report(state: TargetState, distance: u16) -> Frame {
+		return Frame::Report(Report {
+			target: Target {
+				state,
+				moving_distance: distance,
+				moving_energy: 60,
+				stationary_distance: distance,
+				stationary_energy: 40,
+				detection_distance: distance,
+			},
+			engineering: None,
+		});
+	}
+
+	#[test]
+	fn test_readings() {
+		let fake = FakeSensor::default();
+		let mut sensor = Ld2410Sensor::new(Ld2410::new(fake.clone(), fake.clock()));
+
+		fake.send(&report(TargetState::Stationary, 250).encode());
+		assert_eq!(sensor.poll(), Ok(Some(PresenceReading {
+			presence: true,
+			motion: MotionLevel::Motionless,
+			distance: Some(2.5),
+			direction: None,
+		})));
+		assert_eq!(sensor.poll(), Ok(None));
+
+		// Someone walks towards the sensor, reported ten times per second
+		let mut reading = None;
+		for step in 1..=10 {
+			fake.set_time(step * 100);
+			fake.send(&report(TargetState::Moving, 250 - step as u16 * 10).encode());
+			reading = sensor.poll().unwrap().or(reading);
+		}
+		let reading = reading.unwrap();
+		assert_eq!(reading.motion, MotionLevel::Active);
+		assert_eq!(reading.distance, Some(1.5));
+		assert_eq!(reading.direction, Some(Direction::Approaching));
+
+		fake.send(&report(TargetState::None, 0).encode());
+		assert_eq!(sensor.poll(), Ok(Some(PresenceReading::default())));
+	}
+}
@@ -0,0 +1,160 @@
+use embedded_io::{Read, ReadReady, Write};
+use mr24hpc1::{DriverError, Frame, HumanPresence, Motion, Mr24hpc1, OpenFunction, Presence, Proximity, RawDetector, RawDetectorConfig, SensorEvent};
+
+use super::*;
+use crate::clock::Clock;
+
+fn motion_level(motion: Motion) -> MotionLevel {
+	return match motion {
+		Motion::None => MotionLevel::None,
+		Motion::Motionless => MotionLevel::Motionless,
+		Motion::Active => MotionLevel::Active,
+	};
+}
+
+/// The MR24HPC1 as a `PresenceSensor`. It either uses the sensor's own detection,
+/// or derives presence from the raw measurements of the open function mode.
+pub struct Mr24hpc1Sensor<U, C> {
+	driver: Mr24hpc1<U, C>,
+	/// Only used for the raw measurements.
+	detector: Option<RawDetector>,
+	direction: DirectionEstimator,
+	/// Time of the latest raw measurement, in ms.
+	last_measurement: u64,
+	reading: PresenceReading,
+}
+
+impl<U: Read + ReadReady + Write, C: Clock> Mr24hpc1Sensor<U, C> {
+	/// Uses the sensor's own detection, which does not measure the distance.
+	pub fn new(driver: Mr24hpc1<U, C>) -> Self {
+		let last_measurement = driver.clock().now_ms();
+		return Self { driver, detector: None, direction: DirectionEstimator::new(), last_measurement, reading: PresenceReading::default() };
+	}
+
+	/// Uses the raw measurements. The open function mode has to be switched on
+	/// with `mr24hpc1::Command::SetOpenFunction`.
+	pub fn raw(driver: Mr24hpc1<U, C>, config: RawDetectorConfig) -> Self {
+		let mut sensor = Self::new(driver);
+		sensor.detector = Some(RawDetector::new(config));
+		return sensor;
+	}
+
+	/// The driver, e.g. to send commands.
+	pub fn driver(&mut self) -> &mut Mr24hpc1<U, C> {
+		return &mut self.driver;
+	}
+}
+
+impl<U: Read + ReadReady + Write, C: Clock> PresenceSensor for Mr24hpc1Sensor<U, C> {
+	type Error = DriverError<U::Error>;
+
+	fn poll(&mut self) -> Result<Option<PresenceReading>, Self::Error> {
+		let now = self.driver.clock().now_ms();
+		let mut reading = self.reading.clone();
+		for event in self.driver.events() {
+			let SensorEvent::Frame(frame) = event? else {
+				continue;
+			};
+			match (frame, &mut self.detector) {
+				(Frame::HumanPresenceReport(HumanPresence::PresenceInformation(presence)), None) => {
+					reading.presence = presence == Presence::Occupied;
+					// The sensor doesn't always report that the motion has ended
+					if !reading.presence {
+						reading.motion = MotionLevel::None;
+						reading.direction = None;
+					}
+				},
+				(Frame::HumanPresenceReport(HumanPresence::MotionInformation(motion)), None) => {
+					reading.motion = motion_level(motion);
+				},
+				(Frame::HumanPresenceReport(HumanPresence::ProximityReport(proximity)), None) => {
+					reading.direction = match proximity {
+						Proximity::None => None,
+						Proximity::Approaching => Some(Direction::Approaching),
+						Proximity::MovingAway => Some(Direction::MovingAway),
+					};
+				},
+				(Frame::OpenFunction(OpenFunction::SensorInformation(measurement)), Some(detector)) => {
+					let elapsed = now.saturating_sub(self.last_measurement) as f32 / 1000.0;
+					self.last_measurement = now;
+					let result = detector.update(&measurement, elapsed);
+					reading.presence = result.presence == Presence::Occupied;
+					reading.motion = motion_level(result.motion);
+					reading.distance = result.distance;
+					reading.direction = self.direction.update(result.distance, elapsed);
+				},
+				_ => (),
+			}
+		}
+
+		if reading == self.reading {
+			return Ok(None);
+		}
+		self.reading = reading.clone();
+		return Ok(Some(reading));
+	}
+
+	fn is_lost(&self) -> bool {
+		return self.driver.is_lost();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use mr24hpc1::SensorInformation;
+
+	use super::*;
+	use crate::presence::tests::FakeSensor;
+
+	fn measurement(energy: u8, distance: u8) -> Frame {
+		return Frame::OpenFunction(OpenFunction::SensorInformation(SensorInformation {
+			existence_energy: energy, static_distance: distance, motion_energy: energy, motion_distance: distance, motion_speed: 10,
+		}));
+	}
+
+	#[test]
+	fn test_own_detection() {
+		let fake = FakeSensor::default();
+		let mut sensor = Mr24hpc1Sensor::new(Mr24hpc1::new(fake.clone(), fake.clock()));
+		assert_eq!(sensor.poll(), Ok(None));
+
+		fake.send(&Frame::HumanPresenceReport(HumanPresence::PresenceInformation(Presence::Occupied)).encode());
+		fake.send(&Frame::HumanPresenceReport(HumanPresence::MotionInformation(Motion::Active)).encode());
+		fake.send(&Frame::HumanPresenceReport(HumanPresence::ProximityReport(Proximity::Approaching)).encode());
+		// Raw measurements are ignored
+		fake.send(&measurement(200, 1).encode());
+		assert_eq!(sensor.poll(), Ok(Some(PresenceReading {
+			presence: true,
+			motion: MotionLevel::Active,
+			distance: None,
+			direction: Some(Direction::Approaching),
+		})));
+		assert_eq!(sensor.poll(), Ok(None));
+
+		// Leaving the room also ends the motion
+		fake.send(&Frame::HumanPresenceReport(HumanPresence::PresenceInformation(Presence::Unoccupied)).encode());
+		assert_eq!(sensor.poll(), Ok(Some(PresenceReading::default())));
+	}
+
+	#[test]
+	fn test_raw_detection() {
+		let fake = FakeSensor::default();
+		let config = RawDetectorConfig { smoothing: 0.0, hold_time: 5.0, ..RawDetectorConfig::default() };
+		let mut sensor = Mr24hpc1Sensor::raw(Mr24hpc1::new(fake.clone(), fake.clock()), config);
+
+		// The sensor's own detection is ignored
+		fake.send(&Frame::HumanPresenceReport(HumanPresence::PresenceInformation(Presence::Occupied)).encode());
+		assert_eq!(sensor.poll(), Ok(None));
+
+		fake.set_time(1000);
+		fake.send(&measurement(200, 4).encode());
+		let reading = sensor.poll().unwrap().unwrap();
+		assert!(reading.presence);
+		assert_eq!(reading.motion, MotionLevel::Active);
+		assert_eq!(reading.distance, Some(2.0));
+
+		fake.set_time(10_000);
+		fake.send(&measurement(0, 4).encode());
+		assert_eq!(sensor.poll(), Ok(Some(PresenceReading::default())));
+	}
+}
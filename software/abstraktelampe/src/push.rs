@@ -0,0 +1,233 @@
+//! Messages which the lamp pushes to connected clients, e.g. over a WebSocket, so
+//! that several web pages and remotes stay consistent when the lamp is changed by
+//! buttons or automations. The lamp regularly takes a `Snapshot`, and the
+//! `Publisher` turns it into messages about what changed.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use serde::{Deserialize, Serialize};
+
+use crate::api::{Lamp, LightResource, SensorsResource, TelemetryResource};
+use crate::clock::Clock;
+use crate::color::XyColor;
+use crate::occupancy::OccupancyState;
+
+/// Minimum time between two fade messages, in ms.
+const FADE_INTERVAL_MS: u64 = 200;
+
+/// Minimum time between two sensor messages, in ms. Presence changes are sent immediately.
+const SENSORS_INTERVAL_MS: u64 = 1_000;
+
+/// Minimum time between two telemetry messages, in ms.
+const TELEMETRY_INTERVAL_MS: u64 = 5_000;
+
+/// Changes of the output below this are not worth a fade message.
+const FADE_EPSILON: f32 = 0.001;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ZoneLight {
+	pub zone: String,
+	#[serde(flatten)]
+	pub light: LightResource,
+}
+
+/// What the LEDs of a zone currently emit, while they fade towards the zone's state.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ZoneOutput {
+	pub zone: String,
+	/// Including dimming by occupancy and effects.
+	pub brightness: f32,
+	pub xy: XyColor,
+}
+
+impl ZoneOutput {
+	fn differs(&self, other: &ZoneOutput) -> bool {
+		return (self.brightness - other.brightness).abs() > FADE_EPSILON
+			|| (self.xy.x - other.xy.x).abs() > FADE_EPSILON
+			|| (self.xy.y - other.xy.y).abs() > FADE_EPSILON;
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PushMessage {
+	/// Target state of each zone.
+	Light { zones: Vec<ZoneLight> },
+	/// Progress of a fade towards the target state.
+	Fade { zones: Vec<ZoneOutput> },
+	Sensors(SensorsResource),
+	/// Somebody arrived, left, or stopped moving.
+	Presence { occupancy: OccupancyState },
+	Telemetry(TelemetryResource),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Snapshot {
+	pub light: Vec<ZoneLight>,
+	pub outputs: Vec<ZoneOutput>,
+	pub sensors: SensorsResource,
+	pub telemetry: TelemetryResource,
+}
+
+impl Snapshot {
+	/// Takes everything but the outputs, which only the LED task knows, from `lamp`.
+	pub fn new(lamp: &impl Lamp, outputs: Vec<ZoneOutput>) -> Self {
+		let tick = lamp.tick();
+		let light = lamp.with_zones(|zones| {
+			return zones.config().zones.iter().zip(zones.states())
+				.map(|(zone, state)| ZoneLight { zone: zone.name.clone(), light: LightResource::new(state, tick) })
+				.collect();
+		});
+		return Self { light, outputs, sensors: lamp.sensors(), telemetry: lamp.telemetry() };
+	}
+
+	/// All of the state, e.g. for a client which just connected.
+	pub fn messages(&self) -> Vec<PushMessage> {
+		return Vec::from([
+			PushMessage::Light { zones: self.light.clone() },
+			PushMessage::Fade { zones: self.outputs.clone() },
+			PushMessage::Sensors(self.sensors.clone()),
+			PushMessage::Telemetry(self.telemetry.clone()),
+		]);
+	}
+}
+
+/// Decides which messages to push, and limits how often changing values are sent.
+pub struct Publisher<C> {
+	clock: C,
+	light: Vec<ZoneLight>,
+	outputs: Vec<ZoneOutput>,
+	sensors: Option<SensorsResource>,
+	telemetry: Option<TelemetryResource>,
+	/// Times at which the last message of each kind was sent, in ms.
+	fade_sent: u64,
+	sensors_sent: u64,
+	telemetry_sent: u64,
+}
+
+impl<C: Clock> Publisher<C> {
+	pub fn new(clock: C) -> Self {
+		return Self {
+			clock,
+			light: Vec::new(),
+			outputs: Vec::new(),
+			sensors: None,
+			telemetry: None,
+			fade_sent: 0,
+			sensors_sent: 0,
+			telemetry_sent: 0,
+		};
+	}
+
+	/// Messages about everything that changed since the last messages.
+	pub fn update(&mut self, snapshot: &Snapshot) -> Vec<PushMessage> {
+		let now = self.clock.now_ms();
+		let mut messages = Vec::new();
+
+		if snapshot.light != self.light {
+			self.light = snapshot.light.clone();
+			messages.push(PushMessage::Light { zones: self.light.clone() });
+		}
+
+		let faded = snapshot.outputs.len() != self.outputs.len()
+			|| snapshot.outputs.iter().zip(&self.outputs).any(|(output, sent)| output.differs(sent));
+		if faded && now >= self.fade_sent + FADE_INTERVAL_MS {
+			self.outputs = snapshot.outputs.clone();
+			self.fade_sent = now;
+			messages.push(PushMessage::Fade { zones: self.outputs.clone() });
+		}
+
+		let occupancy = self.sensors.as_ref().map(|sensors| sensors.occupancy);
+		if occupancy != Some(snapshot.sensors.occupancy) {
+			messages.push(PushMessage::Presence { occupancy: snapshot.sensors.occupancy });
+		}
+		// The other lamps of a corridor need to know about detections at once
+		let corridor = self.sensors.as_ref().map(|sensors| &sensors.corridor);
+		let urgent = occupancy != Some(snapshot.sensors.occupancy) || corridor != Some(&snapshot.sensors.corridor);
+		if self.sensors.as_ref() != Some(&snapshot.sensors) && (urgent || now >= self.sensors_sent + SENSORS_INTERVAL_MS) {
+			self.sensors = Some(snapshot.sensors.clone());
+			self.sensors_sent = now;
+			messages.push(PushMessage::Sensors(snapshot.sensors.clone()));
+		}
+
+		if self.telemetry.as_ref() != Some(&snapshot.telemetry) && (self.telemetry.is_none() || now >= self.telemetry_sent + TELEMETRY_INTERVAL_MS) {
+			self.telemetry = Some(snapshot.telemetry.clone());
+			self.telemetry_sent = now;
+			messages.push(PushMessage::Telemetry(snapshot.telemetry.clone()));
+		}
+
+		return messages;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use alloc::string::ToString;
+	use alloc::vec;
+	use crate::clock::ManualClock;
+	use crate::corridor::CorridorDetection;
+	use crate::presence::Direction;
+	use super::*;
+
+	fn snapshot(brightness: f32, output: f32, occupancy: OccupancyState, illuminance: f32) -> Snapshot {
+		let light = LightResource { on: true, brightness, temperature: Some(2700.0), xy: None, transition: 1.0 };
+		return Snapshot {
+			light: vec![ZoneLight { zone: "main".to_string(), light }],
+			outputs: vec![ZoneOutput { zone: "main".to_string(), brightness: output, xy: XyColor::new(0.46, 0.41) }],
+			sensors: SensorsResource { illuminance: Some(illuminance), ambient_temperature: None, occupancy, corridor: None },
+			telemetry: TelemetryResource { temperature: 40.0, voltage: 12.0, current: 0.5, power: 6.0 },
+		};
+	}
+
+	fn kinds(messages: &[PushMessage]) -> Vec<&'static str> {
+		return messages.iter().map(|message| match message {
+			PushMessage::Light { .. } => "light",
+			PushMessage::Fade { .. } => "fade",
+			PushMessage::Sensors(_) => "sensors",
+			PushMessage::Presence { .. } => "presence",
+			PushMessage::Telemetry(_) => "telemetry",
+		}).collect();
+	}
+
+	#[test]
+	fn test_only_changes_are_pushed() {
+		let clock = ManualClock::new(10_000);
+		let mut publisher = Publisher::new(&clock);
+		let first = snapshot(1.0, 1.0, OccupancyState::Active, 100.0);
+		assert_eq!(kinds(&publisher.update(&first)), ["light", "fade", "presence", "sensors", "telemetry"]);
+		clock.advance(100);
+		assert!(publisher.update(&first).is_empty());
+
+		// A new target is pushed at once, the fade towards it at most every 200 ms
+		assert_eq!(kinds(&publisher.update(&snapshot(2.0, 1.0, OccupancyState::Active, 100.0))), ["light"]);
+		clock.advance(50);
+		assert!(publisher.update(&snapshot(2.0, 1.5, OccupancyState::Active, 100.0)).is_empty());
+		clock.advance(50);
+		let messages = publisher.update(&snapshot(2.0, 1.6, OccupancyState::Active, 100.0));
+		assert_eq!(messages, vec![PushMessage::Fade { zones: snapshot(2.0, 1.6, OccupancyState::Active, 100.0).outputs }]);
+
+		// Presence is pushed at once, the illuminance at most every second
+		assert_eq!(kinds(&publisher.update(&snapshot(2.0, 1.6, OccupancyState::Still, 100.0))), ["presence", "sensors"]);
+		assert!(publisher.update(&snapshot(2.0, 1.6, OccupancyState::Still, 120.0)).is_empty());
+		clock.advance(1_000);
+		assert_eq!(kinds(&publisher.update(&snapshot(2.0, 1.6, OccupancyState::Still, 120.0))), ["sensors"]);
+
+		// So are detections of the corridor
+		let mut detected = snapshot(2.0, 1.6, OccupancyState::Still, 120.0);
+		detected.sensors.corridor = Some(CorridorDetection { lamp: "hall".to_string(), direction: Some(Direction::Approaching) });
+		assert_eq!(kinds(&publisher.update(&detected)), ["sensors"]);
+	}
+
+	#[test]
+	fn test_json_format() {
+		let message = PushMessage::Presence { occupancy: OccupancyState::FadingDown { step: 0 } };
+		assert_eq!(serde_json::to_string(&message).unwrap(), r#"{"type":"presence","occupancy":{"state":"fading_down","step":0}}"#);
+		let message = &snapshot(2.0, 1.0, OccupancyState::Active, 100.0).messages()[0];
+		assert_eq!(serde_json::to_string(message).unwrap(), r#"{"type":"light","zones":[{"zone":"main","on":true,"brightness":2.0,"temperature":2700.0,"xy":null,"transition":1.0}]}"#);
+		let message = PushMessage::Telemetry(snapshot(2.0, 1.0, OccupancyState::Active, 100.0).telemetry);
+		assert_eq!(serde_json::to_string(&message).unwrap(), r#"{"type":"telemetry","temperature":40.0,"voltage":12.0,"current":0.5,"power":6.0}"#);
+		let mut sensors = snapshot(2.0, 1.0, OccupancyState::Active, 100.0).sensors;
+		sensors.corridor = Some(CorridorDetection { lamp: "hall".to_string(), direction: None });
+		assert_eq!(serde_json::to_string(&PushMessage::Sensors(sensors)).unwrap(), r#"{"type":"sensors","illuminance":100.0,"ambient_temperature":null,"occupancy":{"state":"active"},"corridor":{"lamp":"hall","direction":null}}"#);
+	}
+}
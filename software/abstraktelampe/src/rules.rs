@@ -0,0 +1,463 @@
+use chrono::{DateTime, Duration, NaiveTime, TimeZone, Utc};
+use core::fmt;
+use serde::{Deserialize, Serialize};
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+pub use crate::presence::MotionLevel;
+use crate::scene::MAX_NAME_LEN;
+use crate::zone::ZoneSelector;
+
+/// Maximum number of rules. The firmware persists all rules as a single NVS blob.
+pub const MAX_RULES: usize = 32;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuleError {
+	TooManyRules,
+	InvalidName,
+	NoActions,
+	InvalidBrightness,
+	InvalidTemperature,
+	InvalidTransition,
+}
+
+impl fmt::Display for RuleError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			RuleError::TooManyRules => write!(f, "Can't store more than {} rules.", MAX_RULES),
+			RuleError::InvalidName => write!(f, "Rule, scene and timer names must be between 1 and {} bytes long.", MAX_NAME_LEN),
+			RuleError::NoActions => write!(f, "A rule needs at least one action."),
+			RuleError::InvalidBrightness => write!(f, "Brightness must not be negative."),
+			RuleError::InvalidTemperature => write!(f, "Color temperature must be between 1000 and 25000 K."),
+			RuleError::InvalidTransition => write!(f, "Transition time must not be negative."),
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Button {
+	A,
+	B,
+	C,
+}
+
+/// Something that happened, which the rule engine is told about.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+	Presence(bool),
+	Motion(MotionLevel),
+	/// Ambient light in lux.
+	AmbientLight(f32),
+	Button { button: Button, long: bool },
+	/// Only time has passed. Should be sent regularly, e.g. once per second,
+	/// so that timers and time windows are evaluated.
+	Tick,
+}
+
+/// What makes a rule fire. Triggers fire on changes, not on every event:
+/// repeated reports of the same presence state don't fire again.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Trigger {
+	/// Somebody arrived (`present: true`), or everybody left.
+	Presence { present: bool },
+	/// The motion level changed to `level`.
+	Motion { level: MotionLevel },
+	/// The ambient light entered the given range of lux. A missing bound is unbounded.
+	AmbientLight {
+		#[serde(default)]
+		above: Option<f32>,
+		#[serde(default)]
+		below: Option<f32>,
+	},
+	Button {
+		button: Button,
+		#[serde(default)]
+		long: bool,
+	},
+	/// Local time entered the window `[from, to)`.
+	TimeWindow { from: NaiveTime, to: NaiveTime },
+	/// A timer started by a `start_timer` action has expired.
+	Timer { name: String },
+}
+
+/// Must hold for a triggered rule to execute its actions.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Condition {
+	Presence { present: bool },
+	Motion { level: MotionLevel },
+	AmbientLight {
+		#[serde(default)]
+		above: Option<f32>,
+		#[serde(default)]
+		below: Option<f32>,
+	},
+	/// Local time is within `[from, to)`. Wraps around midnight if `from` is after `to`.
+	TimeWindow { from: NaiveTime, to: NaiveTime },
+	Timer { name: String, running: bool },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Action {
+	/// Recalls a stored scene.
+	Scene { name: String },
+	/// Fades to a brightness, and optionally a color temperature in K, within `transition` seconds.
+	Fade {
+		brightness: f32,
+		#[serde(default)]
+		temperature: Option<f32>,
+		#[serde(default)]
+		transition: f32,
+		#[serde(default, skip_serializing_if = "ZoneSelector::is_all")]
+		zones: ZoneSelector,
+	},
+	/// (Re)starts a timer which expires after `duration` seconds.
+	StartTimer { name: String, duration: u32 },
+	CancelTimer { name: String },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Rule {
+	pub name: String,
+	#[serde(default = "enabled_by_default")]
+	pub enabled: bool,
+	pub trigger: Trigger,
+	#[serde(default)]
+	pub conditions: Vec<Condition>,
+	pub actions: Vec<Action>,
+}
+
+fn enabled_by_default() -> bool {
+	return true;
+}
+
+fn validate_name(name: &str) -> Result<(), RuleError> {
+	if name.is_empty() || name.len() > MAX_NAME_LEN {
+		return Err(RuleError::InvalidName);
+	}
+	return Ok(());
+}
+
+impl Rule {
+	pub fn validate(&self) -> Result<(), RuleError> {
+		validate_name(&self.name)?;
+		if self.actions.is_empty() {
+			return Err(RuleError::NoActions);
+		}
+		if let Trigger::Timer { name } = &self.trigger {
+			validate_name(name)?;
+		}
+		for action in &self.actions {
+			match action {
+				Action::Scene { name } | Action::StartTimer { name, .. } | Action::CancelTimer { name } => validate_name(name)?,
+				Action::Fade { brightness, temperature, transition, .. } => {
+					if brightness.is_nan() || *brightness < 0.0 {
+						return Err(RuleError::InvalidBrightness);
+					}
+					if temperature.is_some_and(|t| !(1000.0..=25000.0).contains(&t)) {
+						return Err(RuleError::InvalidTemperature);
+					}
+					if transition.is_nan() || *transition < 0.0 {
+						return Err(RuleError::InvalidTransition);
+					}
+				},
+			}
+		}
+		return Ok(());
+	}
+}
+
+pub fn validate_rules(rules: &[Rule]) -> Result<(), RuleError> {
+	if rules.len() > MAX_RULES {
+		return Err(RuleError::TooManyRules);
+	}
+	for rule in rules {
+		rule.validate()?;
+	}
+	return Ok(());
+}
+
+fn in_lux_range(lux: f32, above: Option<f32>, below: Option<f32>) -> bool {
+	if above.is_some_and(|above| lux <= above) {
+		return false;
+	}
+	return !below.is_some_and(|below| lux >= below);
+}
+
+fn in_time_window(time: NaiveTime, from: NaiveTime, to: NaiveTime) -> bool {
+	if from <= to {
+		return time >= from && time < to;
+	}
+	return time >= from || time < to;
+}
+
+/// Something a trigger can match, derived from an event and the state before it.
+enum Occurrence {
+	Event(Event),
+	TimeWindowEntered,
+	TimerExpired(String),
+}
+
+/// Evaluates rules against events. Everything the engine knows comes in through
+/// `handle`, including the time, so it behaves the same on the host as on the lamp.
+pub struct RuleEngine {
+	rules: Vec<Rule>,
+	present: Option<bool>,
+	motion: Option<MotionLevel>,
+	lux: Option<f32>,
+	/// Ambient light before the latest measurement, to detect when it enters a range
+	previous_lux: Option<f32>,
+	local_time: Option<NaiveTime>,
+	/// Running timers with the time at which they expire
+	timers: BTreeMap<String, DateTime<Utc>>,
+}
+
+impl RuleEngine {
+	pub fn new(rules: Vec<Rule>) -> Self {
+		return Self { rules, present: None, motion: None, lux: None, previous_lux: None, local_time: None, timers: BTreeMap::new() };
+	}
+
+	pub fn rules(&self) -> &[Rule] {
+		return &self.rules;
+	}
+
+	/// Replaces all rules. Running timers are cancelled, the known state of the sensors is kept.
+	pub fn set_rules(&mut self, rules: Vec<Rule>) {
+		self.rules = rules;
+		self.timers.clear();
+	}
+
+	/// Processes an event at `now` and returns the light actions to execute, in order.
+	/// Timer actions are handled by the engine itself and are not returned.
+	///
+	/// Within one call, rules triggered by the event itself run first, then rules
+	/// whose time window was entered, then rules of expired timers in the order in
+	/// which they expired. In each step, rules run in the order in which they are listed.
+	pub fn handle<Tz: TimeZone>(&mut self, event: Event, now: DateTime<Utc>, tz: &Tz) -> Vec<Action> {
+		let local_time = now.with_timezone(tz).time();
+		let previous_time = self.local_time.replace(local_time);
+
+		let mut expired: Vec<(DateTime<Utc>, String)> = self.timers.iter()
+			.filter(|(_, until)| **until <= now)
+			.map(|(name, until)| (*until, name.clone()))
+			.collect();
+		expired.sort();
+		for (_, name) in &expired {
+			self.timers.remove(name);
+		}
+
+		let mut occurrences = Vec::new();
+		let changed = match &event {
+			Event::Presence(present) => self.present.replace(*present) != Some(*present),
+			Event::Motion(level) => self.motion.replace(*level) != Some(*level),
+			Event::AmbientLight(lux) => {
+				self.previous_lux = self.lux.replace(*lux);
+				true
+			},
+			Event::Button { .. } => true,
+			Event::Tick => false,
+		};
+		if changed {
+			occurrences.push(Occurrence::Event(event));
+		}
+		if previous_time.is_some() {
+			occurrences.push(Occurrence::TimeWindowEntered);
+		}
+		occurrences.extend(expired.into_iter().map(|(_, name)| Occurrence::TimerExpired(name)));
+
+		let mut output = Vec::new();
+		for occurrence in occurrences {
+			let triggered: Vec<Vec<Action>> = self.rules.iter()
+				.filter(|rule| rule.enabled && self.matches(&rule.trigger, &occurrence, previous_time))
+				.filter(|rule| rule.conditions.iter().all(|condition| self.holds(condition)))
+				.map(|rule| rule.actions.clone())
+				.collect();
+			for action in triggered.into_iter().flatten() {
+				match action {
+					Action::StartTimer { name, duration } => {
+						self.timers.insert(name, now + Duration::seconds(duration as i64));
+					},
+					Action::CancelTimer { name } => {
+						self.timers.remove(&name);
+					},
+					light => output.push(light),
+				}
+			}
+		}
+		return output;
+	}
+
+	pub fn is_timer_running(&self, name: &str) -> bool {
+		return self.timers.contains_key(name);
+	}
+
+	fn matches(&self, trigger: &Trigger, occurrence: &Occurrence, previous_time: Option<NaiveTime>) -> bool {
+		match (trigger, occurrence) {
+			(Trigger::Presence { present }, Occurrence::Event(Event::Presence(actual))) => return present == actual,
+			(Trigger::Motion { level }, Occurrence::Event(Event::Motion(actual))) => return level == actual,
+			(Trigger::AmbientLight { above, below }, Occurrence::Event(Event::AmbientLight(_))) => {
+				return self.lux.is_some_and(|lux| in_lux_range(lux, *above, *below))
+					&& !self.previous_lux_in_range(*above, *below);
+			},
+			(Trigger::Button { button, long }, Occurrence::Event(Event::Button { button: actual, long: actual_long })) => {
+				return button == actual && long == actual_long;
+			},
+			(Trigger::TimeWindow { from, to }, Occurrence::TimeWindowEntered) => {
+				let (Some(previous), Some(now)) = (previous_time, self.local_time) else {
+					return false;
+				};
+				return !in_time_window(previous, *from, *to) && in_time_window(now, *from, *to);
+			},
+			(Trigger::Timer { name }, Occurrence::TimerExpired(expired)) => return name == expired,
+			_ => return false,
+		}
+	}
+
+	fn previous_lux_in_range(&self, above: Option<f32>, below: Option<f32>) -> bool {
+		return self.previous_lux.is_some_and(|lux| in_lux_range(lux, above, below));
+	}
+
+	fn holds(&self, condition: &Condition) -> bool {
+		match condition {
+			Condition::Presence { present } => return self.present == Some(*present),
+			Condition::Motion { level } => return self.motion == Some(*level),
+			Condition::AmbientLight { above, below } => return self.lux.is_some_and(|lux| in_lux_range(lux, *above, *below)),
+			Condition::TimeWindow { from, to } => return self.local_time.is_some_and(|time| in_time_window(time, *from, *to)),
+			Condition::Timer { name, running } => return self.is_timer_running(name) == *running,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use chrono_tz::Europe::Berlin;
+
+	/// Local time in Berlin on 2024-03-04, which is UTC+1
+	fn at(hour: u32, minute: u32, second: u32) -> DateTime<Utc> {
+		return Utc.with_ymd_and_hms(2024, 3, 4, hour, minute, second).unwrap() - Duration::hours(1);
+	}
+
+	fn scene(name: &str) -> Action {
+		return Action::Scene { name: name.to_string() };
+	}
+
+	/// Light on when somebody arrives in the dark, off five minutes after everybody left.
+	fn presence_rules() -> Vec<Rule> {
+		return serde_json::from_str(r#"[
+			{
+				"name": "arrive",
+				"trigger": {"type": "presence", "present": true},
+				"conditions": [{"type": "ambient_light", "below": 50.0}],
+				"actions": [{"type": "cancel_timer", "name": "leave"}, {"type": "scene", "name": "on"}]
+			},
+			{
+				"name": "leave",
+				"trigger": {"type": "presence", "present": false},
+				"actions": [{"type": "start_timer", "name": "leave", "duration": 300}]
+			},
+			{
+				"name": "off",
+				"trigger": {"type": "timer", "name": "leave"},
+				"actions": [{"type": "fade", "brightness": 0.0, "transition": 10.0}]
+			}
+		]"#).unwrap();
+	}
+
+	#[test]
+	fn test_presence_with_timer() {
+		let rules = presence_rules();
+		assert_eq!(validate_rules(&rules), Ok(()));
+		let mut engine = RuleEngine::new(rules);
+
+		// Unknown ambient light does not satisfy the condition
+		assert_eq!(engine.handle(Event::Presence(true), at(20, 0, 0), &Berlin), vec![]);
+		assert_eq!(engine.handle(Event::AmbientLight(10.0), at(20, 0, 1), &Berlin), vec![]);
+		assert_eq!(engine.handle(Event::Presence(false), at(20, 0, 2), &Berlin), vec![]);
+		assert!(engine.is_timer_running("leave"));
+		// Repeated reports are not a change
+		assert_eq!(engine.handle(Event::Presence(false), at(20, 1, 0), &Berlin), vec![]);
+
+		// Coming back in time cancels the timer
+		assert_eq!(engine.handle(Event::Presence(true), at(20, 2, 0), &Berlin), vec![scene("on")]);
+		assert!(!engine.is_timer_running("leave"));
+		assert_eq!(engine.handle(Event::Tick, at(20, 10, 0), &Berlin), vec![]);
+
+		// Leaving for good
+		engine.handle(Event::Presence(false), at(20, 11, 0), &Berlin);
+		assert_eq!(engine.handle(Event::Tick, at(20, 15, 59), &Berlin), vec![]);
+		assert_eq!(
+			engine.handle(Event::Tick, at(20, 16, 0), &Berlin),
+			vec![Action::Fade { brightness: 0.0, temperature: None, transition: 10.0, zones: ZoneSelector::All }]
+		);
+		assert_eq!(engine.handle(Event::Tick, at(20, 16, 1), &Berlin), vec![]);
+	}
+
+	#[test]
+	fn test_time_window() {
+		let rule = Rule {
+			name: "night".to_string(),
+			enabled: true,
+			trigger: Trigger::TimeWindow {
+				from: NaiveTime::from_hms_opt(22, 0, 0).unwrap(),
+				to: NaiveTime::from_hms_opt(6, 0, 0).unwrap(),
+			},
+			conditions: vec![Condition::Presence { present: true }],
+			actions: vec![scene("night")],
+		};
+		let mut engine = RuleEngine::new(vec![rule]);
+
+		engine.handle(Event::Presence(true), at(21, 59, 58), &Berlin);
+		assert_eq!(engine.handle(Event::Tick, at(21, 59, 59), &Berlin), vec![]);
+		assert_eq!(engine.handle(Event::Tick, at(22, 0, 0), &Berlin), vec![scene("night")]);
+		assert_eq!(engine.handle(Event::Tick, at(22, 0, 1), &Berlin), vec![]);
+	}
+
+	#[test]
+	fn test_ambient_light_fires_on_entering_range() {
+		let rule: Rule = serde_json::from_str(r#"{
+			"name": "dusk",
+			"trigger": {"type": "ambient_light", "below": 20.0},
+			"conditions": [{"type": "motion", "level": "active"}],
+			"actions": [{"type": "fade", "brightness": 5.0, "temperature": 2700.0}]
+		}"#).unwrap();
+		let mut engine = RuleEngine::new(vec![rule]);
+
+		engine.handle(Event::Motion(MotionLevel::Active), at(18, 0, 0), &Berlin);
+		assert_eq!(engine.handle(Event::AmbientLight(30.0), at(18, 0, 1), &Berlin), vec![]);
+		assert_eq!(engine.handle(Event::AmbientLight(15.0), at(18, 0, 2), &Berlin).len(), 1);
+		assert_eq!(engine.handle(Event::AmbientLight(12.0), at(18, 0, 3), &Berlin), vec![]);
+	}
+
+	#[test]
+	fn test_buttons_and_disabled_rules() {
+		let mut rules: Vec<Rule> = serde_json::from_str(r#"[
+			{"name": "b", "trigger": {"type": "button", "button": "B", "long": true}, "actions": [{"type": "scene", "name": "all off"}]},
+			{"name": "c", "enabled": false, "trigger": {"type": "button", "button": "C"}, "actions": [{"type": "scene", "name": "x"}]}
+		]"#).unwrap();
+		rules.push(rules[0].clone());
+		let mut engine = RuleEngine::new(rules);
+
+		let short = Event::Button { button: Button::B, long: false };
+		let long = Event::Button { button: Button::B, long: true };
+		assert_eq!(engine.handle(short, at(12, 0, 0), &Berlin), vec![]);
+		assert_eq!(engine.handle(long.clone(), at(12, 0, 1), &Berlin), vec![scene("all off"), scene("all off")]);
+		// Buttons are not a state, pressing again fires again
+		assert_eq!(engine.handle(long, at(12, 0, 2), &Berlin).len(), 2);
+		assert_eq!(engine.handle(Event::Button { button: Button::C, long: false }, at(12, 0, 3), &Berlin), vec![]);
+	}
+
+	#[test]
+	fn test_validation() {
+		let mut rules = presence_rules();
+		rules[2].actions = vec![Action::Fade { brightness: 1.0, temperature: Some(100.0), transition: 0.0, zones: ZoneSelector::All }];
+		assert_eq!(validate_rules(&rules), Err(RuleError::InvalidTemperature));
+		rules[2].actions = vec![];
+		assert_eq!(validate_rules(&rules), Err(RuleError::NoActions));
+		let too_many = vec![presence_rules()[0].clone(); MAX_RULES + 1];
+		assert_eq!(validate_rules(&too_many), Err(RuleError::TooManyRules));
+	}
+}
@@ -0,0 +1,268 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use serde::{Deserialize, Serialize};
+
+use crate::color::XyColor;
+use crate::effect::EffectConfig;
+#[cfg(not(feature = "std"))]
+use crate::float::Float;
+use crate::zone::ZoneSelector;
+
+/// Maximum number of scenes in a `SceneStore`. The firmware persists the whole
+/// store as a single NVS blob, so this also bounds the size of that blob.
+pub const MAX_SCENES: usize = 16;
+
+/// Maximum length of a scene name in bytes.
+pub const MAX_NAME_LEN: usize = 32;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SceneError {
+	InvalidName,
+	InvalidColor,
+	InvalidBrightness,
+	InvalidTransition,
+	UnknownEffect,
+	StoreFull,
+	NotFound,
+}
+
+impl fmt::Display for SceneError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			SceneError::InvalidName => write!(f, "Scene name must be between 1 and {} bytes long.", MAX_NAME_LEN),
+			SceneError::InvalidColor => write!(f, "Scene color is outside of valid bounds."),
+			SceneError::InvalidBrightness => write!(f, "Scene brightness must not be negative."),
+			SceneError::InvalidTransition => write!(f, "Scene transition time must not be negative."),
+			SceneError::UnknownEffect => write!(f, "Scene effect must be one of candle, breathing, color_cycle, lightning or strobe."),
+			SceneError::StoreFull => write!(f, "Can't store more than {} scenes.", MAX_SCENES),
+			SceneError::NotFound => write!(f, "No scene with that name."),
+		}
+	}
+}
+
+/// The color a scene fades to, either given as a color temperature or as an
+/// arbitrary chromaticity.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorTarget {
+	/// Correlated color temperature in K.
+	Temperature(f32),
+	/// Chromaticity on the CIE 1931 xy diagram.
+	Xy(XyColor),
+}
+
+impl ColorTarget {
+	pub fn is_valid(&self) -> bool {
+		match self {
+			ColorTarget::Temperature(t) => *t >= 1000.0 && *t <= 25000.0,
+			ColorTarget::Xy(xy) => xy.x > 0.0 && xy.y > 0.0 && xy.x + xy.y <= 1.0,
+		}
+	}
+}
+
+/// A named light setting that can be stored on the lamp and recalled later.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Scene {
+	pub name: String,
+	pub color: ColorTarget,
+	pub brightness: f32,
+	/// Time in seconds to fade from the previous state into this scene.
+	#[serde(default)]
+	pub transition: f32,
+	/// Name of a light effect that runs on top of the scene, if any.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub effect: Option<String>,
+	/// Zones that the scene applies to. Other zones keep their state.
+	#[serde(default, skip_serializing_if = "ZoneSelector::is_all")]
+	pub zones: ZoneSelector,
+}
+
+impl Scene {
+	pub fn validate(&self) -> Result<(), SceneError> {
+		if self.name.is_empty() || self.name.len() > MAX_NAME_LEN {
+			return Err(SceneError::InvalidName);
+		}
+		if !self.color.is_valid() {
+			return Err(SceneError::InvalidColor);
+		}
+		if self.brightness.is_nan() || self.brightness < 0.0 {
+			return Err(SceneError::InvalidBrightness);
+		}
+		if self.transition.is_nan() || self.transition < 0.0 {
+			return Err(SceneError::InvalidTransition);
+		}
+		if self.effect.as_deref().is_some_and(|effect| EffectConfig::from_name(effect).is_none()) {
+			return Err(SceneError::UnknownEffect);
+		}
+		return Ok(());
+	}
+
+	/// Interpolation factor per tick to reach this scene within its transition time.
+	pub fn dim_speed(&self, tick: f32) -> f32 {
+		return dim_speed(self.transition, tick);
+	}
+}
+
+/// Interpolation factor per tick, so that an exponential fade which is
+/// updated every `tick` seconds covers 99% of the distance to its target
+/// within `transition` seconds.
+pub fn dim_speed(transition: f32, tick: f32) -> f32 {
+	let steps = transition / tick;
+	if steps <= 1.0 {
+		return 1.0;
+	}
+	return 1.0 - 0.01f32.powf(1.0 / steps);
+}
+
+/// Inverse of `dim_speed`: the transition time in seconds of a fade with the given
+/// interpolation factor per tick.
+pub fn transition(dim_speed: f32, tick: f32) -> f32 {
+	if dim_speed >= 1.0 {
+		return 0.0;
+	}
+	if dim_speed <= 0.0 {
+		return f32::INFINITY;
+	}
+	return tick * 0.01f32.ln() / (1.0 - dim_speed).ln();
+}
+
+/// An ordered collection of scenes with unique names.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct SceneStore {
+	scenes: Vec<Scene>,
+}
+
+impl SceneStore {
+	pub fn new() -> Self {
+		return Self::default();
+	}
+
+	pub fn list(&self) -> &[Scene] {
+		return &self.scenes;
+	}
+
+	pub fn get(&self, name: &str) -> Option<&Scene> {
+		return self.scenes.iter().find(|scene| scene.name == name);
+	}
+
+	/// Adds a scene, or replaces the scene with the same name.
+	pub fn insert(&mut self, scene: Scene) -> Result<(), SceneError> {
+		scene.validate()?;
+		if let Some(existing) = self.scenes.iter_mut().find(|s| s.name == scene.name) {
+			*existing = scene;
+			return Ok(());
+		}
+		if self.scenes.len() >= MAX_SCENES {
+			return Err(SceneError::StoreFull);
+		}
+		self.scenes.push(scene);
+		return Ok(());
+	}
+
+	pub fn remove(&mut self, name: &str) -> Result<Scene, SceneError> {
+		let index = self.scenes.iter().position(|scene| scene.name == name).ok_or(SceneError::NotFound)?;
+		return Ok(self.scenes.remove(index));
+	}
+
+	/// Returns the scene following the one called `current`, wrapping around at
+	/// the end. Starts with the first scene if `current` is `None` or unknown.
+	pub fn next(&self, current: Option<&str>) -> Option<&Scene> {
+		let index = current
+			.and_then(|name| self.scenes.iter().position(|scene| scene.name == name))
+			.map(|index| (index + 1) % self.scenes.len())
+			.unwrap_or(0);
+		return self.scenes.get(index);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn scene(name: &str, temperature: f32) -> Scene {
+		Scene {
+			name: name.to_string(),
+			color: ColorTarget::Temperature(temperature),
+			brightness: 2.0,
+			transition: 1.0,
+			effect: None,
+			zones: ZoneSelector::All,
+		}
+	}
+
+	#[test]
+	fn test_insert_replace_remove() {
+		let mut store = SceneStore::new();
+		store.insert(scene("evening", 2700.0)).unwrap();
+		store.insert(scene("work", 5000.0)).unwrap();
+		store.insert(scene("evening", 2200.0)).unwrap();
+
+		assert_eq!(store.list().len(), 2);
+		assert_eq!(store.get("evening").unwrap().color, ColorTarget::Temperature(2200.0));
+
+		assert_eq!(store.remove("work").unwrap().name, "work");
+		assert_eq!(store.remove("work"), Err(SceneError::NotFound));
+		assert_eq!(store.list().len(), 1);
+	}
+
+	#[test]
+	fn test_validation() {
+		let mut store = SceneStore::new();
+		assert_eq!(store.insert(scene("", 2700.0)), Err(SceneError::InvalidName));
+		assert_eq!(store.insert(scene("hot", 900.0)), Err(SceneError::InvalidColor));
+		let mut dark = scene("dark", 2700.0);
+		dark.brightness = f32::NAN;
+		assert_eq!(store.insert(dark), Err(SceneError::InvalidBrightness));
+		let mut disco = scene("disco", 2700.0);
+		disco.effect = Some("disco".to_string());
+		assert_eq!(store.insert(disco.clone()), Err(SceneError::UnknownEffect));
+		disco.effect = Some("candle".to_string());
+		assert_eq!(store.insert(disco), Ok(()));
+
+		for i in 1..MAX_SCENES {
+			store.insert(scene(&format!("scene {}", i), 3000.0)).unwrap();
+		}
+		assert_eq!(store.insert(scene("one too many", 3000.0)), Err(SceneError::StoreFull));
+	}
+
+	#[test]
+	fn test_next_wraps_around() {
+		let mut store = SceneStore::new();
+		assert!(store.next(None).is_none());
+		store.insert(scene("a", 2700.0)).unwrap();
+		store.insert(scene("b", 4000.0)).unwrap();
+		assert_eq!(store.next(None).unwrap().name, "a");
+		assert_eq!(store.next(Some("a")).unwrap().name, "b");
+		assert_eq!(store.next(Some("b")).unwrap().name, "a");
+		assert_eq!(store.next(Some("deleted")).unwrap().name, "a");
+	}
+
+	#[test]
+	fn test_json_format() {
+		let parsed: Scene = serde_json::from_str(
+			r#"{"name": "reading", "color": {"xy": {"x": 0.45, "y": 0.41}}, "brightness": 4.0}"#
+		).unwrap();
+		assert_eq!(parsed.color, ColorTarget::Xy(XyColor::new(0.45, 0.41)));
+		assert_eq!(parsed.transition, 0.0);
+		assert_eq!(parsed.effect, None);
+
+		let json = serde_json::to_string(&scene("evening", 2700.0)).unwrap();
+		assert_eq!(json, r#"{"name":"evening","color":{"temperature":2700.0},"brightness":2.0,"transition":1.0}"#);
+	}
+
+	#[test]
+	fn test_dim_speed() {
+		let mut s = scene("a", 2700.0);
+		s.transition = 0.0;
+		assert_eq!(s.dim_speed(0.005), 1.0);
+
+		s.transition = 2.0;
+		let speed = s.dim_speed(0.005);
+		let remaining = (1.0 - speed).powi(400);
+		assert!((remaining - 0.01).abs() < 0.001);
+		assert!((transition(speed, 0.005) - 2.0).abs() < 0.001);
+		assert_eq!(transition(1.0, 0.005), 0.0);
+	}
+}
@@ -0,0 +1,222 @@
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc, Weekday};
+use serde::{Deserialize, Serialize};
+use alloc::{string::String, vec, vec::Vec};
+
+use crate::sun::{sun_event, Location, SunEvent};
+
+/// Steps of the clock which are longer than this are not time passing, but the
+/// clock being set, e.g. via SNTP, in minutes.
+const MAX_CLOCK_STEP: i64 = 5;
+
+/// What makes a schedule entry fire on a given day.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Trigger {
+	/// Local wall clock time, e.g. `{"time": "07:30:00"}`.
+	Time(NaiveTime),
+	/// A sun event at the lamp's location, shifted by `offset` minutes,
+	/// e.g. `{"sun": {"event": "civil_dusk", "offset": -15}}`.
+	Sun {
+		event: SunEvent,
+		#[serde(default)]
+		offset: i64,
+	},
+}
+
+/// Recalls a scene at a fixed local time or at a sun event on some days of the week.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ScheduleEntry {
+	#[serde(flatten)]
+	pub trigger: Trigger,
+	#[serde(default = "all_weekdays")]
+	pub weekdays: Vec<Weekday>,
+	/// Name of the scene to recall.
+	pub scene: String,
+}
+
+fn all_weekdays() -> Vec<Weekday> {
+	return vec![Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri, Weekday::Sat, Weekday::Sun];
+}
+
+impl ScheduleEntry {
+	/// Checks whether this entry was due at some point in the half-open
+	/// interval `(previous, now]` of local time.
+	///
+	/// Working on local wall clock time makes daylight saving transitions
+	/// behave like a person would expect: if the clock jumps forward over the
+	/// scheduled time, the entry fires right after the jump. If the clock jumps
+	/// back, `now` is before `previous` and nothing fires a second time. The
+	/// intervals from `ScheduleCursor` have these properties.
+	///
+	/// Sun triggers need the time zone to convert the event into local time,
+	/// and never fire while the location is unknown or on days on which the
+	/// event does not happen at all.
+	pub fn is_due<Tz: TimeZone>(&self, previous: NaiveDateTime, now: NaiveDateTime, tz: &Tz, location: Option<&Location>) -> bool {
+		if now <= previous {
+			return false;
+		}
+		let mut date = previous.date();
+		while date <= now.date() {
+			let candidate = self.local_time_on(date, tz, location);
+			if candidate.is_some_and(|candidate| candidate > previous && candidate <= now) && self.weekdays.contains(&date.weekday()) {
+				return true;
+			}
+			date = match date.succ_opt() {
+				Some(next) => next,
+				None => return false,
+			};
+		}
+		return false;
+	}
+
+	/// Local time at which this entry fires on the given date, if any.
+	pub fn local_time_on<Tz: TimeZone>(&self, date: NaiveDate, tz: &Tz, location: Option<&Location>) -> Option<NaiveDateTime> {
+		match &self.trigger {
+			Trigger::Time(time) => return Some(date.and_time(*time)),
+			Trigger::Sun { event, offset } => {
+				let utc = sun_event(date, *event, location?)? + Duration::minutes(*offset);
+				return Some(utc.with_timezone(tz).naive_local());
+			},
+		}
+	}
+}
+
+/// Turns the passing time into the intervals of local time in which the
+/// schedule is checked.
+pub struct ScheduleCursor {
+	previous_utc: DateTime<Utc>,
+	previous_local: NaiveDateTime,
+}
+
+impl ScheduleCursor {
+	pub fn new<Tz: TimeZone>(now: DateTime<Utc>, tz: &Tz) -> Self {
+		return Self { previous_utc: now, previous_local: now.with_timezone(tz).naive_local() };
+	}
+
+	/// Returns the interval `(previous, now]` of local time which passed since
+	/// the last call, if any. Jumps are told apart on UTC: a daylight saving
+	/// transition is a step of local time only, and still yields an interval
+	/// while the local time moves forward. After a fall back, nothing is
+	/// returned until the local time has passed the repeated hour. If the clock
+	/// itself is set, nothing is returned, and the cursor starts over from `now`.
+	pub fn advance<Tz: TimeZone>(&mut self, now: DateTime<Utc>, tz: &Tz) -> Option<(NaiveDateTime, NaiveDateTime)> {
+		let step = now - self.previous_utc;
+		self.previous_utc = now;
+		let local = now.with_timezone(tz).naive_local();
+		if step < Duration::zero() || step >= Duration::minutes(MAX_CLOCK_STEP) {
+			self.previous_local = local;
+			return None;
+		}
+		if local <= self.previous_local {
+			return None;
+		}
+		let previous = self.previous_local;
+		self.previous_local = local;
+		return Some((previous, local));
+	}
+}
+
+/// Returns all entries which were due in `(previous, now]`, in the order in which they are listed.
+pub fn due_entries<'a, Tz: TimeZone>(
+	entries: &'a [ScheduleEntry],
+	previous: NaiveDateTime,
+	now: NaiveDateTime,
+	tz: &'a Tz,
+	location: Option<&'a Location>,
+) -> impl Iterator<Item = &'a ScheduleEntry> {
+	return entries.iter().filter(move |entry| entry.is_due(previous, now, tz, location));
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use chrono_tz::Europe::Berlin;
+
+	fn at(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+		// 2024-03-04 is a Monday
+		return NaiveDate::from_ymd_opt(2024, 3, day).unwrap().and_hms_opt(hour, minute, 0).unwrap();
+	}
+
+	#[test]
+	fn test_is_due() {
+		let entry: ScheduleEntry = serde_json::from_str(
+			r#"{"time": "07:30:00", "weekdays": ["Mon", "Wed"], "scene": "morning"}"#
+		).unwrap();
+
+		assert!(entry.is_due(at(4, 7, 29), at(4, 7, 30), &Utc, None));
+		assert!(!entry.is_due(at(4, 7, 30), at(4, 7, 31), &Utc, None));
+		assert!(!entry.is_due(at(5, 7, 29), at(5, 7, 31), &Utc, None)); // Tuesday
+		assert!(entry.is_due(at(5, 23, 0), at(6, 8, 0), &Utc, None)); // missed while offline
+		assert!(!entry.is_due(at(4, 7, 31), at(4, 7, 29), &Utc, None)); // clock went backwards
+	}
+
+	#[test]
+	fn test_weekdays_default_to_every_day() {
+		let entry: ScheduleEntry = serde_json::from_str(r#"{"time": "22:00:00", "scene": "night"}"#).unwrap();
+		assert_eq!(entry.weekdays.len(), 7);
+
+		let entries = [entry];
+		assert_eq!(due_entries(&entries, at(9, 21, 59), at(9, 22, 0), &Utc, None).count(), 1);
+	}
+
+	#[test]
+	fn test_sun_trigger() {
+		let entry: ScheduleEntry = serde_json::from_str(
+			r#"{"sun": {"event": "sunset", "offset": -30}, "scene": "evening"}"#
+		).unwrap();
+		let berlin = Location::new(52.52, 13.405);
+
+		// Sunset in Berlin on 2024-03-04 is at 16:52 UTC, which is 17:52 local time
+		assert!(!entry.is_due(at(4, 17, 0), at(4, 17, 20), &Berlin, Some(&berlin)));
+		assert!(entry.is_due(at(4, 17, 20), at(4, 17, 23), &Berlin, Some(&berlin)));
+		assert!(!entry.is_due(at(4, 17, 23), at(4, 18, 0), &Berlin, Some(&berlin)));
+
+		// Without a location, sun triggers never fire
+		assert!(!entry.is_due(at(4, 0, 0), at(4, 23, 59), &Berlin, None));
+
+		// No sunset during polar day
+		let tromso = Location::new(69.65, 18.96);
+		let summer = NaiveDate::from_ymd_opt(2024, 6, 21).unwrap();
+		assert_eq!(entry.local_time_on(summer, &Berlin, Some(&tromso)), None);
+	}
+
+	/// How often `entry` fires while the clock runs in steps of 10 s for `hours`.
+	fn fired(entry: &ScheduleEntry, start: DateTime<Utc>, hours: i64) -> usize {
+		let mut cursor = ScheduleCursor::new(start, &Berlin);
+		let mut count = 0;
+		for step in 1..=hours * 360 {
+			let now = start + Duration::seconds(step * 10);
+			if let Some((previous, local)) = cursor.advance(now, &Berlin) {
+				if entry.is_due(previous, local, &Berlin, None) {
+					count += 1;
+				}
+			}
+		}
+		return count;
+	}
+
+	#[test]
+	fn test_daylight_saving_transitions() {
+		let entry: ScheduleEntry = serde_json::from_str(r#"{"time": "02:30:00", "scene": "night"}"#).unwrap();
+		// 02:30 is skipped in spring, and happens twice in autumn. It fires once on both days.
+		let spring = Utc.with_ymd_and_hms(2024, 3, 30, 23, 0, 0).unwrap();
+		assert_eq!(fired(&entry, spring, 4), 1);
+		let autumn = Utc.with_ymd_and_hms(2024, 10, 26, 23, 0, 0).unwrap();
+		assert_eq!(fired(&entry, autumn, 4), 1);
+
+		// Entries in the repeated hour fire only once
+		let entry: ScheduleEntry = serde_json::from_str(r#"{"time": "02:10:00", "scene": "night"}"#).unwrap();
+		assert_eq!(fired(&entry, autumn, 4), 1);
+	}
+
+	#[test]
+	fn test_clock_set() {
+		let mut cursor = ScheduleCursor::new(Utc.with_ymd_and_hms(1970, 1, 1, 0, 0, 0).unwrap(), &Berlin);
+		let now = Utc.with_ymd_and_hms(2024, 3, 4, 6, 30, 0).unwrap();
+		assert_eq!(cursor.advance(now, &Berlin), None);
+		assert_eq!(cursor.advance(now + Duration::seconds(1), &Berlin), Some((at(4, 7, 30), at(4, 7, 30) + Duration::seconds(1))));
+		// Setting the clock back starts over, too
+		assert_eq!(cursor.advance(now - Duration::hours(1), &Berlin), None);
+		assert_eq!(cursor.advance(now - Duration::minutes(59), &Berlin), Some((at(4, 6, 30), at(4, 6, 31))));
+	}
+}
@@ -0,0 +1,208 @@
+use chrono::{DateTime, Duration, NaiveDate, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+#[cfg(not(feature = "std"))]
+use crate::float::Float;
+
+/// A position on earth, in degrees. North and east are positive.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Location {
+	pub latitude: f64,
+	pub longitude: f64,
+}
+
+impl Location {
+	pub fn new(latitude: f64, longitude: f64) -> Self {
+		return Self { latitude, longitude };
+	}
+}
+
+/// Events in the course of a day which are defined by the elevation of the sun.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SunEvent {
+	NauticalDawn,
+	CivilDawn,
+	Sunrise,
+	Sunset,
+	CivilDusk,
+	NauticalDusk,
+}
+
+impl SunEvent {
+	/// Elevation of the center of the sun in degrees, at which the event happens.
+	pub fn elevation(self) -> f64 {
+		return match self {
+			// Upper limb touches the horizon, corrected for atmospheric refraction
+			SunEvent::Sunrise | SunEvent::Sunset => -0.833,
+			SunEvent::CivilDawn | SunEvent::CivilDusk => -6.0,
+			SunEvent::NauticalDawn | SunEvent::NauticalDusk => -12.0,
+		};
+	}
+
+	pub fn is_morning(self) -> bool {
+		return matches!(self, SunEvent::NauticalDawn | SunEvent::CivilDawn | SunEvent::Sunrise);
+	}
+}
+
+/// Declination of the sun and the equation of time, which are all we need to
+/// know about the position of the earth at a given time.
+struct SolarParameters {
+	/// In degrees
+	declination: f64,
+	/// In minutes
+	equation_of_time: f64,
+}
+
+/// Computes the solar parameters with the algorithm from the NOAA solar calculator
+/// (which is based on "Astronomical Algorithms" by Jean Meeus). It is accurate to
+/// about a minute for the times of sun events between 1800 and 2100.
+fn solar_parameters(time: DateTime<Utc>) -> SolarParameters {
+	let julian_day = time.timestamp() as f64 / 86400.0 + 2440587.5;
+	let t = (julian_day - 2451545.0) / 36525.0;
+
+	let mean_longitude = (280.46646 + t * (36000.76983 + t * 0.0003032)) % 360.0;
+	let mean_anomaly = 357.52911 + t * (35999.05029 - 0.0001537 * t);
+	let eccentricity = 0.016708634 - t * (0.000042037 + 0.0000001267 * t);
+
+	let m = mean_anomaly.to_radians();
+	let equation_of_center = m.sin() * (1.914602 - t * (0.004817 + 0.000014 * t))
+		+ (2.0 * m).sin() * (0.019993 - 0.000101 * t)
+		+ (3.0 * m).sin() * 0.000289;
+	let true_longitude = mean_longitude + equation_of_center;
+	let omega = (125.04 - 1934.136 * t).to_radians();
+	let apparent_longitude = true_longitude - 0.00569 - 0.00478 * omega.sin();
+
+	let mean_obliquity = 23.0 + (26.0 + (21.448 - t * (46.815 + t * (0.00059 - t * 0.001813))) / 60.0) / 60.0;
+	let obliquity = (mean_obliquity + 0.00256 * omega.cos()).to_radians();
+
+	let declination = (obliquity.sin() * apparent_longitude.to_radians().sin()).asin();
+
+	let y = (obliquity / 2.0).tan().powi(2);
+	let l = mean_longitude.to_radians();
+	let equation_of_time = 4.0 * (
+		y * (2.0 * l).sin()
+		- 2.0 * eccentricity * m.sin()
+		+ 4.0 * eccentricity * y * m.sin() * (2.0 * l).cos()
+		- 0.5 * y * y * (4.0 * l).sin()
+		- 1.25 * eccentricity * eccentricity * (2.0 * m).sin()
+	).to_degrees();
+
+	return SolarParameters { declination: declination.to_degrees(), equation_of_time };
+}
+
+/// Elevation of the center of the sun above the horizon in degrees, without
+/// correction for atmospheric refraction. Negative values mean that the sun
+/// is below the horizon.
+pub fn solar_elevation(time: DateTime<Utc>, location: &Location) -> f64 {
+	let parameters = solar_parameters(time);
+	let minutes = time.num_seconds_from_midnight() as f64 / 60.0;
+	let true_solar_time = minutes + parameters.equation_of_time + 4.0 * location.longitude;
+	let hour_angle = (true_solar_time / 4.0 - 180.0).to_radians();
+
+	let latitude = location.latitude.to_radians();
+	let declination = parameters.declination.to_radians();
+	let cos_zenith = latitude.sin() * declination.sin() + latitude.cos() * declination.cos() * hour_angle.cos();
+	return 90.0 - cos_zenith.clamp(-1.0, 1.0).acos().to_degrees();
+}
+
+/// Computes when an event happens on the given (local) date. Returns `None` if the
+/// sun does not reach the event's elevation on that day, e.g. there is no sunset
+/// during polar day.
+pub fn sun_event(date: NaiveDate, event: SunEvent, location: &Location) -> Option<DateTime<Utc>> {
+	let midnight = date.and_hms_opt(0, 0, 0)?.and_utc();
+	// Start with the solar noon as the first estimate, and refine it with the
+	// solar parameters at the estimated event time.
+	let mut estimate = midnight + Duration::minutes((720.0 - 4.0 * location.longitude) as i64);
+	for _ in 0..3 {
+		let parameters = solar_parameters(estimate);
+		let latitude = location.latitude.to_radians();
+		let declination = parameters.declination.to_radians();
+		let cos_hour_angle = (event.elevation().to_radians().sin() - latitude.sin() * declination.sin())
+			/ (latitude.cos() * declination.cos());
+		if !(-1.0..=1.0).contains(&cos_hour_angle) {
+			return None;
+		}
+		let hour_angle = cos_hour_angle.acos().to_degrees();
+		let solar_noon = 720.0 - 4.0 * location.longitude - parameters.equation_of_time;
+		let minutes = if event.is_morning() { solar_noon - 4.0 * hour_angle } else { solar_noon + 4.0 * hour_angle };
+		estimate = midnight + Duration::milliseconds((minutes * 60_000.0) as i64);
+	}
+	return Some(estimate);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use chrono::TimeZone;
+
+	const BERLIN: Location = Location { latitude: 52.52, longitude: 13.405 };
+	const TROMSO: Location = Location { latitude: 69.65, longitude: 18.96 };
+	const SYDNEY: Location = Location { latitude: -33.87, longitude: 151.21 };
+
+	fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+		return NaiveDate::from_ymd_opt(year, month, day).unwrap();
+	}
+
+	fn utc(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+		return Utc.with_ymd_and_hms(year, month, day, hour, minute, 0).unwrap();
+	}
+
+	fn assert_close(actual: Option<DateTime<Utc>>, expected: DateTime<Utc>) {
+		let actual = actual.unwrap();
+		let difference = (actual - expected).num_seconds().abs();
+		assert!(difference <= 60, "Expected {}, got {}", expected, actual);
+	}
+
+	// Reference values were computed with NREL's Solar Position Algorithm (SPA)
+	// by searching for the time at which the sun reaches the event's elevation.
+
+	#[test]
+	fn test_berlin_summer_solstice() {
+		let day = date(2024, 6, 21);
+		assert_close(sun_event(day, SunEvent::NauticalDawn, &BERLIN), utc(2024, 6, 21, 0, 29));
+		assert_close(sun_event(day, SunEvent::CivilDawn, &BERLIN), utc(2024, 6, 21, 1, 53));
+		assert_close(sun_event(day, SunEvent::Sunrise, &BERLIN), utc(2024, 6, 21, 2, 43));
+		assert_close(sun_event(day, SunEvent::Sunset, &BERLIN), utc(2024, 6, 21, 19, 33));
+		assert_close(sun_event(day, SunEvent::CivilDusk, &BERLIN), utc(2024, 6, 21, 20, 24));
+		assert_close(sun_event(day, SunEvent::NauticalDusk, &BERLIN), utc(2024, 6, 21, 21, 47));
+	}
+
+	#[test]
+	fn test_berlin_winter_solstice() {
+		let day = date(2024, 12, 21);
+		assert_close(sun_event(day, SunEvent::NauticalDawn, &BERLIN), utc(2024, 12, 21, 5, 49));
+		assert_close(sun_event(day, SunEvent::CivilDawn, &BERLIN), utc(2024, 12, 21, 6, 33));
+		assert_close(sun_event(day, SunEvent::Sunrise, &BERLIN), utc(2024, 12, 21, 7, 15));
+		assert_close(sun_event(day, SunEvent::Sunset, &BERLIN), utc(2024, 12, 21, 14, 54));
+		assert_close(sun_event(day, SunEvent::CivilDusk, &BERLIN), utc(2024, 12, 21, 15, 36));
+		assert_close(sun_event(day, SunEvent::NauticalDusk, &BERLIN), utc(2024, 12, 21, 16, 20));
+	}
+
+	#[test]
+	fn test_southern_hemisphere() {
+		let day = date(2024, 1, 15);
+		assert_close(sun_event(day, SunEvent::Sunrise, &SYDNEY), utc(2024, 1, 14, 18, 59));
+		assert_close(sun_event(day, SunEvent::Sunset, &SYDNEY), utc(2024, 1, 15, 9, 9));
+	}
+
+	#[test]
+	fn test_polar_day_and_night() {
+		assert_eq!(sun_event(date(2024, 6, 21), SunEvent::Sunset, &TROMSO), None);
+		assert_eq!(sun_event(date(2024, 12, 21), SunEvent::Sunrise, &TROMSO), None);
+		// The sun never rises, but there is civil twilight around noon
+		assert!(sun_event(date(2024, 12, 21), SunEvent::CivilDawn, &TROMSO).is_some());
+	}
+
+	#[test]
+	fn test_solar_elevation() {
+		// Solar noon in Berlin at the summer solstice: 90° - 52.52° + 23.44°
+		let noon = solar_elevation(utc(2024, 6, 21, 11, 6), &BERLIN);
+		assert!((noon - 60.91).abs() < 0.1, "{}", noon);
+
+		let midnight = solar_elevation(utc(2024, 6, 21, 23, 8), &BERLIN);
+		assert!((midnight - -14.05).abs() < 0.1, "{}", midnight);
+
+		let at_sunrise = solar_elevation(sun_event(date(2024, 3, 20), SunEvent::Sunrise, &BERLIN).unwrap(), &BERLIN);
+		assert!((at_sunrise - SunEvent::Sunrise.elevation()).abs() < 0.01, "{}", at_sunrise);
+	}
+}
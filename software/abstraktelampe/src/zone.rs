@@ -0,0 +1,365 @@
+use alloc::string::{String, ToString};
+use alloc::{vec, vec::Vec};
+use core::fmt;
+use delaunator::{triangulate, Point};
+use serde::{Deserialize, Serialize};
+
+use crate::color::XyColor;
+use crate::effect::EffectConfig;
+use crate::scene::MAX_NAME_LEN;
+
+/// Maximum number of zones, which is also bounded by the number of output channels.
+pub const MAX_ZONES: usize = 8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ZoneError {
+	InvalidName,
+	DuplicateName(String),
+	NoZones,
+	TooManyZones,
+	InvalidLed,
+	NotEnoughLeds,
+	ChannelInUse(usize),
+	UnknownZone(String),
+	UnknownGroup(String),
+}
+
+impl fmt::Display for ZoneError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			ZoneError::InvalidName => write!(f, "Zone, group and LED names must be between 1 and {} bytes long.", MAX_NAME_LEN),
+			ZoneError::DuplicateName(name) => write!(f, "The name '{}' is used more than once.", name),
+			ZoneError::NoZones => write!(f, "There must be at least one zone."),
+			ZoneError::TooManyZones => write!(f, "Can't have more than {} zones.", MAX_ZONES),
+			ZoneError::InvalidLed => write!(f, "LED color is outside of valid bounds, or its brightness is not positive."),
+			ZoneError::NotEnoughLeds => write!(f, "A zone needs at least three LEDs of different colors."),
+			ZoneError::ChannelInUse(channel) => write!(f, "Output channel {} is used more than once.", channel),
+			ZoneError::UnknownZone(name) => write!(f, "No zone named '{}'.", name),
+			ZoneError::UnknownGroup(name) => write!(f, "No zone group named '{}'.", name),
+		}
+	}
+}
+
+fn validate_name(name: &str) -> Result<(), ZoneError> {
+	if name.is_empty() || name.len() > MAX_NAME_LEN {
+		return Err(ZoneError::InvalidName);
+	}
+	return Ok(());
+}
+
+/// An LED of a zone, together with the output channel that drives it.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ZoneLed {
+	pub name: String,
+	pub channel: usize,
+	/// Chromaticity of the LED on the CIE 1931 xy diagram.
+	pub x: f32,
+	pub y: f32,
+	/// Luminous flux at full duty, in arbitrary but consistent units.
+	pub max_brightness: f32,
+}
+
+impl ZoneLed {
+	pub fn new(name: &str, channel: usize, x: f32, y: f32, max_brightness: f32) -> Self {
+		return Self { name: name.to_string(), channel, x, y, max_brightness };
+	}
+}
+
+/// A set of LEDs which always show the same color, e.g. one LED chain. The LEDs
+/// are the zone's profile: they define which colors it can mix.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Zone {
+	pub name: String,
+	pub leds: Vec<ZoneLed>,
+}
+
+impl Zone {
+	pub fn validate(&self) -> Result<(), ZoneError> {
+		validate_name(&self.name)?;
+		for led in &self.leds {
+			validate_name(&led.name)?;
+			let valid_color = led.x > 0.0 && led.y > 0.0 && led.x + led.y <= 1.0;
+			if !valid_color || led.max_brightness.is_nan() || led.max_brightness <= 0.0 {
+				return Err(ZoneError::InvalidLed);
+			}
+		}
+		if self.triangles().is_empty() {
+			return Err(ZoneError::NotEnoughLeds);
+		}
+		return Ok(());
+	}
+
+	/// Triangulation of the zone's gamut, as indices into `leds`. Each color within
+	/// a triangle is mixed from the three LEDs at its corners.
+	pub fn triangles(&self) -> Vec<[usize; 3]> {
+		let points: Vec<Point> = self.leds.iter().map(|led| Point { x: led.x as f64, y: led.y as f64 }).collect();
+		return triangulate(&points).triangles.chunks(3).map(|t| [t[0], t[1], t[2]]).collect();
+	}
+}
+
+/// A name for several zones, so that they can be controlled together.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ZoneGroup {
+	pub name: String,
+	pub zones: Vec<String>,
+}
+
+/// All zones of a lamp and the groups they form.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ZoneConfig {
+	pub zones: Vec<Zone>,
+	#[serde(default)]
+	pub groups: Vec<ZoneGroup>,
+}
+
+impl Default for ZoneConfig {
+	/// A single zone with the LEDs of the main module, on the channels in the
+	/// order of its LEDC drivers.
+	fn default() -> Self {
+		return Self {
+			zones: vec![Zone {
+				name: "main".to_string(),
+				// With these LEDs, only temperatures from 2150 to 6800 can be mapped
+				leds: vec![
+					ZoneLed::new("R", 0, 0.6400, 0.3500, 165.0),
+					ZoneLed::new("G", 1, 0.4070, 0.5370, 460.0),
+					ZoneLed::new("B", 2, 0.1470, 0.1100, 130.0),
+					ZoneLed::new("CW", 3, 0.3447, 0.3553, 310.0),
+					ZoneLed::new("WW", 4, 0.5066, 0.4158, 170.0),
+					ZoneLed::new("PA", 5, 0.5650, 0.4250, 230.0),
+				],
+			}],
+			groups: Vec::new(),
+		};
+	}
+}
+
+impl ZoneConfig {
+	pub fn validate(&self) -> Result<(), ZoneError> {
+		if self.zones.is_empty() {
+			return Err(ZoneError::NoZones);
+		}
+		if self.zones.len() > MAX_ZONES {
+			return Err(ZoneError::TooManyZones);
+		}
+		let mut names: Vec<&str> = Vec::new();
+		let mut channels: Vec<usize> = Vec::new();
+		for zone in &self.zones {
+			zone.validate()?;
+			if names.contains(&zone.name.as_str()) {
+				return Err(ZoneError::DuplicateName(zone.name.clone()));
+			}
+			names.push(&zone.name);
+			for led in &zone.leds {
+				if channels.contains(&led.channel) {
+					return Err(ZoneError::ChannelInUse(led.channel));
+				}
+				channels.push(led.channel);
+			}
+		}
+		for group in &self.groups {
+			validate_name(&group.name)?;
+			if names.contains(&group.name.as_str()) {
+				return Err(ZoneError::DuplicateName(group.name.clone()));
+			}
+			names.push(&group.name);
+			if let Some(unknown) = group.zones.iter().find(|name| self.zone_index(name).is_none()) {
+				return Err(ZoneError::UnknownZone(unknown.clone()));
+			}
+		}
+		return Ok(());
+	}
+
+	pub fn zone_index(&self, name: &str) -> Option<usize> {
+		return self.zones.iter().position(|zone| zone.name == name);
+	}
+
+	/// Indices of the zones that the selector refers to.
+	pub fn resolve(&self, selector: &ZoneSelector) -> Result<Vec<usize>, ZoneError> {
+		return match selector {
+			ZoneSelector::All => Ok((0..self.zones.len()).collect()),
+			ZoneSelector::Zone(name) => self.zone_index(name).map(|index| vec![index]).ok_or_else(|| ZoneError::UnknownZone(name.clone())),
+			ZoneSelector::Group(name) => {
+				let group = self.groups.iter().find(|group| &group.name == name).ok_or_else(|| ZoneError::UnknownGroup(name.clone()))?;
+				group.zones.iter().map(|zone| self.zone_index(zone).ok_or_else(|| ZoneError::UnknownZone(zone.clone()))).collect()
+			},
+		};
+	}
+}
+
+/// Which zones a change applies to.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ZoneSelector {
+	#[default]
+	All,
+	Zone(String),
+	Group(String),
+}
+
+impl ZoneSelector {
+	pub fn is_all(&self) -> bool {
+		return *self == ZoneSelector::All;
+	}
+}
+
+/// The targets of a zone, which the LED task fades to.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LightState {
+	/// Color temperature in K, used if there is no `xy` color.
+	pub temperature: f32,
+	pub xy: Option<XyColor>,
+	pub brightness: f32,
+	/// Interpolation factor per tick of the LED task.
+	pub dim_speed: f32,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub effect: Option<EffectConfig>,
+}
+
+impl Default for LightState {
+	fn default() -> Self {
+		return Self { temperature: 3000.0, xy: None, brightness: 0.001, dim_speed: 0.01, effect: None };
+	}
+}
+
+impl LightState {
+	/// Sets a color temperature as the target, replacing any xy color.
+	pub fn set_temperature(&mut self, temperature: f32) {
+		self.temperature = temperature;
+		self.xy = None;
+	}
+}
+
+/// The zone configuration together with the current state of each zone.
+pub struct Zones {
+	config: ZoneConfig,
+	states: Vec<LightState>,
+}
+
+impl Zones {
+	pub fn new(config: ZoneConfig) -> Self {
+		let states = vec![LightState::default(); config.zones.len()];
+		return Self { config, states };
+	}
+
+	pub fn config(&self) -> &ZoneConfig {
+		return &self.config;
+	}
+
+	/// States in the order of the zones in the config.
+	pub fn states(&self) -> &[LightState] {
+		return &self.states;
+	}
+
+	/// State of the first zone, which is the one that the lamp's own sensors see.
+	pub fn primary(&self) -> &LightState {
+		return &self.states[0];
+	}
+
+	/// Changes the state of all zones that the selector refers to.
+	pub fn update(&mut self, selector: &ZoneSelector, mut change: impl FnMut(&mut LightState)) -> Result<(), ZoneError> {
+		for index in self.config.resolve(selector)? {
+			change(&mut self.states[index]);
+		}
+		return Ok(());
+	}
+
+	/// Changes the state of every zone.
+	pub fn update_all(&mut self, change: impl FnMut(&mut LightState)) {
+		self.states.iter_mut().for_each(change);
+	}
+}
+
+/// The default zone and a shelf zone on the channels after it, for tests of all modules.
+#[cfg(test)]
+pub(crate) fn test_zones() -> ZoneConfig {
+	let mut config = ZoneConfig::default();
+	config.zones.push(Zone {
+		name: "shelf".to_string(),
+		leds: vec![
+			ZoneLed::new("WW", 6, 0.50, 0.41, 100.0),
+			ZoneLed::new("CW", 7, 0.31, 0.33, 100.0),
+			ZoneLed::new("A", 8, 0.57, 0.42, 50.0),
+		],
+	});
+	return config;
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn config() -> ZoneConfig {
+		let mut config = test_zones();
+		config.zones.push(Zone { name: "desk".to_string(), leds: config.zones[1].leds.iter().map(|led| ZoneLed { channel: led.channel + 3, ..led.clone() }).collect() });
+		config.groups.push(ZoneGroup { name: "ambient".to_string(), zones: vec!["shelf".to_string(), "desk".to_string()] });
+		return config;
+	}
+
+	#[test]
+	fn test_validation() {
+		assert_eq!(ZoneConfig::default().validate(), Ok(()));
+		assert_eq!(config().validate(), Ok(()));
+
+		let mut shared_channel = config();
+		shared_channel.zones[2].leds[0].channel = 0;
+		assert_eq!(shared_channel.validate(), Err(ZoneError::ChannelInUse(0)));
+
+		let mut duplicate = config();
+		duplicate.groups[0].name = "desk".to_string();
+		assert_eq!(duplicate.validate(), Err(ZoneError::DuplicateName("desk".to_string())));
+
+		let mut unknown = config();
+		unknown.groups[0].zones.push("attic".to_string());
+		assert_eq!(unknown.validate(), Err(ZoneError::UnknownZone("attic".to_string())));
+
+		// Two white LEDs can only mix the colors on the line between them
+		let mut line = config();
+		line.zones[1].leds.pop();
+		assert_eq!(line.validate(), Err(ZoneError::NotEnoughLeds));
+
+		assert_eq!(ZoneConfig { zones: Vec::new(), groups: Vec::new() }.validate(), Err(ZoneError::NoZones));
+	}
+
+	#[test]
+	fn test_triangles_cover_gamut() {
+		let zone = &ZoneConfig::default().zones[0];
+		let triangles = zone.triangles();
+		// Six points with two of them inside the hull of the other four
+		assert_eq!(triangles.len(), 6);
+		for triangle in triangles {
+			let mut corners = triangle.to_vec();
+			corners.sort();
+			corners.dedup();
+			assert_eq!(corners.len(), 3);
+		}
+	}
+
+	#[test]
+	fn test_selectors() {
+		let config = config();
+		assert_eq!(config.resolve(&ZoneSelector::All), Ok(vec![0, 1, 2]));
+		assert_eq!(config.resolve(&ZoneSelector::Zone("desk".to_string())), Ok(vec![2]));
+		assert_eq!(config.resolve(&ZoneSelector::Group("ambient".to_string())), Ok(vec![1, 2]));
+		assert_eq!(config.resolve(&ZoneSelector::Group("desk".to_string())), Err(ZoneError::UnknownGroup("desk".to_string())));
+
+		let selector: ZoneSelector = serde_json::from_str(r#"{"group": "ambient"}"#).unwrap();
+		assert_eq!(selector, ZoneSelector::Group("ambient".to_string()));
+		assert_eq!(serde_json::from_str::<ZoneSelector>(r#""all""#).unwrap(), ZoneSelector::All);
+	}
+
+	#[test]
+	fn test_zones_are_independent() {
+		let mut zones = Zones::new(config());
+		zones.update(&ZoneSelector::Zone("shelf".to_string()), |state| state.brightness = 5.0).unwrap();
+		zones.update(&ZoneSelector::Group("ambient".to_string()), |state| state.set_temperature(2200.0)).unwrap();
+		assert_eq!(zones.states()[0], LightState::default());
+		assert_eq!(zones.states()[1].brightness, 5.0);
+		assert_eq!(zones.states()[1].temperature, 2200.0);
+		assert_eq!(zones.states()[2].brightness, LightState::default().brightness);
+		assert_eq!(zones.states()[2].temperature, 2200.0);
+		assert!(zones.update(&ZoneSelector::Zone("attic".to_string()), |state| state.brightness = 0.0).is_err());
+		zones.update_all(|state| state.brightness = 0.0);
+		assert!(zones.states().iter().all(|state| state.brightness == 0.0));
+	}
+}
@@ -0,0 +1,70 @@
+// SPDX-FileCopyrightText: 2024 Lena Schimmel <mail@lenaschimmel.de>
+// SPDX-License-Identifier: CERN-OHL-S-2.0+
+// This file is part of besteLampe!.
+// 
+// besteLampe! is free software: you can redistribute it and/or modify it under the
+// terms of the GNU General Public License as published by the Free Software Foundation, 
+// either version 3 of the License, or (at your option) any later version.
+// 
+// besteLampe! is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; 
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. 
+// See the GNU General Public License for more details.
+// 
+// You should have received a copy of the GNU General Public License along with besteLampe!.
+// If not, see <https://www.gnu.org/licenses/>. 
+
+use std::sync::{Arc, Mutex};
+
+use esp_idf_hal::io::{ErrorType, Read, ReadReady, Write};
+
+use abstraktelampe::capture::{CaptureBuffer, CaptureSource};
+
+/// Bytes of serial data which are kept for download.
+pub const CAPTURE_CAPACITY: usize = 16 * 1024;
+
+/// Time since boot in ms. Used as the timestamp of captured data.
+pub fn uptime_ms() -> u64 {
+    return unsafe { esp_idf_svc::sys::esp_timer_get_time() } as u64 / 1000;
+}
+
+/// Serial port which records all received bytes into a capture buffer, so that
+/// they can be downloaded and replayed on a host.
+pub struct Recorder<U> {
+    inner: U,
+    capture: Arc<Mutex<CaptureBuffer>>,
+    source: CaptureSource,
+}
+
+impl<U> Recorder<U> {
+    pub fn new(inner: U, capture: Arc<Mutex<CaptureBuffer>>, source: CaptureSource) -> Self {
+        return Self { inner, capture, source };
+    }
+}
+
+impl<U: ErrorType> ErrorType for Recorder<U> {
+    type Error = U::Error;
+}
+
+impl<U: Read> Read for Recorder<U> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let len = self.inner.read(buf)?;
+        self.capture.lock().unwrap().record(uptime_ms(), self.source, &buf[..len]);
+        return Ok(len);
+    }
+}
+
+impl<U: ReadReady> ReadReady for Recorder<U> {
+    fn read_ready(&mut self) -> Result<bool, Self::Error> {
+        return self.inner.read_ready();
+    }
+}
+
+impl<U: Write> Write for Recorder<U> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        return self.inner.write(buf);
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        return self.inner.flush();
+    }
+}
@@ -32,4 +32,12 @@ pub struct Config {
 
     #[default("Etc/GMT")]
     time_zone: &'static str,
+
+    /// Location of the lamp, used for sun events until the GPS has a fix.
+    /// Leave both at 0.0 if the location is unknown.
+    #[default(0.0)]
+    latitude: f64,
+
+    #[default(0.0)]
+    longitude: f64,
 }
\ No newline at end of file
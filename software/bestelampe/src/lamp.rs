@@ -0,0 +1,77 @@
+// SPDX-FileCopyrightText: 2024 Lena Schimmel <mail@lenaschimmel.de>
+// SPDX-License-Identifier: CERN-OHL-S-2.0+
+// This file is part of besteLampe!.
+// 
+// besteLampe! is free software: you can redistribute it and/or modify it under the
+// terms of the GNU General Public License as published by the Free Software Foundation, 
+// either version 3 of the License, or (at your option) any later version.
+// 
+// besteLampe! is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; 
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. 
+// See the GNU General Public License for more details.
+// 
+// You should have received a copy of the GNU General Public License along with besteLampe!.
+// If not, see <https://www.gnu.org/licenses/>. 
+
+use std::sync::{Arc, RwLock};
+
+use abstraktelampe::ambient::AmbientReading;
+use abstraktelampe::api::{self, DeviceResource, Lamp, SensorsResource, TelemetryResource};
+use abstraktelampe::circadian::NaturalLight;
+use abstraktelampe::zone::Zones;
+
+use crate::capture::uptime_ms;
+use crate::task::leds::TICK;
+use crate::task::presence::{LampCorridor, LampOccupancy};
+
+/// The lamp as seen by the JSON API and the MQTT client.
+pub struct ApiLamp {
+    pub zones: Arc<RwLock<Zones>>,
+    pub natural_light: Arc<RwLock<NaturalLight>>,
+    pub occupancy: Arc<RwLock<LampOccupancy>>,
+    pub corridor: Arc<RwLock<LampCorridor>>,
+    pub ambient: Arc<RwLock<Option<AmbientReading>>>,
+    pub thermal: Arc<RwLock<f32>>,
+    pub voltage: Arc<RwLock<f32>>,
+    pub current: Arc<RwLock<f32>>,
+}
+
+impl Lamp for ApiLamp {
+    fn with_zones<R>(&self, f: impl FnOnce(&mut Zones) -> R) -> R {
+        return f(&mut self.zones.write().unwrap());
+    }
+
+    fn light_changed(&self) {
+        self.natural_light.write().unwrap().override_manually(chrono::Utc::now());
+    }
+
+    fn sensors(&self) -> SensorsResource {
+        let ambient = self.ambient.read().unwrap().clone();
+        return SensorsResource {
+            illuminance: ambient.as_ref().map(|reading| reading.lux()),
+            ambient_temperature: ambient.as_ref().and_then(|reading| reading.cct()),
+            occupancy: self.occupancy.read().unwrap().state(),
+            corridor: self.corridor.read().unwrap().own_detection(),
+        };
+    }
+
+    fn telemetry(&self) -> TelemetryResource {
+        let voltage = *self.voltage.read().unwrap();
+        let current = *self.current.read().unwrap();
+        return TelemetryResource { temperature: *self.thermal.read().unwrap(), voltage, current, power: voltage * current };
+    }
+
+    fn device(&self) -> DeviceResource {
+        return DeviceResource {
+            name: "besteLampe!".to_string(),
+            firmware_version: env!("CARGO_PKG_VERSION").to_string(),
+            api_version: api::VERSION,
+            uptime: uptime_ms() / 1000,
+            zones: self.zones.read().unwrap().config().zones.iter().map(|zone| zone.name.clone()).collect(),
+        };
+    }
+
+    fn tick(&self) -> f32 {
+        return TICK.as_secs_f32();
+    }
+}
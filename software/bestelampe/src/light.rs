@@ -0,0 +1,77 @@
+// SPDX-FileCopyrightText: 2024 Lena Schimmel <mail@lenaschimmel.de>
+// SPDX-License-Identifier: CERN-OHL-S-2.0+
+// This file is part of besteLampe!.
+// 
+// besteLampe! is free software: you can redistribute it and/or modify it under the
+// terms of the GNU General Public License as published by the Free Software Foundation, 
+// either version 3 of the License, or (at your option) any later version.
+// 
+// besteLampe! is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; 
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. 
+// See the GNU General Public License for more details.
+// 
+// You should have received a copy of the GNU General Public License along with besteLampe!.
+// If not, see <https://www.gnu.org/licenses/>. 
+
+use crate::prelude::*;
+
+use std::sync::RwLock;
+
+use abstraktelampe::effect::EffectConfig;
+use abstraktelampe::rules::Action;
+use abstraktelampe::scene::{dim_speed, ColorTarget, Scene, SceneStore};
+use abstraktelampe::zone::Zones;
+
+use crate::task::leds::TICK;
+
+/// Writes the targets of a scene into the state of its zones, so that the LED
+/// task fades to it within the scene's transition time. Starts the scene's effect,
+/// or stops the running effect if the scene has none.
+#[named]
+pub fn recall_scene(scene: &Scene, zones: &RwLock<Zones>) {
+    info!(target: function_name!(), "Recalling scene '{}'.", scene.name);
+    let effect = scene.effect.as_deref().and_then(|name| {
+        let effect = EffectConfig::from_name(name);
+        if effect.is_none() {
+            warn!(target: function_name!(), "Scene '{}' uses unknown effect '{}'.", scene.name, name);
+        }
+        effect
+    });
+    let result = zones.write().unwrap().update(&scene.zones, |state| {
+        match &scene.color {
+            ColorTarget::Temperature(temperature) => state.set_temperature(*temperature),
+            ColorTarget::Xy(xy) => state.xy = Some(xy.clone()),
+        }
+        state.brightness = scene.brightness;
+        state.dim_speed = scene.dim_speed(TICK.as_secs_f32());
+        state.effect = effect.clone();
+    });
+    if let Err(err) = result {
+        warn!(target: function_name!(), "Scene '{}' can't be recalled: {}", scene.name, err);
+    }
+}
+
+/// Executes an action of the rule engine. Timer actions are handled by the
+/// engine itself, so they are ignored here.
+#[named]
+pub fn apply_rule_action(action: &Action, scenes: &SceneStore, zones: &RwLock<Zones>) {
+    match action {
+        Action::Scene { name } => match scenes.get(name) {
+            Some(scene) => recall_scene(scene, zones),
+            None => warn!(target: function_name!(), "Rule wants to recall scene '{}', which does not exist.", name),
+        },
+        Action::Fade { brightness, temperature, transition, zones: selector } => {
+            let result = zones.write().unwrap().update(selector, |state| {
+                if let Some(temperature) = temperature {
+                    state.set_temperature(*temperature);
+                }
+                state.brightness = *brightness;
+                state.dim_speed = dim_speed(*transition, TICK.as_secs_f32());
+            });
+            if let Err(err) = result {
+                warn!(target: function_name!(), "Rule can't fade: {}", err);
+            }
+        },
+        Action::StartTimer { .. } | Action::CancelTimer { .. } => {},
+    }
+}
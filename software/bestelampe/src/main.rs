@@ -14,7 +14,8 @@
 // If not, see <https://www.gnu.org/licenses/>. 
 
 use std::thread;
-use std::sync::{Arc, RwLock};
+use std::time::Instant;
+use std::sync::{mpsc, Arc, Mutex, RwLock};
 
 use esp_idf_hal::gpio::DriveStrength;
 use esp_idf_hal::{
@@ -24,6 +25,7 @@ use esp_idf_hal::{
 
 use esp_idf_svc::{
     log::EspLogger,
+    nvs::EspDefaultNvsPartition,
     sntp,
 };
 
@@ -31,26 +33,59 @@ use ::function_name::named;
 use chrono_tz::Tz;
 use chrono::Utc;
 
+use abstraktelampe::alarm::SunriseAlarm;
+use abstraktelampe::ambient::{AmbientReading, ColorMatcher};
+use abstraktelampe::capture::CaptureBuffer;
+use abstraktelampe::channels::RawMode;
+use abstraktelampe::circadian::NaturalLight;
+use abstraktelampe::daylight::DaylightHarvester;
+use abstraktelampe::dmx::{DmxConfig, DmxReceiver, Protocol};
+use abstraktelampe::mqtt::MqttConfig;
+use abstraktelampe::corridor::Corridor;
+use abstraktelampe::occupancy::Occupancy;
+use abstraktelampe::presence::{MotionLevel, PresenceReading, SensorSlots};
+use abstraktelampe::push::ZoneOutput;
+use abstraktelampe::rules::{Event, RuleEngine};
+use abstraktelampe::scene::SceneStore;
+use abstraktelampe::schedule::{due_entries, ScheduleCursor, ScheduleEntry};
+use abstraktelampe::sun::Location;
+use abstraktelampe::zone::{ZoneConfig, Zones};
+
 use log::*;
+mod capture;
+use crate::capture::{uptime_ms, CAPTURE_CAPACITY};
+
 mod pwm;
 
 mod task;
 use crate::task::buttons::test_buttons;
-use crate::task::leds::test_leds;
+use crate::task::dmx::{run_dmx, LampDmx};
+use crate::task::leds::{test_leds, LampRawMode};
+use crate::task::mqtt::run_mqtt;
 use crate::task::ota::test_ota;
-use crate::task::presence::test_presence_sensor;
+use crate::task::presence::{test_presence_sensor, LampCorridor, LampOccupancy, RightSlot};
 use crate::task::server::run_server;
 use crate::task::thermal::test_thermal_sensor;
 use crate::task::wifi::start_wifi;
 use crate::task::light_sensor::test_light_sensor;
 use crate::task::i2c::test_i2c;
 use crate::task::uart::test_uart;
+use crate::task::rules::run_rules;
 
 extern crate ina219;
 
 mod config;
 use crate::config::CONFIG;
 
+mod lamp;
+use crate::lamp::ApiLamp;
+
+mod light;
+use crate::light::recall_scene;
+
+mod storage;
+use crate::storage::{Storage, KEY_ALARM, KEY_CIRCADIAN, KEY_COLOR_MATCHING, KEY_DAYLIGHT, KEY_RULES, KEY_SCENES, KEY_SCHEDULE, KEY_ZONES, KEY_OCCUPANCY, KEY_MQTT, KEY_DMX, KEY_CORRIDOR, KEY_SENSOR_SLOTS};
+
 mod prelude {
     pub use log::*;
     pub use ::function_name::named;
@@ -58,6 +93,14 @@ mod prelude {
     pub use anyhow::{ Result, anyhow };
 }
 
+/// The alarm updates its targets once per second, so the LED task needs to
+/// follow them within about that time to get a smooth sunrise.
+const ALARM_DIM_SPEED: f32 = 0.01;
+
+/// The natural light curve changes slowly, but when it takes over after a manual
+/// override, it should fade over several seconds instead of jumping.
+const NATURAL_LIGHT_DIM_SPEED: f32 = 0.002;
+
 #[named]
 fn main() -> ! {
     esp_idf_svc::sys::link_patches();
@@ -68,14 +111,71 @@ fn main() -> ! {
     let thermal: Arc<RwLock<f32>> = Arc::new(RwLock::new(0.0));
     let voltage: Arc<RwLock<f32>> = Arc::new(RwLock::new(0.0));
     let current: Arc<RwLock<f32>> = Arc::new(RwLock::new(0.0));
-    let light_temperature_target: Arc<RwLock<f32>> = Arc::new(RwLock::new(3000.0));
-    let light_brightness_target: Arc<RwLock<f32>> = Arc::new(RwLock::new(0.001));
-    let light_dim_speed: Arc<RwLock<f32>> = Arc::new(RwLock::new(0.01));
     let update_requested: Arc<RwLock<bool>> = Arc::new(RwLock::new(false));
     let time_offset: Arc<RwLock<i64>> = Arc::new(RwLock::new(0));
+    let ambient: Arc<RwLock<Option<AmbientReading>>> = Arc::new(RwLock::new(None));
+    let location: Arc<RwLock<Option<Location>>> = Arc::new(RwLock::new(
+        // 0.0, 0.0 is in the ocean, so nobody will actually configure that
+        if CONFIG.latitude != 0.0 || CONFIG.longitude != 0.0 {
+            Some(Location::new(CONFIG.latitude, CONFIG.longitude))
+        } else {
+            None
+        }
+    ));
     
     let peripherals: Peripherals = Peripherals::take().expect("Need Peripherals.");
 
+    // Persistent storage, shared with the wifi driver
+    let nvs_partition = EspDefaultNvsPartition::take().expect("Need NVS partition.");
+    let storage = Storage::new(nvs_partition.clone()).expect("Need NVS storage.");
+    let zone_config: ZoneConfig = storage.load_or_default(KEY_ZONES);
+    let zones: Arc<RwLock<Zones>> = Arc::new(RwLock::new(Zones::new(zone_config)));
+    let scenes: Arc<RwLock<SceneStore>> = Arc::new(RwLock::new(storage.load_or_default(KEY_SCENES)));
+    let schedule: Arc<RwLock<Vec<ScheduleEntry>>> = Arc::new(RwLock::new(storage.load_or_default(KEY_SCHEDULE)));
+    let alarm: Arc<RwLock<SunriseAlarm>> = Arc::new(RwLock::new(SunriseAlarm::new(storage.load_or_default(KEY_ALARM))));
+    let natural_light: Arc<RwLock<NaturalLight>> = Arc::new(RwLock::new(NaturalLight::new(storage.load_or_default(KEY_CIRCADIAN))));
+    let rules: Arc<RwLock<RuleEngine>> = Arc::new(RwLock::new(RuleEngine::new(storage.load_or_default(KEY_RULES))));
+    let daylight: Arc<RwLock<DaylightHarvester>> = Arc::new(RwLock::new(DaylightHarvester::new(storage.load_or_default(KEY_DAYLIGHT))));
+    let color_matcher: Arc<RwLock<ColorMatcher>> = Arc::new(RwLock::new(ColorMatcher::new(storage.load_or_default(KEY_COLOR_MATCHING))));
+    let occupancy: Arc<RwLock<LampOccupancy>> = Arc::new(RwLock::new(Occupancy::new(
+        storage.load_or_default(KEY_OCCUPANCY),
+        uptime_ms,
+    )));
+    // Somebody has just switched the lamp on, so the room counts as occupied
+    // until the hold time has passed without the sensors detecting anybody
+    occupancy.write().unwrap().update(&PresenceReading { presence: false, motion: MotionLevel::Active, distance: None, direction: None });
+    let corridor: Arc<RwLock<LampCorridor>> = Arc::new(RwLock::new(Corridor::new(
+        storage.load_or_default(KEY_CORRIDOR),
+        uptime_ms,
+    )));
+    let mqtt: Arc<RwLock<MqttConfig>> = Arc::new(RwLock::new(storage.load_or_default(KEY_MQTT)));
+    // Live control over Art-Net and sACN
+    let dmx_config: DmxConfig = storage.load_or_default(KEY_DMX);
+    // The footprint depends on the zones, which may have changed since the config was stored
+    let dmx_config = match dmx_config.validate(zones.read().unwrap().config()) {
+        Ok(()) => dmx_config,
+        Err(err) => {
+            error!(target: function_name!(), "Stored DMX config is invalid, using the default: {}", err);
+            DmxConfig::default()
+        },
+    };
+    let dmx: Arc<RwLock<LampDmx>> = Arc::new(RwLock::new(DmxReceiver::new(dmx_config, uptime_ms)));
+    // The sensor slots are only read here, so a new config takes effect after a restart
+    let sensor_slots: SensorSlots = storage.load_or_default(KEY_SENSOR_SLOTS);
+    let storage: Arc<Mutex<Storage>> = Arc::new(Mutex::new(storage));
+
+    // Duties of the output channels while they are controlled directly
+    let raw_mode: Arc<RwLock<LampRawMode>> = Arc::new(RwLock::new(RawMode::new(uptime_ms)));
+
+    // What the LEDs currently emit, for the live view of the web interface
+    let outputs: Arc<RwLock<Vec<ZoneOutput>>> = Arc::new(RwLock::new(Vec::new()));
+
+    // Raw serial data from the sensors, for debugging their protocols
+    let capture: Arc<Mutex<CaptureBuffer>> = Arc::new(Mutex::new(CaptureBuffer::new(CAPTURE_CAPACITY)));
+
+    // Events from sensors and buttons for the rule engine
+    let (event_sender, event_receiver) = mpsc::channel::<Event>();
+
 
     // I2C
     let pin_i2c_pwr : AnyIOPin = peripherals.pins.gpio20.into();
@@ -87,6 +187,10 @@ fn main() -> ! {
     let voltage_for_i2c = voltage.clone();
     let current_for_i2c = current.clone();
     let time_offset_for_i2c = time_offset.clone();
+    let ambient_for_i2c = ambient.clone();
+    let events_for_i2c = event_sender.clone();
+    // New readings for daylight harvesting and color matching, which must not see a reading twice
+    let (reading_sender, reading_receiver) = mpsc::channel::<AmbientReading>();
     let i2c = peripherals.i2c0;
     let _i2c_thread = thread::spawn(|| {
         test_i2c(
@@ -97,37 +201,51 @@ fn main() -> ! {
             voltage_for_i2c, 
             current_for_i2c,
             time_offset_for_i2c,
+            ambient_for_i2c,
+            reading_sender,
+            events_for_i2c,
         ).unwrap_or_default();
         error!(target: function_name!(), "I2C thread has ended :(");
     });
 
-    // // Serial / UART, configured for GPS time
-    // let time_offset_for_uart = time_offset.clone();
-    // let _uart_thread = thread::spawn(|| {
-    //     test_uart(
-    //         peripherals.pins.gpio8.into(),
-    //         peripherals.pins.gpio0.into(),
-    //         peripherals.uart1, time_offset_for_uart
-    //     ).unwrap_or_default();
-    //     error!(target: function_name!(), "UART thread has ended :(");
-    // });
+    // The right sensor slot shares its pins with the GPS module, so it is either
+    // read by the presence task or by the GPS task.
+    let right_slot: RightSlot = (peripherals.pins.gpio8.into(), peripherals.pins.gpio0.into(), peripherals.uart0);
+    let (right_sensor, gps) = match sensor_slots.right {
+        Some(_) => (Some(right_slot), None),
+        None => (None, Some(right_slot)),
+    };
+
+    // Serial / UART, configured for GPS time
+    if let Some((pin_rx, pin_tx, uart)) = gps {
+        let time_offset_for_uart = time_offset.clone();
+        let location_for_uart = location.clone();
+        let capture_for_uart = capture.clone();
+        let _uart_thread = thread::spawn(move || {
+            test_uart(pin_rx, pin_tx, uart, time_offset_for_uart, location_for_uart, capture_for_uart).unwrap_or_default();
+            error!(target: function_name!(), "UART thread has ended :(");
+        });
+    }
 
-    // // Light sensor
+    // // Light sensor, standalone. `test_i2c` owns I2C0 and reads the same sensor.
     // let i2c = peripherals.i2c0;
+    // let events_for_light_sensor = event_sender.clone();
     // let _light_sensor_thread = thread::spawn(|| {
-    //     test_light_sensor(i2c, peripherals.pins.gpio6.into(), peripherals.pins.gpio7.into()).unwrap_or_default();
+    //     test_light_sensor(i2c, peripherals.pins.gpio6.into(), peripherals.pins.gpio7.into(), events_for_light_sensor).unwrap_or_default();
     //     error!(target: function_name!(), "Light sensor has ended :(");
     // });
 
     // Buttons
-    // let light_brightness_target_clone_for_buttons = light_brightness_target.clone();
-    // let light_temperature_target_clone_for_buttons = light_temperature_target.clone();
-    // let light_dim_speed_for_buttons = light_dim_speed.clone();
+    // let zones_for_buttons = zones.clone();
+    // let scenes_for_buttons = scenes.clone();
+    // let alarm_for_buttons = alarm.clone();
+    // let natural_light_for_buttons = natural_light.clone();
+    // let events_for_buttons = event_sender.clone();
     // let _button_thread = thread::spawn(|| {
     //     let pin_a : AnyIOPin = peripherals.pins.gpio22.into();
     //     let pin_b : AnyIOPin = peripherals.pins.gpio23.into();
     //     let pin_c : AnyIOPin = peripherals.pins.gpio0.into();
-    //     test_buttons(pin_a, pin_b, pin_c, light_brightness_target_clone_for_buttons, light_temperature_target_clone_for_buttons, light_dim_speed_for_buttons).unwrap_or_default();
+    //     test_buttons(pin_a, pin_b, pin_c, zones_for_buttons, scenes_for_buttons, alarm_for_buttons, natural_light_for_buttons, events_for_buttons).unwrap_or_default();
     //     error!(target: function_name!(), "Button thread has ended :(");
     // });
 
@@ -140,20 +258,50 @@ fn main() -> ! {
     // To use the RMT periferal as a fake UART controller, we could use peripherals.rmt.channel2, // Only channel 2 and 3 can RX
 
     // // Presence sensor
-    let light_brightness_target_clone_for_presence = light_brightness_target.clone();
-    let _presence_thread = thread::spawn(|| {
+    let events_for_presence = event_sender.clone();
+    let capture_for_presence = capture.clone();
+    let occupancy_for_presence = occupancy.clone();
+    let corridor_for_presence = corridor.clone();
+    let _presence_thread = thread::spawn(move || {
         test_presence_sensor(
             peripherals.pins.gpio17.into(),
             peripherals.pins.gpio16.into(), 
             peripherals.uart1,
-            light_brightness_target_clone_for_presence).unwrap();
+            right_sensor,
+            sensor_slots,
+            events_for_presence,
+            occupancy_for_presence,
+            corridor_for_presence,
+            capture_for_presence).unwrap();
         warn!("Presence sensor thread has ended :(");
     });
+
+    // Rule engine
+    let rules_for_engine = rules.clone();
+    let zones_for_rules = zones.clone();
+    let scenes_for_rules = scenes.clone();
+    let natural_light_for_rules = natural_light.clone();
+    let _rules_thread = thread::spawn(|| {
+        if let Err(err) = run_rules(
+            event_receiver,
+            rules_for_engine,
+            zones_for_rules,
+            scenes_for_rules,
+            natural_light_for_rules,
+        ) {
+            error!(target: function_name!(), "Rule engine has ended: {:?}", err);
+        }
+    });
     
     // LED control
-    let light_temperature_target_clone = light_temperature_target.clone();
-    let light_brightness_target_clone = light_brightness_target.clone();
-    let light_dim_speed_clone = light_dim_speed.clone();
+    let zones_for_leds = zones.clone();
+    let occupancy_for_leds = occupancy.clone();
+    let corridor_for_leds = corridor.clone();
+    let raw_mode_for_leds = raw_mode.clone();
+    let dmx_for_leds = dmx.clone();
+    let thermal_for_leds = thermal.clone();
+    let outputs_for_leds = outputs.clone();
+    let outputs_for_main = outputs.clone();
     let _led_thread = thread::spawn(|| {
         let ledc = peripherals.ledc;
         let pin_r  : AnyIOPin = peripherals.pins.gpio22.into();  // LED 8
@@ -175,9 +323,13 @@ fn main() -> ! {
             pin_ww,
             pin_a,
             pin_pa,
-            light_temperature_target_clone,
-            light_brightness_target_clone,
-            light_dim_speed_clone
+            zones_for_leds,
+            occupancy_for_leds,
+            corridor_for_leds,
+            raw_mode_for_leds,
+            dmx_for_leds,
+            thermal_for_leds,
+            outputs_for_leds,
         ).expect("LEDs should just work.");
     });
 
@@ -190,12 +342,77 @@ fn main() -> ! {
     // });
 
     // Wifi & web interface server
-    let light_brightness_target_for_server = light_brightness_target.clone();
+    let zones_for_server = zones.clone();
+    let scenes_for_server = scenes.clone();
+    let schedule_for_server = schedule.clone();
+    let alarm_for_server = alarm.clone();
+    let natural_light_for_server = natural_light.clone();
+    let rules_for_server = rules.clone();
+    let daylight_for_server = daylight.clone();
+    let color_matcher_for_server = color_matcher.clone();
+    let occupancy_for_server = occupancy.clone();
+    let corridor_for_server = corridor.clone();
+    let location_for_server = location.clone();
+    let ambient_for_server = ambient.clone();
+    let raw_mode_for_server = raw_mode.clone();
+    let mqtt_for_server = mqtt.clone();
+    let dmx_for_server = dmx.clone();
+    let dmx_for_artnet = dmx.clone();
+    let dmx_for_sacn = dmx;
+    let lamp_for_mqtt = ApiLamp {
+        zones: zones.clone(),
+        natural_light: natural_light.clone(),
+        occupancy: occupancy.clone(),
+        corridor,
+        ambient: ambient.clone(),
+        thermal: thermal.clone(),
+        voltage: voltage.clone(),
+        current: current.clone(),
+    };
     let _wifi_thread = thread::spawn(|| {
-        start_wifi(peripherals.modem, CONFIG.wifi_ap_active).unwrap();
+        start_wifi(peripherals.modem, nvs_partition, CONFIG.wifi_ap_active).unwrap();
         let _sntp = sntp::EspSntp::new_default().unwrap();
         info!(target: function_name!(), "SNTP initialized");
-        run_server(light_temperature_target, light_brightness_target_for_server, light_dim_speed, update_requested, thermal, voltage, current).unwrap();
+        // Home automation, which needs the network
+        let _mqtt_thread = thread::spawn(|| {
+            if let Err(err) = run_mqtt(lamp_for_mqtt, mqtt) {
+                error!(target: function_name!(), "MQTT client has ended: {:?}", err);
+            }
+        });
+        let _artnet_thread = thread::spawn(|| {
+            if let Err(err) = run_dmx(dmx_for_artnet, Protocol::ArtNet) {
+                error!(target: function_name!(), "Art-Net receiver has ended: {:?}", err);
+            }
+        });
+        let _sacn_thread = thread::spawn(|| {
+            if let Err(err) = run_dmx(dmx_for_sacn, Protocol::Sacn) {
+                error!(target: function_name!(), "sACN receiver has ended: {:?}", err);
+            }
+        });
+        run_server(
+            zones_for_server,
+            update_requested,
+            thermal,
+            voltage,
+            current,
+            scenes_for_server,
+            schedule_for_server,
+            alarm_for_server,
+            natural_light_for_server,
+            rules_for_server,
+            daylight_for_server,
+            color_matcher_for_server,
+            occupancy_for_server,
+            corridor_for_server,
+            location_for_server,
+            ambient_for_server,
+            raw_mode_for_server,
+            outputs,
+            mqtt_for_server,
+            dmx_for_server,
+            capture,
+            storage,
+        ).unwrap();
     });
 
     let tz: Tz = CONFIG.time_zone.parse().unwrap();
@@ -208,17 +425,87 @@ fn main() -> ! {
     // Keep the main thread alive
     info!(target: function_name!(), "Entering infinite loop in main thread...");
     let mut count: u8 = 0;
+    let mut schedule_cursor = ScheduleCursor::new(Utc::now(), &tz);
+    let mut previous_sensor_update = Instant::now();
+    // Results of the latest sensor reading, which hold until the next one
+    let mut harvested_brightness: Option<f32> = None;
+    let mut matched_temperature: Option<f32> = None;
     loop {
         let now = Utc::now();
-        let local_now = now.with_timezone(&tz);
-        //trace!("Current time: {:?}", local_now);
+        //trace!("Current time: {:?}", now.with_timezone(&tz));
+
+        // Large jumps happen when the clock is set via SNTP. Don't fire everything
+        // that would have been due since 1970 in that case.
+        if let Some((previous, local)) = schedule_cursor.advance(now, &tz) {
+            let location = *location.read().unwrap();
+            for entry in due_entries(&schedule.read().unwrap(), previous, local, &tz, location.as_ref()) {
+                match scenes.read().unwrap().get(&entry.scene) {
+                    Some(scene) => {
+                        recall_scene(scene, &zones);
+                        // The scene should stay until the natural light takes over again
+                        natural_light.write().unwrap().override_manually(now);
+                    },
+                    None => warn!(target: function_name!(), "Scheduled scene '{}' does not exist.", entry.scene),
+                }
+            }
+        }
+
+        let alarm_output = alarm.write().unwrap().update(now, &tz);
+        let natural_output = natural_light.write().unwrap().update(now, &tz, location.read().unwrap().as_ref());
+        // The alarm and the natural light are for the whole room, so they control all zones.
+        if let Some(output) = &alarm_output {
+            zones.write().unwrap().update_all(|state| {
+                state.set_temperature(output.temperature);
+                state.brightness = output.brightness;
+                state.dim_speed = ALARM_DIM_SPEED;
+            });
+        } else if let Some(output) = &natural_output {
+            zones.write().unwrap().update_all(|state| {
+                state.set_temperature(output.temperature);
+                state.brightness = output.brightness;
+                state.dim_speed = NATURAL_LIGHT_DIM_SPEED;
+            });
+        }
+
+        // Daylight harvesting only takes care of the brightness, and color matching only
+        // of the color temperature, so both can be combined with the natural light.
+        // They also pause after manual changes. Readings arrive less often than this
+        // loop runs, so their results are applied in every iteration, after the
+        // natural light, which would otherwise take over between readings.
+        if let Some(reading) = reading_receiver.try_iter().last() {
+            let sensor_elapsed = previous_sensor_update.elapsed().as_secs_f32();
+            previous_sensor_update = Instant::now();
+            // The sensors only see the light of the primary zone, as it is emitted after
+            // fading, occupancy dimming and thermal limits.
+            let lamp_temperature = zones.read().unwrap().primary().temperature;
+            let lamp_brightness = outputs_for_main.read().unwrap().first()
+                .map_or(0.0, |output| output.brightness);
+
+            harvested_brightness = daylight.write().unwrap().update(reading.lux(), lamp_brightness, sensor_elapsed);
+            let fallback = natural_output.as_ref().map(|output| output.temperature);
+            matched_temperature = color_matcher.write().unwrap().update(&reading, lamp_brightness, lamp_temperature, sensor_elapsed, fallback);
+        }
+        let paused = alarm_output.is_some() || natural_light.read().unwrap().is_overridden(now);
+        if !paused && (harvested_brightness.is_some() || matched_temperature.is_some()) {
+            zones.write().unwrap().update_all(|state| {
+                if let Some(brightness) = harvested_brightness {
+                    state.brightness = brightness;
+                    state.dim_speed = NATURAL_LIGHT_DIM_SPEED;
+                }
+                if let Some(temperature) = matched_temperature {
+                    state.set_temperature(temperature);
+                    state.dim_speed = NATURAL_LIGHT_DIM_SPEED;
+                }
+            });
+        }
+
         std::thread::sleep(core::time::Duration::from_millis(1000));
         //info!(target: function_name!(), "LED enable / alert : {:?}", led_en.get_level());
         if(count < 10) {
             count += 1;
             if (count == 4) {
                 led_en.set_low(); // low = enabled    
-                *light_brightness_target.write().unwrap() = 0.0;
+                zones.write().unwrap().update_all(|state| state.brightness = 0.0);
             }
         }
     }
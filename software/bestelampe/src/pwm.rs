@@ -49,6 +49,14 @@ impl XyColor {
 		return XyColor{x, y};
 	}
 
+	pub fn x(&self) -> f32 {
+		return self.x;
+	}
+
+	pub fn y(&self) -> f32 {
+		return self.y;
+	}
+
 	/// Combine the xy value with a Y value for brightness in the xyY color space 
 	/// and convert the result to a color in the XYZ color space.
 	#[allow(non_snake_case)]
@@ -59,6 +67,14 @@ impl XyColor {
 	
 		return Xyz::new(X, Y, Z);
 	}
+
+	/// Linear interpolation on the xy chromaticity diagram.
+	pub fn lerp(&self, other: &XyColor, factor: f32) -> XyColor {
+		return XyColor {
+			x: self.x + (other.x - self.x) * factor,
+			y: self.y + (other.y - self.y) * factor,
+		};
+	}
 }
 
 pub struct Led<'p> {
@@ -67,11 +83,11 @@ pub struct Led<'p> {
 	xy_color: XyColor,
 	//xyz_color: Xyz<f32>,
 	max_brightness: f32,
-	name: &'p str,
+	name: String,
 }
 
 impl<'p> Led<'p> {
-	pub fn new(index: usize, name: &'p str, driver: LedcDriver<'p>, x: f32, y: f32, max_brightness: f32) -> Self {
+	pub fn new(index: usize, name: String, driver: LedcDriver<'p>, x: f32, y: f32, max_brightness: f32) -> Self {
 		let xy_color = XyColor {x, y};
 		//let xyz_color = xy_color.with_brightness(max_brightness);
 		return Self { index, name, driver, xy_color, max_brightness };
@@ -111,60 +127,15 @@ pub struct Pwm<'p> {
 }
 
 impl<'p> Pwm<'p> {
-	/// create a Pwm object with 6 LED driver channels
-	pub fn new(
-		driver_0: LedcDriver<'p>,
-		driver_1: LedcDriver<'p>,
-		driver_2: LedcDriver<'p>,
-		driver_3: LedcDriver<'p>,
-		driver_4: LedcDriver<'p>,
-		driver_5: LedcDriver<'p>,
-	) -> Result<Self, EspError> {
-
-
-
-		// let channel = self.get_channel_by_name(channel_name);
-
-
-		// TODO: For some advanced features I'd need to re-assign a LED to another driver
-		// but keep it on the same pin, or configure it as `off`. So if I have at most
-		// 4 LEDs active at all times, I could use up to 2 drivers for non-LED pins.
-
-		// with these 5 leds, only temperatures from 2150 to 6800 can be mapped
-
-		let led_r  = Rc::new(RefCell::new(Led::new(0, "R", driver_0, 0.6400, 0.3500, 165.0)));
-		let led_g  = Rc::new(RefCell::new(Led::new(1, "G", driver_1, 0.4070, 0.5370, 460.0)));
-		let led_b  = Rc::new(RefCell::new(Led::new(2, "B", driver_2, 0.1470, 0.1100, 130.0)));
-		let led_cw = Rc::new(RefCell::new(Led::new(3,"CW", driver_3, 0.3447, 0.3553, 310.0)));
-		//let led_ww = Rc::new(RefCell::new(Led::new(4,"WW", driver_4, 0.4334, 0.4030, 220.0)));
-		let led_ww = Rc::new(RefCell::new(Led::new(4,"WW", driver_4, 0.5066, 0.4158, 170.0)));
-		let led_pa  = Rc::new(RefCell::new(Led::new(5, "PA", driver_5, 0.5650, 0.4250, 230.0)));
-
-		let leds: Vec<Rc<RefCell<Led<'_>>>> = [
-			led_r.clone(),
-			led_g.clone(),
-			led_b.clone(),
-			led_cw.clone(),
-			led_ww.clone(),
-			led_pa.clone(),
-		].to_vec();
-
-		// TODO: implement triangulation for an arbitrary number of LED channels 
-		// (given as xy color). For now, these are just hardcoded values for the first prototype.
-		let t0 = LedTriangle::new(led_r.clone(), led_pa.clone() , led_ww.clone());
-		let t1 = LedTriangle::new(led_g.clone(), led_pa.clone() , led_ww.clone());
-		let t2 = LedTriangle::new(led_r.clone(), led_cw.clone(), led_ww.clone());
-		let t3 = LedTriangle::new(led_g.clone(), led_cw.clone(), led_ww.clone());
-		let t4 = LedTriangle::new(led_r.clone(), led_cw.clone(), led_b.clone() );
-		let t5 = LedTriangle::new(led_g.clone(), led_cw.clone(), led_b.clone() );	
-
-		let triangles = [
-			//t1, t3, t5
-			t0, t1, t2, t3, t4, t5
-		].to_vec();
-	
+	/// Create a Pwm object for the LEDs of a zone. `triangles` are indices into `leds`,
+	/// as returned by `Zone::triangles`.
+	pub fn new(leds: Vec<Led<'p>>, triangles: &[[usize; 3]]) -> Result<Self, EspError> {
+		let leds: Vec<Rc<RefCell<Led<'_>>>> = leds.into_iter().map(|led| Rc::new(RefCell::new(led))).collect();
+		let triangles = triangles.iter()
+			.map(|[a, b, c]| LedTriangle::new(leds[*a].clone(), leds[*b].clone(), leds[*c].clone()))
+			.collect();
+
 		return Ok(Self {
-			//timer_driver,
 			leds,
 			triangles,
 			gamma: 2.0
@@ -172,20 +143,10 @@ impl<'p> Pwm<'p> {
 	}
 
 	pub fn report(&self) {
-		let l_0 = self.leds[0].borrow();
-		let l_1 = self.leds[1].borrow();
-		let l_2 = self.leds[2].borrow();
-		let l_3 = self.leds[3].borrow();
-		let l_4 = self.leds[4].borrow();
-		let l_5 = self.leds[5].borrow();
-		info!("{}: {}, {}: {}, {}: {}, {}: {}, {}: {}, {}: {}, ",
-			l_0.name, l_0.driver.get_duty(),
-			l_1.name, l_1.driver.get_duty(),
-			l_2.name, l_2.driver.get_duty(),
-			l_3.name, l_3.driver.get_duty(),
-			l_4.name, l_4.driver.get_duty(),
-			l_5.name, l_5.driver.get_duty(),
-		);
+		let duties: Vec<String> = self.leds.iter()
+			.map(|led| { let led = led.borrow(); format!("{}: {}", led.name, led.driver.get_duty()) })
+			.collect();
+		info!("{}", duties.join(", "));
 	}
 
 	fn gamma_correct(&self, brightness: f32) -> f32 {
@@ -334,9 +295,10 @@ impl<'p> Pwm<'p> {
 		//info!("D: {:?}", all_duties);
 
 		// Turn off all LEDs that were not turned on just now.
-		for i in 0..5 {
-			if !active_leds.contains(&i) {
-				self.leds[i].borrow_mut().driver.set_duty(0)?;
+		for led in &self.leds {
+			let mut led = led.borrow_mut();
+			if !active_leds.contains(&led.index) {
+				led.driver.set_duty(0)?;
 			}
 		}
 
@@ -363,21 +325,48 @@ impl<'p> Pwm<'p> {
 		return self.set_color(target_xyz);
 	}
 
+	/// Set the LEDs to light with a given chromaticity and brightness.
+	pub fn set_xy_and_brightness(
+		self: &mut Self,
+		xy: &XyColor,
+		brightness: f32,
+	) -> Result<(), EspError> {
+		let target_xyz: Xyz<f32> = xy.with_brightness(self.gamma_correct(brightness));
+		return self.set_color(target_xyz);
+	}
+
+	/// Turns on only the LED named "PA" (phosphor converted amber), if the zone has one.
 	pub fn set_amber(self: &mut Self, brightness_up_to_one: f32) -> Result<(), EspError> {
-		for i in 0..6 {
-			self.leds[i].borrow_mut().driver.set_duty(0)?;
+		for led in &self.leds {
+			let mut led = led.borrow_mut();
+			let duty = if led.name == "PA" { (led.driver.get_max_duty() as f32 * brightness_up_to_one) as u32 } else { 0 };
+			led.driver.set_duty(duty)?;
 		}
-		let amber_driver = &mut self.leds[5].borrow_mut().driver;
-		amber_driver.set_duty((amber_driver.get_max_duty() as f32 * brightness_up_to_one) as u32)?;
 		Ok(())
 	}
 
-	pub fn set_duties(self: &mut Self, brightness_up_to_one: &Vec<f32>) -> Result<(), EspError> {
-		info!("Set duties: {:?}", brightness_up_to_one);
-		for i in 0..6 {
-			let driver = &mut self.leds[i].borrow_mut().driver;
-			driver.set_duty((driver.get_max_duty() as f32 * brightness_up_to_one[i]) as u32)?;
+	/// Sets the duty of each LED directly, bypassing the color pipeline. `duties`
+	/// are relative to the maximum duty, and indexed by output channel.
+	/// Returns the brightness and chromaticity which the LEDs emit, as the inverse
+	/// of `set_color`. The chromaticity is `None` while all LEDs are off.
+	pub fn set_duties(self: &mut Self, duties: &[f32]) -> Result<(f32, Option<XyColor>), EspError> {
+		let mut luminance = 0.0;
+		let mut x = 0.0;
+		let mut y = 0.0;
+		for led in &self.leds {
+			let mut led = led.borrow_mut();
+			let duty = duties.get(led.index).copied().unwrap_or(0.0);
+			let max_duty = led.driver.get_max_duty();
+			led.driver.set_duty((max_duty as f32 * duty) as u32)?;
+
+			let share = duty * led.max_brightness;
+			luminance += share;
+			x += share * led.xy_color.x;
+			y += share * led.xy_color.y;
 		}
-		Ok(())
+		if luminance <= 0.0 {
+			return Ok((0.0, None));
+		}
+		return Ok((luminance.powf(1.0 / self.gamma), Some(XyColor { x: x / luminance, y: y / luminance })));
 	}
 }
\ No newline at end of file
@@ -0,0 +1,86 @@
+// SPDX-FileCopyrightText: 2024 Lena Schimmel <mail@lenaschimmel.de>
+// SPDX-License-Identifier: CERN-OHL-S-2.0+
+// This file is part of besteLampe!.
+// 
+// besteLampe! is free software: you can redistribute it and/or modify it under the
+// terms of the GNU General Public License as published by the Free Software Foundation, 
+// either version 3 of the License, or (at your option) any later version.
+// 
+// besteLampe! is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; 
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. 
+// See the GNU General Public License for more details.
+// 
+// You should have received a copy of the GNU General Public License along with besteLampe!.
+// If not, see <https://www.gnu.org/licenses/>. 
+
+use crate::prelude::*;
+
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use serde::{de::DeserializeOwned, Serialize};
+
+const NAMESPACE: &str = "bestelampe";
+
+/// Largest value we store in a single NVS entry. NVS itself allows a bit more
+/// for blobs, but this is plenty for our JSON and keeps the read buffer small.
+const MAX_VALUE_LEN: usize = 4000;
+
+// NVS keys must not be longer than 15 characters.
+pub const KEY_SCENES: &str = "scenes";
+pub const KEY_SCHEDULE: &str = "schedule";
+pub const KEY_ALARM: &str = "alarm";
+pub const KEY_CIRCADIAN: &str = "circadian";
+pub const KEY_RULES: &str = "rules";
+pub const KEY_DAYLIGHT: &str = "daylight";
+pub const KEY_COLOR_MATCHING: &str = "color_matching";
+pub const KEY_ZONES: &str = "zones";
+pub const KEY_OCCUPANCY: &str = "occupancy";
+pub const KEY_MQTT: &str = "mqtt";
+pub const KEY_DMX: &str = "dmx";
+pub const KEY_CORRIDOR: &str = "corridor";
+pub const KEY_SENSOR_SLOTS: &str = "sensor_slots";
+
+/// Persistent key-value storage in the NVS partition of the flash.
+/// Values are stored as JSON, so that the layout of the stored structs can
+/// evolve with `#[serde(default)]` instead of needing a migration.
+pub struct Storage {
+    nvs: EspNvs<NvsDefault>,
+}
+
+impl Storage {
+    pub fn new(partition: EspDefaultNvsPartition) -> Result<Self> {
+        let nvs = EspNvs::new(partition, NAMESPACE, true)?;
+        return Ok(Self { nvs });
+    }
+
+    /// Returns `Ok(None)` if nothing was stored under that key yet.
+    #[named]
+    pub fn load<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>> {
+        let mut buf = vec![0u8; MAX_VALUE_LEN];
+        match self.nvs.get_raw(key, &mut buf)? {
+            Some(data) => {
+                debug!(target: function_name!(), "Loaded {} bytes for key '{}'.", data.len(), key);
+                return Ok(Some(serde_json::from_slice(data)?));
+            },
+            None => return Ok(None),
+        }
+    }
+
+    /// Falls back to the default if nothing was stored yet, or if the stored
+    /// value can't be read, e.g. after an incompatible change of its layout.
+    #[named]
+    pub fn load_or_default<T: DeserializeOwned + Default>(&self, key: &str) -> T {
+        return self.load(key).unwrap_or_else(|err| {
+            error!(target: function_name!(), "Could not load the value for key '{}': {:?}", key, err);
+            None
+        }).unwrap_or_default();
+    }
+
+    pub fn save<T: Serialize>(&mut self, key: &str, value: &T) -> Result<()> {
+        let data = serde_json::to_vec(value)?;
+        if data.len() > MAX_VALUE_LEN {
+            return Err(anyhow!("Value for key '{}' is too big: {} bytes", key, data.len()));
+        }
+        self.nvs.set_raw(key, &data)?;
+        return Ok(());
+    }
+}
@@ -15,16 +15,34 @@
 
 use crate::prelude::*;
 use std::sync::{Arc, RwLock};
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
 use esp_idf_hal::gpio::{AnyIOPin, PinDriver};
+use chrono::Utc;
+
+use abstraktelampe::alarm::SunriseAlarm;
+use abstraktelampe::circadian::NaturalLight;
+use abstraktelampe::rules::{Button, Event};
+use abstraktelampe::scene::SceneStore;
+use abstraktelampe::zone::Zones;
+
+use crate::light::recall_scene;
+
+/// Releasing button B after holding it at least this long recalls the first
+/// scene, or stops a running alarm. Shorter presses step through the scenes,
+/// or snooze a running alarm.
+const LONG_PRESS: Duration = Duration::from_millis(800);
 
 #[named]
 pub fn test_buttons(
     pin_a: AnyIOPin, 
     pin_b: AnyIOPin,
     pin_c: AnyIOPin,
-    light_brightness_target: Arc<RwLock<f32>>,
-    light_temperature_target: Arc<RwLock<f32>>,
-    light_dim_speed:  Arc<RwLock<f32>>,
+    zones: Arc<RwLock<Zones>>,
+    scenes: Arc<RwLock<SceneStore>>,
+    alarm: Arc<RwLock<SunriseAlarm>>,
+    natural_light: Arc<RwLock<NaturalLight>>,
+    events: Sender<Event>,
 ) -> Result<()> 
     {
     let in_a = PinDriver::input(pin_a)?;
@@ -34,28 +52,88 @@ pub fn test_buttons(
     let mut temperature_index = 0;
 
     let temperatures: [f32; 8] = [1050.0, 1700.0, 2300.0, 2700.0, 3500.0, 5700.0, 10_000.0, 20_000.0];
-    let mut b_high_before: bool = false;
+    let mut b_pressed_since: Option<Instant> = None;
+    let mut current_scene: Option<String> = None;
+    let mut a_was_high = false;
+    let mut c_was_high = false;
 
     loop {
+        // Rules only see the moment a button is pressed, not the dimming while it is held.
+        if in_a.is_high() && !a_was_high {
+            events.send(Event::Button { button: Button::A, long: false })?;
+        }
+        if in_c.is_high() && !c_was_high {
+            events.send(Event::Button { button: Button::C, long: false })?;
+        }
+        a_was_high = in_a.is_high();
+        c_was_high = in_c.is_high();
+
+        // Dimming manually means that someone is awake, so the alarm is no longer needed.
+        if (in_a.is_high() || in_c.is_high()) && alarm.read().unwrap().is_active() {
+            info!(target: function_name!(), "Stopping alarm because of manual dimming.");
+            alarm.write().unwrap().stop();
+        }
+        if in_a.is_high() || in_c.is_high() {
+            natural_light.write().unwrap().override_manually(Utc::now());
+        }
+
+        // The buttons control all zones at once, each relative to its own brightness.
         if in_a.is_high() {
-            let brightness = *light_brightness_target.read().unwrap();
-            let new_brightness = f32::min(30.0, brightness + 0.2);
-            *light_brightness_target.write().unwrap() = new_brightness;
-            *light_dim_speed.write().unwrap() = 0.1;
-            debug!(target: function_name!(), "Touch-dim to {}", new_brightness);
+            let mut zones = zones.write().unwrap();
+            zones.update_all(|state| {
+                state.brightness = f32::min(30.0, state.brightness + 0.2);
+                state.dim_speed = 0.1;
+            });
+            debug!(target: function_name!(), "Touch-dim to {}", zones.primary().brightness);
         } else if in_c.is_high() {
-            let brightness = *light_brightness_target.read().unwrap();
-            let new_brightness = f32::max(0.0, brightness - 0.2);
-            *light_brightness_target.write().unwrap() = new_brightness;
-            *light_dim_speed.write().unwrap() = 0.1;
-            debug!(target: function_name!(), "Touch-dim to {}", new_brightness);
-        } else if in_b.is_high() && !b_high_before {
-            temperature_index = (temperature_index + 1) % temperatures.len();
-            *light_temperature_target.write().unwrap() = temperatures[temperature_index];
-            debug!(target: function_name!(), "Touch-temperatrue to {}", temperatures[temperature_index]);
-            *light_dim_speed.write().unwrap() = 0.1;
+            let mut zones = zones.write().unwrap();
+            zones.update_all(|state| {
+                state.brightness = f32::max(0.0, state.brightness - 0.2);
+                state.dim_speed = 0.1;
+            });
+            debug!(target: function_name!(), "Touch-dim to {}", zones.primary().brightness);
+        } else if in_b.is_high() && b_pressed_since.is_none() {
+            b_pressed_since = Some(Instant::now());
+        }
+
+        if in_b.is_low() {
+            if let Some(pressed_since) = b_pressed_since.take() {
+                let long_press = pressed_since.elapsed() >= LONG_PRESS;
+                events.send(Event::Button { button: Button::B, long: long_press })?;
+                let alarm_active = alarm.read().unwrap().is_active();
+                if alarm_active && long_press {
+                    info!(target: function_name!(), "Alarm stopped.");
+                    alarm.write().unwrap().stop();
+                } else if alarm_active {
+                    info!(target: function_name!(), "Alarm snoozed.");
+                    alarm.write().unwrap().snooze(Utc::now());
+                } else {
+                    natural_light.write().unwrap().override_manually(Utc::now());
+                    let scenes = scenes.read().unwrap();
+                    let scene = if long_press {
+                        scenes.list().first()
+                    } else {
+                        scenes.next(current_scene.as_deref())
+                    };
+                    match scene {
+                        Some(scene) => {
+                            debug!(target: function_name!(), "Touch-scene to {}", scene.name);
+                            recall_scene(scene, &zones);
+                            current_scene = Some(scene.name.clone());
+                        },
+                        None => {
+                            // Without any stored scenes, step through some color temperatures instead.
+                            temperature_index = (temperature_index + 1) % temperatures.len();
+                            zones.write().unwrap().update_all(|state| {
+                                state.set_temperature(temperatures[temperature_index]);
+                                state.dim_speed = 0.1;
+                            });
+                            debug!(target: function_name!(), "Touch-temperatrue to {}", temperatures[temperature_index]);
+                        },
+                    }
+                }
+            }
         }
-        b_high_before = in_b.is_high();
         //println!("Buttons: {}, {}, {}", in_a.is_high(), in_b.is_high(), in_c.is_high());
         std::thread::sleep(core::time::Duration::from_millis(100));
     }
@@ -0,0 +1,93 @@
+// SPDX-FileCopyrightText: 2024 Lena Schimmel <mail@lenaschimmel.de>
+// SPDX-License-Identifier: CERN-OHL-S-2.0+
+// This file is part of besteLampe!.
+// 
+// besteLampe! is free software: you can redistribute it and/or modify it under the
+// terms of the GNU General Public License as published by the Free Software Foundation, 
+// either version 3 of the License, or (at your option) any later version.
+// 
+// besteLampe! is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; 
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. 
+// See the GNU General Public License for more details.
+// 
+// You should have received a copy of the GNU General Public License along with besteLampe!.
+// If not, see <https://www.gnu.org/licenses/>. 
+
+use crate::prelude::*;
+
+use std::io::ErrorKind;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use abstraktelampe::dmx::{parse, DmxError, DmxReceiver, Protocol, ARTNET_PORT, SACN_PORT};
+
+pub type LampDmx = DmxReceiver<fn() -> u64>;
+
+/// Largest packet of either protocol, which is an sACN packet with 512 slots.
+const MAX_PACKET: usize = 638;
+
+/// Interval in which the task checks for config changes while no packets arrive.
+const CONFIG_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Receives the packets of one protocol and passes them to `dmx`, which decides
+/// whether they are used.
+#[named]
+pub fn run_dmx(dmx: Arc<RwLock<LampDmx>>, protocol: Protocol) -> Result<()> {
+    let port = match protocol {
+        Protocol::ArtNet => ARTNET_PORT,
+        Protocol::Sacn => SACN_PORT,
+    };
+    let socket = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, port))?;
+    socket.set_read_timeout(Some(CONFIG_INTERVAL))?;
+    info!(target: function_name!(), "Listening for {:?} on port {}.", protocol, port);
+
+    let mut buf = [0; MAX_PACKET];
+    let mut joined: Option<u16> = None;
+    loop {
+        let (enabled, universe) = {
+            let dmx = dmx.read().unwrap();
+            (dmx.config().enabled, dmx.config().universe)
+        };
+
+        // sACN is sent to a multicast group per universe, Art-Net is broadcast or unicast
+        if protocol == Protocol::Sacn {
+            let wanted = if enabled { Some(universe) } else { None };
+            if joined != wanted {
+                if let Some(universe) = joined {
+                    socket.leave_multicast_v4(&multicast_group(universe), &Ipv4Addr::UNSPECIFIED)?;
+                }
+                if let Some(universe) = wanted {
+                    socket.join_multicast_v4(&multicast_group(universe), &Ipv4Addr::UNSPECIFIED)?;
+                    info!(target: function_name!(), "Joined multicast group of sACN universe {}.", universe);
+                }
+                joined = wanted;
+            }
+        }
+
+        let (len, sender) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => continue,
+            Err(err) => return Err(err.into()),
+        };
+        let SocketAddr::V4(sender) = sender else {
+            continue;
+        };
+        if !enabled {
+            continue;
+        }
+        match parse(&buf[..len]) {
+            Ok(packet) if packet.protocol == protocol => {
+                dmx.write().unwrap().receive(&packet, sender.ip().octets());
+            },
+            // Art-Net polls and sACN discovery are normal, and nothing to worry about
+            Ok(_) | Err(DmxError::UnsupportedPacket) => {},
+            Err(err) => debug!(target: function_name!(), "Ignoring packet from {}: {}", sender, err),
+        }
+    }
+}
+
+fn multicast_group(universe: u16) -> Ipv4Addr {
+    let [high, low] = universe.to_be_bytes();
+    return Ipv4Addr::new(239, 255, high, low);
+}
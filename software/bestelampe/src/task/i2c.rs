@@ -16,6 +16,7 @@
 use std::{error::Error, thread};
 use std::cell::RefCell;
 use std::sync::{Arc, RwLock};
+use std::sync::mpsc::Sender;
 
 use crate::prelude::*;
 
@@ -55,6 +56,8 @@ use chrono::Utc;
 use chrono::LocalResult::Single;
 
 use veml6040::wrapper::AutoVeml6040;
+use abstraktelampe::ambient::AmbientReading;
+use abstraktelampe::rules::Event;
 
 #[named]
 pub fn test_i2c(
@@ -65,6 +68,9 @@ pub fn test_i2c(
     voltage: Arc<RwLock<f32>>, 
     current: Arc<RwLock<f32>>,
     time_offset: Arc<RwLock<i64>>,
+    ambient: Arc<RwLock<Option<AmbientReading>>>,
+    readings: Sender<AmbientReading>,
+    events: Sender<Event>,
 ) -> Result<(), Box<dyn Error>> {
     let config = I2cConfig::new().baudrate(100.kHz().into()).scl_enable_pullup(true).sda_enable_pullup(true);
     let mut i2c = I2cDriver::new(i2c, sda, scl, &config)?;
@@ -179,7 +185,7 @@ pub fn test_i2c(
     info!(target: function_name!(), "5V port expander is initialized.");
     fx_5_6.set_low().unwrap(); // power for left presence sensor, inverted
     fx_5_7.set_low().unwrap(); // power for right presence sensor, inverted
-    info!(target: function_name!(), "Enabled power supply for right presence sensor (actually GPS), and left presence sensor");
+    info!(target: function_name!(), "Enabled power supply for the left and right presence sensor");
 
 
     info!(target: function_name!(), "Creating the VEML device...");
@@ -305,6 +311,7 @@ pub fn test_i2c(
         if let Some(measure) = ina_measurement_power_30 {
             let mut power_sum = (measure.bus_voltage.voltage_mv() as f32 / 1000.0) * (measure.current.0 as f32 / 1_000_000.0);
             let mut voltage_sum = (measure.bus_voltage.voltage_mv() as f32 / 1000.0);
+            let mut current_sum = measure.current.0 as f32 / 1_000_000.0;
             let mut power_cnt = 1u8;
             // This sensor is availble in general, now try to make 5 measurements
             for i in 0..5 {
@@ -312,6 +319,7 @@ pub fn test_i2c(
                 if let Some(loop_measure) = ina_measurement_power_30 {
                     power_sum += (loop_measure.bus_voltage.voltage_mv() as f32 / 1000.0) * (loop_measure.current.0 as f32 / 1_000_000.0);
                     voltage_sum += (loop_measure.bus_voltage.voltage_mv() as f32 / 1000.0);
+                    current_sum += loop_measure.current.0 as f32 / 1_000_000.0;
                     power_cnt += 1u8;
                 }
             }
@@ -319,18 +327,16 @@ pub fn test_i2c(
             let text_cnt = format!("AC: {:.02}W, {:.02}V ({} S.)", power_sum / (power_cnt as f32), voltage_sum / (power_cnt as f32), power_cnt);
             info!(target: function_name!(), "Power - {}", text_cnt);
 
+            // For the telemetry, which computes the power from these
+            *voltage.write().unwrap() = voltage_sum / (power_cnt as f32);
+            *current.write().unwrap() = current_sum / (power_cnt as f32);
+
             //let text_pwr = Text::with_baseline(text_cnt.as_str(), Point::new(0, 40), text_style, Baseline::Top);
             //text_pwr.draw(&mut display).unwrap();
         }
 
         //println!("Ina: {:#?}", ina_measurement);
 
-        // let mut voltage_write = voltage.write().unwrap();
-        // *voltage_write = (ina_measurement.unwrap().bus_voltage.voltage_mv() as f32) / 1000.0;
-
-        // let mut current_write = current.write().unwrap();
-        // *current_write = ina_measurement.unwrap().current.0 as f32 / 1_000_000.0;
-
         // voltage = ina_measurement.shunt_voltage.voltage_mv();
         // current = ina_measurement.current
         // info!(target: function_name!(), "Voltage: {} V, Current: {} A, Power: {} W", voltage, current, voltage * current);
@@ -356,8 +362,19 @@ pub fn test_i2c(
                 // from the datasheet or some official example. Anyway, I should try using `measurement.white``
                 // instead at some time.
                 info!(target: function_name!(), "Brightness: {} lx, color temperature: {} K", measurement.green, cct);
+                let reading = AmbientReading {
+                    red: measurement.red as f32,
+                    green: measurement.green as f32,
+                    blue: measurement.blue as f32,
+                };
+                *ambient.write().unwrap() = Some(reading.clone());
+                // For the rules. The receivers only go away when the rule engine or the
+                // main loop have ended, which must not stop the thermal readings.
+                let _ = events.send(Event::AmbientLight(reading.lux()));
+                let _ = readings.send(reading);
             }
             Err(err) => {
+                *ambient.write().unwrap() = None;
                 warn!(target: function_name!(), "Error in sensor loop iteration: {:?}", err);
                 std::thread::sleep(core::time::Duration::from_millis(750));
             }
@@ -16,6 +16,7 @@
 use crate::prelude::*;
 
 use std::sync::{Arc, RwLock};
+use std::time::Instant;
 
 use esp_idf_hal::{
     gpio::{AnyIOPin, PinDriver}, ledc::{config::TimerConfig, LedcDriver, LedcTimerDriver, LEDC}, prelude::*
@@ -23,7 +24,100 @@ use esp_idf_hal::{
 
 use prisma::Lerp;
 
-use crate::pwm::Pwm;
+use abstraktelampe::channels::{OutputLimits, RawMode};
+use abstraktelampe::color;
+use abstraktelampe::dmx::DmxOutput;
+use abstraktelampe::effect::{Effect, EffectConfig};
+use abstraktelampe::push;
+use abstraktelampe::scene::dim_speed;
+use abstraktelampe::zone::{LightState, Zones};
+
+use crate::pwm::{Led, Pwm, XyColor};
+use crate::task::dmx::LampDmx;
+use crate::task::presence::{LampCorridor, LampOccupancy};
+
+/// Interval in which the LED task updates the PWM duties. Fade speeds are given per tick.
+pub const TICK: core::time::Duration = core::time::Duration::from_millis(5);
+
+/// How long it takes to follow a change of the occupancy brightness, in seconds.
+const OCCUPANCY_TRANSITION: f32 = 3.0;
+
+pub type LampRawMode = RawMode<fn() -> u64>;
+
+/// The LEDs of a zone, and how far they have faded towards the zone's targets.
+struct ZoneOutput<'p> {
+    pwm: Pwm<'p>,
+    brightness: f32,
+    temperature: f32,
+    xy: XyColor,
+    effect: Option<EffectConfig>,
+    effect_started: Instant,
+}
+
+impl<'p> ZoneOutput<'p> {
+    fn new(pwm: Pwm<'p>, state: &LightState) -> Result<Self> {
+        return Ok(Self {
+            pwm,
+            brightness: state.brightness,
+            temperature: state.temperature,
+            xy: Pwm::temperature_to_xy(state.temperature)?,
+            effect: None,
+            effect_started: Instant::now(),
+        });
+    }
+
+    /// Fades towards `state`. The brightness is scaled by `dimming`, e.g. while
+    /// the room is left. Returns the brightness and color that the LEDs emit.
+    #[named]
+    fn update(&mut self, state: LightState, dimming: f32) -> Result<(f32, XyColor)> {
+        self.brightness = self.brightness.lerp(&state.brightness, state.dim_speed);
+        self.temperature = self.temperature.lerp(&state.temperature, state.dim_speed);
+
+        // Color temperatures are faded along the Planckian locus. Free xy colors,
+        // and changes between both kinds of targets, are faded in a straight
+        // line on the xy diagram.
+        let target_xy = match state.xy {
+            Some(target) => XyColor::new(target.x, target.y),
+            None => Pwm::temperature_to_xy(self.temperature)?,
+        };
+        self.xy = self.xy.lerp(&target_xy, state.dim_speed);
+
+        // Effects are rendered on top of the faded state, so that e.g. a candle
+        // keeps flickering while the scene below it changes.
+        if state.effect != self.effect {
+            info!(target: function_name!(), "Effect changed to {:?}", state.effect);
+            self.effect = state.effect;
+            self.effect_started = Instant::now();
+        }
+        let (brightness, xy) = match &self.effect {
+            Some(effect) => {
+                let output = effect.render(self.effect_started.elapsed().as_secs_f32());
+                let effect_xy = match output.color {
+                    Some(color) => XyColor::new(color.x, color.y),
+                    None => self.xy.clone(),
+                };
+                (self.brightness * output.brightness * dimming, effect_xy)
+            },
+            None => (self.brightness * dimming, self.xy.clone()),
+        };
+        self.pwm.set_xy_and_brightness(&xy, brightness)?;
+        return Ok((brightness, xy));
+    }
+}
+
+/// Drives all zones with raw duties, bypassing their color pipelines, and
+/// reports what they emit. A zone whose LEDs are all off keeps its last color.
+fn set_duties(outputs: &mut [ZoneOutput<'_>], duties: &[f32], emitted: &RwLock<Vec<push::ZoneOutput>>) -> Result<()> {
+    for (index, output) in outputs.iter_mut().enumerate() {
+        let (brightness, xy) = output.pwm.set_duties(duties)?;
+        let current = &mut emitted.write().unwrap()[index];
+        current.brightness = brightness;
+        if let Some(xy) = xy {
+            current.xy = color::XyColor::new(xy.x(), xy.y());
+        }
+    }
+    return Ok(());
+}
 
 #[named]
 pub fn test_leds(
@@ -36,9 +130,13 @@ pub fn test_leds(
     pin_ww: AnyIOPin,
     pin_a:  AnyIOPin,
     pin_pa:  AnyIOPin,
-    light_temperature_target: Arc<RwLock<f32>>,
-    light_brightness_target: Arc<RwLock<f32>>,
-    light_dim_speed: Arc<RwLock<f32>>,
+    zones: Arc<RwLock<Zones>>,
+    occupancy: Arc<RwLock<LampOccupancy>>,
+    corridor: Arc<RwLock<LampCorridor>>,
+    raw_mode: Arc<RwLock<LampRawMode>>,
+    dmx: Arc<RwLock<LampDmx>>,
+    thermal: Arc<RwLock<f32>>,
+    emitted: Arc<RwLock<Vec<push::ZoneOutput>>>,
 ) -> Result<()> {
 
     // FIXME For ESP32-C6 `Resolution::Bits14` is the largest enum that is defined. But the C6 supports resolutions up to Bits20.
@@ -48,13 +146,17 @@ pub fn test_leds(
         &TimerConfig::default().frequency(2400.Hz().into()).resolution(esp_idf_hal::ledc::Resolution::Bits14)
     ).expect("Get LEDC timer.");
     
-    let driver_0 = LedcDriver::new(ledc.channel0, &timer_driver, pin_r ).expect("Get LEDC driver.");
-    let driver_1 = LedcDriver::new(ledc.channel1, &timer_driver, pin_g ).expect("Get LEDC driver.");
-    let driver_2 = LedcDriver::new(ledc.channel2, &timer_driver, pin_b ).expect("Get LEDC driver.");
-    let driver_3 = LedcDriver::new(ledc.channel3, &timer_driver, pin_cw).expect("Get LEDC driver.");
-    let driver_4 = LedcDriver::new(ledc.channel4, &timer_driver, pin_ww).expect("Get LEDC driver.");
-    let driver_5 = LedcDriver::new(ledc.channel5, &timer_driver, pin_pa ).expect("Get LEDC driver.");
+    // Output channels, which zones refer to by their index
+    let mut drivers: Vec<Option<LedcDriver<'_>>> = vec![
+        Some(LedcDriver::new(ledc.channel0, &timer_driver, pin_r ).expect("Get LEDC driver.")),
+        Some(LedcDriver::new(ledc.channel1, &timer_driver, pin_g ).expect("Get LEDC driver.")),
+        Some(LedcDriver::new(ledc.channel2, &timer_driver, pin_b ).expect("Get LEDC driver.")),
+        Some(LedcDriver::new(ledc.channel3, &timer_driver, pin_cw).expect("Get LEDC driver.")),
+        Some(LedcDriver::new(ledc.channel4, &timer_driver, pin_ww).expect("Get LEDC driver.")),
+        Some(LedcDriver::new(ledc.channel5, &timer_driver, pin_pa ).expect("Get LEDC driver.")),
+    ];
 
+    // The ESP32-C6 only has six LEDC channels, so these LEDs can't be used for now
     let mut unused_pin_1 = PinDriver::output(pin_nw).unwrap();
     let mut unused_pin_2 = PinDriver::output(pin_a).unwrap();
     unused_pin_1.set_low();
@@ -62,43 +164,88 @@ pub fn test_leds(
 
     info!(target: function_name!(), "Before LED main loop...");
     std::thread::sleep(core::time::Duration::from_millis(500));
-    
-    let mut pwm = Pwm::new(
-        driver_0,
-        driver_1,
-        driver_2,
-        driver_3,
-        driver_4,
-        driver_5,
-    )?;
-    
-    let (mut target_brightness, mut target_temperature, mut dim_speed) = {(
-        *(light_brightness_target.read().unwrap()),
-        *(light_temperature_target.read().unwrap()),
-        *(light_dim_speed.read().unwrap()),
 
-    )};
-    let (mut brightness, mut temperature) = (target_brightness, target_temperature);
+    // Zones are only set up once, changes to their config take effect after a restart
+    let mut outputs = Vec::new();
+    {
+        let zones = zones.read().unwrap();
+        for (zone, state) in zones.config().zones.iter().zip(zones.states()) {
+            let mut leds = Vec::new();
+            let mut available = zone.clone();
+            available.leds.clear();
+            for led in &zone.leds {
+                match drivers.get_mut(led.channel).and_then(Option::take) {
+                    Some(driver) => {
+                        leds.push(Led::new(led.channel, led.name.clone(), driver, led.x, led.y, led.max_brightness));
+                        available.leds.push(led.clone());
+                    },
+                    None => warn!(target: function_name!(), "Zone '{}': There is no output channel {} for LED '{}'.", zone.name, led.channel, led.name),
+                }
+            }
+            info!(target: function_name!(), "Zone '{}' uses {} LEDs.", zone.name, leds.len());
+            outputs.push(ZoneOutput::new(Pwm::new(leds, &available.triangles())?, state)?);
+        }
+    }
+    *emitted.write().unwrap() = zones.read().unwrap().config().zones.iter()
+        .map(|zone| push::ZoneOutput { zone: zone.name.clone(), brightness: 0.0, xy: color::XyColor::new(0.0, 0.0) })
+        .collect();
 
-    let mut count: i32 = 0;
+    // Channels which don't belong to any zone stay dark
+    for driver in drivers.iter_mut().flatten() {
+        driver.set_duty(0)?;
+    }
+
+    // Occupancy dims all zones, because the presence sensors watch the whole room.
+    // So does the corridor, which treats the whole lamp as one.
+    let occupancy_dim_speed = dim_speed(OCCUPANCY_TRANSITION, TICK.as_secs_f32());
+    let mut dimming = 1.0_f32;
+    let limits = OutputLimits::default();
+    let mut raw = false;
+    let mut live = false;
     loop {
-        std::thread::sleep(core::time::Duration::from_millis(5));
-        count += 1;
-        
-        {
-            target_brightness = *(light_brightness_target.read().unwrap());
-            target_temperature = *(light_temperature_target.read().unwrap());
-            dim_speed = *(light_dim_speed.read().unwrap());
-        }
-        
-        brightness = brightness.lerp(&target_brightness, dim_speed);
-        temperature = temperature.lerp(&target_temperature, dim_speed);
+        std::thread::sleep(TICK);
+        let temperature = *thermal.read().unwrap();
+        let target = occupancy.read().unwrap().brightness() * corridor.read().unwrap().own_brightness();
+        dimming = dimming.lerp(&target, occupancy_dim_speed);
 
-        // if count % 1000 == 0 {
-        //     info!(target: function_name!(), "Current temp: {}, brightness: {}", temperature, brightness);
-        // }
+        // Raw duties lock out the color pipeline, but not the limits
+        let raw_duties = raw_mode.read().unwrap().duties().map(|duties| limits.limit(duties, temperature));
+        if raw != raw_duties.is_some() {
+            raw = raw_duties.is_some();
+            info!(target: function_name!(), "Raw channel mode {}.", if raw { "started" } else { "ended" });
+        }
+        if let Some(duties) = raw_duties {
+            set_duties(&mut outputs, &duties, &emitted)?;
+            continue;
+        }
 
-        pwm.set_temperature_and_brightness(temperature, brightness)?;
+        // DMX takes over from the zones, but not from raw mode. On stage, the light
+        // must not be dimmed by occupancy.
+        let dmx_output = dmx.write().unwrap().output(zones.read().unwrap().config());
+        if live != dmx_output.is_some() {
+            live = dmx_output.is_some();
+            info!(target: function_name!(), "DMX control {}.", if live { "started" } else { "ended" });
+        }
+        let thermal_factor = limits.thermal_factor(temperature);
+        let (dmx_states, factor) = match dmx_output {
+            Some(DmxOutput::Raw(duties)) => {
+                let duties = limits.limit(&duties, temperature);
+                set_duties(&mut outputs, &duties, &emitted)?;
+                continue;
+            },
+            Some(DmxOutput::Zones(states)) => (Some(states), thermal_factor),
+            None => (None, dimming * thermal_factor),
+        };
+        for (index, output) in outputs.iter_mut().enumerate() {
+            let state = match &dmx_states {
+                Some(states) => states[index].clone(),
+                None => zones.read().unwrap().states()[index].clone(),
+            };
+            let (brightness, xy) = output.update(state, factor)?;
+            let current = &mut emitted.write().unwrap()[index];
+            current.brightness = brightness;
+            current.xy = color::XyColor::new(xy.x(), xy.y());
+        }
     }
     
 }
\ No newline at end of file
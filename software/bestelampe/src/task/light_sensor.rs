@@ -14,6 +14,7 @@
 // If not, see <https://www.gnu.org/licenses/>. 
 
 use std::error::Error;
+use std::sync::mpsc::Sender;
 
 use crate::prelude::*;
 
@@ -23,9 +24,11 @@ use esp_idf_hal::{
 };
 
 use veml6040::wrapper::AutoVeml6040;
+use abstraktelampe::ambient::AmbientReading;
+use abstraktelampe::rules::Event;
 
 #[named]
-pub fn test_light_sensor(i2c: I2C0, scl: AnyIOPin, sda: AnyIOPin) -> Result<(), Box<dyn Error>> {
+pub fn test_light_sensor(i2c: I2C0, scl: AnyIOPin, sda: AnyIOPin, events: Sender<Event>) -> Result<(), Box<dyn Error>> {
     let config = I2cConfig::new().baudrate(100.kHz().into()).scl_enable_pullup(false).sda_enable_pullup(false);
     let i2c = I2cDriver::new(i2c, sda, scl, &config)?;
 
@@ -44,6 +47,12 @@ pub fn test_light_sensor(i2c: I2C0, scl: AnyIOPin, sda: AnyIOPin) -> Result<(),
                 // from the datasheet or some official example. Anyway, I should try using `measurement.white``
                 // instead at some time.
                 info!(target: function_name!(), "Brightness: {} lx, color temperature: {} K", measurement.green, cct);
+                let reading = AmbientReading {
+                    red: measurement.red as f32,
+                    green: measurement.green as f32,
+                    blue: measurement.blue as f32,
+                };
+                events.send(Event::AmbientLight(reading.lux()))?;
             }
             Err(err) => {
                 warn!(target: function_name!(), "Error in sensor loop iteration: {:?}", err);
@@ -22,4 +22,7 @@ pub mod leds;
 pub mod ota;
 pub mod server;
 pub mod i2c;
-pub mod uart;
\ No newline at end of file
+pub mod uart;
+pub mod rules;
+pub mod mqtt;
+pub mod dmx;
\ No newline at end of file
@@ -0,0 +1,153 @@
+// SPDX-FileCopyrightText: 2024 Lena Schimmel <mail@lenaschimmel.de>
+// SPDX-License-Identifier: CERN-OHL-S-2.0+
+// This file is part of besteLampe!.
+// 
+// besteLampe! is free software: you can redistribute it and/or modify it under the
+// terms of the GNU General Public License as published by the Free Software Foundation, 
+// either version 3 of the License, or (at your option) any later version.
+// 
+// besteLampe! is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; 
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. 
+// See the GNU General Public License for more details.
+// 
+// You should have received a copy of the GNU General Public License along with besteLampe!.
+// If not, see <https://www.gnu.org/licenses/>. 
+
+use crate::prelude::*;
+
+use esp_idf_svc::mqtt::client::{Details, EspMqttClient, EventPayload, LwtConfiguration, MqttClientConfiguration, QoS};
+
+use std::sync::{mpsc, Arc, RwLock};
+use std::time::Duration;
+
+use abstraktelampe::api::Lamp;
+use abstraktelampe::mqtt::{discovery, Command, MqttConfig, MqttError, Publication, SensorsState, StatePublisher, OFFLINE, ONLINE};
+use abstraktelampe::zone::ZoneSelector;
+
+use crate::capture::uptime_ms;
+use crate::lamp::ApiLamp;
+
+/// Interval in which the state is compared with what was published.
+const PUBLISH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Time to wait before connecting again after the client could not be created,
+/// and in which a disabled client checks whether it was enabled.
+const RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+/// What the MQTT client's callback passes to the task.
+enum Incoming {
+    Connected,
+    Disconnected,
+    Message { topic: String, payload: Vec<u8> },
+}
+
+/// Connects to the broker while MQTT is enabled, and reconnects with the new
+/// config whenever it is changed. The ESP-IDF client reconnects by itself if the
+/// connection is lost.
+#[named]
+pub fn run_mqtt(lamp: ApiLamp, mqtt: Arc<RwLock<MqttConfig>>) -> Result<()> {
+    loop {
+        let config = mqtt.read().unwrap().clone();
+        if config.enabled {
+            info!(target: function_name!(), "Connecting to MQTT broker at {}...", config.url);
+            if let Err(err) = run_session(&lamp, &mqtt, &config) {
+                error!(target: function_name!(), "MQTT client failed: {:?}", err);
+            }
+        }
+        std::thread::sleep(RETRY_INTERVAL);
+    }
+}
+
+/// Runs a client with `config` until the config is changed.
+#[named]
+fn run_session(lamp: &ApiLamp, mqtt: &RwLock<MqttConfig>, config: &MqttConfig) -> Result<()> {
+    let availability = config.availability_topic();
+    let client_config = MqttClientConfiguration {
+        client_id: Some(&config.node_id),
+        username: config.username.as_deref(),
+        password: config.password.as_deref(),
+        lwt: Some(LwtConfiguration { topic: &availability, payload: OFFLINE.as_bytes(), qos: QoS::AtLeastOnce, retain: true }),
+        ..Default::default()
+    };
+    let (sender, receiver) = mpsc::channel();
+    let mut client = EspMqttClient::new_cb(&config.url, &client_config, move |event| {
+        let incoming = match event.payload() {
+            EventPayload::Connected(_) => Incoming::Connected,
+            EventPayload::Disconnected => Incoming::Disconnected,
+            // Commands are small, so they are never split into chunks
+            EventPayload::Received { topic: Some(topic), data, details: Details::Complete, .. } => {
+                Incoming::Message { topic: topic.to_string(), payload: data.to_vec() }
+            },
+            _ => return,
+        };
+        let _ = sender.send(incoming);
+    })?;
+
+    let mut publisher = StatePublisher::new(uptime_ms);
+    let mut connected = false;
+    loop {
+        if *mqtt.read().unwrap() != *config {
+            info!(target: function_name!(), "MQTT config changed, reconnecting.");
+            if connected {
+                client.publish(&availability, QoS::AtLeastOnce, true, OFFLINE.as_bytes())?;
+            }
+            return Ok(());
+        }
+
+        while let Ok(incoming) = receiver.try_recv() {
+            match incoming {
+                Incoming::Connected => {
+                    info!(target: function_name!(), "Connected to MQTT broker.");
+                    connected = true;
+                    match discovery(config, &lamp.device()) {
+                        Ok(publications) => publish(&mut client, &publications),
+                        Err(err) => error!(target: function_name!(), "Not announcing the lamp to Home Assistant: {}", err),
+                    }
+                    client.publish(&availability, QoS::AtLeastOnce, true, ONLINE.as_bytes())?;
+                    client.subscribe(&config.command_filter(), QoS::AtLeastOnce)?;
+                    // The broker may have lost the retained states
+                    publisher.reset();
+                },
+                Incoming::Disconnected => {
+                    warn!(target: function_name!(), "Disconnected from MQTT broker.");
+                    connected = false;
+                },
+                Incoming::Message { topic, payload } => {
+                    if let Err(err) = handle_command(lamp, config, &topic, &payload) {
+                        warn!(target: function_name!(), "Ignoring command to {}: {}", topic, err);
+                    }
+                },
+            }
+        }
+
+        if connected {
+            let sensors = SensorsState::new(&lamp.sensors(), &lamp.telemetry());
+            let publications = lamp.with_zones(|zones| publisher.update(config, zones.config(), zones.states(), &sensors));
+            publish(&mut client, &publications);
+        }
+        std::thread::sleep(PUBLISH_INTERVAL);
+    }
+}
+
+fn handle_command(lamp: &ApiLamp, config: &MqttConfig, topic: &str, payload: &[u8]) -> Result<(), MqttError> {
+    let command = Command::parse(config, payload)?;
+    let tick = lamp.tick();
+    lamp.with_zones(|zones| {
+        let zone = config.command_zone(topic, zones.config())?.to_string();
+        return zones.update(&ZoneSelector::Zone(zone.clone()), |state| command.apply(state, tick))
+            .map_err(|_| MqttError::UnknownZone(zone));
+    })?;
+    lamp.light_changed();
+    return Ok(());
+}
+
+/// Publishes messages. Failures are only logged, since the next change or
+/// reconnect publishes the state again.
+#[named]
+fn publish(client: &mut EspMqttClient<'_>, publications: &[Publication]) {
+    for publication in publications {
+        if let Err(err) = client.publish(&publication.topic, QoS::AtLeastOnce, publication.retain, publication.payload.as_bytes()) {
+            warn!(target: function_name!(), "Could not publish to {}: {:?}", publication.topic, err);
+        }
+    }
+}
@@ -16,7 +16,8 @@
 use crate::prelude::*;
 
 use std::time::Duration;
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::mpsc::Sender;
 
 use enumset::EnumSet;
 use esp_idf_hal::rmt::{RmtChannel, CHANNEL2};
@@ -24,11 +25,20 @@ use esp_idf_hal::{
     prelude::*,
     delay::TickType,
     gpio::AnyIOPin,
+    io::{Read, ReadReady, Write},
     uart, 
-    uart::{UART1, config::*},
+    uart::{UART0, UART1, config::*},
 };
 
-use mr24hpc1::{mr_parser, Frame, HumanPresence, Motion};
+use ld2410::Ld2410;
+use mr24hpc1::{Command, Mr24hpc1, RawDetectorConfig};
+use abstraktelampe::capture::{CaptureBuffer, CaptureSource};
+use abstraktelampe::corridor::Corridor;
+use abstraktelampe::occupancy::{Occupancy, OccupancyState};
+use abstraktelampe::presence::{fuse, Ld2410Sensor, MotionLevel, Mr24hpc1Sensor, PresenceReading, PresenceSensor, SensorModel, SensorSlots};
+use abstraktelampe::rules::Event;
+
+use crate::capture::{uptime_ms, Recorder};
 
 use esp_idf_hal::{
     delay::FreeRtos,
@@ -118,21 +128,61 @@ pub fn test_presence_sensor_rmt(
     Ok(())
 }
 
-#[named]
-pub fn test_presence_sensor(
-    pin_rx: AnyIOPin,
-    pin_tx: AnyIOPin,
-    uart_device: UART1, 
-    light_brightness_target: Arc<RwLock<f32>>,
-) ->  Result<()>  {
-    info!(target: function_name!(), "Connecting to GPIO 17 to sample the sensor");
+/// Occupancy of the room, from the presence sensors. Its brightness dims the LEDs.
+pub type LampOccupancy = Occupancy<fn() -> u64>;
 
-    std::thread::sleep(core::time::Duration::from_millis(500));
+/// Lamps along a corridor, which this one may be part of. Its own brightness dims the LEDs, too.
+pub type LampCorridor = Corridor<fn() -> u64>;
+
+/// Pins and serial port of the right sensor slot, which it shares with the GPS module.
+pub type RightSlot = (AnyIOPin, AnyIOPin, UART0);
+
+fn baudrate(model: SensorModel) -> Hertz {
+    return match model {
+        SensorModel::Mr24hpc1 => Hertz(115_200),
+        SensorModel::Ld2410 => Hertz(256_000),
+    };
+}
 
+/// Captures record the model, so that they can be replayed with the right decoder.
+fn capture_source(model: SensorModel) -> CaptureSource {
+    return match model {
+        SensorModel::Mr24hpc1 => CaptureSource::Mr24hpc1,
+        SensorModel::Ld2410 => CaptureSource::Ld2410,
+    };
+}
+
+/// Whether presence and motion are derived from the raw measurements of the
+/// MR24HPC1's open function mode, instead of using the sensor's own detection.
+const RAW_PRESENCE: bool = true;
+
+/// A presence sensor of any model, so that the sensors in both slots can be
+/// read by the same loop.
+trait SlotSensor {
+    fn poll(&mut self) -> Result<Option<PresenceReading>>;
+    fn is_lost(&self) -> bool;
+}
+
+impl<S> SlotSensor for S
+where
+    S: PresenceSensor,
+    S::Error: std::error::Error + Send + Sync + 'static,
+{
+    fn poll(&mut self) -> Result<Option<PresenceReading>> {
+        return Ok(PresenceSensor::poll(self)?);
+    }
+
+    fn is_lost(&self) -> bool {
+        return PresenceSensor::is_lost(self);
+    }
+}
+
+/// Serial port settings for a sensor.
+fn uart_config(model: SensorModel) -> uart::config::Config {
     // Initialize config manually, because `uart::config::Config::default()`
     // crashes on ESP32-C6 due to an invalid SourceClock.
-    let config = uart::config::Config {
-        baudrate: Hertz(115_200),
+    return uart::config::Config {
+        baudrate: baudrate(model),
         data_bits: DataBits::DataBits8,
         parity: Parity::ParityNone,
         stop_bits: StopBits::STOP1,
@@ -146,57 +196,152 @@ pub fn test_presence_sensor(
         queue_size: 0,
         _non_exhaustive: (),
     };
+}
+
+/// Asks the sensor for its firmware, and sets it up for the presence detection.
+#[named]
+fn open_sensor<U>(model: SensorModel, uart: U, slot: &str) -> Box<dyn SlotSensor>
+where
+    U: Read + ReadReady + Write + 'static,
+    U::Error: std::fmt::Debug + Send + Sync + 'static,
+{
+    let clock: fn() -> u64 = uptime_ms;
+    return match model {
+        SensorModel::Mr24hpc1 => {
+            let mut driver = Mr24hpc1::new(uart, clock);
+            match driver.init() {
+                Ok(device) => info!(target: function_name!(), "Found {} with firmware {} in the {} slot.", device.product_model, device.firmware_version, slot),
+                Err(error) => warn!(target: function_name!(), "Presence sensor in the {} slot did not answer: {}", slot, error),
+            }
+            if let Err(error) = driver.command(Command::SetOpenFunction(RAW_PRESENCE)) {
+                warn!(target: function_name!(), "Could not switch the open function mode in the {} slot: {}", slot, error);
+            }
+            if RAW_PRESENCE {
+                Box::new(Mr24hpc1Sensor::raw(driver, RawDetectorConfig::default()))
+            } else {
+                Box::new(Mr24hpc1Sensor::new(driver))
+            }
+        },
+        SensorModel::Ld2410 => {
+            let mut driver = Ld2410::new(uart, clock);
+            match driver.init() {
+                Ok(version) => info!(target: function_name!(), "Found LD2410 with firmware {} in the {} slot.", version, slot),
+                Err(error) => warn!(target: function_name!(), "Presence sensor in the {} slot did not answer: {}", slot, error),
+            }
+            Box::new(Ld2410Sensor::new(driver))
+        },
+    };
+}
+
+#[named]
+pub fn test_presence_sensor(
+    pin_rx_left: AnyIOPin,
+    pin_tx_left: AnyIOPin,
+    uart_left: UART1,
+    right_slot: Option<RightSlot>,
+    slots: SensorSlots,
+    events: Sender<Event>,
+    occupancy: Arc<RwLock<LampOccupancy>>,
+    corridor: Arc<RwLock<LampCorridor>>,
+    capture: Arc<Mutex<CaptureBuffer>>,
+) ->  Result<()>  {
+    info!(target: function_name!(), "Connecting to the presence sensors {:?}", slots);
 
-    let uart: uart::UartDriver = uart::UartDriver::new(
-        uart_device,
-        pin_tx,
-        pin_rx,
-        Option::<AnyIOPin>::None,
-        Option::<AnyIOPin>::None,
-        &config
-    )?;
+    std::thread::sleep(core::time::Duration::from_millis(500));
+
+    let mut sensors: Vec<(&str, Box<dyn SlotSensor>)> = Vec::new();
+    if let Some(model) = slots.left {
+        let uart = uart::UartDriver::new(uart_left, pin_tx_left, pin_rx_left, Option::<AnyIOPin>::None, Option::<AnyIOPin>::None, &uart_config(model))?;
+        // Only the left slot is captured, because a capture holds a single radar stream
+        let uart = Recorder::new(uart, capture, capture_source(model));
+        sensors.push(("left", open_sensor(model, uart, "left")));
+    }
+    // Without a sensor in the right slot, its pins belong to the GPS module
+    if let (Some(model), Some((pin_rx_right, pin_tx_right, uart_right))) = (slots.right, right_slot) {
+        let uart = uart::UartDriver::new(uart_right, pin_tx_right, pin_rx_right, Option::<AnyIOPin>::None, Option::<AnyIOPin>::None, &uart_config(model))?;
+        sensors.push(("right", open_sensor(model, uart, "right")));
+    }
+    return run_presence_sensors(sensors, events, occupancy, corridor);
+}
+
+/// Combines the readings of the presence sensors with the fusion policy of the
+/// occupancy config, turns them into the occupancy of the room, and its changes
+/// into events for the rules. The combined reading also switches this lamp's
+/// part of the corridor.
+#[named]
+fn run_presence_sensors(mut sensors: Vec<(&str, Box<dyn SlotSensor>)>, events: Sender<Event>, occupancy: Arc<RwLock<LampOccupancy>>, corridor: Arc<RwLock<LampCorridor>>) -> Result<()> {
+    // Latest reading of each sensor, which is `None` while it is lost
+    let mut readings: Vec<Option<PresenceReading>> = vec![None; sensors.len()];
+    let mut lost = vec![false; sensors.len()];
+    // Whether the last poll of each sensor failed, which counts as lost as well
+    let mut failed = vec![false; sensors.len()];
+    let mut last_fused: Option<PresenceReading> = None;
 
-    let mut cycles_without_data = 0;
     info!(target: function_name!(), "Try to read stuff...");
     loop {
-        let mut buf = [0_u8; 20];
-        let len: usize = uart.read(&mut buf, TickType::from(Duration::from_millis(50)).ticks())?;
-        
-        if len >= 9 {
-            cycles_without_data = 0;
-            match mr_parser(&buf[0..len]) {
-                Ok((_, frame)) => {
-                    info!(target: function_name!(), "Parsed presence data: {:?}", frame);
-                    match frame {
-                        Frame::HumanPresenceReport(HumanPresence::BodyMovementParameter(movement)) => {
-                            info!(target: function_name!(), "Movement: {:?}", movement);
-                           // *light_brightness_target.write().unwrap() = movement as f32;
-                        },
-                        Frame::HumanPresenceReport(HumanPresence::MotionInformation(motion)) => {
-                            info!(target: function_name!(), "Motion: {:?}", motion);
-                            *light_brightness_target.write().unwrap() = match motion {
-                                Motion::None => 0.2,
-                                Motion::Motionless => 0.8,
-                                Motion::Active => 2.0,
-                            }
-                        },
-                        _ => {
-
-                        }
+        let mut changed = false;
+        for (index, (slot, sensor)) in sensors.iter_mut().enumerate() {
+            match sensor.poll() {
+                Ok(Some(reading)) => {
+                    // Distances change with every report, so only log changes of the other values
+                    let last = readings[index].as_ref();
+                    if last.map(|last| (last.presence, last.motion, last.direction)) != Some((reading.presence, reading.motion, reading.direction)) {
+                        info!(target: function_name!(), "Presence in the {} slot: {:?}", slot, reading);
                     }
+                    readings[index] = Some(reading);
+                    failed[index] = false;
+                    changed = true;
                 },
-                Err(_) => {
-                    warn!(target: function_name!(), "Error while parsing presence data '{:x?}'", buf);
+                Ok(None) => failed[index] = false,
+                Err(err) => {
+                    // Only log the first error, because the next poll will follow in a moment
+                    if !failed[index] {
+                        error!(target: function_name!(), "Could not read the presence sensor in the {} slot: {:?}", slot, err);
+                    }
+                    failed[index] = true;
                 },
             }
-        } else if len > 0 {
-            warn!(target: function_name!(), "Short presence data '{:x?}'", buf);
-            cycles_without_data = 0;
+            let is_lost = sensor.is_lost() || failed[index];
+            if is_lost != lost[index] {
+                lost[index] = is_lost;
+                if lost[index] {
+                    warn!(target: function_name!(), "Presence sensor in the {} slot stopped sending data.", slot);
+                    readings[index] = None;
+                } else {
+                    info!(target: function_name!(), "Presence sensor in the {} slot is back.", slot);
+                }
+                changed = true;
+            }
+        }
+
+        let all_lost = lost.iter().all(|&lost| lost);
+        let fused = if all_lost {
+            // Broken sensors must not leave the room dark, so they count as motion
+            PresenceReading { presence: true, motion: MotionLevel::Active, distance: None, direction: None }
         } else {
-            cycles_without_data += 1;
-            if cycles_without_data > 100 && cycles_without_data % 100 == 0 {
-                warn!(target: function_name!(), "Had {} cycles without any data.", cycles_without_data);
+            fuse(occupancy.read().unwrap().config().fusion, &readings)
+        };
+        // The policy may have changed, so compare with the previous result as well
+        changed |= last_fused.as_ref() != Some(&fused);
+        let change = {
+            let mut occupancy = occupancy.write().unwrap();
+            if changed || all_lost {
+                occupancy.update(&fused)
+            } else {
+                occupancy.tick()
             }
+        };
+        corridor.write().unwrap().update_own(&fused);
+        last_fused = Some(fused);
+        if let Some(state) = change {
+            info!(target: function_name!(), "Occupancy: {:?}", state);
+            events.send(Event::Presence(state.is_occupied()))?;
+            events.send(Event::Motion(match state {
+                OccupancyState::Active => MotionLevel::Active,
+                OccupancyState::Still => MotionLevel::Motionless,
+                OccupancyState::FadingDown { .. } | OccupancyState::Vacant => MotionLevel::None,
+            }))?;
         }
+        std::thread::sleep(Duration::from_millis(50));
     }
 }
@@ -0,0 +1,66 @@
+// SPDX-FileCopyrightText: 2024 Lena Schimmel <mail@lenaschimmel.de>
+// SPDX-License-Identifier: CERN-OHL-S-2.0+
+// This file is part of besteLampe!.
+// 
+// besteLampe! is free software: you can redistribute it and/or modify it under the
+// terms of the GNU General Public License as published by the Free Software Foundation, 
+// either version 3 of the License, or (at your option) any later version.
+// 
+// besteLampe! is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; 
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. 
+// See the GNU General Public License for more details.
+// 
+// You should have received a copy of the GNU General Public License along with besteLampe!.
+// If not, see <https://www.gnu.org/licenses/>. 
+
+use crate::prelude::*;
+
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use chrono::Utc;
+use chrono_tz::Tz;
+
+use abstraktelampe::circadian::NaturalLight;
+use abstraktelampe::rules::{Event, RuleEngine};
+use abstraktelampe::scene::SceneStore;
+use abstraktelampe::zone::Zones;
+
+use crate::light::apply_rule_action;
+
+/// If no event arrives within this time, the engine gets a tick, so that
+/// timers and time windows are evaluated.
+const TICK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Feeds all events from the sensor and button tasks into the rule engine, and
+/// executes the resulting actions. This is the only place where rules are evaluated,
+/// so their actions are applied in a well-defined order.
+#[named]
+pub fn run_rules(
+    events: Receiver<Event>,
+    rules: Arc<RwLock<RuleEngine>>,
+    zones: Arc<RwLock<Zones>>,
+    scenes: Arc<RwLock<SceneStore>>,
+    natural_light: Arc<RwLock<NaturalLight>>,
+) -> Result<()> {
+    let tz: Tz = CONFIG.time_zone.parse().map_err(|err| anyhow!("Invalid time zone: {}", err))?;
+    loop {
+        let event = match events.recv_timeout(TICK_INTERVAL) {
+            Ok(event) => event,
+            Err(RecvTimeoutError::Timeout) => Event::Tick,
+            Err(RecvTimeoutError::Disconnected) => return Err(anyhow!("All event senders are gone.")),
+        };
+        if event != Event::Tick {
+            debug!(target: function_name!(), "Event: {:?}", event);
+        }
+
+        let now = Utc::now();
+        let actions = rules.write().unwrap().handle(event, now, &tz);
+        for action in actions {
+            info!(target: function_name!(), "Rule action: {:?}", action);
+            apply_rule_action(&action, &scenes.read().unwrap(), &zones);
+            // Automations take precedence over the natural light, just like manual changes
+            natural_light.write().unwrap().override_manually(now);
+        }
+    }
+}
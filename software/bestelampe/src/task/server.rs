@@ -18,22 +18,62 @@ use crate::prelude::*;
 use esp_idf_hal::io::{Read, Write};
 use esp_idf_svc::http::{
         Method,
-        server::EspHttpServer,
+        server::{EspHttpConnection, EspHttpServer, Request},
+        server::ws::{EspHttpWsConnection, EspHttpWsDetachedSender},
 };
+use esp_idf_svc::ws::FrameType;
 
 use serde::Deserialize;
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
+
+use abstraktelampe::alarm::{AlarmConfig, SunriseAlarm};
+use abstraktelampe::ambient::{AmbientReading, ColorMatcher, ColorMatchingConfig};
+use abstraktelampe::api;
+use abstraktelampe::capture::CaptureBuffer;
+use abstraktelampe::channels::RawRequest;
+use abstraktelampe::circadian::{CircadianConfig, NaturalLight};
+use abstraktelampe::corridor::{CorridorConfig, CorridorDetection};
+use abstraktelampe::daylight::{DaylightConfig, DaylightHarvester};
+use abstraktelampe::dmx::DmxConfig;
+use abstraktelampe::mqtt::{validate_zone_ids, MqttConfig};
+use abstraktelampe::occupancy::OccupancyConfig;
+use abstraktelampe::presence::SensorSlots;
+use abstraktelampe::push::{Publisher, PushMessage, Snapshot, ZoneOutput};
+use abstraktelampe::effect::EffectConfig;
+use abstraktelampe::rules::{validate_rules, Rule, RuleEngine};
+use abstraktelampe::scene::{Scene, SceneStore};
+use abstraktelampe::schedule::ScheduleEntry;
+use abstraktelampe::sun::{solar_elevation, sun_event, Location, SunEvent};
+use abstraktelampe::zone::{ZoneConfig, ZoneSelector, Zones};
+use chrono_tz::Tz;
+
+use crate::capture::uptime_ms;
+use crate::lamp::ApiLamp;
+use crate::light::recall_scene;
+use crate::task::dmx::LampDmx;
+use crate::task::leds::LampRawMode;
+use crate::task::presence::{LampCorridor, LampOccupancy};
+use crate::storage::{Storage, KEY_ALARM, KEY_CIRCADIAN, KEY_COLOR_MATCHING, KEY_DAYLIGHT, KEY_RULES, KEY_SCENES, KEY_SCHEDULE, KEY_ZONES, KEY_OCCUPANCY, KEY_MQTT, KEY_DMX, KEY_CORRIDOR, KEY_SENSOR_SLOTS};
 
 #[derive(Deserialize)]
 struct FormData {
     brightness: f32,
     temperature: f32,
     speed: f32,
+    #[serde(default)]
+    zones: ZoneSelector,
+}
+
+#[derive(Deserialize)]
+struct FormDataSceneName {
+    name: String,
 }
 
 #[derive(Deserialize)]
-struct FormDataChannels {
-    channels: [u16; 12],
+struct FormDataEffect {
+    effect: Option<EffectConfig>,
+    #[serde(default)]
+    zones: ZoneSelector,
 }
 
 static INDEX_HTML: &str = include_str!("../http_server_page.html");
@@ -41,22 +81,51 @@ static INDEX_HTML: &str = include_str!("../http_server_page.html");
 // Max payload length
 const MAX_LEN: usize = 128;
 
+// Max payload length for requests that contain a whole scene or schedule
+const MAX_LEN_SCENE: usize = 512;
+const MAX_LEN_SCHEDULE: usize = 2048;
+const MAX_LEN_RULES: usize = 4000;
+const MAX_LEN_ZONES: usize = 4000;
+
 // Need lots of stack to parse JSON
 const STACK_SIZE: usize = 10240;
 
+// Every path and method needs its own handler, and the default only allows 32
+const MAX_URI_HANDLERS: usize = 64;
+
+/// Interval in which changes are pushed to the WebSocket clients.
+const PUSH_INTERVAL: core::time::Duration = core::time::Duration::from_millis(100);
 
 #[named]
 pub fn run_server(
-    light_temperature_target: Arc<RwLock<f32>>,
-    light_brightness_target: Arc<RwLock<f32>>,
-    light_dim_speed: Arc<RwLock<f32>>,
+    zones: Arc<RwLock<Zones>>,
     update_requested: Arc<RwLock<bool>>,
     thermal: Arc<RwLock<f32>>, 
     voltage: Arc<RwLock<f32>>, 
     current: Arc<RwLock<f32>>,
+    scenes: Arc<RwLock<SceneStore>>,
+    schedule: Arc<RwLock<Vec<ScheduleEntry>>>,
+    alarm: Arc<RwLock<SunriseAlarm>>,
+    natural_light: Arc<RwLock<NaturalLight>>,
+    rules: Arc<RwLock<RuleEngine>>,
+    daylight: Arc<RwLock<DaylightHarvester>>,
+    color_matcher: Arc<RwLock<ColorMatcher>>,
+    occupancy: Arc<RwLock<LampOccupancy>>,
+    corridor: Arc<RwLock<LampCorridor>>,
+    location: Arc<RwLock<Option<Location>>>,
+    ambient: Arc<RwLock<Option<AmbientReading>>>,
+    raw_mode: Arc<RwLock<LampRawMode>>,
+    outputs: Arc<RwLock<Vec<ZoneOutput>>>,
+    mqtt: Arc<RwLock<MqttConfig>>,
+    dmx: Arc<RwLock<LampDmx>>,
+    capture: Arc<Mutex<CaptureBuffer>>,
+    storage: Arc<Mutex<Storage>>,
 ) -> Result<()> {
     let server_configuration = esp_idf_svc::http::server::Configuration {
         stack_size: STACK_SIZE,
+        max_uri_handlers: MAX_URI_HANDLERS,
+        // For the JSON API, which handles all of its resources in one place
+        uri_match_wildcard: true,
         ..Default::default()
     };
     let mut server: EspHttpServer<'_> = EspHttpServer::new(&server_configuration).or(Err(anyhow!("Could not create server.")))?;
@@ -78,33 +147,42 @@ pub fn run_server(
     })?;
 
 
-    server.fn_handler::<anyhow::Error, _>("/channels", Method::Get, |mut req| {
-        let len = req.header("Content-Length") .and_then(|v| v.parse::<u64>().ok()).unwrap_or(0) as usize;
+    // Raw duties per output channel, e.g. to calibrate LED boards
+    server.fn_handler::<anyhow::Error, _>("/channels", Method::Get, |req| {
+        let json = serde_json::to_string(&raw_mode.read().unwrap().status())?;
+        req.into_response(200, None, &[("Content-Type", "application/json")])?
+            .write_all(json.as_bytes())?;
+        Ok(())
+    })?;
 
-        if len > MAX_LEN {
-            req.into_status_response(413)?
-                .write_all("Request too big".as_bytes())?;
+    server.fn_handler::<anyhow::Error, _>("/channels", Method::Post, |mut req| {
+        let Some(buf) = read_body(&mut req, MAX_LEN_SCENE)? else {
+            req.into_status_response(413)?.write_all("Request too big".as_bytes())?;
+            return Ok(());
+        };
+        let request = match serde_json::from_slice::<RawRequest>(&buf) {
+            Ok(request) => request,
+            Err(err) => {
+                req.into_status_response(400)?.write_all(format!("JSON error: {}", err).as_bytes())?;
+                return Ok(());
+            },
+        };
+        let result = raw_mode.write().unwrap().start(request, zones.read().unwrap().config());
+        if let Err(err) = result {
+            req.into_status_response(400)?.write_all(err.to_string().as_bytes())?;
             return Ok(());
         }
 
-        let mut buf = vec![0; len];
-        req.read_exact(&mut buf)?;
-        let mut resp = req.into_ok_response()?;
-
-        if let Ok(form) = serde_json::from_slice::<FormDataChannels>(&buf) {
-            warn!(target: function_name!(),
-                ": Got an HTTP request to set the channels to {:?}, but that is not yet implemented.",
-                form.channels
-            );
-            write!(
-                resp,
-                "WARNING: Got an HTTP request to set the channels to {:?}, but that is not yet implemented.",
-                form.channels
-            )?;
-        } else {
-            resp.write_all("JSON error".as_bytes())?;
-        }
+        info!(target: function_name!(), "Raw channel mode: {:?}", raw_mode.read().unwrap().status());
+        let json = serde_json::to_string(&raw_mode.read().unwrap().status())?;
+        req.into_response(200, None, &[("Content-Type", "application/json")])?
+            .write_all(json.as_bytes())?;
+        Ok(())
+    })?;
 
+    server.fn_handler::<anyhow::Error, _>("/channels/release", Method::Post, |req| {
+        raw_mode.write().unwrap().stop();
+        req.into_ok_response()?.write_all("Returned to normal mode.".as_bytes())?;
         Ok(())
     })?;
 
@@ -122,14 +200,22 @@ pub fn run_server(
         let mut resp = req.into_ok_response()?;
 
         if let Ok(form) = serde_json::from_slice::<FormData>(&buf) {
-            write!(
-                resp,
-                "Set color temperature to {}K, brightness to {} with speed {}...",
-                form.temperature, form.brightness, form.speed
-            )?;
-            *light_brightness_target.write().unwrap() = form.brightness;
-            *light_temperature_target.write().unwrap() = form.temperature;
-            *light_dim_speed.write().unwrap() = form.speed;   
+            let result = zones.write().unwrap().update(&form.zones, |state| {
+                state.brightness = form.brightness;
+                state.set_temperature(form.temperature);
+                state.dim_speed = form.speed;
+            });
+            match result {
+                Ok(()) => {
+                    write!(
+                        resp,
+                        "Set color temperature to {}K, brightness to {} with speed {}...",
+                        form.temperature, form.brightness, form.speed
+                    )?;
+                    natural_light.write().unwrap().override_manually(chrono::Utc::now());
+                },
+                Err(err) => resp.write_all(err.to_string().as_bytes())?,
+            }
         } else {
             resp.write_all("JSON error".as_bytes())?;
         }
@@ -137,6 +223,612 @@ pub fn run_server(
         Ok(())
     })?;
 
+    server.fn_handler::<anyhow::Error, _>("/scenes", Method::Get, |req| {
+        let json = serde_json::to_string(&*scenes.read().unwrap())?;
+        req.into_response(200, None, &[("Content-Type", "application/json")])?
+            .write_all(json.as_bytes())?;
+        Ok(())
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/scenes", Method::Post, |mut req| {
+        let Some(buf) = read_body(&mut req, MAX_LEN_SCENE)? else {
+            req.into_status_response(413)?.write_all("Request too big".as_bytes())?;
+            return Ok(());
+        };
+        let scene = match serde_json::from_slice::<Scene>(&buf) {
+            Ok(scene) => scene,
+            Err(err) => {
+                req.into_status_response(400)?.write_all(format!("JSON error: {}", err).as_bytes())?;
+                return Ok(());
+            },
+        };
+
+        let name = scene.name.clone();
+        let mut scenes = scenes.write().unwrap();
+        if let Err(err) = scenes.insert(scene) {
+            req.into_status_response(400)?.write_all(err.to_string().as_bytes())?;
+            return Ok(());
+        }
+        storage.lock().unwrap().save(KEY_SCENES, &*scenes)?;
+        info!(target: function_name!(), "Stored scene '{}'.", name);
+        write!(req.into_ok_response()?, "Stored scene '{}'.", name)?;
+        Ok(())
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/scenes/recall", Method::Post, |mut req| {
+        let Some(buf) = read_body(&mut req, MAX_LEN)? else {
+            req.into_status_response(413)?.write_all("Request too big".as_bytes())?;
+            return Ok(());
+        };
+        let Ok(form) = serde_json::from_slice::<FormDataSceneName>(&buf) else {
+            req.into_status_response(400)?.write_all("JSON error".as_bytes())?;
+            return Ok(());
+        };
+
+        match scenes.read().unwrap().get(&form.name) {
+            Some(scene) => {
+                recall_scene(scene, &zones);
+                natural_light.write().unwrap().override_manually(chrono::Utc::now());
+                write!(req.into_ok_response()?, "Recalled scene '{}'.", form.name)?;
+            },
+            None => {
+                write!(req.into_status_response(404)?, "No scene named '{}'.", form.name)?;
+            },
+        }
+        Ok(())
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/scenes/delete", Method::Post, |mut req| {
+        let Some(buf) = read_body(&mut req, MAX_LEN)? else {
+            req.into_status_response(413)?.write_all("Request too big".as_bytes())?;
+            return Ok(());
+        };
+        let Ok(form) = serde_json::from_slice::<FormDataSceneName>(&buf) else {
+            req.into_status_response(400)?.write_all("JSON error".as_bytes())?;
+            return Ok(());
+        };
+
+        let mut scenes = scenes.write().unwrap();
+        match scenes.remove(&form.name) {
+            Ok(_) => {
+                storage.lock().unwrap().save(KEY_SCENES, &*scenes)?;
+                info!(target: function_name!(), "Deleted scene '{}'.", form.name);
+                write!(req.into_ok_response()?, "Deleted scene '{}'.", form.name)?;
+            },
+            Err(err) => {
+                req.into_status_response(404)?.write_all(err.to_string().as_bytes())?;
+            },
+        }
+        Ok(())
+    })?;
+
+    // Running effects by zone name
+    server.fn_handler::<anyhow::Error, _>("/effect", Method::Get, |req| {
+        let zones = zones.read().unwrap();
+        let effects: serde_json::Map<String, serde_json::Value> = zones.config().zones.iter().zip(zones.states())
+            .map(|(zone, state)| Ok((zone.name.clone(), serde_json::to_value(&state.effect)?)))
+            .collect::<Result<_, serde_json::Error>>()?;
+        let json = serde_json::to_string(&effects)?;
+        req.into_response(200, None, &[("Content-Type", "application/json")])?
+            .write_all(json.as_bytes())?;
+        Ok(())
+    })?;
+
+    // Starts an effect on top of the current light of some zones, or stops it if
+    // `effect` is `null`. Effects are not persisted, they end with a reboot or the next scene.
+    server.fn_handler::<anyhow::Error, _>("/effect", Method::Post, |mut req| {
+        let Some(buf) = read_body(&mut req, MAX_LEN)? else {
+            req.into_status_response(413)?.write_all("Request too big".as_bytes())?;
+            return Ok(());
+        };
+        let FormDataEffect { effect, zones: selector } = match serde_json::from_slice::<FormDataEffect>(&buf) {
+            Ok(form) => form,
+            Err(err) => {
+                req.into_status_response(400)?.write_all(format!("JSON error: {}", err).as_bytes())?;
+                return Ok(());
+            },
+        };
+        if let Some(Err(err)) = effect.as_ref().map(EffectConfig::validate) {
+            req.into_status_response(400)?.write_all(err.to_string().as_bytes())?;
+            return Ok(());
+        }
+
+        let message = match &effect {
+            Some(effect) => format!("Started effect '{}'.", effect.name()),
+            None => "Stopped effect.".to_string(),
+        };
+        if let Err(err) = zones.write().unwrap().update(&selector, |state| state.effect = effect.clone()) {
+            req.into_status_response(404)?.write_all(err.to_string().as_bytes())?;
+            return Ok(());
+        }
+        info!(target: function_name!(), "{}", message);
+        req.into_ok_response()?.write_all(message.as_bytes())?;
+        Ok(())
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/zones", Method::Get, |req| {
+        let json = serde_json::to_string(zones.read().unwrap().config())?;
+        req.into_response(200, None, &[("Content-Type", "application/json")])?
+            .write_all(json.as_bytes())?;
+        Ok(())
+    })?;
+
+    // The LED task assigns the output channels to zones when it starts, so a new
+    // zone config is only stored here and takes effect after a restart.
+    server.fn_handler::<anyhow::Error, _>("/zones", Method::Post, |mut req| {
+        let Some(buf) = read_body(&mut req, MAX_LEN_ZONES)? else {
+            req.into_status_response(413)?.write_all("Request too big".as_bytes())?;
+            return Ok(());
+        };
+        let config = match serde_json::from_slice::<ZoneConfig>(&buf) {
+            Ok(config) => config,
+            Err(err) => {
+                req.into_status_response(400)?.write_all(format!("JSON error: {}", err).as_bytes())?;
+                return Ok(());
+            },
+        };
+        if let Err(err) = config.validate() {
+            req.into_status_response(400)?.write_all(err.to_string().as_bytes())?;
+            return Ok(());
+        }
+        if let Err(err) = validate_zone_ids(config.zones.iter().map(|zone| zone.name.as_str())) {
+            req.into_status_response(400)?.write_all(err.to_string().as_bytes())?;
+            return Ok(());
+        }
+
+        storage.lock().unwrap().save(KEY_ZONES, &config)?;
+        info!(target: function_name!(), "Stored zone config with {} zones.", config.zones.len());
+        req.into_ok_response()?.write_all("Stored zone config. It takes effect after a restart.".as_bytes())?;
+        Ok(())
+    })?;
+
+    // Current targets by zone name
+    server.fn_handler::<anyhow::Error, _>("/zones/state", Method::Get, |req| {
+        let zones = zones.read().unwrap();
+        let states: serde_json::Map<String, serde_json::Value> = zones.config().zones.iter().zip(zones.states())
+            .map(|(zone, state)| Ok((zone.name.clone(), serde_json::to_value(state)?)))
+            .collect::<Result<_, serde_json::Error>>()?;
+        let json = serde_json::to_string(&states)?;
+        req.into_response(200, None, &[("Content-Type", "application/json")])?
+            .write_all(json.as_bytes())?;
+        Ok(())
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/schedule", Method::Get, |req| {
+        let json = serde_json::to_string(&*schedule.read().unwrap())?;
+        req.into_response(200, None, &[("Content-Type", "application/json")])?
+            .write_all(json.as_bytes())?;
+        Ok(())
+    })?;
+
+    // Replaces the whole schedule. Entries may refer to scenes which don't exist (yet),
+    // those are skipped with a warning when they are due.
+    server.fn_handler::<anyhow::Error, _>("/schedule", Method::Post, |mut req| {
+        let Some(buf) = read_body(&mut req, MAX_LEN_SCHEDULE)? else {
+            req.into_status_response(413)?.write_all("Request too big".as_bytes())?;
+            return Ok(());
+        };
+        let entries = match serde_json::from_slice::<Vec<ScheduleEntry>>(&buf) {
+            Ok(entries) => entries,
+            Err(err) => {
+                req.into_status_response(400)?.write_all(format!("JSON error: {}", err).as_bytes())?;
+                return Ok(());
+            },
+        };
+
+        storage.lock().unwrap().save(KEY_SCHEDULE, &entries)?;
+        let count = entries.len();
+        *schedule.write().unwrap() = entries;
+        info!(target: function_name!(), "Stored schedule with {} entries.", count);
+        write!(req.into_ok_response()?, "Stored schedule with {} entries.", count)?;
+        Ok(())
+    })?;
+
+    // Today's sun events in local time, mostly to check the location before
+    // relying on sun triggers in the schedule.
+    server.fn_handler::<anyhow::Error, _>("/sun", Method::Get, |req| {
+        let Some(location) = *location.read().unwrap() else {
+            req.into_status_response(404)?.write_all("Location is unknown".as_bytes())?;
+            return Ok(());
+        };
+        let tz: Tz = CONFIG.time_zone.parse().map_err(|err| anyhow!("Invalid time zone: {}", err))?;
+        let now = chrono::Utc::now();
+        let today = now.with_timezone(&tz).date_naive();
+        let mut events = serde_json::Map::new();
+        for event in [SunEvent::NauticalDawn, SunEvent::CivilDawn, SunEvent::Sunrise, SunEvent::Sunset, SunEvent::CivilDusk, SunEvent::NauticalDusk] {
+            let time = sun_event(today, event, &location).map(|time| time.with_timezone(&tz).naive_local());
+            events.insert(serde_json::to_value(event)?.as_str().unwrap_or_default().to_string(), serde_json::to_value(time)?);
+        }
+        let json = serde_json::json!({
+            "location": location,
+            "elevation": solar_elevation(now, &location),
+            "events": events,
+        });
+        req.into_response(200, None, &[("Content-Type", "application/json")])?
+            .write_all(json.to_string().as_bytes())?;
+        Ok(())
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/rules", Method::Get, |req| {
+        let json = serde_json::to_string(rules.read().unwrap().rules())?;
+        req.into_response(200, None, &[("Content-Type", "application/json")])?
+            .write_all(json.as_bytes())?;
+        Ok(())
+    })?;
+
+    // Replaces all rules. Like the schedule, rules may refer to scenes which don't exist (yet).
+    server.fn_handler::<anyhow::Error, _>("/rules", Method::Post, |mut req| {
+        let Some(buf) = read_body(&mut req, MAX_LEN_RULES)? else {
+            req.into_status_response(413)?.write_all("Request too big".as_bytes())?;
+            return Ok(());
+        };
+        let new_rules = match serde_json::from_slice::<Vec<Rule>>(&buf) {
+            Ok(new_rules) => new_rules,
+            Err(err) => {
+                req.into_status_response(400)?.write_all(format!("JSON error: {}", err).as_bytes())?;
+                return Ok(());
+            },
+        };
+        if let Err(err) = validate_rules(&new_rules) {
+            req.into_status_response(400)?.write_all(err.to_string().as_bytes())?;
+            return Ok(());
+        }
+
+        storage.lock().unwrap().save(KEY_RULES, &new_rules)?;
+        let count = new_rules.len();
+        rules.write().unwrap().set_rules(new_rules);
+        info!(target: function_name!(), "Stored {} rules.", count);
+        write!(req.into_ok_response()?, "Stored {} rules.", count)?;
+        Ok(())
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/circadian", Method::Get, |req| {
+        let json = serde_json::to_string(natural_light.read().unwrap().config())?;
+        req.into_response(200, None, &[("Content-Type", "application/json")])?
+            .write_all(json.as_bytes())?;
+        Ok(())
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/circadian", Method::Post, |mut req| {
+        let Some(buf) = read_body(&mut req, MAX_LEN_SCHEDULE)? else {
+            req.into_status_response(413)?.write_all("Request too big".as_bytes())?;
+            return Ok(());
+        };
+        let config = match serde_json::from_slice::<CircadianConfig>(&buf) {
+            Ok(config) => config,
+            Err(err) => {
+                req.into_status_response(400)?.write_all(format!("JSON error: {}", err).as_bytes())?;
+                return Ok(());
+            },
+        };
+        if let Err(err) = config.curve.validate() {
+            req.into_status_response(400)?.write_all(err.to_string().as_bytes())?;
+            return Ok(());
+        }
+
+        storage.lock().unwrap().save(KEY_CIRCADIAN, &config)?;
+        natural_light.write().unwrap().set_config(config);
+        info!(target: function_name!(), "Stored natural light config.");
+        req.into_ok_response()?.write_all("Stored natural light config.".as_bytes())?;
+        Ok(())
+    })?;
+
+    // Ends a manual override early, so that the light follows the curve again.
+    server.fn_handler::<anyhow::Error, _>("/circadian/resume", Method::Post, |req| {
+        natural_light.write().unwrap().resume();
+        req.into_ok_response()?.write_all("Resumed.".as_bytes())?;
+        Ok(())
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/daylight", Method::Get, |req| {
+        let json = serde_json::to_string(daylight.read().unwrap().config())?;
+        req.into_response(200, None, &[("Content-Type", "application/json")])?
+            .write_all(json.as_bytes())?;
+        Ok(())
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/daylight", Method::Post, |mut req| {
+        let Some(buf) = read_body(&mut req, MAX_LEN_SCENE)? else {
+            req.into_status_response(413)?.write_all("Request too big".as_bytes())?;
+            return Ok(());
+        };
+        let config = match serde_json::from_slice::<DaylightConfig>(&buf) {
+            Ok(config) => config,
+            Err(err) => {
+                req.into_status_response(400)?.write_all(format!("JSON error: {}", err).as_bytes())?;
+                return Ok(());
+            },
+        };
+        if let Err(err) = config.validate() {
+            req.into_status_response(400)?.write_all(err.to_string().as_bytes())?;
+            return Ok(());
+        }
+
+        storage.lock().unwrap().save(KEY_DAYLIGHT, &config)?;
+        daylight.write().unwrap().set_config(config);
+        info!(target: function_name!(), "Stored daylight harvesting config.");
+        req.into_ok_response()?.write_all("Stored daylight harvesting config.".as_bytes())?;
+        Ok(())
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/color_matching", Method::Get, |req| {
+        let json = serde_json::to_string(color_matcher.read().unwrap().config())?;
+        req.into_response(200, None, &[("Content-Type", "application/json")])?
+            .write_all(json.as_bytes())?;
+        Ok(())
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/color_matching", Method::Post, |mut req| {
+        let Some(buf) = read_body(&mut req, MAX_LEN_SCENE)? else {
+            req.into_status_response(413)?.write_all("Request too big".as_bytes())?;
+            return Ok(());
+        };
+        let config = match serde_json::from_slice::<ColorMatchingConfig>(&buf) {
+            Ok(config) => config,
+            Err(err) => {
+                req.into_status_response(400)?.write_all(format!("JSON error: {}", err).as_bytes())?;
+                return Ok(());
+            },
+        };
+        if let Err(err) = config.validate() {
+            req.into_status_response(400)?.write_all(err.to_string().as_bytes())?;
+            return Ok(());
+        }
+
+        storage.lock().unwrap().save(KEY_COLOR_MATCHING, &config)?;
+        color_matcher.write().unwrap().set_config(config);
+        info!(target: function_name!(), "Stored color matching config.");
+        req.into_ok_response()?.write_all("Stored color matching config.".as_bytes())?;
+        Ok(())
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/occupancy", Method::Get, |req| {
+        let json = serde_json::to_string(occupancy.read().unwrap().config())?;
+        req.into_response(200, None, &[("Content-Type", "application/json")])?
+            .write_all(json.as_bytes())?;
+        Ok(())
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/occupancy", Method::Post, |mut req| {
+        let Some(buf) = read_body(&mut req, MAX_LEN_SCENE)? else {
+            req.into_status_response(413)?.write_all("Request too big".as_bytes())?;
+            return Ok(());
+        };
+        let config = match serde_json::from_slice::<OccupancyConfig>(&buf) {
+            Ok(config) => config,
+            Err(err) => {
+                req.into_status_response(400)?.write_all(format!("JSON error: {}", err).as_bytes())?;
+                return Ok(());
+            },
+        };
+        if let Err(err) = config.validate() {
+            req.into_status_response(400)?.write_all(err.to_string().as_bytes())?;
+            return Ok(());
+        }
+
+        storage.lock().unwrap().save(KEY_OCCUPANCY, &config)?;
+        occupancy.write().unwrap().set_config(config);
+        info!(target: function_name!(), "Stored occupancy config.");
+        req.into_ok_response()?.write_all("Stored occupancy config.".as_bytes())?;
+        Ok(())
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/corridor", Method::Get, |req| {
+        let json = serde_json::to_string(corridor.read().unwrap().config())?;
+        req.into_response(200, None, &[("Content-Type", "application/json")])?
+            .write_all(json.as_bytes())?;
+        Ok(())
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/corridor", Method::Post, |mut req| {
+        let Some(buf) = read_body(&mut req, MAX_LEN_ZONES)? else {
+            req.into_status_response(413)?.write_all("Request too big".as_bytes())?;
+            return Ok(());
+        };
+        let config = match serde_json::from_slice::<CorridorConfig>(&buf) {
+            Ok(config) => config,
+            Err(err) => {
+                req.into_status_response(400)?.write_all(format!("JSON error: {}", err).as_bytes())?;
+                return Ok(());
+            },
+        };
+        if let Err(err) = config.validate() {
+            req.into_status_response(400)?.write_all(err.to_string().as_bytes())?;
+            return Ok(());
+        }
+
+        storage.lock().unwrap().save(KEY_CORRIDOR, &config)?;
+        corridor.write().unwrap().set_config(config);
+        info!(target: function_name!(), "Stored corridor config.");
+        req.into_ok_response()?.write_all("Stored corridor config.".as_bytes())?;
+        Ok(())
+    })?;
+
+    // The sensors of the other lamps are not read by this one, so their
+    // detections have to be forwarded here, e.g. by home automation.
+    server.fn_handler::<anyhow::Error, _>("/corridor/detect", Method::Post, |mut req| {
+        let Some(buf) = read_body(&mut req, MAX_LEN)? else {
+            req.into_status_response(413)?.write_all("Request too big".as_bytes())?;
+            return Ok(());
+        };
+        let detection = match serde_json::from_slice::<CorridorDetection>(&buf) {
+            Ok(detection) => detection,
+            Err(err) => {
+                req.into_status_response(400)?.write_all(format!("JSON error: {}", err).as_bytes())?;
+                return Ok(());
+            },
+        };
+        if let Err(err) = corridor.write().unwrap().detect_named(&detection) {
+            req.into_status_response(400)?.write_all(err.to_string().as_bytes())?;
+            return Ok(());
+        }
+        req.into_ok_response()?.write_all("Switched on the corridor.".as_bytes())?;
+        Ok(())
+    })?;
+
+    // The sensor tasks are started with the stored slots, so a new config takes
+    // effect after a restart. An empty right slot is used for the GPS module.
+    server.fn_handler::<anyhow::Error, _>("/sensor-slots", Method::Get, |req| {
+        let slots: SensorSlots = storage.lock().unwrap().load_or_default(KEY_SENSOR_SLOTS);
+        let json = serde_json::to_string(&slots)?;
+        req.into_response(200, None, &[("Content-Type", "application/json")])?
+            .write_all(json.as_bytes())?;
+        Ok(())
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/sensor-slots", Method::Post, |mut req| {
+        let Some(buf) = read_body(&mut req, MAX_LEN)? else {
+            req.into_status_response(413)?.write_all("Request too big".as_bytes())?;
+            return Ok(());
+        };
+        let slots = match serde_json::from_slice::<SensorSlots>(&buf) {
+            Ok(slots) => slots,
+            Err(err) => {
+                req.into_status_response(400)?.write_all(format!("JSON error: {}", err).as_bytes())?;
+                return Ok(());
+            },
+        };
+
+        storage.lock().unwrap().save(KEY_SENSOR_SLOTS, &slots)?;
+        info!(target: function_name!(), "Stored sensor slots {:?}.", slots);
+        req.into_ok_response()?.write_all("Stored sensor slots. They take effect after a restart.".as_bytes())?;
+        Ok(())
+    })?;
+
+    // The password is not sent back. A config without a password keeps the stored
+    // one, and an empty password or null removes it.
+    server.fn_handler::<anyhow::Error, _>("/mqtt", Method::Get, |req| {
+        let config = MqttConfig { password: None, ..mqtt.read().unwrap().clone() };
+        let json = serde_json::to_string(&config)?;
+        req.into_response(200, None, &[("Content-Type", "application/json")])?
+            .write_all(json.as_bytes())?;
+        Ok(())
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/mqtt", Method::Post, |mut req| {
+        let Some(buf) = read_body(&mut req, MAX_LEN_SCENE)? else {
+            req.into_status_response(413)?.write_all("Request too big".as_bytes())?;
+            return Ok(());
+        };
+        let stored_password = mqtt.read().unwrap().password.clone();
+        let config = match MqttConfig::from_update(&buf, stored_password) {
+            Ok(config) => config,
+            Err(err) => {
+                req.into_status_response(400)?.write_all(format!("JSON error: {}", err).as_bytes())?;
+                return Ok(());
+            },
+        };
+        if let Err(err) = config.validate() {
+            req.into_status_response(400)?.write_all(err.to_string().as_bytes())?;
+            return Ok(());
+        }
+
+        storage.lock().unwrap().save(KEY_MQTT, &config)?;
+        // The MQTT task reconnects with the new config
+        *mqtt.write().unwrap() = config;
+        info!(target: function_name!(), "Stored MQTT config.");
+        req.into_ok_response()?.write_all("Stored MQTT config.".as_bytes())?;
+        Ok(())
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/dmx", Method::Get, |req| {
+        let json = serde_json::to_string(dmx.read().unwrap().config())?;
+        req.into_response(200, None, &[("Content-Type", "application/json")])?
+            .write_all(json.as_bytes())?;
+        Ok(())
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/dmx/status", Method::Get, |req| {
+        let json = serde_json::to_string(&dmx.write().unwrap().status())?;
+        req.into_response(200, None, &[("Content-Type", "application/json")])?
+            .write_all(json.as_bytes())?;
+        Ok(())
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/dmx", Method::Post, |mut req| {
+        let Some(buf) = read_body(&mut req, MAX_LEN_SCENE)? else {
+            req.into_status_response(413)?.write_all("Request too big".as_bytes())?;
+            return Ok(());
+        };
+        let config = match serde_json::from_slice::<DmxConfig>(&buf) {
+            Ok(config) => config,
+            Err(err) => {
+                req.into_status_response(400)?.write_all(format!("JSON error: {}", err).as_bytes())?;
+                return Ok(());
+            },
+        };
+        let valid = config.validate(zones.read().unwrap().config());
+        if let Err(err) = valid {
+            req.into_status_response(400)?.write_all(err.to_string().as_bytes())?;
+            return Ok(());
+        }
+
+        storage.lock().unwrap().save(KEY_DMX, &config)?;
+        dmx.write().unwrap().set_config(config);
+        info!(target: function_name!(), "Stored DMX config.");
+        req.into_ok_response()?.write_all("Stored DMX config.".as_bytes())?;
+        Ok(())
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/alarm", Method::Get, |req| {
+        let json = serde_json::to_string(alarm.read().unwrap().config())?;
+        req.into_response(200, None, &[("Content-Type", "application/json")])?
+            .write_all(json.as_bytes())?;
+        Ok(())
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/alarm", Method::Post, |mut req| {
+        let Some(buf) = read_body(&mut req, MAX_LEN_SCENE)? else {
+            req.into_status_response(413)?.write_all("Request too big".as_bytes())?;
+            return Ok(());
+        };
+        let config = match serde_json::from_slice::<AlarmConfig>(&buf) {
+            Ok(config) => config,
+            Err(err) => {
+                req.into_status_response(400)?.write_all(format!("JSON error: {}", err).as_bytes())?;
+                return Ok(());
+            },
+        };
+        if let Err(err) = config.validate() {
+            req.into_status_response(400)?.write_all(err.to_string().as_bytes())?;
+            return Ok(());
+        }
+
+        storage.lock().unwrap().save(KEY_ALARM, &config)?;
+        alarm.write().unwrap().set_config(config);
+        info!(target: function_name!(), "Stored alarm config.");
+        req.into_ok_response()?.write_all("Stored alarm config.".as_bytes())?;
+        Ok(())
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/alarm/snooze", Method::Post, |req| {
+        alarm.write().unwrap().snooze(chrono::Utc::now());
+        req.into_ok_response()?.write_all("Snoozed.".as_bytes())?;
+        Ok(())
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/alarm/stop", Method::Post, |req| {
+        alarm.write().unwrap().stop();
+        req.into_ok_response()?.write_all("Stopped.".as_bytes())?;
+        Ok(())
+    })?;
+
+    // Raw serial data of the sensors, which the replay tool can decode on a host
+    server.fn_handler::<anyhow::Error, _>("/capture", Method::Get, |req| {
+        // Formatting takes a while, and the sensor tasks must not wait for the lock meanwhile
+        let buffer = capture.lock().unwrap().clone();
+        let text = buffer.to_text();
+        req.into_response(200, None, &[("Content-Type", "text/plain")])?
+            .write_all(text.as_bytes())?;
+        Ok(())
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/capture/clear", Method::Post, |req| {
+        capture.lock().unwrap().clear();
+        req.into_ok_response()?.write_all("Cleared capture.".as_bytes())?;
+        Ok(())
+    })?;
+
     server.fn_handler::<anyhow::Error, _>("/ota/start", Method::Post, |req| {
         info!(target: function_name!(), "Got ota start request.");
         *update_requested.write().unwrap() = true;
@@ -145,10 +837,99 @@ pub fn run_server(
         Ok(())
     })?;
 
+    let api_lamp = ApiLamp {
+        zones: zones.clone(),
+        natural_light: natural_light.clone(),
+        occupancy: occupancy.clone(),
+        corridor: corridor.clone(),
+        ambient,
+        thermal: thermal.clone(),
+        voltage: voltage.clone(),
+        current: current.clone(),
+    };
+    let api_lamp = &api_lamp;
+    let api_path = format!("{}/*", api::PREFIX);
+    // All methods go to the API, which answers those a resource does not support with 405
+    let api_methods = [
+        (Method::Get, api::Method::Get),
+        (Method::Put, api::Method::Put),
+        (Method::Patch, api::Method::Patch),
+        (Method::Post, api::Method::Post),
+        (Method::Delete, api::Method::Delete),
+    ];
+    for (method, api_method) in api_methods {
+        server.fn_handler::<anyhow::Error, _>(&api_path, method, move |mut req| {
+            let Some(buf) = read_body(&mut req, MAX_LEN_SCENE)? else {
+                req.into_status_response(413)?.write_all(r#"{"error":{"code":"too_large","message":"Request too big"}}"#.as_bytes())?;
+                return Ok(());
+            };
+            let path = req.uri().to_string();
+            let response = api::handle(api_lamp, api_method, &path, &buf);
+            req.into_response(response.status, None, &[("Content-Type", "application/json")])?
+                .write_all(response.body.as_bytes())?;
+            Ok(())
+        })?;
+    }
+
+    // Live state for the web interface. New clients get the whole state, and
+    // then only changes, which the loop below pushes.
+    let clients: Mutex<Vec<(i32, EspHttpWsDetachedSender)>> = Mutex::new(Vec::new());
+    let clients = &clients;
+    let outputs = &outputs;
+    server.ws_handler("/ws", move |ws: &mut EspHttpWsConnection| -> Result<()> {
+        if ws.is_new() {
+            info!(target: function_name!(), "WebSocket client {} connected.", ws.session());
+            let snapshot = Snapshot::new(api_lamp, outputs.read().unwrap().clone());
+            for message in snapshot.messages() {
+                ws.send(FrameType::Text(false), serde_json::to_string(&message)?.as_bytes())?;
+            }
+            clients.lock().unwrap().push((ws.session(), ws.create_detached_sender()?));
+        } else if ws.is_closed() {
+            info!(target: function_name!(), "WebSocket client {} disconnected.", ws.session());
+            clients.lock().unwrap().retain(|(session, _)| *session != ws.session());
+        } else {
+            // Clients change the lamp through the API, so anything they send is ignored
+            let (_, len) = ws.recv(&mut [])?;
+            ws.recv(&mut vec![0; len])?;
+        }
+        return Ok(());
+    })?;
+
     info!(target: function_name!(), "Handlers attached.");
 
+    let mut publisher = Publisher::new(uptime_ms);
     loop {
-        trace!(target: function_name!(), "Inside server keep-alive loop.");
-        std::thread::sleep(core::time::Duration::from_millis(2000));
+        std::thread::sleep(PUSH_INTERVAL);
+        let snapshot = Snapshot::new(api_lamp, outputs.read().unwrap().clone());
+        let messages = publisher.update(&snapshot);
+        if !messages.is_empty() {
+            push(clients, &messages);
+        }
+    }
+}
+
+/// Sends messages to all WebSocket clients, and forgets the clients which can't be reached.
+#[named]
+fn push(clients: &Mutex<Vec<(i32, EspHttpWsDetachedSender)>>, messages: &[PushMessage]) {
+    let frames: Vec<String> = messages.iter().filter_map(|message| serde_json::to_string(message).ok()).collect();
+    clients.lock().unwrap().retain_mut(|(session, sender)| {
+        for frame in &frames {
+            if let Err(err) = sender.send(FrameType::Text(false), frame.as_bytes()) {
+                warn!(target: function_name!(), "Dropping WebSocket client {}: {:?}", session, err);
+                return false;
+            }
+        }
+        return true;
+    });
+}
+
+/// Reads the whole body of a request. Returns `None` if the body is longer than `max_len`.
+fn read_body(req: &mut Request<&mut EspHttpConnection<'_>>, max_len: usize) -> Result<Option<Vec<u8>>> {
+    let len = req.header("Content-Length").and_then(|v| v.parse::<u64>().ok()).unwrap_or(0) as usize;
+    if len > max_len {
+        return Ok(None);
     }
+    let mut buf = vec![0; len];
+    req.read_exact(&mut buf)?;
+    return Ok(Some(buf));
 }
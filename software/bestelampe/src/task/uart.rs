@@ -16,8 +16,12 @@
 use crate::prelude::*;
 
 use std::time::Duration;
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
 use chrono::Utc;
+use abstraktelampe::capture::{CaptureBuffer, CaptureSource};
+use abstraktelampe::sun::Location;
+
+use crate::capture::Recorder;
 
 use std::io::BufReader;
 use std::io::BufRead;
@@ -28,11 +32,12 @@ use esp_idf_hal::{
     delay::TickType,
     gpio::AnyIOPin,
     uart, 
-    uart::{UART1, config::*},
+    uart::{UART0, config::*},
     //io::BufRead,
 };
 
 use nmea_parser::{
+    gnss::GgaQualityIndicator,
     NmeaParser,
     ParsedMessage,
 };
@@ -41,10 +46,12 @@ use nmea_parser::{
 pub fn test_uart(
     pin_rx: AnyIOPin,
     pin_tx: AnyIOPin,
-    uart_device: UART1,
+    uart_device: UART0,
     time_offset: Arc<RwLock<i64>>,
+    location: Arc<RwLock<Option<Location>>>,
+    capture: Arc<Mutex<CaptureBuffer>>,
 ) ->  Result<()>  {
-    info!(target: function_name!(), "Connecting to GPIO 17 to sample the sensor");
+    info!(target: function_name!(), "Connecting to the GPS module in the right sensor slot");
 
     std::thread::sleep(core::time::Duration::from_millis(500));
 
@@ -77,7 +84,7 @@ pub fn test_uart(
 
     let mut cycles_without_data = 0;
     info!(target: function_name!(), "Try to read stuff...");
-    let mut buf_reader: BufReader<_> = BufReader::new(ToStd::new(uart));
+    let mut buf_reader: BufReader<_> = BufReader::new(ToStd::new(Recorder::new(uart, capture, CaptureSource::Gps)));
     let mut parser = NmeaParser::new();
     loop {
         let mut line = String::new();
@@ -100,7 +107,15 @@ pub fn test_uart(
                         },
                         _ => {}
                     }
-                   
+                    if rmc.status_active == Some(true) {
+                        update_location(&location, rmc.latitude, rmc.longitude);
+                    }
+                }
+                Ok(ParsedMessage::Gga(gga)) => {
+                    // A missing quality field is parsed as invalid, too
+                    if gga.quality != GgaQualityIndicator::Invalid {
+                        update_location(&location, gga.latitude, gga.longitude);
+                    }
                 }
                 _ => {
                 }
@@ -114,3 +129,22 @@ pub fn test_uart(
         }
     }
 }
+
+/// Stores a GPS fix as the lamp's location. The location only changes
+/// noticeably for sun events when the lamp is moved by several kilometers,
+/// so small jitter of the fix is not even logged.
+#[named]
+fn update_location(location: &RwLock<Option<Location>>, latitude: Option<f64>, longitude: Option<f64>) {
+    if let (Some(latitude), Some(longitude)) = (latitude, longitude) {
+        let fix = Location::new(latitude, longitude);
+        let mut location_write = location.write().unwrap();
+        let moved = match *location_write {
+            Some(previous) => (previous.latitude - latitude).abs() > 0.1 || (previous.longitude - longitude).abs() > 0.1,
+            None => true,
+        };
+        if moved {
+            info!(target: function_name!(), "Location from GPS: {:.4}, {:.4}", latitude, longitude);
+        }
+        *location_write = Some(fix);
+    }
+}
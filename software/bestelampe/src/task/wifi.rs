@@ -31,10 +31,9 @@ const CHANNEL: u8 = 11;
 /// Does not have any retry-loop or error handling.
 /// Method returns when the wifi is ready to be used.
 #[named]
-pub fn start_wifi(modem: Modem, as_access_point: bool) -> Result<()> {
+pub fn start_wifi(modem: Modem, nvs: EspDefaultNvsPartition, as_access_point: bool) -> Result<()> {
     info!(target: function_name!(), "Inside 'start_wifi'...");
     let sys_loop = EspSystemEventLoop::take()?;
-    let nvs = EspDefaultNvsPartition::take()?;
 
     let ipv4_client_cfg =
         esp_idf_svc::ipv4::ClientConfiguration::DHCP(esp_idf_svc::ipv4::DHCPClientSettings {
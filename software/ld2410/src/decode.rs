@@ -0,0 +1,184 @@
+// SPDX-FileCopyrightText: 2024 Lena Schimmel <mail@lenaschimmel.de>
+// SPDX-License-Identifier: CERN-OHL-S-2.0+
+// This file is part of besteLampe!.
+// 
+// besteLampe! is free software: you can redistribute it and/or modify it under the
+// terms of the GNU General Public License as published by the Free Software Foundation, 
+// either version 3 of the License, or (at your option) any later version.
+// 
+// besteLampe! is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; 
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. 
+// See the GNU General Public License for more details.
+// 
+// You should have received a copy of the GNU General Public License along with besteLampe!.
+// If not, see <https://www.gnu.org/licenses/>. 
+
+use crate::*;
+
+/// Decodes frames from a stream of bytes which arrive in arbitrary chunks, e.g.
+/// from a UART. Bytes before a frame header are skipped, so the decoder finds
+/// the start of the next frame after line noise or a corrupted frame.
+///
+/// All bytes are kept in a fixed buffer, so decoding never allocates.
+pub struct Decoder {
+    buffer: heapless::Vec<u8, MAX_FRAME_LEN>,
+    skipped: usize,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+/// Whether `bytes` start with a frame header, or with the start of one if they
+/// are shorter than a header.
+fn starts_with_header(bytes: &[u8]) -> bool {
+    let len = bytes.len().min(DATA_HEADER.len());
+    return bytes[..len] == DATA_HEADER[..len] || bytes[..len] == COMMAND_HEADER[..len];
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        return Self { buffer: heapless::Vec::new(), skipped: 0 };
+    }
+
+    /// Number of bytes which were skipped because they did not belong to a frame.
+    pub fn skipped_bytes(&self) -> usize {
+        return self.skipped;
+    }
+
+    /// Returns all frames which are complete after adding `chunk`. Errors are
+    /// returned for frames that are corrupted or not supported, and decoding
+    /// continues with the bytes after them.
+    pub fn decode<'d, 'c>(&'d mut self, chunk: &'c [u8]) -> Frames<'d, 'c> {
+        return Frames { decoder: self, chunk };
+    }
+
+    /// Adds as many bytes as fit into the buffer, and returns how many that were.
+    fn feed(&mut self, chunk: &[u8]) -> usize {
+        let count = chunk.len().min(self.buffer.capacity() - self.buffer.len());
+        // Can't fail, because only the free space is filled
+        let _ = self.buffer.extend_from_slice(&chunk[..count]);
+        return count;
+    }
+
+    fn discard(&mut self, count: usize) {
+        self.buffer.copy_within(count.., 0);
+        self.buffer.truncate(self.buffer.len() - count);
+    }
+
+    /// Discards everything before the first frame header. The start of a header
+    /// at the end is kept.
+    fn resync(&mut self) {
+        let start = (0..self.buffer.len())
+            .find(|&i| starts_with_header(&self.buffer[i..]))
+            .unwrap_or(self.buffer.len());
+        self.skipped += start;
+        self.discard(start);
+    }
+
+    /// Returns the first frame in the buffer, or `None` if more bytes are needed.
+    fn next_frame(&mut self) -> Option<Result<Frame, Error>> {
+        self.resync();
+        if self.buffer.len() >= 6 {
+            let data_len = u16::from_le_bytes([self.buffer[4], self.buffer[5]]) as usize;
+            if FRAME_OVERHEAD + data_len > MAX_FRAME_LEN {
+                // Most likely, the length is corrupted. Search for the next header.
+                self.discard(1);
+                return Some(Err(Error::TooLong));
+            }
+        }
+        return match ld_parser(&self.buffer) {
+            Ok((rest, frame)) => {
+                self.discard(self.buffer.len() - rest.len());
+                Some(Ok(frame))
+            },
+            Err(Error::Truncated) => None,
+            Err(error) if error.is_corrupted() => {
+                // The header may have been line noise, and a real frame may start within this one
+                self.discard(1);
+                Some(Err(error))
+            },
+            Err(error) => {
+                // The frame was intact, but could not be interpreted, so skip all of it
+                let data_len = u16::from_le_bytes([self.buffer[4], self.buffer[5]]) as usize;
+                self.discard(FRAME_OVERHEAD + data_len);
+                Some(Err(error))
+            },
+        };
+    }
+}
+
+/// Iterator over the frames which are completed by a chunk of bytes.
+pub struct Frames<'d, 'c> {
+    decoder: &'d mut Decoder,
+    chunk: &'c [u8],
+}
+
+impl Iterator for Frames<'_, '_> {
+    type Item = Result<Frame, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(result) = self.decoder.next_frame() {
+                return Some(result);
+            }
+            if self.chunk.is_empty() {
+                return None;
+            }
+            let count = self.decoder.feed(self.chunk);
+            self.chunk = &self.chunk[count..];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+    use super::*;
+    use crate::tests::TEST_INPUT;
+
+    fn decode_in_chunks(input: &[u8], chunk_size: usize) -> (Vec<Result<Frame, Error>>, Decoder) {
+        let mut decoder = Decoder::new();
+        let mut results = Vec::new();
+        for chunk in input.chunks(chunk_size) {
+            results.extend(decoder.decode(chunk));
+        }
+        return (results, decoder);
+    }
+
+    #[test]
+    fn test_chunks() {
+        for chunk_size in 1..=TEST_INPUT.len() {
+            let (results, decoder) = decode_in_chunks(&TEST_INPUT, chunk_size);
+            assert_eq!(results.len(), 3, "Chunk size {}", chunk_size);
+            assert!(matches!(&results[1], Ok(Frame::Report(report)) if report.target.state == TargetState::Both));
+            assert_eq!(results[2], Ok(Frame::Ack(Ack::ConfigurationEnded)));
+            // The garbage before each frame
+            assert_eq!(decoder.skipped_bytes(), 4);
+        }
+    }
+
+    #[test]
+    fn test_resync() {
+        let input = hex!(
+            "f4 f3 00 fd"                                      // Garbage, including partial headers
+            "fd fc fb fa 04 00 fe 01 00 00 f8 f7 f6 f5"        // Wrong tail
+            "f4 fd fc fb fa 04 00 fe 01 00 00 04 03 02 01"     // Stray header byte before a good frame
+            "f4 f3 f2 f1 02 00 07 aa f8 f7 f6 f5"              // Unknown data type
+            "fd fc fb fa ff ff"                                // Corrupted length
+            "fd fc fb fa 04 00 64 01 01 00 04 03 02 01"
+        );
+        for chunk_size in 1..=input.len() {
+            let (results, _) = decode_in_chunks(&input, chunk_size);
+            assert_eq!(results, vec![
+                Err(Error::BadTail),
+                Ok(Frame::Ack(Ack::ConfigurationEnded)),
+                Err(Error::UnknownDataType(0x07)),
+                Err(Error::TooLong),
+                Ok(Frame::Rejected(0x0064)),
+            ], "Chunk size {}", chunk_size);
+        }
+    }
+}
@@ -0,0 +1,367 @@
+// SPDX-FileCopyrightText: 2024 Lena Schimmel <mail@lenaschimmel.de>
+// SPDX-License-Identifier: CERN-OHL-S-2.0+
+// This file is part of besteLampe!.
+// 
+// besteLampe! is free software: you can redistribute it and/or modify it under the
+// terms of the GNU General Public License as published by the Free Software Foundation, 
+// either version 3 of the License, or (at your option) any later version.
+// 
+// besteLampe! is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; 
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. 
+// See the GNU General Public License for more details.
+// 
+// You should have received a copy of the GNU General Public License along with besteLampe!.
+// If not, see <https://www.gnu.org/licenses/>. 
+
+use core::fmt;
+
+use embedded_io::{Read, ReadReady, Write};
+
+use crate::*;
+
+pub use uptime_clock::Clock;
+
+/// How long to wait for the answer to a command.
+pub const COMMAND_TIMEOUT_MS: u64 = 1000;
+
+/// How long to sleep between reads while waiting for the answer to a command.
+const POLL_INTERVAL_MS: u64 = 10;
+
+/// The sensor reports about ten times per second. After this long without any
+/// frame, it is reported as lost.
+pub const LOST_TIMEOUT_MS: u64 = 5000;
+
+/// Number of events which are kept while waiting for the answer to a command.
+/// If more arrive, the oldest ones are dropped.
+const MAX_QUEUED_EVENTS: usize = 16;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DriverError<E> {
+    Io(E),
+    /// The sensor did not answer a command in time.
+    Timeout,
+    /// The sensor answered that it did not accept the command.
+    Rejected,
+}
+
+impl<E: fmt::Debug> fmt::Display for DriverError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DriverError::Io(error) => write!(f, "Communication with the sensor failed: {:?}", error),
+            DriverError::Timeout => write!(f, "The sensor did not answer in time."),
+            DriverError::Rejected => write!(f, "The sensor rejected the command."),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<E: fmt::Debug> std::error::Error for DriverError<E> {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SensorEvent {
+    Report(Report),
+    /// Bytes were received which could not be decoded.
+    Error(Error),
+    /// The sensor stopped sending reports.
+    Lost,
+    /// The sensor sends reports again after it was lost.
+    Recovered,
+}
+
+fn push_event(events: &mut heapless::Deque<SensorEvent, MAX_QUEUED_EVENTS>, event: SensorEvent) {
+    if events.is_full() {
+        events.pop_front();
+    }
+    let _ = events.push_back(event);
+}
+
+/// Driver for the LD2410 on a serial port.
+pub struct Ld2410<U, C> {
+    uart: U,
+    clock: C,
+    decoder: Decoder,
+    events: heapless::Deque<SensorEvent, MAX_QUEUED_EVENTS>,
+    /// The latest ack, which has not been matched with a command yet.
+    answer: Option<Frame>,
+    /// Time at which the last frame was received.
+    last_seen: u64,
+    lost: bool,
+}
+
+impl<U: Read + ReadReady + Write, C: Clock> Ld2410<U, C> {
+    pub fn new(uart: U, clock: C) -> Self {
+        let now = clock.now_ms();
+        return Self {
+            uart,
+            clock,
+            decoder: Decoder::new(),
+            events: heapless::Deque::new(),
+            answer: None,
+            last_seen: now,
+            lost: false,
+        };
+    }
+
+    pub fn release(self) -> U {
+        return self.uart;
+    }
+
+    pub fn clock(&self) -> &C {
+        return &self.clock;
+    }
+
+    /// Whether the sensor did not send any frames for `LOST_TIMEOUT_MS`.
+    pub fn is_lost(&self) -> bool {
+        return self.lost;
+    }
+
+    /// Checks that the sensor answers, and reads its firmware version.
+    pub fn init(&mut self) -> Result<FirmwareVersion, DriverError<U::Error>> {
+        return match self.command(Command::ReadFirmwareVersion)? {
+            Ack::FirmwareVersion(version) => Ok(version),
+            _ => unreachable!("Only matching answers are returned."),
+        };
+    }
+
+    /// Sends a command and waits for its answer. The sensor is switched into
+    /// configuration mode for the command, and back afterwards. All reports which
+    /// arrive in the meantime are kept, and returned by `events`.
+    pub fn command(&mut self, command: Command) -> Result<Ack, DriverError<U::Error>> {
+        self.raw_command(&Command::EnableConfiguration)?;
+        let result = self.raw_command(&command);
+        // The sensor leaves configuration mode by itself when it restarts
+        if command != Command::Restart {
+            self.raw_command(&Command::EndConfiguration)?;
+        }
+        return result;
+    }
+
+    /// Returns the events which are available right now, without blocking.
+    /// Should be called regularly, so that a lost sensor is noticed.
+    pub fn events(&mut self) -> Events<'_, U, C> {
+        return Events { driver: self, failed: false };
+    }
+
+    /// Sends a single command, without switching to configuration mode.
+    fn raw_command(&mut self, command: &Command) -> Result<Ack, DriverError<U::Error>> {
+        self.answer = None;
+        self.uart.write_all(&command.encode()).map_err(DriverError::Io)?;
+        self.uart.flush().map_err(DriverError::Io)?;
+        let start = self.clock.now_ms();
+        loop {
+            match self.answer.take() {
+                Some(Frame::Ack(ack)) if command.is_answered_by(&Frame::Ack(ack.clone())) => return Ok(ack),
+                Some(Frame::Rejected(word)) if word == command.word() => return Err(DriverError::Rejected),
+                _ => (),
+            }
+            if self.clock.now_ms() - start > COMMAND_TIMEOUT_MS {
+                return Err(DriverError::Timeout);
+            }
+            self.clock.sleep_ms(POLL_INTERVAL_MS);
+            self.receive()?;
+        }
+    }
+
+    /// Reads and decodes all bytes that are available, and notices when the
+    /// sensor stops sending.
+    fn receive(&mut self) -> Result<(), DriverError<U::Error>> {
+        let now = self.clock.now_ms();
+        while self.uart.read_ready().map_err(DriverError::Io)? {
+            let mut buffer = [0_u8; 64];
+            let len = self.uart.read(&mut buffer).map_err(DriverError::Io)?;
+            for result in self.decoder.decode(&buffer[..len]) {
+                match result {
+                    Ok(frame) => {
+                        self.last_seen = now;
+                        if self.lost {
+                            self.lost = false;
+                            push_event(&mut self.events, SensorEvent::Recovered);
+                        }
+                        match frame {
+                            Frame::Report(report) => push_event(&mut self.events, SensorEvent::Report(report)),
+                            answer => self.answer = Some(answer),
+                        }
+                    },
+                    Err(error) => push_event(&mut self.events, SensorEvent::Error(error)),
+                }
+            }
+        }
+
+        if !self.lost && now - self.last_seen > LOST_TIMEOUT_MS {
+            self.lost = true;
+            push_event(&mut self.events, SensorEvent::Lost);
+        }
+        return Ok(());
+    }
+}
+
+/// Iterator over the events which are available without blocking. It ends
+/// after the first communication error.
+pub struct Events<'d, U, C> {
+    driver: &'d mut Ld2410<U, C>,
+    failed: bool,
+}
+
+impl<U: Read + ReadReady + Write, C: Clock> Iterator for Events<'_, U, C> {
+    type Item = Result<SensorEvent, DriverError<U::Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        if self.driver.events.is_empty() {
+            if let Err(error) = self.driver.receive() {
+                self.failed = true;
+                return Some(Err(error));
+            }
+        }
+        return self.driver.events.pop_front().map(Ok);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::collections::VecDeque;
+    use std::rc::Rc;
+
+    use super::*;
+
+    /// Serial port which answers commands like a sensor would, and advances the
+    /// clock whenever the driver waits for data.
+    struct FakeSerial {
+        time: Rc<Cell<u64>>,
+        rx: VecDeque<u8>,
+        tx: Vec<u8>,
+        answers: Vec<(Command, Frame)>,
+        broken: bool,
+    }
+
+    impl FakeSerial {
+        fn new(time: Rc<Cell<u64>>, answers: Vec<(Command, Frame)>) -> Self {
+            return Self { time, rx: VecDeque::new(), tx: Vec::new(), answers, broken: false };
+        }
+
+        fn send(&mut self, frame: &Frame) {
+            self.rx.extend(frame.encode());
+        }
+    }
+
+    impl embedded_io::ErrorType for FakeSerial {
+        type Error = embedded_io::ErrorKind;
+    }
+
+    impl Read for FakeSerial {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            // Deliver in small chunks, like a UART would
+            let len = buf.len().min(self.rx.len()).min(7);
+            for byte in buf.iter_mut().take(len) {
+                *byte = self.rx.pop_front().unwrap();
+            }
+            return Ok(len);
+        }
+    }
+
+    impl ReadReady for FakeSerial {
+        fn read_ready(&mut self) -> Result<bool, Self::Error> {
+            if self.broken {
+                return Err(embedded_io::ErrorKind::BrokenPipe);
+            }
+            if self.rx.is_empty() {
+                self.time.set(self.time.get() + 10);
+            }
+            return Ok(!self.rx.is_empty());
+        }
+    }
+
+    impl Write for FakeSerial {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            self.tx.extend_from_slice(buf);
+            let answer = self.answers.iter()
+                .find(|(command, _)| &command.encode()[..] == buf)
+                .map(|(_, answer)| answer.encode());
+            if let Some(answer) = answer {
+                self.rx.extend(answer);
+            }
+            return Ok(buf.len());
+        }
+
+        fn flush(&mut self) -> Result<(), Self::Error> {
+            return Ok(());
+        }
+    }
+
+    fn driver(answers: Vec<(Command, Frame)>) -> (Ld2410<FakeSerial, impl Clock>, Rc<Cell<u64>>) {
+        let time = Rc::new(Cell::new(0));
+        let clock_time = time.clone();
+        let driver = Ld2410::new(FakeSerial::new(time.clone(), answers), move || clock_time.get());
+        return (driver, time);
+    }
+
+    fn report(state: TargetState, distance: u16) -> Report {
+        return Report {
+            target: Target {
+                state,
+                moving_distance: distance,
+                moving_energy: 50,
+                stationary_distance: distance,
+                stationary_energy: 50,
+                detection_distance: distance,
+            },
+            engineering: None,
+        };
+    }
+
+    const VERSION: FirmwareVersion = FirmwareVersion { major: 1, minor: 0x07, build: 0x22062416 };
+
+    #[test]
+    fn test_commands_in_configuration_mode() {
+        let (mut driver, _) = driver(vec![
+            (Command::EnableConfiguration, Frame::Ack(Ack::ConfigurationEnabled { protocol_version: 1, buffer_size: 64 })),
+            (Command::EndConfiguration, Frame::Ack(Ack::ConfigurationEnded)),
+            (Command::ReadFirmwareVersion, Frame::Ack(Ack::FirmwareVersion(VERSION))),
+            (Command::SetEngineeringMode(true), Frame::Rejected(0x0062)),
+        ]);
+        // A report arrives before the answer, and is kept
+        driver.uart.send(&Frame::Report(report(TargetState::Moving, 120)));
+
+        assert_eq!(driver.init(), Ok(VERSION));
+        let mut expected = Vec::new();
+        expected.extend(Command::EnableConfiguration.encode());
+        expected.extend(Command::ReadFirmwareVersion.encode());
+        expected.extend(Command::EndConfiguration.encode());
+        assert_eq!(driver.uart.tx, expected);
+
+        assert_eq!(driver.command(Command::SetEngineeringMode(true)), Err(DriverError::Rejected));
+        assert_eq!(driver.command(Command::ReadParameters), Err(DriverError::Timeout));
+
+        let events: Vec<_> = driver.events().collect();
+        assert_eq!(events, vec![Ok(SensorEvent::Report(report(TargetState::Moving, 120)))]);
+    }
+
+    #[test]
+    fn test_lost_sensor() {
+        let (mut driver, time) = driver(vec![]);
+        driver.uart.send(&Frame::Report(report(TargetState::Stationary, 80)));
+        assert_eq!(driver.events().count(), 1);
+
+        time.set(LOST_TIMEOUT_MS + 100);
+        assert_eq!(driver.events().collect::<Vec<_>>(), vec![Ok(SensorEvent::Lost)]);
+        assert!(driver.is_lost());
+        assert_eq!(driver.events().count(), 0);
+
+        driver.uart.send(&Frame::Report(report(TargetState::None, 0)));
+        assert_eq!(driver.events().collect::<Vec<_>>(), vec![
+            Ok(SensorEvent::Recovered),
+            Ok(SensorEvent::Report(report(TargetState::None, 0))),
+        ]);
+        assert!(!driver.is_lost());
+    }
+
+    #[test]
+    fn test_events_end_after_error() {
+        let (mut driver, _) = driver(vec![]);
+        driver.uart.broken = true;
+        assert_eq!(driver.events().collect::<Vec<_>>(), vec![Err(DriverError::Io(embedded_io::ErrorKind::BrokenPipe))]);
+    }
+}
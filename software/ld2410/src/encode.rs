@@ -0,0 +1,269 @@
+// SPDX-FileCopyrightText: 2024 Lena Schimmel <mail@lenaschimmel.de>
+// SPDX-License-Identifier: CERN-OHL-S-2.0+
+// This file is part of besteLampe!.
+// 
+// besteLampe! is free software: you can redistribute it and/or modify it under the
+// terms of the GNU General Public License as published by the Free Software Foundation, 
+// either version 3 of the License, or (at your option) any later version.
+// 
+// besteLampe! is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; 
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. 
+// See the GNU General Public License for more details.
+// 
+// You should have received a copy of the GNU General Public License along with besteLampe!.
+// If not, see <https://www.gnu.org/licenses/>. 
+
+use crate::*;
+
+/// Length of a frame without any data: header, data length and tail.
+pub const FRAME_OVERHEAD: usize = DATA_HEADER.len() + 2 + DATA_TAIL.len();
+
+/// Maximum length of the frames which this crate can encode.
+pub const MAX_FRAME_LEN: usize = FRAME_OVERHEAD + MAX_DATA_LEN;
+
+pub type FrameBuffer = heapless::Vec<u8, MAX_FRAME_LEN>;
+
+/// Value of a command, which consists of up to three parameters.
+pub type CommandValue = heapless::Vec<u8, 18>;
+
+/// Builds a frame, or returns `None` if the data is longer than `MAX_DATA_LEN`.
+pub fn encode_frame(header: &[u8; 4], data: &[u8], tail: &[u8; 4]) -> Option<FrameBuffer> {
+    if data.len() > MAX_DATA_LEN {
+        return None;
+    }
+    let mut frame = FrameBuffer::new();
+    // Can't fail, because the length was checked above
+    let _ = frame.extend_from_slice(header);
+    let _ = frame.extend_from_slice(&(data.len() as u16).to_le_bytes());
+    let _ = frame.extend_from_slice(data);
+    let _ = frame.extend_from_slice(tail);
+    return Some(frame);
+}
+
+/// Builds a command frame, or an ack frame if `word` has the `ACK_BIT` set.
+fn encode_command_frame(word: u16, value: &[u8]) -> Option<FrameBuffer> {
+    let mut data: heapless::Vec<u8, MAX_DATA_LEN> = heapless::Vec::new();
+    data.extend_from_slice(&word.to_le_bytes()).ok()?;
+    data.extend_from_slice(value).ok()?;
+    return encode_frame(&COMMAND_HEADER, &data, &COMMAND_TAIL);
+}
+
+/// Commands which can be sent to the sensor. All commands but `EnableConfiguration`
+/// are only accepted in configuration mode.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    EnableConfiguration,
+    EndConfiguration,
+    /// Sets the farthest gates in which targets are detected, and how long the
+    /// sensor keeps reporting a target after it is gone, in seconds.
+    SetMaxGatesAndDuration { moving_gate: u8, stationary_gate: u8, unoccupied_duration: u16 },
+    ReadParameters,
+    /// In engineering mode, the sensor additionally reports the energy of each gate.
+    SetEngineeringMode(bool),
+    /// Sets the sensitivities from 0 to 100 of one gate, or of all gates if `gate` is `None`.
+    SetGateSensitivity { gate: Option<u8>, moving: u8, stationary: u8 },
+    ReadFirmwareVersion,
+    /// Takes effect after a restart.
+    FactoryReset,
+    Restart,
+}
+
+/// Parameter of a command, which is a parameter word and a 32 bit value.
+fn parameter(value: &mut CommandValue, word: u16, parameter: u32) {
+    // Can't fail, because no command has more than three parameters
+    let _ = value.extend_from_slice(&word.to_le_bytes());
+    let _ = value.extend_from_slice(&parameter.to_le_bytes());
+}
+
+impl Command {
+    pub fn word(&self) -> u16 {
+        return match self {
+            Command::EnableConfiguration => 0x00ff,
+            Command::EndConfiguration => 0x00fe,
+            Command::SetMaxGatesAndDuration { .. } => 0x0060,
+            Command::ReadParameters => 0x0061,
+            Command::SetEngineeringMode(true) => 0x0062,
+            Command::SetEngineeringMode(false) => 0x0063,
+            Command::SetGateSensitivity { .. } => 0x0064,
+            Command::ReadFirmwareVersion => 0x00a0,
+            Command::FactoryReset => 0x00a2,
+            Command::Restart => 0x00a3,
+        };
+    }
+
+    pub fn value(&self) -> CommandValue {
+        let mut value = CommandValue::new();
+        match self {
+            // Protocol version
+            Command::EnableConfiguration => { let _ = value.extend_from_slice(&1_u16.to_le_bytes()); },
+            Command::SetMaxGatesAndDuration { moving_gate, stationary_gate, unoccupied_duration } => {
+                parameter(&mut value, 0x0000, *moving_gate as u32);
+                parameter(&mut value, 0x0001, *stationary_gate as u32);
+                parameter(&mut value, 0x0002, *unoccupied_duration as u32);
+            },
+            Command::SetGateSensitivity { gate, moving, stationary } => {
+                parameter(&mut value, 0x0000, gate.map_or(0xffff, |gate| gate as u32));
+                parameter(&mut value, 0x0001, *moving as u32);
+                parameter(&mut value, 0x0002, *stationary as u32);
+            },
+            _ => (),
+        }
+        return value;
+    }
+
+    /// Whether `frame` is the sensor's answer to this command.
+    pub fn is_answered_by(&self, frame: &Frame) -> bool {
+        return match frame {
+            Frame::Ack(ack) => ack_word(ack) == self.word(),
+            Frame::Rejected(word) => *word == self.word(),
+            Frame::Report(_) => false,
+        };
+    }
+
+    pub fn encode(&self) -> FrameBuffer {
+        return encode_command_frame(self.word(), &self.value()).expect("The value of all commands fits into a frame.");
+    }
+}
+
+/// Command word of the command which is answered by `ack`.
+fn ack_word(ack: &Ack) -> u16 {
+    return match ack {
+        Ack::ConfigurationEnabled { .. } => 0x00ff,
+        Ack::ConfigurationEnded => 0x00fe,
+        Ack::MaxGatesAndDurationSet => 0x0060,
+        Ack::Parameters(_) => 0x0061,
+        Ack::EngineeringMode(true) => 0x0062,
+        Ack::EngineeringMode(false) => 0x0063,
+        Ack::GateSensitivitySet => 0x0064,
+        Ack::FirmwareVersion(_) => 0x00a0,
+        Ack::FactoryReset => 0x00a2,
+        Ack::Restart => 0x00a3,
+    };
+}
+
+impl Frame {
+    /// Builds the frame as the sensor would send it. This is mostly useful to
+    /// simulate a sensor.
+    pub fn encode(&self) -> FrameBuffer {
+        let mut data: heapless::Vec<u8, MAX_DATA_LEN> = heapless::Vec::new();
+        // Can't fail, because the longest frame, with parameters for 9 gates, has 28 bytes of data
+        let mut push = |bytes: &[u8]| { let _ = data.extend_from_slice(bytes); };
+        match self {
+            Frame::Report(report) => {
+                let target = &report.target;
+                push(&[if report.engineering.is_some() { 0x01 } else { 0x02 }, HEAD, target.state.to_byte()]);
+                push(&target.moving_distance.to_le_bytes());
+                push(&[target.moving_energy]);
+                push(&target.stationary_distance.to_le_bytes());
+                push(&[target.stationary_energy]);
+                push(&target.detection_distance.to_le_bytes());
+                if let Some(engineering) = &report.engineering {
+                    push(&[engineering.max_moving_gate, engineering.max_stationary_gate]);
+                    push(&engineering.moving_energies);
+                    push(&engineering.stationary_energies);
+                }
+                push(&DATA_END);
+                return encode_frame(&DATA_HEADER, &data, &DATA_TAIL).expect("All reports fit into a frame.");
+            },
+            Frame::Rejected(word) => {
+                push(&(word | ACK_BIT).to_le_bytes());
+                push(&1_u16.to_le_bytes());
+            },
+            Frame::Ack(ack) => {
+                push(&(ack_word(ack) | ACK_BIT).to_le_bytes());
+                // Status: success
+                push(&0_u16.to_le_bytes());
+                match ack {
+                    Ack::ConfigurationEnabled { protocol_version, buffer_size } => {
+                        push(&protocol_version.to_le_bytes());
+                        push(&buffer_size.to_le_bytes());
+                    },
+                    Ack::Parameters(parameters) => {
+                        push(&[HEAD, parameters.max_gate, parameters.max_moving_gate, parameters.max_stationary_gate]);
+                        push(&parameters.moving_sensitivities);
+                        push(&parameters.stationary_sensitivities);
+                        push(&parameters.unoccupied_duration.to_le_bytes());
+                    },
+                    Ack::FirmwareVersion(version) => {
+                        // Firmware type, which is always 0
+                        push(&[0x00, 0x00, version.minor, version.major]);
+                        push(&version.build.to_le_bytes());
+                    },
+                    _ => (),
+                }
+            },
+        }
+        return encode_frame(&COMMAND_HEADER, &data, &COMMAND_TAIL).expect("All acks fit into a frame.");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+    use super::*;
+
+    #[test]
+    fn test_encode_commands() {
+        assert_eq!(&Command::EnableConfiguration.encode()[..], &hex!("fd fc fb fa 04 00 ff 00 01 00 04 03 02 01"));
+        assert_eq!(&Command::EndConfiguration.encode()[..], &hex!("fd fc fb fa 02 00 fe 00 04 03 02 01"));
+        assert_eq!(&Command::SetEngineeringMode(true).encode()[..], &hex!("fd fc fb fa 02 00 62 00 04 03 02 01"));
+        assert_eq!(
+            &Command::SetMaxGatesAndDuration { moving_gate: 8, stationary_gate: 8, unoccupied_duration: 5 }.encode()[..],
+            &hex!("fd fc fb fa 14 00 60 00 00 00 08 00 00 00 01 00 08 00 00 00 02 00 05 00 00 00 04 03 02 01"),
+        );
+        assert_eq!(
+            &Command::SetGateSensitivity { gate: None, moving: 40, stationary: 40 }.encode()[..],
+            &hex!("fd fc fb fa 14 00 64 00 00 00 ff ff 00 00 01 00 28 00 00 00 02 00 28 00 00 00 04 03 02 01"),
+        );
+        assert_eq!(encode_frame(&DATA_HEADER, &[0; MAX_DATA_LEN + 1], &DATA_TAIL), None);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let gates = |values: &[u8]| GateValues::from_slice(values).unwrap();
+        let target = Target {
+            state: TargetState::Moving,
+            moving_distance: 240,
+            moving_energy: 77,
+            stationary_distance: 0,
+            stationary_energy: 0,
+            detection_distance: 240,
+        };
+        let frames = [
+            Frame::Report(Report { target: target.clone(), engineering: None }),
+            Frame::Report(Report { target, engineering: Some(Engineering {
+                max_moving_gate: 4,
+                max_stationary_gate: 2,
+                moving_energies: gates(&[10, 20, 30, 40, 50]),
+                stationary_energies: gates(&[5, 6, 7]),
+            })}),
+            Frame::Ack(Ack::ConfigurationEnabled { protocol_version: 1, buffer_size: 64 }),
+            Frame::Ack(Ack::ConfigurationEnded),
+            Frame::Ack(Ack::Parameters(Parameters {
+                max_gate: 8,
+                max_moving_gate: 6,
+                max_stationary_gate: 5,
+                moving_sensitivities: gates(&[50, 50, 40, 30, 20, 15, 15, 15, 15]),
+                stationary_sensitivities: gates(&[0, 0, 40, 40, 30, 30, 20, 20, 20]),
+                unoccupied_duration: 5,
+            })),
+            Frame::Ack(Ack::EngineeringMode(false)),
+            Frame::Ack(Ack::FirmwareVersion(FirmwareVersion { major: 2, minor: 0x04, build: 0x23031617 })),
+            Frame::Ack(Ack::Restart),
+            Frame::Rejected(0x0061),
+        ];
+        for frame in frames {
+            let encoded = frame.encode();
+            assert_eq!(ld_parser(&encoded), Ok((&[][..], frame)));
+        }
+    }
+
+    #[test]
+    fn test_commands_are_answered() {
+        let command = Command::SetEngineeringMode(false);
+        assert!(command.is_answered_by(&Frame::Ack(Ack::EngineeringMode(false))));
+        assert!(command.is_answered_by(&Frame::Rejected(0x0063)));
+        assert!(!command.is_answered_by(&Frame::Ack(Ack::EngineeringMode(true))));
+        assert!(!Command::ReadParameters.is_answered_by(&Frame::Ack(Ack::ConfigurationEnded)));
+    }
+}
@@ -0,0 +1,471 @@
+// SPDX-FileCopyrightText: 2024 Lena Schimmel <mail@lenaschimmel.de>
+// SPDX-License-Identifier: CERN-OHL-S-2.0+
+// This file is part of besteLampe!.
+// 
+// besteLampe! is free software: you can redistribute it and/or modify it under the
+// terms of the GNU General Public License as published by the Free Software Foundation, 
+// either version 3 of the License, or (at your option) any later version.
+// 
+// besteLampe! is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; 
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. 
+// See the GNU General Public License for more details.
+// 
+// You should have received a copy of the GNU General Public License along with besteLampe!.
+// If not, see <https://www.gnu.org/licenses/>. 
+
+#![cfg_attr(not(feature = "std"), no_std)]
+#![allow(clippy::needless_return)]
+
+//! Protocol of the HLK-LD2410, a 24 GHz mmWave presence sensor. It reports a
+//! target about ten times per second in "data frames", and answers commands
+//! with "ack frames". All numbers are little endian.
+
+use core::fmt;
+
+use nom::IResult;
+use nom::number::complete::{le_u16, le_u32, u8 as byte};
+use nom::bytes::complete::{tag, take};
+
+mod decode;
+mod driver;
+mod encode;
+pub use decode::*;
+pub use driver::*;
+pub use encode::*;
+
+/// First bytes of frames with reports.
+pub const DATA_HEADER: [u8; 4] = [0xf4, 0xf3, 0xf2, 0xf1];
+
+/// Last bytes of frames with reports.
+pub const DATA_TAIL: [u8; 4] = [0xf8, 0xf7, 0xf6, 0xf5];
+
+/// First bytes of commands and their acks.
+pub const COMMAND_HEADER: [u8; 4] = [0xfd, 0xfc, 0xfb, 0xfa];
+
+/// Last bytes of commands and their acks.
+pub const COMMAND_TAIL: [u8; 4] = [0x04, 0x03, 0x02, 0x01];
+
+/// Number of distance gates. Gate 0 is closest to the sensor.
+pub const GATES: usize = 9;
+
+/// Width of a distance gate, in m.
+pub const GATE_WIDTH: f32 = 0.75;
+
+/// Maximum length of the data of the frames which this crate supports.
+pub const MAX_DATA_LEN: usize = 64;
+
+/// Value for each distance gate, from 0 to 100.
+pub type GateValues = heapless::Vec<u8, GATES>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TargetState {
+    None,
+    Moving,
+    Stationary,
+    /// A moving and a stationary target at the same time.
+    Both,
+}
+
+impl TargetState {
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        return match byte {
+            0x00 => Some(TargetState::None),
+            0x01 => Some(TargetState::Moving),
+            0x02 => Some(TargetState::Stationary),
+            0x03 => Some(TargetState::Both),
+            _ => None,
+        };
+    }
+
+    pub fn to_byte(self) -> u8 {
+        return match self {
+            TargetState::None => 0x00,
+            TargetState::Moving => 0x01,
+            TargetState::Stationary => 0x02,
+            TargetState::Both => 0x03,
+        };
+    }
+
+    pub fn is_moving(self) -> bool {
+        return matches!(self, TargetState::Moving | TargetState::Both);
+    }
+
+    pub fn is_stationary(self) -> bool {
+        return matches!(self, TargetState::Stationary | TargetState::Both);
+    }
+}
+
+/// The target which the sensor reports in every data frame. Distances are in cm,
+/// energies from 0 to 100.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Target {
+    pub state: TargetState,
+    pub moving_distance: u16,
+    pub moving_energy: u8,
+    pub stationary_distance: u16,
+    pub stationary_energy: u8,
+    /// Distance of the nearest target of either kind.
+    pub detection_distance: u16,
+}
+
+impl Target {
+    pub fn moving_distance_m(&self) -> f32 {
+        return self.moving_distance as f32 / 100.0;
+    }
+
+    pub fn stationary_distance_m(&self) -> f32 {
+        return self.stationary_distance as f32 / 100.0;
+    }
+
+    pub fn detection_distance_m(&self) -> f32 {
+        return self.detection_distance as f32 / 100.0;
+    }
+}
+
+/// Energies of each distance gate, which the sensor additionally reports in
+/// engineering mode.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Engineering {
+    pub max_moving_gate: u8,
+    pub max_stationary_gate: u8,
+    /// One energy for each gate up to `max_moving_gate`.
+    pub moving_energies: GateValues,
+    /// One energy for each gate up to `max_stationary_gate`.
+    pub stationary_energies: GateValues,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub target: Target,
+    /// Only present in engineering mode.
+    pub engineering: Option<Engineering>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FirmwareVersion {
+    pub major: u8,
+    pub minor: u8,
+    pub build: u32,
+}
+
+impl fmt::Display for FirmwareVersion {
+    /// Formats the version like the manufacturer's tools, e.g. `V1.07.22062416`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "V{}.{:02x}.{:08x}", self.major, self.minor, self.build);
+    }
+}
+
+/// Detection parameters, as read from the sensor.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Parameters {
+    /// Farthest gate that the sensor supports, usually 8.
+    pub max_gate: u8,
+    /// Farthest gate in which moving targets are detected.
+    pub max_moving_gate: u8,
+    /// Farthest gate in which stationary targets are detected.
+    pub max_stationary_gate: u8,
+    /// One sensitivity for each gate up to `max_gate`.
+    pub moving_sensitivities: GateValues,
+    pub stationary_sensitivities: GateValues,
+    /// How long the sensor keeps reporting a target after it is gone, in seconds.
+    pub unoccupied_duration: u16,
+}
+
+/// Successful answers to commands.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Ack {
+    ConfigurationEnabled { protocol_version: u16, buffer_size: u16 },
+    ConfigurationEnded,
+    MaxGatesAndDurationSet,
+    Parameters(Parameters),
+    EngineeringMode(bool),
+    GateSensitivitySet,
+    FirmwareVersion(FirmwareVersion),
+    FactoryReset,
+    Restart,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Frame {
+    Report(Report),
+    Ack(Ack),
+    /// The sensor did not accept the command with this command word, e.g.
+    /// because it was not in configuration mode.
+    Rejected(u16),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// The input ends before the end of the frame. Read more bytes and try again.
+    Truncated,
+    /// The input does not start with a frame header.
+    BadHeader,
+    /// The frame does not end with the frame tail where its length says it should.
+    BadTail,
+    /// The data length is larger than any frame this crate supports, which most
+    /// likely means that it is corrupted.
+    TooLong,
+    UnknownDataType(u8),
+    UnknownCommand(u16),
+    /// The frame has a known type, but its data has the wrong length or a value
+    /// which the protocol does not define.
+    InvalidValue,
+}
+
+impl Error {
+    /// Whether the frame is intact, but of a kind that this crate does not
+    /// understand.
+    pub fn is_unsupported(&self) -> bool {
+        return matches!(self, Error::UnknownDataType(_) | Error::UnknownCommand(_));
+    }
+
+    /// Whether the bytes are not a valid frame at all, e.g. because of line noise.
+    pub fn is_corrupted(&self) -> bool {
+        return matches!(self, Error::Truncated | Error::BadHeader | Error::BadTail | Error::TooLong);
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Truncated => write!(f, "The frame is incomplete."),
+            Error::BadHeader => write!(f, "The frame does not start with a frame header."),
+            Error::BadTail => write!(f, "The frame does not end with the frame tail."),
+            Error::TooLong => write!(f, "The frame is longer than any supported frame."),
+            Error::UnknownDataType(data_type) => write!(f, "The data type {:#04x} is unknown.", data_type),
+            Error::UnknownCommand(command) => write!(f, "The ack for command {:#06x} is unknown.", command),
+            Error::InvalidValue => write!(f, "The data of the frame is invalid."),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {}
+
+/// The command word of an ack is the command word of the command, with this bit set.
+pub const ACK_BIT: u16 = 0x0100;
+
+/// Marks the start of the target in data frames, and of the parameters in their ack.
+const HEAD: u8 = 0xaa;
+
+/// Last bytes of the data of data frames.
+const DATA_END: [u8; 2] = [0x55, 0x00];
+
+/// Errors of the nom parsers don't matter, because all of them mean that the
+/// data of a frame of known type is invalid.
+type PResult<'a, T> = IResult<&'a [u8], T, ()>;
+
+fn gate_values(input: &[u8], max_gate: u8) -> PResult<'_, GateValues> {
+    let (input, values) = take(max_gate as usize + 1)(input)?;
+    let values = GateValues::from_slice(values).map_err(|_| nom::Err::Error(()))?;
+    return Ok((input, values));
+}
+
+fn parse_target(input: &[u8]) -> PResult<'_, Target> {
+    let (input, state) = byte(input)?;
+    let state = TargetState::from_byte(state).ok_or(nom::Err::Error(()))?;
+    let (input, moving_distance) = le_u16(input)?;
+    let (input, moving_energy) = byte(input)?;
+    let (input, stationary_distance) = le_u16(input)?;
+    let (input, stationary_energy) = byte(input)?;
+    let (input, detection_distance) = le_u16(input)?;
+    return Ok((input, Target { state, moving_distance, moving_energy, stationary_distance, stationary_energy, detection_distance }));
+}
+
+fn parse_engineering(input: &[u8]) -> PResult<'_, Engineering> {
+    let (input, max_moving_gate) = byte(input)?;
+    let (input, max_stationary_gate) = byte(input)?;
+    let (input, moving_energies) = gate_values(input, max_moving_gate)?;
+    let (input, stationary_energies) = gate_values(input, max_stationary_gate)?;
+    return Ok((input, Engineering { max_moving_gate, max_stationary_gate, moving_energies, stationary_energies }));
+}
+
+fn parse_report(data: &[u8]) -> Result<Frame, Error> {
+    let Some((&data_type, input)) = data.split_first() else {
+        return Err(Error::InvalidValue);
+    };
+    if data_type != 0x01 && data_type != 0x02 {
+        return Err(Error::UnknownDataType(data_type));
+    }
+    let parser = |input| -> PResult<'_, Report> {
+        let (input, _) = tag(&[HEAD][..])(input)?;
+        let (input, target) = parse_target(input)?;
+        let (input, engineering) = match data_type {
+            0x01 => parse_engineering(input).map(|(input, engineering)| (input, Some(engineering)))?,
+            _ => (input, None),
+        };
+        // Newer modules add more values in engineering mode, e.g. from a light
+        // sensor. They are ignored.
+        if !input.ends_with(&DATA_END) {
+            return Err(nom::Err::Error(()));
+        }
+        return Ok((&[], Report { target, engineering }));
+    };
+    return match parser(input) {
+        Ok((_, report)) => Ok(Frame::Report(report)),
+        Err(_) => Err(Error::InvalidValue),
+    };
+}
+
+fn parse_parameters(input: &[u8]) -> PResult<'_, Parameters> {
+    let (input, _) = tag(&[HEAD][..])(input)?;
+    let (input, max_gate) = byte(input)?;
+    let (input, max_moving_gate) = byte(input)?;
+    let (input, max_stationary_gate) = byte(input)?;
+    let (input, moving_sensitivities) = gate_values(input, max_gate)?;
+    let (input, stationary_sensitivities) = gate_values(input, max_gate)?;
+    let (input, unoccupied_duration) = le_u16(input)?;
+    return Ok((input, Parameters { max_gate, max_moving_gate, max_stationary_gate, moving_sensitivities, stationary_sensitivities, unoccupied_duration }));
+}
+
+fn parse_firmware_version(input: &[u8]) -> PResult<'_, FirmwareVersion> {
+    let (input, _firmware_type) = le_u16(input)?;
+    let (input, version) = le_u16(input)?;
+    let (input, build) = le_u32(input)?;
+    let [minor, major] = version.to_le_bytes();
+    return Ok((input, FirmwareVersion { major, minor, build }));
+}
+
+fn parse_ack(data: &[u8]) -> Result<Frame, Error> {
+    let (input, (word, status)) = nom::sequence::pair(le_u16, le_u16)(data).map_err(|_: nom::Err<()>| Error::InvalidValue)?;
+    if word & ACK_BIT == 0 {
+        return Err(Error::UnknownCommand(word));
+    }
+    let command = word & !ACK_BIT;
+    if status != 0 {
+        return Ok(Frame::Rejected(command));
+    }
+    let result: PResult<'_, Ack> = match command {
+        0x00ff => nom::sequence::pair(le_u16, le_u16)(input)
+            .map(|(input, (protocol_version, buffer_size))| (input, Ack::ConfigurationEnabled { protocol_version, buffer_size })),
+        0x00fe => Ok((input, Ack::ConfigurationEnded)),
+        0x0060 => Ok((input, Ack::MaxGatesAndDurationSet)),
+        0x0061 => parse_parameters(input).map(|(input, parameters)| (input, Ack::Parameters(parameters))),
+        0x0062 => Ok((input, Ack::EngineeringMode(true))),
+        0x0063 => Ok((input, Ack::EngineeringMode(false))),
+        0x0064 => Ok((input, Ack::GateSensitivitySet)),
+        0x00a0 => parse_firmware_version(input).map(|(input, version)| (input, Ack::FirmwareVersion(version))),
+        0x00a2 => Ok((input, Ack::FactoryReset)),
+        0x00a3 => Ok((input, Ack::Restart)),
+        _ => return Err(Error::UnknownCommand(command)),
+    };
+    return match result {
+        Ok((_, ack)) => Ok(Frame::Ack(ack)),
+        Err(_) => Err(Error::InvalidValue),
+    };
+}
+
+/// Parses a single frame at the start of `input`, and returns the remaining bytes
+/// after it.
+pub fn ld_parser(input: &[u8]) -> Result<(&[u8], Frame), Error> {
+    // Frame layout: header (4), data length (2), data, tail (4)
+    let header_len = input.len().min(4);
+    let tail = if input[..header_len] == DATA_HEADER[..header_len] {
+        DATA_TAIL
+    } else if input[..header_len] == COMMAND_HEADER[..header_len] {
+        COMMAND_TAIL
+    } else {
+        return Err(Error::BadHeader);
+    };
+    if input.len() < 6 {
+        return Err(Error::Truncated);
+    }
+    let data_len = u16::from_le_bytes([input[4], input[5]]) as usize;
+    let tail_index = 6 + data_len;
+    let end = tail_index + tail.len();
+    if input.len() < end {
+        return Err(Error::Truncated);
+    }
+    if input[tail_index..end] != tail {
+        return Err(Error::BadTail);
+    }
+
+    let data = &input[6..tail_index];
+    let frame = if tail == DATA_TAIL { parse_report(data)? } else { parse_ack(data)? };
+    return Ok((&input[end..], frame));
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+    use super::*;
+
+    /// Two basic reports and an ack, each with some garbage before it.
+    pub(crate) const TEST_INPUT: [u8; 64] = hex!(
+        "00 f4 f3 f2 f1 0d 00 02 aa 02 51 00 00 00 00 3b 00 00 55 00 f8 f7 f6 f5"
+        "ff ff f4 f3 f2 f1 0d 00 02 aa 03 2c 01 3c 96 00 28 2c 01 55 00 f8 f7 f6 f5"
+        "f4 fd fc fb fa 04 00 fe 01 00 00 04 03 02 01"
+    );
+
+    #[test]
+    fn test_parse_reports() {
+        let (rest, frame) = ld_parser(&TEST_INPUT[1..]).unwrap();
+        assert_eq!(frame, Frame::Report(Report {
+            target: Target {
+                state: TargetState::Stationary,
+                moving_distance: 0x51,
+                moving_energy: 0,
+                stationary_distance: 0,
+                stationary_energy: 0x3b,
+                detection_distance: 0,
+            },
+            engineering: None,
+        }));
+        assert_eq!(ld_parser(rest), Err(Error::BadHeader));
+
+        let (_, frame) = ld_parser(&rest[2..]).unwrap();
+        let Frame::Report(report) = frame else {
+            panic!("Expected a report, got {:?}", frame);
+        };
+        assert_eq!(report.target.state, TargetState::Both);
+        assert_eq!(report.target.moving_distance_m(), 3.0);
+        assert_eq!(report.target.stationary_distance_m(), 1.5);
+        assert_eq!(report.target.detection_distance_m(), 3.0);
+    }
+
+    #[test]
+    fn test_parse_engineering_report() {
+        // With the additional light sensor value and output pin state of the LD2410B
+        let input = hex!(
+            "f4 f3 f2 f1 23 00 01 aa 03 1e 00 3c 00 00 39 00 00"
+            "08 08 3c 22 05 03 03 04 03 06 05 00 00 39 10 13 06 06 08 04"
+            "c8 01 55 00 f8 f7 f6 f5"
+        );
+        let (rest, frame) = ld_parser(&input).unwrap();
+        assert!(rest.is_empty());
+        let Frame::Report(Report { engineering: Some(engineering), .. }) = frame else {
+            panic!("Expected an engineering report, got {:?}", frame);
+        };
+        assert_eq!(engineering.max_moving_gate, 8);
+        assert_eq!(&engineering.moving_energies[..], &[0x3c, 0x22, 0x05, 0x03, 0x03, 0x04, 0x03, 0x06, 0x05]);
+        assert_eq!(&engineering.stationary_energies[..], &[0x00, 0x00, 0x39, 0x10, 0x13, 0x06, 0x06, 0x08, 0x04]);
+    }
+
+    #[test]
+    fn test_parse_acks() {
+        assert_eq!(
+            ld_parser(&hex!("fd fc fb fa 08 00 ff 01 00 00 01 00 40 00 04 03 02 01")),
+            Ok((&[][..], Frame::Ack(Ack::ConfigurationEnabled { protocol_version: 1, buffer_size: 0x40 }))),
+        );
+        let (_, frame) = ld_parser(&hex!("fd fc fb fa 0c 00 a0 01 00 00 00 00 07 01 16 24 06 22 04 03 02 01")).unwrap();
+        let Frame::Ack(Ack::FirmwareVersion(version)) = frame else {
+            panic!("Expected a firmware version, got {:?}", frame);
+        };
+        assert_eq!(version.to_string(), "V1.07.22062416");
+        assert_eq!(
+            ld_parser(&hex!("fd fc fb fa 04 00 64 01 01 00 04 03 02 01")),
+            Ok((&[][..], Frame::Rejected(0x0064))),
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(ld_parser(&hex!("f4 f3 f2")), Err(Error::Truncated));
+        assert_eq!(ld_parser(&hex!("f4 f3 f2 f1 0d 00 02 aa")), Err(Error::Truncated));
+        assert_eq!(ld_parser(&hex!("fd fc fb fa 02 00 fe 01 f8 f7 f6 f5")), Err(Error::BadTail));
+        assert_eq!(ld_parser(&hex!("f4 f3 f2 f1 02 00 07 aa f8 f7 f6 f5")), Err(Error::UnknownDataType(0x07)));
+        assert_eq!(ld_parser(&hex!("fd fc fb fa 04 00 99 01 00 00 04 03 02 01")), Err(Error::UnknownCommand(0x0099)));
+        // Invalid target state
+        assert_eq!(ld_parser(&hex!("f4 f3 f2 f1 0d 00 02 aa 07 51 00 00 00 00 3b 00 00 55 00 f8 f7 f6 f5")), Err(Error::InvalidValue));
+    }
+}
@@ -0,0 +1,176 @@
+// SPDX-FileCopyrightText: 2024 Lena Schimmel <mail@lenaschimmel.de>
+// SPDX-License-Identifier: CERN-OHL-S-2.0+
+// This file is part of besteLampe!.
+// 
+// besteLampe! is free software: you can redistribute it and/or modify it under the
+// terms of the GNU General Public License as published by the Free Software Foundation, 
+// either version 3 of the License, or (at your option) any later version.
+// 
+// besteLampe! is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; 
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. 
+// See the GNU General Public License for more details.
+// 
+// You should have received a copy of the GNU General Public License along with besteLampe!.
+// If not, see <https://www.gnu.org/licenses/>. 
+
+use crate::*;
+
+/// Decodes frames from a stream of bytes which arrive in arbitrary chunks, e.g.
+/// from a UART. Bytes before a frame header are skipped, so the decoder finds
+/// the start of the next frame after line noise or a corrupted frame.
+///
+/// All bytes are kept in a fixed buffer, so decoding never allocates.
+pub struct Decoder {
+    buffer: heapless::Vec<u8, MAX_FRAME_LEN>,
+    skipped: usize,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        return Self { buffer: heapless::Vec::new(), skipped: 0 };
+    }
+
+    /// Number of bytes which were skipped because they did not belong to a frame.
+    pub fn skipped_bytes(&self) -> usize {
+        return self.skipped;
+    }
+
+    /// Returns all frames which are complete after adding `chunk`. Errors are
+    /// returned for frames that are corrupted or not supported, and decoding
+    /// continues with the bytes after them.
+    pub fn decode<'d, 'c>(&'d mut self, chunk: &'c [u8]) -> Frames<'d, 'c> {
+        return Frames { decoder: self, chunk };
+    }
+
+    /// Adds as many bytes as fit into the buffer, and returns how many that were.
+    fn feed(&mut self, chunk: &[u8]) -> usize {
+        let count = chunk.len().min(self.buffer.capacity() - self.buffer.len());
+        // Can't fail, because only the free space is filled
+        let _ = self.buffer.extend_from_slice(&chunk[..count]);
+        return count;
+    }
+
+    fn discard(&mut self, count: usize) {
+        self.buffer.copy_within(count.., 0);
+        self.buffer.truncate(self.buffer.len() - count);
+    }
+
+    /// Discards everything before the first frame header. A single 0x53 at the
+    /// end may be the start of a header, so it is kept.
+    fn resync(&mut self) {
+        let start = (0..self.buffer.len())
+            .find(|&i| self.buffer[i] == HEADER[0] && (i + 1 == self.buffer.len() || self.buffer[i + 1] == HEADER[1]))
+            .unwrap_or(self.buffer.len());
+        self.skipped += start;
+        self.discard(start);
+    }
+
+    /// Returns the first frame in the buffer, or `None` if more bytes are needed.
+    fn next_frame(&mut self) -> Option<Result<Frame, Error>> {
+        self.resync();
+        if self.buffer.len() >= 6 {
+            let data_len = u16::from_be_bytes([self.buffer[4], self.buffer[5]]) as usize;
+            if FRAME_OVERHEAD + data_len > MAX_FRAME_LEN {
+                // Most likely, the length is corrupted. Search for the next header.
+                self.discard(1);
+                return Some(Err(Error::TooLong));
+            }
+        }
+        return match mr_parser(&self.buffer) {
+            Ok((rest, frame)) => {
+                self.discard(self.buffer.len() - rest.len());
+                Some(Ok(frame))
+            },
+            Err(Error::Truncated) => None,
+            Err(error) if error.is_corrupted() => {
+                // The header may have been line noise, and a real frame may start within this one
+                self.discard(1);
+                Some(Err(error))
+            },
+            Err(error) => {
+                // The frame was intact, but could not be interpreted, so skip all of it
+                let data_len = u16::from_be_bytes([self.buffer[4], self.buffer[5]]) as usize;
+                self.discard(FRAME_OVERHEAD + data_len);
+                Some(Err(error))
+            },
+        };
+    }
+}
+
+/// Iterator over the frames which are completed by a chunk of bytes.
+pub struct Frames<'d, 'c> {
+    decoder: &'d mut Decoder,
+    chunk: &'c [u8],
+}
+
+impl Iterator for Frames<'_, '_> {
+    type Item = Result<Frame, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(result) = self.decoder.next_frame() {
+                return Some(result);
+            }
+            if self.chunk.is_empty() {
+                return None;
+            }
+            let count = self.decoder.feed(self.chunk);
+            self.chunk = &self.chunk[count..];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+    use super::*;
+    use crate::tests::TEST_INPUT;
+
+    fn decode_in_chunks(input: &[u8], chunk_size: usize) -> (Vec<Result<Frame, Error>>, Decoder) {
+        let mut decoder = Decoder::new();
+        let mut results = Vec::new();
+        for chunk in input.chunks(chunk_size) {
+            results.extend(decoder.decode(chunk));
+        }
+        return (results, decoder);
+    }
+
+    #[test]
+    fn test_chunks() {
+        for chunk_size in 1..=TEST_INPUT.len() {
+            let (results, decoder) = decode_in_chunks(&TEST_INPUT, chunk_size);
+            assert_eq!(results.len(), 5, "Chunk size {}", chunk_size);
+            assert_eq!(results[3], Ok(Frame::HumanPresenceReport(HumanPresence::BodyMovementParameter(0x0c))));
+            // The zero bytes between the frames
+            assert_eq!(decoder.skipped_bytes(), 6);
+        }
+    }
+
+    #[test]
+    fn test_resync() {
+        let input = hex!(
+            "00 53 ff 59 53"                     // Garbage, including a partial header
+            "53 59 80 03 00 01 06 99 54 43"      // Bad checksum
+            "53 53 59 80 01 00 01 01 2f 54 43"   // Stray header byte before a good frame
+            "53 59 42 01 00 01 00 f0 54 43"      // Unknown control word
+            "53 59 80 03 ff ff"                  // Corrupted length
+            "53 59 80 03 00 01 06 36 54 43"
+        );
+        for chunk_size in 1..=input.len() {
+            let (results, _) = decode_in_chunks(&input, chunk_size);
+            assert_eq!(results, vec![
+                Err(Error::BadChecksum { expected: 0x36, actual: 0x99 }),
+                Ok(Frame::HumanPresenceReport(HumanPresence::PresenceInformation(Presence::Occupied))),
+                Err(Error::UnknownControlWord(0x42)),
+                Err(Error::TooLong),
+                Ok(Frame::HumanPresenceReport(HumanPresence::BodyMovementParameter(6))),
+            ], "Chunk size {}", chunk_size);
+        }
+    }
+}
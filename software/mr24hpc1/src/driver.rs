@@ -0,0 +1,376 @@
+// SPDX-FileCopyrightText: 2024 Lena Schimmel <mail@lenaschimmel.de>
+// SPDX-License-Identifier: CERN-OHL-S-2.0+
+// This file is part of besteLampe!.
+// 
+// besteLampe! is free software: you can redistribute it and/or modify it under the
+// terms of the GNU General Public License as published by the Free Software Foundation, 
+// either version 3 of the License, or (at your option) any later version.
+// 
+// besteLampe! is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; 
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. 
+// See the GNU General Public License for more details.
+// 
+// You should have received a copy of the GNU General Public License along with besteLampe!.
+// If not, see <https://www.gnu.org/licenses/>. 
+
+use core::fmt;
+
+use embedded_io::{Read, ReadReady, Write};
+
+use crate::*;
+
+pub use uptime_clock::Clock;
+
+/// How long to wait for the answer to a command.
+pub const COMMAND_TIMEOUT_MS: u64 = 1000;
+
+/// How long to sleep between reads while waiting for the answer to a command.
+const POLL_INTERVAL_MS: u64 = 10;
+
+/// After this long without any frame, the driver asks the sensor for a heartbeat.
+pub const HEARTBEAT_INTERVAL_MS: u64 = 10_000;
+
+/// After this long without any frame, the sensor is reported as lost.
+pub const HEARTBEAT_TIMEOUT_MS: u64 = 30_000;
+
+/// Number of events which are kept while waiting for the answer to a command.
+/// If more arrive, the oldest ones are dropped.
+const MAX_QUEUED_EVENTS: usize = 16;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DriverError<E> {
+    Io(E),
+    /// The sensor did not answer a command in time.
+    Timeout,
+}
+
+impl<E: fmt::Debug> fmt::Display for DriverError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DriverError::Io(error) => write!(f, "Communication with the sensor failed: {:?}", error),
+            DriverError::Timeout => write!(f, "The sensor did not answer in time."),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<E: fmt::Debug> std::error::Error for DriverError<E> {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SensorEvent {
+    Frame(Frame),
+    /// Bytes were received which could not be decoded.
+    Error(Error),
+    /// The sensor stopped sending frames, even after it was asked for a heartbeat.
+    Lost,
+    /// The sensor sends frames again after it was lost.
+    Recovered,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceInfo {
+    pub product_model: Text,
+    pub firmware_version: Text,
+}
+
+fn push_event(events: &mut heapless::Deque<SensorEvent, MAX_QUEUED_EVENTS>, event: SensorEvent) {
+    if events.is_full() {
+        events.pop_front();
+    }
+    let _ = events.push_back(event);
+}
+
+/// Driver for the MR24HPC1 on a serial port.
+pub struct Mr24hpc1<U, C> {
+    uart: U,
+    clock: C,
+    decoder: Decoder,
+    events: heapless::Deque<SensorEvent, MAX_QUEUED_EVENTS>,
+    /// Time at which the last frame was received.
+    last_seen: u64,
+    /// Time at which the sensor was last asked for a heartbeat.
+    last_heartbeat_query: u64,
+    lost: bool,
+}
+
+impl<U: Read + ReadReady + Write, C: Clock> Mr24hpc1<U, C> {
+    pub fn new(uart: U, clock: C) -> Self {
+        let now = clock.now_ms();
+        return Self {
+            uart,
+            clock,
+            decoder: Decoder::new(),
+            events: heapless::Deque::new(),
+            last_seen: now,
+            last_heartbeat_query: now,
+            lost: false,
+        };
+    }
+
+    pub fn release(self) -> U {
+        return self.uart;
+    }
+
+    pub fn clock(&self) -> &C {
+        return &self.clock;
+    }
+
+    /// Whether the sensor did not send any frames for `HEARTBEAT_TIMEOUT_MS`.
+    pub fn is_lost(&self) -> bool {
+        return self.lost;
+    }
+
+    /// Checks that the sensor answers, and reads its product information.
+    pub fn init(&mut self) -> Result<DeviceInfo, DriverError<U::Error>> {
+        let product_model = match self.command(Command::QueryProductModel)? {
+            Frame::ProductInformation(ProductInformation::ProductModel(text)) => text,
+            _ => unreachable!("Only matching answers are returned."),
+        };
+        let firmware_version = match self.command(Command::QueryFirmwareVersion)? {
+            Frame::ProductInformation(ProductInformation::FirmwareVersion(text)) => text,
+            _ => unreachable!("Only matching answers are returned."),
+        };
+        return Ok(DeviceInfo { product_model, firmware_version });
+    }
+
+    /// Sends a command and waits for its answer. All other frames which arrive in
+    /// the meantime are kept, and returned by `events`.
+    pub fn command(&mut self, command: Command) -> Result<Frame, DriverError<U::Error>> {
+        self.send(&command)?;
+        let start = self.clock.now_ms();
+        loop {
+            // The answer may arrive after other frames which were already queued
+            if let Some(index) = self.events.iter().position(|event| matches!(event, SensorEvent::Frame(frame) if command.is_answered_by(frame))) {
+                return Ok(self.take_event(index));
+            }
+            if self.clock.now_ms() - start > COMMAND_TIMEOUT_MS {
+                return Err(DriverError::Timeout);
+            }
+            self.clock.sleep_ms(POLL_INTERVAL_MS);
+            self.receive()?;
+        }
+    }
+
+    /// Returns the events which are available right now, without blocking.
+    /// Should be called regularly, so that a lost sensor is noticed.
+    pub fn events(&mut self) -> Events<'_, U, C> {
+        return Events { driver: self, failed: false };
+    }
+
+    fn send(&mut self, command: &Command) -> Result<(), DriverError<U::Error>> {
+        self.uart.write_all(&command.encode()).map_err(DriverError::Io)?;
+        self.uart.flush().map_err(DriverError::Io)?;
+        return Ok(());
+    }
+
+    fn take_event(&mut self, index: usize) -> Frame {
+        // `Deque` can't remove from the middle, so rotate the event to the front
+        for _ in 0..index {
+            let event = self.events.pop_front().unwrap();
+            let _ = self.events.push_back(event);
+        }
+        let Some(SensorEvent::Frame(frame)) = self.events.pop_front() else {
+            unreachable!("The event at the index is a frame.");
+        };
+        for _ in index..self.events.len() {
+            let event = self.events.pop_front().unwrap();
+            let _ = self.events.push_back(event);
+        }
+        return frame;
+    }
+
+    /// Reads and decodes all bytes that are available, and supervises the heartbeat.
+    fn receive(&mut self) -> Result<(), DriverError<U::Error>> {
+        let now = self.clock.now_ms();
+        while self.uart.read_ready().map_err(DriverError::Io)? {
+            let mut buffer = [0_u8; 64];
+            let len = self.uart.read(&mut buffer).map_err(DriverError::Io)?;
+            for result in self.decoder.decode(&buffer[..len]) {
+                match result {
+                    Ok(frame) => {
+                        self.last_seen = now;
+                        if self.lost {
+                            self.lost = false;
+                            push_event(&mut self.events, SensorEvent::Recovered);
+                        }
+                        push_event(&mut self.events, SensorEvent::Frame(frame));
+                    },
+                    Err(error) => push_event(&mut self.events, SensorEvent::Error(error)),
+                }
+            }
+        }
+
+        let silent_for = now - self.last_seen;
+        if !self.lost && silent_for > HEARTBEAT_TIMEOUT_MS {
+            self.lost = true;
+            push_event(&mut self.events, SensorEvent::Lost);
+        }
+        if silent_for > HEARTBEAT_INTERVAL_MS && now - self.last_heartbeat_query > HEARTBEAT_INTERVAL_MS {
+            self.last_heartbeat_query = now;
+            self.send(&Command::QueryHeartbeat)?;
+        }
+        return Ok(());
+    }
+}
+
+/// Iterator over the events which are available without blocking. It ends
+/// after the first communication error.
+pub struct Events<'d, U, C> {
+    driver: &'d mut Mr24hpc1<U, C>,
+    failed: bool,
+}
+
+impl<U: Read + ReadReady + Write, C: Clock> Iterator for Events<'_, U, C> {
+    type Item = Result<SensorEvent, DriverError<U::Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        if self.driver.events.is_empty() {
+            if let Err(error) = self.driver.receive() {
+                self.failed = true;
+                return Some(Err(error));
+            }
+        }
+        return self.driver.events.pop_front().map(Ok);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::collections::VecDeque;
+    use std::rc::Rc;
+
+    use super::*;
+
+    /// Serial port which answers commands like a sensor would, and advances the
+    /// clock whenever the driver waits for data.
+    struct FakeSerial {
+        time: Rc<Cell<u64>>,
+        rx: VecDeque<u8>,
+        tx: Vec<u8>,
+        answers: Vec<(Command, Frame)>,
+        broken: bool,
+    }
+
+    impl FakeSerial {
+        fn new(time: Rc<Cell<u64>>, answers: Vec<(Command, Frame)>) -> Self {
+            return Self { time, rx: VecDeque::new(), tx: Vec::new(), answers, broken: false };
+        }
+
+        fn send(&mut self, frame: &Frame) {
+            self.rx.extend(frame.encode());
+        }
+    }
+
+    impl embedded_io::ErrorType for FakeSerial {
+        type Error = embedded_io::ErrorKind;
+    }
+
+    impl Read for FakeSerial {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            // Deliver in small chunks, like a UART would
+            let len = buf.len().min(self.rx.len()).min(7);
+            for byte in buf.iter_mut().take(len) {
+                *byte = self.rx.pop_front().unwrap();
+            }
+            return Ok(len);
+        }
+    }
+
+    impl ReadReady for FakeSerial {
+        fn read_ready(&mut self) -> Result<bool, Self::Error> {
+            if self.broken {
+                return Err(embedded_io::ErrorKind::BrokenPipe);
+            }
+            if self.rx.is_empty() {
+                self.time.set(self.time.get() + 10);
+            }
+            return Ok(!self.rx.is_empty());
+        }
+    }
+
+    impl Write for FakeSerial {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            self.tx.extend_from_slice(buf);
+            let answer = self.answers.iter()
+                .find(|(command, _)| &command.encode()[..] == buf)
+                .map(|(_, answer)| answer.encode());
+            if let Some(answer) = answer {
+                self.rx.extend(answer);
+            }
+            return Ok(buf.len());
+        }
+
+        fn flush(&mut self) -> Result<(), Self::Error> {
+            return Ok(());
+        }
+    }
+
+    fn driver(answers: Vec<(Command, Frame)>) -> (Mr24hpc1<FakeSerial, impl Clock>, Rc<Cell<u64>>) {
+        let time = Rc::new(Cell::new(0));
+        let clock_time = time.clone();
+        let driver = Mr24hpc1::new(FakeSerial::new(time.clone(), answers), move || clock_time.get());
+        return (driver, time);
+    }
+
+    fn text(text: &str) -> Text {
+        return Text::try_from(text).unwrap();
+    }
+
+    #[test]
+    fn test_init_and_commands() {
+        let (mut driver, _) = driver(vec![
+            (Command::QueryProductModel, Frame::ProductInformation(ProductInformation::ProductModel(text("MR24HPC1")))),
+            (Command::QueryFirmwareVersion, Frame::ProductInformation(ProductInformation::FirmwareVersion(text("G24VD1SYV001006")))),
+            (Command::SetSceneMode(SceneMode::Bedroom), Frame::OperationStatus(OperationStatus::SceneMode(SceneMode::Bedroom))),
+        ]);
+        // A report arrives before the answer, and is kept
+        let report = Frame::HumanPresenceReport(HumanPresence::PresenceInformation(Presence::Occupied));
+        driver.uart.send(&report);
+
+        let info = driver.init().unwrap();
+        assert_eq!(info, DeviceInfo { product_model: text("MR24HPC1"), firmware_version: text("G24VD1SYV001006") });
+        assert_eq!(driver.command(Command::SetSceneMode(SceneMode::Bedroom)), Ok(Frame::OperationStatus(OperationStatus::SceneMode(SceneMode::Bedroom))));
+        assert_eq!(driver.command(Command::QuerySensitivity), Err(DriverError::Timeout));
+
+        let events: Vec<_> = driver.events().collect();
+        assert_eq!(events, vec![Ok(SensorEvent::Frame(report))]);
+    }
+
+    #[test]
+    fn test_heartbeat_supervision() {
+        let (mut driver, time) = driver(vec![
+            (Command::QueryHeartbeat, Frame::Heartbeat(Heartbeat::Heartbeat)),
+        ]);
+
+        // The sensor is asked for a heartbeat, and answers
+        time.set(HEARTBEAT_INTERVAL_MS + 1);
+        assert_eq!(driver.events().count(), 0);
+        assert_eq!(&driver.uart.tx[..], &Command::QueryHeartbeat.encode()[..]);
+        assert_eq!(driver.events().collect::<Vec<_>>(), vec![Ok(SensorEvent::Frame(Frame::Heartbeat(Heartbeat::Heartbeat)))]);
+        assert!(!driver.is_lost());
+
+        // The sensor is unplugged
+        driver.uart.answers.clear();
+        time.set(2 * HEARTBEAT_INTERVAL_MS + HEARTBEAT_TIMEOUT_MS);
+        assert_eq!(driver.events().collect::<Vec<_>>(), vec![Ok(SensorEvent::Lost)]);
+        assert!(driver.is_lost());
+        assert_eq!(driver.events().count(), 0);
+
+        // And plugged in again
+        let report = Frame::HumanPresenceReport(HumanPresence::MotionInformation(Motion::Active));
+        driver.uart.send(&report);
+        assert_eq!(driver.events().collect::<Vec<_>>(), vec![Ok(SensorEvent::Recovered), Ok(SensorEvent::Frame(report))]);
+        assert!(!driver.is_lost());
+    }
+
+    #[test]
+    fn test_events_end_after_error() {
+        let (mut driver, _) = driver(vec![]);
+        driver.uart.broken = true;
+        assert_eq!(driver.events().collect::<Vec<_>>(), vec![Err(DriverError::Io(embedded_io::ErrorKind::BrokenPipe))]);
+    }
+}
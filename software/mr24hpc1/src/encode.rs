@@ -0,0 +1,335 @@
+// SPDX-FileCopyrightText: 2024 Lena Schimmel <mail@lenaschimmel.de>
+// SPDX-License-Identifier: CERN-OHL-S-2.0+
+// This file is part of besteLampe!.
+// 
+// besteLampe! is free software: you can redistribute it and/or modify it under the
+// terms of the GNU General Public License as published by the Free Software Foundation, 
+// either version 3 of the License, or (at your option) any later version.
+// 
+// besteLampe! is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; 
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. 
+// See the GNU General Public License for more details.
+// 
+// You should have received a copy of the GNU General Public License along with besteLampe!.
+// If not, see <https://www.gnu.org/licenses/>. 
+
+use crate::*;
+
+/// Length of a frame without any data: header, control word, command, data length, checksum and tail.
+pub const FRAME_OVERHEAD: usize = HEADER.len() + 4 + 1 + TAIL.len();
+
+/// Maximum length of the frames which this crate can encode.
+pub const MAX_FRAME_LEN: usize = FRAME_OVERHEAD + MAX_RAW_DATA_LEN;
+
+pub type FrameBuffer = heapless::Vec<u8, MAX_FRAME_LEN>;
+
+/// Data of a command, which is at most a 32 bit number.
+pub type CommandData = heapless::Vec<u8, 4>;
+
+/// Data byte of commands which query a value instead of setting it.
+const QUERY: u8 = 0x0f;
+
+/// Builds a frame, or returns `None` if the data is longer than `MAX_RAW_DATA_LEN`.
+pub fn encode_frame(control: u8, command: u8, data: &[u8]) -> Option<FrameBuffer> {
+    if data.len() > MAX_RAW_DATA_LEN {
+        return None;
+    }
+    let mut frame = FrameBuffer::new();
+    // Can't fail, because the length was checked above
+    let _ = frame.extend_from_slice(&HEADER);
+    let _ = frame.extend_from_slice(&[control, command]);
+    let _ = frame.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    let _ = frame.extend_from_slice(data);
+    let _ = frame.push(checksum(&frame));
+    let _ = frame.extend_from_slice(&TAIL);
+    return Some(frame);
+}
+
+/// Commands which can be sent to the sensor. The sensor answers each of them with
+/// a frame that has the same control word and command, and contains the new or
+/// queried value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    QueryHeartbeat,
+    ResetModule,
+    QueryProductModel,
+    QueryProductId,
+    QueryHardwareModel,
+    QueryFirmwareVersion,
+    QueryInitializationStatus,
+    SetSceneMode(SceneMode),
+    QuerySceneMode,
+    SetSensitivity(Sensitivity),
+    QuerySensitivity,
+    QueryPresence,
+    QueryMotion,
+    QueryBodyMovement,
+    QueryProximity,
+    SetUnmannedTime(UnmannedTime),
+    QueryUnmannedTime,
+    /// Switches the reports of the "underlying open function" on or off. While
+    /// they are on, the sensor sends raw measurements instead of its own
+    /// interpretation of them.
+    SetOpenFunction(bool),
+    QueryOpenFunction,
+    /// The following settings only apply while the open function is enabled.
+    /// See `OpenFunction` for their meaning.
+    SetExistenceThreshold(u8),
+    QueryExistenceThreshold,
+    SetMotionThreshold(u8),
+    QueryMotionThreshold,
+    SetExistenceBoundary(u8),
+    QueryExistenceBoundary,
+    SetMotionBoundary(u8),
+    QueryMotionBoundary,
+    SetMotionTriggerTime(u32),
+    QueryMotionTriggerTime,
+    SetMotionToStillTime(u32),
+    QueryMotionToStillTime,
+    SetUnoccupiedTime(u32),
+    QueryUnoccupiedTime,
+}
+
+impl Command {
+    pub fn control(&self) -> u8 {
+        return match self {
+            Command::QueryHeartbeat | Command::ResetModule => 0x01,
+            Command::QueryProductModel | Command::QueryProductId
+                | Command::QueryHardwareModel | Command::QueryFirmwareVersion => 0x02,
+            Command::QueryInitializationStatus
+                | Command::SetSceneMode(_) | Command::QuerySceneMode
+                | Command::SetSensitivity(_) | Command::QuerySensitivity => 0x05,
+            Command::QueryPresence | Command::QueryMotion | Command::QueryBodyMovement | Command::QueryProximity
+                | Command::SetUnmannedTime(_) | Command::QueryUnmannedTime => 0x80,
+            Command::SetOpenFunction(_) | Command::QueryOpenFunction
+                | Command::SetExistenceThreshold(_) | Command::QueryExistenceThreshold
+                | Command::SetMotionThreshold(_) | Command::QueryMotionThreshold
+                | Command::SetExistenceBoundary(_) | Command::QueryExistenceBoundary
+                | Command::SetMotionBoundary(_) | Command::QueryMotionBoundary
+                | Command::SetMotionTriggerTime(_) | Command::QueryMotionTriggerTime
+                | Command::SetMotionToStillTime(_) | Command::QueryMotionToStillTime
+                | Command::SetUnoccupiedTime(_) | Command::QueryUnoccupiedTime => 0x08,
+        };
+    }
+
+    pub fn command(&self) -> u8 {
+        return match self {
+            Command::QueryHeartbeat => 0x01,
+            Command::ResetModule => 0x02,
+            Command::QueryProductModel => 0xa1,
+            Command::QueryProductId => 0xa2,
+            Command::QueryHardwareModel => 0xa3,
+            Command::QueryFirmwareVersion => 0xa4,
+            Command::QueryInitializationStatus => 0x81,
+            Command::SetSceneMode(_) => 0x07,
+            Command::QuerySceneMode => 0x87,
+            Command::SetSensitivity(_) => 0x08,
+            Command::QuerySensitivity => 0x88,
+            Command::QueryPresence => 0x81,
+            Command::QueryMotion => 0x82,
+            Command::QueryBodyMovement => 0x83,
+            Command::QueryProximity => 0x8b,
+            Command::SetUnmannedTime(_) => 0x0a,
+            Command::QueryUnmannedTime => 0x8a,
+            Command::SetOpenFunction(_) => 0x00,
+            Command::QueryOpenFunction => 0x80,
+            Command::SetExistenceThreshold(_) => 0x08,
+            Command::QueryExistenceThreshold => 0x88,
+            Command::SetMotionThreshold(_) => 0x09,
+            Command::QueryMotionThreshold => 0x89,
+            Command::SetExistenceBoundary(_) => 0x0a,
+            Command::QueryExistenceBoundary => 0x8a,
+            Command::SetMotionBoundary(_) => 0x0b,
+            Command::QueryMotionBoundary => 0x8b,
+            Command::SetMotionTriggerTime(_) => 0x0c,
+            Command::QueryMotionTriggerTime => 0x8c,
+            Command::SetMotionToStillTime(_) => 0x0d,
+            Command::QueryMotionToStillTime => 0x8d,
+            Command::SetUnoccupiedTime(_) => 0x0e,
+            Command::QueryUnoccupiedTime => 0x8e,
+        };
+    }
+
+    pub fn data(&self) -> CommandData {
+        let byte = match self {
+            Command::SetSceneMode(mode) => mode.to_byte(),
+            Command::SetSensitivity(sensitivity) => sensitivity.to_byte(),
+            Command::SetUnmannedTime(time) => time.to_byte(),
+            Command::SetOpenFunction(enabled) => *enabled as u8,
+            Command::SetExistenceThreshold(value) | Command::SetMotionThreshold(value)
+                | Command::SetExistenceBoundary(value) | Command::SetMotionBoundary(value) => *value,
+            Command::SetMotionTriggerTime(millis) | Command::SetMotionToStillTime(millis)
+                | Command::SetUnoccupiedTime(millis) => return CommandData::from_slice(&millis.to_be_bytes()).unwrap(),
+            _ => QUERY,
+        };
+        return CommandData::from_slice(&[byte]).unwrap();
+    }
+
+    /// Whether `frame` is the sensor's answer to this command.
+    pub fn is_answered_by(&self, frame: &Frame) -> bool {
+        return matches!((self, frame),
+            (Command::QueryHeartbeat, Frame::Heartbeat(Heartbeat::Heartbeat))
+            | (Command::ResetModule, Frame::Heartbeat(Heartbeat::ModuleReset))
+            | (Command::QueryProductModel, Frame::ProductInformation(ProductInformation::ProductModel(_)))
+            | (Command::QueryProductId, Frame::ProductInformation(ProductInformation::ProductId(_)))
+            | (Command::QueryHardwareModel, Frame::ProductInformation(ProductInformation::HardwareModel(_)))
+            | (Command::QueryFirmwareVersion, Frame::ProductInformation(ProductInformation::FirmwareVersion(_)))
+            | (Command::QueryInitializationStatus, Frame::OperationStatus(OperationStatus::InitializationStatus(_)))
+            | (Command::SetSceneMode(_) | Command::QuerySceneMode, Frame::OperationStatus(OperationStatus::SceneMode(_)))
+            | (Command::SetSensitivity(_) | Command::QuerySensitivity, Frame::OperationStatus(OperationStatus::Sensitivity(_)))
+            | (Command::QueryPresence, Frame::HumanPresenceReport(HumanPresence::PresenceInformation(_)))
+            | (Command::QueryMotion, Frame::HumanPresenceReport(HumanPresence::MotionInformation(_)))
+            | (Command::QueryBodyMovement, Frame::HumanPresenceReport(HumanPresence::BodyMovementParameter(_)))
+            | (Command::QueryProximity, Frame::HumanPresenceReport(HumanPresence::ProximityReport(_)))
+            | (Command::SetUnmannedTime(_) | Command::QueryUnmannedTime, Frame::HumanPresenceReport(HumanPresence::UnmannedTime(_)))
+            | (Command::SetOpenFunction(_) | Command::QueryOpenFunction, Frame::OpenFunction(OpenFunction::Enabled(_)))
+            | (Command::SetExistenceThreshold(_) | Command::QueryExistenceThreshold, Frame::OpenFunction(OpenFunction::ExistenceThreshold(_)))
+            | (Command::SetMotionThreshold(_) | Command::QueryMotionThreshold, Frame::OpenFunction(OpenFunction::MotionThreshold(_)))
+            | (Command::SetExistenceBoundary(_) | Command::QueryExistenceBoundary, Frame::OpenFunction(OpenFunction::ExistenceBoundary(_)))
+            | (Command::SetMotionBoundary(_) | Command::QueryMotionBoundary, Frame::OpenFunction(OpenFunction::MotionBoundary(_)))
+            | (Command::SetMotionTriggerTime(_) | Command::QueryMotionTriggerTime, Frame::OpenFunction(OpenFunction::MotionTriggerTime(_)))
+            | (Command::SetMotionToStillTime(_) | Command::QueryMotionToStillTime, Frame::OpenFunction(OpenFunction::MotionToStillTime(_)))
+            | (Command::SetUnoccupiedTime(_) | Command::QueryUnoccupiedTime, Frame::OpenFunction(OpenFunction::UnoccupiedTime(_)))
+        );
+    }
+
+    pub fn encode(&self) -> FrameBuffer {
+        return encode_frame(self.control(), self.command(), &self.data()).expect("The data of all commands fits into a frame.");
+    }
+}
+
+fn proximity_to_byte(proximity: &Proximity) -> u8 {
+    return match proximity {
+        Proximity::None => 0x00,
+        Proximity::Approaching => 0x01,
+        Proximity::MovingAway => 0x02,
+    };
+}
+
+impl Frame {
+    /// Builds the frame as the sensor would send it. This is mostly useful to
+    /// simulate a sensor. Values which can be reported with two commands, e.g.
+    /// as the answer to a query or to a setting, use the command of the setting.
+    pub fn encode(&self) -> FrameBuffer {
+        let (control, command, data): (u8, u8, &[u8]) = match self {
+            Frame::Heartbeat(Heartbeat::Heartbeat) => (0x01, 0x01, &[QUERY]),
+            Frame::Heartbeat(Heartbeat::ModuleReset) => (0x01, 0x02, &[QUERY]),
+            Frame::ProductInformation(information) => match information {
+                ProductInformation::ProductModel(text) => (0x02, 0xa1, text.as_bytes()),
+                ProductInformation::ProductId(text) => (0x02, 0xa2, text.as_bytes()),
+                ProductInformation::HardwareModel(text) => (0x02, 0xa3, text.as_bytes()),
+                ProductInformation::FirmwareVersion(text) => (0x02, 0xa4, text.as_bytes()),
+            },
+            Frame::UartUpgrade(upgrade) => (0x03, upgrade.command, &upgrade.data),
+            Frame::OperationStatus(status) => match status {
+                OperationStatus::InitializationCompleted => (0x05, 0x01, &[QUERY]),
+                OperationStatus::InitializationStatus(completed) => (0x05, 0x81, &[*completed as u8]),
+                OperationStatus::SceneMode(mode) => (0x05, 0x07, &[mode.to_byte()]),
+                OperationStatus::Sensitivity(sensitivity) => (0x05, 0x08, &[sensitivity.to_byte()]),
+            },
+            Frame::HumanPresenceReport(report) => match report {
+                HumanPresence::PresenceInformation(presence) => (0x80, 0x01, match presence {
+                    Presence::Unoccupied => &[0x00],
+                    Presence::Occupied => &[0x01],
+                }),
+                HumanPresence::MotionInformation(motion) => (0x80, 0x02, match motion {
+                    Motion::None => &[0x00],
+                    Motion::Motionless => &[0x01],
+                    Motion::Active => &[0x02],
+                }),
+                HumanPresence::BodyMovementParameter(movement) => (0x80, 0x03, &[*movement]),
+                HumanPresence::ProximityReport(proximity) => (0x80, 0x0b, &[proximity_to_byte(proximity)]),
+                HumanPresence::UnmannedTime(time) => (0x80, 0x0a, &[time.to_byte()]),
+            },
+            Frame::OpenFunction(report) => match report {
+                OpenFunction::Enabled(enabled) => (0x08, 0x00, &[*enabled as u8]),
+                OpenFunction::SensorInformation(info) => (0x08, 0x01, &[
+                    info.existence_energy,
+                    info.static_distance,
+                    info.motion_energy,
+                    info.motion_distance,
+                    info.motion_speed,
+                ]),
+                // Offset by one compared to the other proximity report
+                OpenFunction::ProximityReport(proximity) => (0x08, 0x06, &[proximity_to_byte(proximity) + 1]),
+                OpenFunction::BodyMovementParameter(movement) => (0x08, 0x07, &[*movement]),
+                OpenFunction::ExistenceThreshold(value) => (0x08, 0x08, &[*value]),
+                OpenFunction::MotionThreshold(value) => (0x08, 0x09, &[*value]),
+                OpenFunction::ExistenceBoundary(value) => (0x08, 0x0a, &[*value]),
+                OpenFunction::MotionBoundary(value) => (0x08, 0x0b, &[*value]),
+                OpenFunction::MotionTriggerTime(millis) => (0x08, 0x0c, &millis.to_be_bytes()),
+                OpenFunction::MotionToStillTime(millis) => (0x08, 0x0d, &millis.to_be_bytes()),
+                OpenFunction::UnoccupiedTime(millis) => (0x08, 0x0e, &millis.to_be_bytes()),
+            },
+        };
+        return encode_frame(control, command, data).expect("The data of all frames fits into a frame.");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+    use super::*;
+
+    #[test]
+    fn test_encode_commands() {
+        assert_eq!(&Command::QueryHeartbeat.encode()[..], &hex!("53 59 01 01 00 01 0f be 54 43"));
+        assert_eq!(&Command::QueryProductModel.encode()[..], &hex!("53 59 02 a1 00 01 0f 5f 54 43"));
+        assert_eq!(&Command::QueryPresence.encode()[..], &hex!("53 59 80 81 00 01 0f bd 54 43"));
+        assert_eq!(&Command::SetSceneMode(SceneMode::Bathroom).encode()[..], &hex!("53 59 05 07 00 01 03 bc 54 43"));
+        assert_eq!(&Command::SetOpenFunction(true).encode()[..], &hex!("53 59 08 00 00 01 01 b6 54 43"));
+        assert_eq!(encode_frame(0x03, 0x01, &[0; MAX_RAW_DATA_LEN + 1]), None);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let frames = [
+            Frame::Heartbeat(Heartbeat::Heartbeat),
+            Frame::Heartbeat(Heartbeat::ModuleReset),
+            Frame::ProductInformation(ProductInformation::ProductModel(Text::try_from("MR24HPC1").unwrap())),
+            Frame::ProductInformation(ProductInformation::FirmwareVersion(Text::try_from("G24VD1SYV001006").unwrap())),
+            Frame::UartUpgrade(UartUpgrade { command: 0x02, data: heapless::Vec::from_slice(&[1, 2, 3]).unwrap() }),
+            Frame::OperationStatus(OperationStatus::InitializationCompleted),
+            Frame::OperationStatus(OperationStatus::InitializationStatus(false)),
+            Frame::OperationStatus(OperationStatus::SceneMode(SceneMode::AreaDetection)),
+            Frame::OperationStatus(OperationStatus::Sensitivity(Sensitivity::Medium)),
+            Frame::HumanPresenceReport(HumanPresence::PresenceInformation(Presence::Occupied)),
+            Frame::HumanPresenceReport(HumanPresence::MotionInformation(Motion::Motionless)),
+            Frame::HumanPresenceReport(HumanPresence::BodyMovementParameter(42)),
+            Frame::HumanPresenceReport(HumanPresence::ProximityReport(Proximity::MovingAway)),
+            Frame::HumanPresenceReport(HumanPresence::UnmannedTime(UnmannedTime::Minutes30)),
+            Frame::OpenFunction(OpenFunction::Enabled(true)),
+            Frame::OpenFunction(OpenFunction::SensorInformation(SensorInformation {
+                existence_energy: 120, static_distance: 3, motion_energy: 80, motion_distance: 5, motion_speed: 8,
+            })),
+            Frame::OpenFunction(OpenFunction::ProximityReport(Proximity::None)),
+            Frame::OpenFunction(OpenFunction::BodyMovementParameter(7)),
+            Frame::OpenFunction(OpenFunction::MotionThreshold(33)),
+            Frame::OpenFunction(OpenFunction::ExistenceBoundary(6)),
+            Frame::OpenFunction(OpenFunction::UnoccupiedTime(60_000)),
+        ];
+        for frame in frames {
+            let encoded = frame.encode();
+            assert_eq!(mr_parser(&encoded), Ok((&[][..], frame)));
+        }
+    }
+
+    #[test]
+    fn test_settings_are_answered_in_kind() {
+        // The sensor confirms a setting with a frame that has the same command,
+        // so the parsed answer can be matched with the command
+        let commands = [
+            (Command::SetSceneMode(SceneMode::Bedroom), Frame::OperationStatus(OperationStatus::SceneMode(SceneMode::Bedroom))),
+            (Command::SetSensitivity(Sensitivity::Low), Frame::OperationStatus(OperationStatus::Sensitivity(Sensitivity::Low))),
+            (Command::SetUnmannedTime(UnmannedTime::Minutes5), Frame::HumanPresenceReport(HumanPresence::UnmannedTime(UnmannedTime::Minutes5))),
+            (Command::SetOpenFunction(false), Frame::OpenFunction(OpenFunction::Enabled(false))),
+            (Command::SetExistenceThreshold(20), Frame::OpenFunction(OpenFunction::ExistenceThreshold(20))),
+            (Command::SetMotionBoundary(8), Frame::OpenFunction(OpenFunction::MotionBoundary(8))),
+            (Command::SetMotionTriggerTime(150), Frame::OpenFunction(OpenFunction::MotionTriggerTime(150))),
+            (Command::SetMotionToStillTime(30_000), Frame::OpenFunction(OpenFunction::MotionToStillTime(30_000))),
+        ];
+        for (command, answer) in commands {
+            assert_eq!(mr_parser(&command.encode()), Ok((&[][..], answer)));
+        }
+    }
+}
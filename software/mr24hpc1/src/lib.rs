@@ -13,138 +13,597 @@
 // You should have received a copy of the GNU General Public License along with besteLampe!.
 // If not, see <https://www.gnu.org/licenses/>. 
 
+#![cfg_attr(not(feature = "std"), no_std)]
+#![allow(clippy::needless_return)]
+
+use core::fmt;
+
 use nom::IResult;
 use nom::number::complete::be_u16;
-use nom::bytes::complete::take;
-use nom::bytes::complete::tag;
 use nom::multi::length_data;
-use nom::error_position;
+use nom::error::{ErrorKind, ParseError};
 use nom::branch::alt;
 
-#[derive(Debug)]
+mod decode;
+mod driver;
+mod encode;
+mod raw;
+pub use decode::*;
+pub use driver::*;
+pub use encode::*;
+pub use raw::*;
+
+/// First bytes of each frame.
+pub const HEADER: [u8; 2] = [0x53, 0x59];
+
+/// Last bytes of each frame.
+pub const TAIL: [u8; 2] = [0x54, 0x43];
+
+/// Maximum length of the texts in product information frames.
+pub const MAX_TEXT_LEN: usize = 32;
+
+/// Maximum length of the data of frames that are passed through without being interpreted.
+pub const MAX_RAW_DATA_LEN: usize = 64;
+
+pub type Text = heapless::String<MAX_TEXT_LEN>;
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Proximity {
     None,
     Approaching,
     MovingAway,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Presence {
     Unoccupied,
     Occupied,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Motion {
     None,
     Motionless,
     Active,
 }
 
-#[derive(Debug)]
+/// Time after which the sensor reports `Unoccupied` when nobody is detected anymore.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnmannedTime {
+    None,
+    Seconds10,
+    Seconds30,
+    Minutes1,
+    Minutes2,
+    Minutes5,
+    Minutes10,
+    Minutes30,
+    Minutes60,
+}
+
+impl UnmannedTime {
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        return match byte {
+            0x00 => Some(UnmannedTime::None),
+            0x01 => Some(UnmannedTime::Seconds10),
+            0x02 => Some(UnmannedTime::Seconds30),
+            0x03 => Some(UnmannedTime::Minutes1),
+            0x04 => Some(UnmannedTime::Minutes2),
+            0x05 => Some(UnmannedTime::Minutes5),
+            0x06 => Some(UnmannedTime::Minutes10),
+            0x07 => Some(UnmannedTime::Minutes30),
+            0x08 => Some(UnmannedTime::Minutes60),
+            _ => None,
+        };
+    }
+
+    pub fn to_byte(self) -> u8 {
+        return match self {
+            UnmannedTime::None => 0x00,
+            UnmannedTime::Seconds10 => 0x01,
+            UnmannedTime::Seconds30 => 0x02,
+            UnmannedTime::Minutes1 => 0x03,
+            UnmannedTime::Minutes2 => 0x04,
+            UnmannedTime::Minutes5 => 0x05,
+            UnmannedTime::Minutes10 => 0x06,
+            UnmannedTime::Minutes30 => 0x07,
+            UnmannedTime::Minutes60 => 0x08,
+        };
+    }
+
+    pub fn as_secs(self) -> u32 {
+        return match self {
+            UnmannedTime::None => 0,
+            UnmannedTime::Seconds10 => 10,
+            UnmannedTime::Seconds30 => 30,
+            UnmannedTime::Minutes1 => 60,
+            UnmannedTime::Minutes2 => 120,
+            UnmannedTime::Minutes5 => 300,
+            UnmannedTime::Minutes10 => 600,
+            UnmannedTime::Minutes30 => 1800,
+            UnmannedTime::Minutes60 => 3600,
+        };
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum HumanPresence {
     PresenceInformation(Presence),
     MotionInformation(Motion),
+    /// From 0 (nobody there) over 1 (somebody motionless) up to 100 (lots of motion).
     BodyMovementParameter(u8),
     ProximityReport(Proximity),
+    UnmannedTime(UnmannedTime),
 }
 
-#[derive(Debug)]
-pub enum Frame {
+#[derive(Debug, Clone, PartialEq)]
+pub enum Heartbeat {
+    /// Sent by the sensor periodically, and as the answer to a heartbeat query.
     Heartbeat,
-    ProductInformation,
-    UartUpgrade,
-    OperationStatus,
+    /// Answer to a reset command.
+    ModuleReset,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProductInformation {
+    ProductModel(Text),
+    ProductId(Text),
+    HardwareModel(Text),
+    FirmwareVersion(Text),
+}
+
+/// Frames of the firmware upgrade protocol. We never upgrade the sensor, so they
+/// are only passed through.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UartUpgrade {
+    pub command: u8,
+    pub data: heapless::Vec<u8, MAX_RAW_DATA_LEN>,
+}
+
+/// Preset of the sensor's detection parameters for typical rooms.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SceneMode {
+    LivingRoom,
+    Bedroom,
+    Bathroom,
+    AreaDetection,
+}
+
+impl SceneMode {
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        return match byte {
+            0x01 => Some(SceneMode::LivingRoom),
+            0x02 => Some(SceneMode::Bedroom),
+            0x03 => Some(SceneMode::Bathroom),
+            0x04 => Some(SceneMode::AreaDetection),
+            _ => None,
+        };
+    }
+
+    pub fn to_byte(self) -> u8 {
+        return match self {
+            SceneMode::LivingRoom => 0x01,
+            SceneMode::Bedroom => 0x02,
+            SceneMode::Bathroom => 0x03,
+            SceneMode::AreaDetection => 0x04,
+        };
+    }
+}
+
+/// Sensitivity of the presence detection. Higher sensitivity means a larger range.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sensitivity {
+    Low,
+    Medium,
+    High,
+}
+
+impl Sensitivity {
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        return match byte {
+            0x01 => Some(Sensitivity::Low),
+            0x02 => Some(Sensitivity::Medium),
+            0x03 => Some(Sensitivity::High),
+            _ => None,
+        };
+    }
+
+    pub fn to_byte(self) -> u8 {
+        return match self {
+            Sensitivity::Low => 0x01,
+            Sensitivity::Medium => 0x02,
+            Sensitivity::High => 0x03,
+        };
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OperationStatus {
+    /// Sent once when the sensor has started.
+    InitializationCompleted,
+    /// Answer to the initialization status query.
+    InitializationStatus(bool),
+    SceneMode(SceneMode),
+    Sensitivity(Sensitivity),
+}
+
+/// Raw measurements which the sensor reports in "underlying open function" mode.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SensorInformation {
+    /// Energy of the reflections of static targets, 0 to 250.
+    pub existence_energy: u8,
+    /// Distance of the static target, in steps of 0.5 m.
+    pub static_distance: u8,
+    /// Energy of the reflections of moving targets, 0 to 250.
+    pub motion_energy: u8,
+    /// Distance of the moving target, in steps of 0.5 m.
+    pub motion_distance: u8,
+    /// Speed of the moving target, in steps of 0.5 m/s, offset by 10.
+    /// Positive speeds mean that the target approaches.
+    pub motion_speed: u8,
+}
+
+impl SensorInformation {
+    pub fn static_distance_m(&self) -> f32 {
+        return self.static_distance as f32 * 0.5;
+    }
+
+    pub fn motion_distance_m(&self) -> f32 {
+        return self.motion_distance as f32 * 0.5;
+    }
+
+    pub fn motion_speed_m_s(&self) -> f32 {
+        return (self.motion_speed as f32 - 10.0) * 0.5;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OpenFunction {
+    /// Whether the raw reports are switched on.
+    Enabled(bool),
+    SensorInformation(SensorInformation),
+    ProximityReport(Proximity),
+    /// Like `HumanPresence::BodyMovementParameter`, but sent more often.
+    BodyMovementParameter(u8),
+    /// Existence energy above which the sensor reports presence, 0 to 250.
+    ExistenceThreshold(u8),
+    /// Motion energy above which the sensor reports motion, 0 to 250.
+    MotionThreshold(u8),
+    /// Distance up to which static targets are detected, in steps of 0.5 m.
+    ExistenceBoundary(u8),
+    /// Distance up to which moving targets are detected, in steps of 0.5 m.
+    MotionBoundary(u8),
+    /// How long the motion energy has to be above the threshold to report motion, in ms.
+    MotionTriggerTime(u32),
+    /// How long there has to be no motion before the sensor reports `Motionless`, in ms.
+    MotionToStillTime(u32),
+    /// How long nobody has to be detected before the sensor reports `Unoccupied`, in ms.
+    UnoccupiedTime(u32),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Frame {
+    Heartbeat(Heartbeat),
+    ProductInformation(ProductInformation),
+    UartUpgrade(UartUpgrade),
+    OperationStatus(OperationStatus),
     HumanPresenceReport(HumanPresence),
+    OpenFunction(OpenFunction),
 }
 
-fn parse_proximity_information(input: &[u8]) -> IResult<&[u8], HumanPresence> {
-    let (input, _) = tag([0x0b])(input)?;
-    let (input, data) = length_data(be_u16)(input)?;
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// The input ends before the end of the frame. Read more bytes and try again.
+    Truncated,
+    /// The input does not start with the frame header.
+    BadHeader,
+    /// The frame does not end with the frame tail where its length says it should.
+    BadTail,
+    BadChecksum { expected: u8, actual: u8 },
+    /// The data length is larger than any frame this crate supports, which most
+    /// likely means that it is corrupted.
+    TooLong,
+    UnknownControlWord(u8),
+    UnknownCommand { control: u8, command: u8 },
+    /// The frame has a known type, but its data has the wrong length or a value
+    /// which the protocol does not define.
+    InvalidValue { control: u8, command: u8 },
+}
 
-    let (_, data_byte) = nom::number::complete::u8(data)?;
-    let proximity = match data_byte {
-        0x00 => Proximity::None,
-        0x01 => Proximity::Approaching,
-        0x02 => Proximity::MovingAway,
-        _ => return Err(nom::Err::Error(error_position!(input, nom::error::ErrorKind::IsNot))), // Help, I do not know how to return a custom error here!
-    };
-    return Ok((input, HumanPresence::ProximityReport(proximity)))
+impl Error {
+    /// Whether the frame is intact, but of a kind that this crate does not
+    /// understand.
+    pub fn is_unsupported(&self) -> bool {
+        return matches!(self, Error::UnknownControlWord(_) | Error::UnknownCommand { .. });
+    }
+
+    /// Whether the bytes are not a valid frame at all, e.g. because of line noise.
+    pub fn is_corrupted(&self) -> bool {
+        return matches!(self, Error::Truncated | Error::BadHeader | Error::BadTail | Error::BadChecksum { .. } | Error::TooLong);
+    }
 }
 
-fn parse_presence_information(input: &[u8]) -> IResult<&[u8], HumanPresence> {
-    let (input, _) = tag([0x01])(input)?;
-    let (input, data) = length_data(be_u16)(input)?;
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Truncated => write!(f, "The frame is incomplete."),
+            Error::BadHeader => write!(f, "The frame does not start with the frame header."),
+            Error::BadTail => write!(f, "The frame does not end with the frame tail."),
+            Error::TooLong => write!(f, "The frame is longer than any supported frame."),
+            Error::BadChecksum { expected, actual } => write!(f, "The checksum is {:#04x}, but should be {:#04x}.", actual, expected),
+            Error::UnknownControlWord(control) => write!(f, "The control word {:#04x} is unknown.", control),
+            Error::UnknownCommand { control, command } => write!(f, "The command {:#04x} of control word {:#04x} is unknown.", command, control),
+            Error::InvalidValue { control, command } => write!(f, "The data of command {:#04x} of control word {:#04x} is invalid.", command, control),
+        }
+    }
+}
 
-    let (_, data_byte) = nom::number::complete::u8(data)?;
-    let presence = match data_byte {
-        0x00 => Presence::Unoccupied,
-        0x01 => Presence::Occupied,
-        _ => return Err(nom::Err::Error(error_position!(input, nom::error::ErrorKind::Tag))), // Help, I do not know how to return a custom error here!
+#[cfg(feature = "std")]
+impl std::error::Error for Error {}
+
+/// Error of the parsers for the part of a frame from the control word to the
+/// end of the data, before `mr_parser` adds which frame it was.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+enum PayloadError {
+    UnknownControlWord,
+    UnknownCommand,
+    InvalidValue,
+}
+
+impl<I> ParseError<I> for PayloadError {
+    /// Errors of the basic nom parsers can only happen if the data is too short.
+    fn from_error_kind(_input: I, _kind: ErrorKind) -> Self {
+        return PayloadError::InvalidValue;
+    }
+
+    fn append(_input: I, _kind: ErrorKind, other: Self) -> Self {
+        return other;
+    }
+
+    /// Keeps the most specific error when all branches of `alt` failed, e.g. an
+    /// invalid value of the one command that matched.
+    fn or(self, other: Self) -> Self {
+        return if other > self { other } else { self };
+    }
+}
+
+type PResult<'a, T> = IResult<&'a [u8], T, PayloadError>;
+
+/// Sum of all bytes, modulo 256. The checksum of a frame is calculated over all
+/// bytes from the header to the end of the data.
+pub fn checksum(bytes: &[u8]) -> u8 {
+    return bytes.iter().fold(0_u8, |sum, byte| sum.wrapping_add(*byte));
+}
+
+fn invalid_value() -> nom::Err<PayloadError> {
+    return nom::Err::Error(PayloadError::InvalidValue);
+}
+
+/// Parses the control word, which must be `control`.
+fn control(input: &[u8], control: u8) -> PResult<'_, u8> {
+    return match input.split_first() {
+        Some((&word, rest)) if word == control => Ok((rest, word)),
+        _ => Err(nom::Err::Error(PayloadError::UnknownControlWord)),
     };
-    return Ok((input, HumanPresence::PresenceInformation(presence)))
 }
 
-fn parse_motion_information(input: &[u8]) -> IResult<&[u8], HumanPresence> {
-    let (input, _) = tag([0x02])(input)?;
-    let (input, data) = length_data(be_u16)(input)?;
+/// Parses a command word which is one of `commands`, and returns the data that
+/// follows it. Settings have the same format when they are set and when they
+/// are queried, so many frames have two command words.
+fn command<'a>(input: &'a [u8], commands: &[u8]) -> PResult<'a, &'a [u8]> {
+    let (rest, command) = nom::number::complete::u8(input)?;
+    if !commands.contains(&command) {
+        return Err(nom::Err::Error(PayloadError::UnknownCommand));
+    }
+    return length_data(be_u16)(rest);
+}
 
+fn single_byte<'a, T>(input: &'a [u8], commands: &[u8], map: impl Fn(u8) -> Option<T>) -> PResult<'a, T> {
+    let (input, data) = command(input, commands)?;
     let (_, data_byte) = nom::number::complete::u8(data)?;
-    let motion = match data_byte {
-        0x00 => Motion::None,
-        0x01 => Motion::Motionless,
-        0x02 => Motion::Active,
-        _ => return Err(nom::Err::Error(error_position!(input, nom::error::ErrorKind::Tag))), // Help, I do not know how to return a custom error here!
-    };
+    let value = map(data_byte).ok_or_else(invalid_value)?;
+    return Ok((input, value));
+}
+
+fn parse_text<'a>(input: &'a [u8], commands: &[u8]) -> PResult<'a, Text> {
+    let (input, data) = command(input, commands)?;
+    let text = core::str::from_utf8(data).ok()
+        .and_then(|text| Text::try_from(text.trim_end_matches('\0')).ok())
+        .ok_or_else(invalid_value)?;
+    return Ok((input, text));
+}
+
+fn parse_millis<'a>(input: &'a [u8], commands: &[u8]) -> PResult<'a, u32> {
+    let (input, data) = command(input, commands)?;
+    let (_, millis) = nom::number::complete::be_u32(data)?;
+    return Ok((input, millis));
+}
+
+fn parse_heartbeat(input: &[u8]) -> PResult<'_, Frame> {
+    let (input, _) = control(input, 0x01)?;
+    let (input, heartbeat) = alt((
+        |i| single_byte(i, &[0x01], |_| Some(Heartbeat::Heartbeat)),
+        |i| single_byte(i, &[0x02], |_| Some(Heartbeat::ModuleReset)),
+    ))(input)?;
+    return Ok((input, Frame::Heartbeat(heartbeat)))
+}
+
+fn parse_product_information(input: &[u8]) -> PResult<'_, Frame> {
+    let (input, _) = control(input, 0x02)?;
+    let (input, information) = alt((
+        |i| parse_text(i, &[0xa1]).map(|(i, text)| (i, ProductInformation::ProductModel(text))),
+        |i| parse_text(i, &[0xa2]).map(|(i, text)| (i, ProductInformation::ProductId(text))),
+        |i| parse_text(i, &[0xa3]).map(|(i, text)| (i, ProductInformation::HardwareModel(text))),
+        |i| parse_text(i, &[0xa4]).map(|(i, text)| (i, ProductInformation::FirmwareVersion(text))),
+    ))(input)?;
+    return Ok((input, Frame::ProductInformation(information)))
+}
+
+fn parse_uart_upgrade(input: &[u8]) -> PResult<'_, Frame> {
+    let (input, _) = control(input, 0x03)?;
+    let (rest, command_word) = nom::number::complete::u8(input)?;
+    let (rest, data) = length_data(be_u16)(rest)?;
+    let data = heapless::Vec::from_slice(data).map_err(|_| invalid_value())?;
+    return Ok((rest, Frame::UartUpgrade(UartUpgrade { command: command_word, data })))
+}
+
+fn parse_operation_status(input: &[u8]) -> PResult<'_, Frame> {
+    let (input, _) = control(input, 0x05)?;
+    let (input, status) = alt((
+        |i| single_byte(i, &[0x01], |_| Some(OperationStatus::InitializationCompleted)),
+        |i| single_byte(i, &[0x81], |byte| match byte {
+            0x00 => Some(OperationStatus::InitializationStatus(false)),
+            0x01 => Some(OperationStatus::InitializationStatus(true)),
+            _ => None,
+        }),
+        |i| single_byte(i, &[0x07, 0x87], |byte| SceneMode::from_byte(byte).map(OperationStatus::SceneMode)),
+        |i| single_byte(i, &[0x08, 0x88], |byte| Sensitivity::from_byte(byte).map(OperationStatus::Sensitivity)),
+    ))(input)?;
+    return Ok((input, Frame::OperationStatus(status)))
+}
+
+fn parse_proximity_information(input: &[u8]) -> PResult<'_, HumanPresence> {
+    let (input, proximity) = single_byte(input, &[0x0b, 0x8b], |byte| match byte {
+        0x00 => Some(Proximity::None),
+        0x01 => Some(Proximity::Approaching),
+        0x02 => Some(Proximity::MovingAway),
+        _ => None,
+    })?;
+    return Ok((input, HumanPresence::ProximityReport(proximity)))
+}
+
+fn parse_presence_information(input: &[u8]) -> PResult<'_, HumanPresence> {
+    let (input, presence) = single_byte(input, &[0x01, 0x81], |byte| match byte {
+        0x00 => Some(Presence::Unoccupied),
+        0x01 => Some(Presence::Occupied),
+        _ => None,
+    })?;
+    return Ok((input, HumanPresence::PresenceInformation(presence)))
+}
+
+fn parse_motion_information(input: &[u8]) -> PResult<'_, HumanPresence> {
+    let (input, motion) = single_byte(input, &[0x02, 0x82], |byte| match byte {
+        0x00 => Some(Motion::None),
+        0x01 => Some(Motion::Motionless),
+        0x02 => Some(Motion::Active),
+        _ => None,
+    })?;
     return Ok((input, HumanPresence::MotionInformation(motion)))
 }
 
-fn parse_body_movement_information(input: &[u8]) -> IResult<&[u8], HumanPresence> {
-    let (input, _) = tag([0x03])(input)?;
-    let (input, data) = length_data(be_u16)(input)?;
-    let (_, data_byte) = nom::number::complete::u8(data)?;
-        
+fn parse_body_movement_information(input: &[u8]) -> PResult<'_, HumanPresence> {
+    let (input, data_byte) = single_byte(input, &[0x03, 0x83], Some)?;
     return Ok((input, HumanPresence::BodyMovementParameter(data_byte)))
 }
 
-fn parse_human_presence_report(input: &[u8]) -> IResult<&[u8], Frame> {
-    let (input, _) = tag([0x80])(input)?;
+fn parse_unmanned_time(input: &[u8]) -> PResult<'_, HumanPresence> {
+    let (input, time) = single_byte(input, &[0x0a, 0x8a], UnmannedTime::from_byte)?;
+    return Ok((input, HumanPresence::UnmannedTime(time)))
+}
+
+fn parse_human_presence_report(input: &[u8]) -> PResult<'_, Frame> {
+    let (input, _) = control(input, 0x80)?;
         let (input, human_presence) = alt((
         parse_proximity_information,
         parse_presence_information,
         parse_motion_information,
         parse_body_movement_information,
+        parse_unmanned_time,
     ))(input)?;
         Ok((input, Frame::HumanPresenceReport(human_presence)))
 }
 
-pub fn mr_parser(input: &[u8]) -> IResult<&[u8], Frame> {
-    let (input, _) = tag([0x53, 0x59])(input)?;
-    
-    /*
-    let (input, control) = nom::number::complete::u8(s)?;
-
-    let frame = match control {
-        0x01 => Frame::Heartbeat,
-        0x02 => Frame::ProductInformation,
-        0x03 => Frame::UartUpgrade,
-        0x05 => Frame::OperationStatus,
-        0x80 => {
-            let (_, presence_report ) = parse_human_presence_report(input)?;
-            Frame::HumanPresenceReport(presence_report)
-        },
-        _ => return Err(nom::Err::Error(error_position!(input, nom::error::ErrorKind::Tag))), // Help, I do not know how to return a custom error here!
-    };
-    */
+fn parse_sensor_information(input: &[u8]) -> PResult<'_, OpenFunction> {
+    let (input, data) = command(input, &[0x01])?;
+    let (_, bytes) = nom::bytes::complete::take(5usize)(data)?;
+    return Ok((input, OpenFunction::SensorInformation(SensorInformation {
+        existence_energy: bytes[0],
+        static_distance: bytes[1],
+        motion_energy: bytes[2],
+        motion_distance: bytes[3],
+        motion_speed: bytes[4],
+    })))
+}
 
-    let (input, frame) = alt((
-        parse_human_presence_report,
+fn parse_open_function(input: &[u8]) -> PResult<'_, Frame> {
+    let (input, _) = control(input, 0x08)?;
+    let (input, open_function) = alt((
+        |i| single_byte(i, &[0x00, 0x80], |byte| match byte {
+            0x00 => Some(OpenFunction::Enabled(false)),
+            0x01 => Some(OpenFunction::Enabled(true)),
+            _ => None,
+        }),
+        parse_sensor_information,
+        // Same as the proximity report, but offset by one
+        |i| single_byte(i, &[0x06], |byte| match byte {
+            0x01 => Some(OpenFunction::ProximityReport(Proximity::None)),
+            0x02 => Some(OpenFunction::ProximityReport(Proximity::Approaching)),
+            0x03 => Some(OpenFunction::ProximityReport(Proximity::MovingAway)),
+            _ => None,
+        }),
+        |i| single_byte(i, &[0x07], |byte| Some(OpenFunction::BodyMovementParameter(byte))),
+        |i| single_byte(i, &[0x08, 0x88], |byte| Some(OpenFunction::ExistenceThreshold(byte))),
+        |i| single_byte(i, &[0x09, 0x89], |byte| Some(OpenFunction::MotionThreshold(byte))),
+        |i| single_byte(i, &[0x0a, 0x8a], |byte| Some(OpenFunction::ExistenceBoundary(byte))),
+        |i| single_byte(i, &[0x0b, 0x8b], |byte| Some(OpenFunction::MotionBoundary(byte))),
+        |i| parse_millis(i, &[0x0c, 0x8c]).map(|(i, millis)| (i, OpenFunction::MotionTriggerTime(millis))),
+        |i| parse_millis(i, &[0x0d, 0x8d]).map(|(i, millis)| (i, OpenFunction::MotionToStillTime(millis))),
+        |i| parse_millis(i, &[0x0e, 0x8e]).map(|(i, millis)| (i, OpenFunction::UnoccupiedTime(millis))),
     ))(input)?;
-    
-    let (input, checksum) = nom::number::complete::u8(input)?;
-    let (input, _) = tag([0x54, 0x43])(input)?;
+    return Ok((input, Frame::OpenFunction(open_function)))
+}
 
-    Ok((input, frame))
+/// Parses the payload of a frame, from the control word to the end of the data.
+fn parse_payload(input: &[u8]) -> PResult<'_, Frame> {
+    return alt((
+        parse_heartbeat,
+        parse_product_information,
+        parse_uart_upgrade,
+        parse_operation_status,
+        parse_human_presence_report,
+        parse_open_function,
+    ))(input);
+}
+
+/// Parses a single frame at the start of `input`, and returns the remaining bytes
+/// after it.
+pub fn mr_parser(input: &[u8]) -> Result<(&[u8], Frame), Error> {
+    // Frame layout: header (2), control word, command, data length (2), data, checksum, tail (2)
+    if input.len() < HEADER.len() {
+        return Err(Error::Truncated);
+    }
+    if input[..HEADER.len()] != HEADER {
+        return Err(Error::BadHeader);
+    }
+    if input.len() < 6 {
+        return Err(Error::Truncated);
+    }
+    let control = input[2];
+    let command = input[3];
+    let data_len = u16::from_be_bytes([input[4], input[5]]) as usize;
+    let checksum_index = 6 + data_len;
+    let end = checksum_index + 1 + TAIL.len();
+    if input.len() < end {
+        return Err(Error::Truncated);
+    }
+    if input[checksum_index + 1..end] != TAIL {
+        return Err(Error::BadTail);
+    }
+    let expected = checksum(&input[..checksum_index]);
+    if input[checksum_index] != expected {
+        return Err(Error::BadChecksum { expected, actual: input[checksum_index] });
+    }
+
+    return match parse_payload(&input[2..checksum_index]) {
+        Ok((_, frame)) => Ok((&input[end..], frame)),
+        Err(nom::Err::Error(error) | nom::Err::Failure(error)) => Err(match error {
+            PayloadError::UnknownControlWord => Error::UnknownControlWord(control),
+            PayloadError::UnknownCommand => Error::UnknownCommand { control, command },
+            PayloadError::InvalidValue => Error::InvalidValue { control, command },
+        }),
+        // Only the streaming parsers of nom can run out of input
+        Err(nom::Err::Incomplete(_)) => Err(Error::Truncated),
+    };
 }
 
 #[cfg(test)]
@@ -152,7 +611,7 @@ mod tests {
     use hex_literal::hex;
     use super::*;
 
-    const TEST_INPUT: [u8; 65] = hex!(
+    pub(crate) const TEST_INPUT: [u8; 65] = hex!(
         "53 59 80 03 00 01 06 36 54 43 00 53 59 80 03 00 01 06 36 54 43 00"
         "53 59 80 03 00 01 06 36 54 43 00 00 53 59 80 03 00 01 0c 3c 54 43 00"
         "53 59 80 03 00 01 06 36 54 43 00 53 59 80 03 00 01 06 36 54"
@@ -164,4 +623,100 @@ mod tests {
         println!("Human presence report: {:x?}", parse_human_presence_report(&hex!("80 0b 00 01 02")).unwrap());
         println!("Full frame: {:x?}", mr_parser(&hex!("53 59 80 03 00 01 06 36 54 43")).unwrap());
     }
-}
\ No newline at end of file
+
+    fn parse(input: &[u8]) -> Frame {
+        let (rest, frame) = mr_parser(input).unwrap();
+        assert!(rest.is_empty());
+        return frame;
+    }
+
+    fn text(text: &str) -> Text {
+        return Text::try_from(text).unwrap();
+    }
+
+    #[test]
+    fn test_concatenated_frames() {
+        let mut input = &TEST_INPUT[..];
+        let mut movements = Vec::new();
+        while let Ok((rest, frame)) = mr_parser(input) {
+            movements.push(frame);
+            input = rest;
+            while input.first() == Some(&0x00) {
+                input = &input[1..];
+            }
+        }
+        assert_eq!(movements.len(), 5);
+        assert_eq!(movements[3], Frame::HumanPresenceReport(HumanPresence::BodyMovementParameter(0x0c)));
+        // The last frame is truncated
+        assert_eq!(input.len(), 9);
+        assert_eq!(mr_parser(input), Err(Error::Truncated));
+    }
+
+    #[test]
+    fn test_system_frames() {
+        assert_eq!(parse(&hex!("53 59 01 01 00 01 0f be 54 43")), Frame::Heartbeat(Heartbeat::Heartbeat));
+        assert_eq!(parse(&hex!("53 59 02 a1 00 08 4d 52 32 34 48 50 43 31 68 54 43")),
+            Frame::ProductInformation(ProductInformation::ProductModel(text("MR24HPC1"))));
+        assert_eq!(parse(&hex!("53 59 02 a4 00 0f 47 32 34 56 44 31 53 59 56 30 30 31 30 30 36 02 54 43")),
+            Frame::ProductInformation(ProductInformation::FirmwareVersion(text("G24VD1SYV001006"))));
+        assert_eq!(parse(&hex!("53 59 03 01 00 02 00 01 b3 54 43")),
+            Frame::UartUpgrade(UartUpgrade { command: 0x01, data: heapless::Vec::from_slice(&[0x00, 0x01]).unwrap() }));
+    }
+
+    #[test]
+    fn test_operation_status() {
+        assert_eq!(parse(&hex!("53 59 05 01 00 01 0f c2 54 43")), Frame::OperationStatus(OperationStatus::InitializationCompleted));
+        assert_eq!(parse(&hex!("53 59 05 81 00 01 01 34 54 43")), Frame::OperationStatus(OperationStatus::InitializationStatus(true)));
+        assert_eq!(parse(&hex!("53 59 05 87 00 01 02 3b 54 43")), Frame::OperationStatus(OperationStatus::SceneMode(SceneMode::Bedroom)));
+        assert_eq!(parse(&hex!("53 59 05 08 00 01 03 bd 54 43")), Frame::OperationStatus(OperationStatus::Sensitivity(Sensitivity::High)));
+        // There is no scene mode 9
+        assert_eq!(mr_parser(&hex!("53 59 05 07 00 01 09 c2 54 43")), Err(Error::InvalidValue { control: 0x05, command: 0x07 }));
+    }
+
+    #[test]
+    fn test_human_presence() {
+        assert_eq!(parse(&hex!("53 59 80 81 00 01 01 af 54 43")), Frame::HumanPresenceReport(HumanPresence::PresenceInformation(Presence::Occupied)));
+        assert_eq!(parse(&hex!("53 59 80 0b 00 01 01 39 54 43")), Frame::HumanPresenceReport(HumanPresence::ProximityReport(Proximity::Approaching)));
+        let frame = parse(&hex!("53 59 80 8a 00 01 03 ba 54 43"));
+        assert_eq!(frame, Frame::HumanPresenceReport(HumanPresence::UnmannedTime(UnmannedTime::Minutes1)));
+        assert_eq!(UnmannedTime::Minutes1.as_secs(), 60);
+    }
+
+    #[test]
+    fn test_open_function() {
+        assert_eq!(parse(&hex!("53 59 08 80 00 01 01 36 54 43")), Frame::OpenFunction(OpenFunction::Enabled(true)));
+        assert_eq!(parse(&hex!("53 59 08 06 00 01 03 be 54 43")), Frame::OpenFunction(OpenFunction::ProximityReport(Proximity::MovingAway)));
+        assert_eq!(parse(&hex!("53 59 08 07 00 01 32 ee 54 43")), Frame::OpenFunction(OpenFunction::BodyMovementParameter(50)));
+
+        let Frame::OpenFunction(OpenFunction::SensorInformation(info)) = parse(&hex!("53 59 08 01 00 05 c8 04 64 06 0e fe 54 43")) else {
+            panic!("Not a sensor information frame");
+        };
+        assert_eq!(info.existence_energy, 200);
+        assert_eq!(info.static_distance_m(), 2.0);
+        assert_eq!(info.motion_energy, 100);
+        assert_eq!(info.motion_distance_m(), 3.0);
+        assert_eq!(info.motion_speed_m_s(), 2.0);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(mr_parser(&hex!("59 53 80 03 00 01 06 36 54 43")), Err(Error::BadHeader));
+        assert_eq!(mr_parser(&hex!("53 59 80 03 00 01 06 37 54 43")), Err(Error::BadChecksum { expected: 0x36, actual: 0x37 }));
+        assert_eq!(mr_parser(&hex!("53 59 80 03 00 01 06 36 54 44")), Err(Error::BadTail));
+        assert_eq!(mr_parser(&hex!("53 59 80 03 00 01")), Err(Error::Truncated));
+        // A wrong length makes the tail appear at the wrong position
+        assert_eq!(mr_parser(&hex!("53 59 80 03 00 00 06 36 54 43")), Err(Error::BadTail));
+
+        let unknown_control = mr_parser(&hex!("53 59 42 01 00 01 00 f0 54 43")).unwrap_err();
+        assert_eq!(unknown_control, Error::UnknownControlWord(0x42));
+        assert!(unknown_control.is_unsupported());
+        let unknown_command = mr_parser(&hex!("53 59 80 42 00 01 00 6f 54 43")).unwrap_err();
+        assert_eq!(unknown_command, Error::UnknownCommand { control: 0x80, command: 0x42 });
+        assert!(unknown_command.is_unsupported());
+
+        // Sensor information with too few bytes
+        let invalid = mr_parser(&hex!("53 59 08 01 00 02 c8 04 83 54 43")).unwrap_err();
+        assert_eq!(invalid, Error::InvalidValue { control: 0x08, command: 0x01 });
+        assert!(!invalid.is_unsupported());
+    }
+}
@@ -0,0 +1,184 @@
+// SPDX-FileCopyrightText: 2024 Lena Schimmel <mail@lenaschimmel.de>
+// SPDX-License-Identifier: CERN-OHL-S-2.0+
+// This file is part of besteLampe!.
+// 
+// besteLampe! is free software: you can redistribute it and/or modify it under the
+// terms of the GNU General Public License as published by the Free Software Foundation, 
+// either version 3 of the License, or (at your option) any later version.
+// 
+// besteLampe! is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; 
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. 
+// See the GNU General Public License for more details.
+// 
+// You should have received a copy of the GNU General Public License along with besteLampe!.
+// If not, see <https://www.gnu.org/licenses/>. 
+
+use crate::*;
+
+/// Number of distance gates of 0.5 m each. Targets beyond the last gate are
+/// treated as if they were in it.
+pub const GATES: usize = 10;
+
+/// Width of a distance gate, in m.
+pub const GATE_WIDTH: f32 = 0.5;
+
+/// Configuration of the presence detection from the raw measurements of the open
+/// function mode. Energies are from 0 to 250, durations in seconds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawDetectorConfig {
+    /// Smoothed existence energy above which a static target counts as present,
+    /// for each distance gate. Reflections from far targets are weaker, so
+    /// far gates need lower thresholds.
+    pub existence_thresholds: [u8; GATES],
+    /// Smoothed motion energy above which a target counts as moving, for each distance gate.
+    pub motion_thresholds: [u8; GATES],
+    /// Time constant of the smoothing of both energies.
+    pub smoothing: f32,
+    /// How long presence is held after the energy fell below the threshold.
+    pub hold_time: f32,
+}
+
+impl Default for RawDetectorConfig {
+    fn default() -> Self {
+        return Self {
+            existence_thresholds: [60, 50, 40, 35, 30, 25, 25, 20, 20, 20],
+            motion_thresholds: [80, 70, 60, 50, 45, 40, 35, 30, 30, 30],
+            smoothing: 1.0,
+            hold_time: 30.0,
+        };
+    }
+}
+
+/// Result of the raw presence detection.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawPresence {
+    pub presence: Presence,
+    pub motion: Motion,
+    /// Distance of the nearest detected target, in m.
+    pub distance: Option<f32>,
+}
+
+/// Derives presence and motion from the raw measurements, which allows for more
+/// control than the sensor's own detection, e.g. ignoring movement far away.
+pub struct RawDetector {
+    config: RawDetectorConfig,
+    existence_energy: f32,
+    motion_energy: f32,
+    /// Time since a target was last detected.
+    absent_for: f32,
+}
+
+fn gate(distance: u8) -> usize {
+    return (distance as usize).min(GATES - 1);
+}
+
+impl RawDetector {
+    pub fn new(config: RawDetectorConfig) -> Self {
+        return Self { config, existence_energy: 0.0, motion_energy: 0.0, absent_for: f32::INFINITY };
+    }
+
+    pub fn config(&self) -> &RawDetectorConfig {
+        return &self.config;
+    }
+
+    /// Smoothed existence and motion energy.
+    pub fn energies(&self) -> (f32, f32) {
+        return (self.existence_energy, self.motion_energy);
+    }
+
+    /// Feeds a measurement, taken `elapsed` seconds after the previous one.
+    pub fn update(&mut self, info: &SensorInformation, elapsed: f32) -> RawPresence {
+        // A simple low pass, which does not need `exp` and thus works without std
+        let alpha = if self.config.smoothing > 0.0 { elapsed / (self.config.smoothing + elapsed) } else { 1.0 };
+        self.existence_energy += (info.existence_energy as f32 - self.existence_energy) * alpha;
+        self.motion_energy += (info.motion_energy as f32 - self.motion_energy) * alpha;
+
+        let moving = self.motion_energy > self.config.motion_thresholds[gate(info.motion_distance)] as f32;
+        let existing = self.existence_energy > self.config.existence_thresholds[gate(info.static_distance)] as f32;
+        if moving || existing {
+            self.absent_for = 0.0;
+        } else {
+            self.absent_for += elapsed;
+        }
+
+        if self.absent_for >= self.config.hold_time {
+            return RawPresence { presence: Presence::Unoccupied, motion: Motion::None, distance: None };
+        }
+        let distance = match (moving, existing) {
+            (true, true) => Some(info.motion_distance_m().min(info.static_distance_m())),
+            (true, false) => Some(info.motion_distance_m()),
+            (false, true) => Some(info.static_distance_m()),
+            (false, false) => None,
+        };
+        return RawPresence {
+            presence: Presence::Occupied,
+            motion: if moving { Motion::Active } else { Motion::Motionless },
+            distance,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(existence_energy: u8, static_distance: u8, motion_energy: u8, motion_distance: u8) -> SensorInformation {
+        return SensorInformation { existence_energy, static_distance, motion_energy, motion_distance, motion_speed: 10 };
+    }
+
+    #[test]
+    fn test_smoothing_and_hold() {
+        let mut detector = RawDetector::new(RawDetectorConfig::default());
+        let empty = info(5, 0, 5, 0);
+        assert_eq!(detector.update(&empty, 1.0).presence, Presence::Unoccupied);
+
+        // A single spike of motion energy is smoothed away
+        let spike = info(5, 0, 200, 2);
+        assert_eq!(detector.update(&spike, 0.1).motion, Motion::None);
+        detector.update(&empty, 1.0);
+
+        // Someone walks in and sits down
+        let mut result = detector.update(&spike, 1.0);
+        for _ in 0..5 {
+            result = detector.update(&spike, 1.0);
+        }
+        assert_eq!(result, RawPresence { presence: Presence::Occupied, motion: Motion::Active, distance: Some(1.0) });
+        let sitting = info(120, 3, 5, 3);
+        for _ in 0..10 {
+            result = detector.update(&sitting, 1.0);
+        }
+        assert_eq!(result, RawPresence { presence: Presence::Occupied, motion: Motion::Motionless, distance: Some(1.5) });
+
+        // And leaves. Presence is held for a while.
+        for _ in 0..20 {
+            result = detector.update(&empty, 1.0);
+        }
+        assert_eq!(result.presence, Presence::Occupied);
+        for _ in 0..20 {
+            result = detector.update(&empty, 1.0);
+        }
+        assert_eq!(result.presence, Presence::Unoccupied);
+    }
+
+    #[test]
+    fn test_thresholds_per_gate() {
+        let mut config = RawDetectorConfig::default();
+        // Ignore everything that moves beyond 2 m, e.g. a curtain
+        for threshold in &mut config.motion_thresholds[4..] {
+            *threshold = 250;
+        }
+        let mut detector = RawDetector::new(config);
+        let far = info(5, 0, 100, 6);
+        let near = info(5, 0, 100, 3);
+        for _ in 0..10 {
+            assert_eq!(detector.update(&far, 1.0).motion, Motion::None);
+        }
+        let mut result = detector.update(&near, 1.0);
+        for _ in 0..10 {
+            result = detector.update(&near, 1.0);
+        }
+        assert_eq!(result.motion, Motion::Active);
+        // Distances beyond the last gate use the last gate
+        assert_eq!(gate(40), GATES - 1);
+    }
+}
@@ -0,0 +1,129 @@
+// SPDX-FileCopyrightText: 2024 Lena Schimmel <mail@lenaschimmel.de>
+// SPDX-License-Identifier: CERN-OHL-S-2.0+
+// This file is part of besteLampe!.
+// 
+// besteLampe! is free software: you can redistribute it and/or modify it under the
+// terms of the GNU General Public License as published by the Free Software Foundation, 
+// either version 3 of the License, or (at your option) any later version.
+// 
+// besteLampe! is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; 
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. 
+// See the GNU General Public License for more details.
+// 
+// You should have received a copy of the GNU General Public License along with besteLampe!.
+// If not, see <https://www.gnu.org/licenses/>.
+//
+// -----
+//
+// Replays serial data that the lamp captured from its sensors (see `/capture` in
+// the web server) through the same decoders that the firmware uses, and prints
+// the decoded frames and sentences. Usage: `sensor-replay <capture file>`
+
+use std::error::Error;
+use std::env;
+use std::fs;
+use std::process::ExitCode;
+
+use abstraktelampe::capture::{parse_capture, CaptureChunk, CaptureSource};
+use nmea_parser::{NmeaParser, ParsedMessage};
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            ExitCode::FAILURE
+        },
+    }
+}
+
+fn run() -> Result<(), Box<dyn Error>> {
+    let Some(path) = env::args().nth(1) else {
+        return Err("Usage: sensor-replay <capture file>".into());
+    };
+    let text = fs::read_to_string(path)?;
+    let chunks = parse_capture(&text).map_err(|err| err.to_string())?;
+    for line in replay(&chunks) {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+/// Decodes all chunks, and returns one line per decoded frame, sentence or error.
+fn replay(chunks: &[CaptureChunk]) -> Vec<String> {
+    let mut output = Vec::new();
+    let mut mr24hpc1 = mr24hpc1::Decoder::new();
+    let mut ld2410 = ld2410::Decoder::new();
+    let mut parser = NmeaParser::new();
+    let mut gps_line = String::new();
+
+    for chunk in chunks {
+        let time = chunk.time_ms;
+        match chunk.source {
+            CaptureSource::Mr24hpc1 => {
+                for result in mr24hpc1.decode(&chunk.bytes) {
+                    output.push(match result {
+                        Ok(frame) => format!("{:>8} radar {:?}", time, frame),
+                        Err(err) => format!("{:>8} radar error: {}", time, err),
+                    });
+                }
+            },
+            CaptureSource::Ld2410 => {
+                for result in ld2410.decode(&chunk.bytes) {
+                    output.push(match result {
+                        Ok(frame) => format!("{:>8} radar {:?}", time, frame),
+                        Err(err) => format!("{:>8} radar error: {}", time, err),
+                    });
+                }
+            },
+            CaptureSource::Gps => {
+                // Sentences are only parsed when they are complete, like the
+                // firmware's line reader does
+                gps_line.push_str(&String::from_utf8_lossy(&chunk.bytes));
+                while let Some(end) = gps_line.find('\n') {
+                    let sentence: String = gps_line.drain(..=end).collect();
+                    let sentence = sentence.trim();
+                    if sentence.is_empty() {
+                        continue;
+                    }
+                    output.push(match parser.parse_sentence(sentence) {
+                        Ok(ParsedMessage::Rmc(rmc)) => format!("{:>8} gps   RMC time {:?}, position {:?}, {:?}", time, rmc.timestamp, rmc.latitude, rmc.longitude),
+                        Ok(ParsedMessage::Gga(gga)) => format!("{:>8} gps   GGA position {:?}, {:?}", time, gga.latitude, gga.longitude),
+                        Ok(_) => format!("{:>8} gps   ignored: {}", time, sentence),
+                        Err(err) => format!("{:>8} gps   error: {}", time, err),
+                    });
+                }
+            },
+        }
+    }
+    if mr24hpc1.skipped_bytes() > 0 {
+        output.push(format!("Skipped {} bytes of MR24HPC1 data.", mr24hpc1.skipped_bytes()));
+    }
+    if ld2410.skipped_bytes() > 0 {
+        output.push(format!("Skipped {} bytes of LD2410 data.", ld2410.skipped_bytes()));
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_example_capture() {
+        let chunks = parse_capture(include_str!("../fixtures/example.txt")).unwrap();
+        let output = replay(&chunks);
+        assert_eq!(output, vec![
+            "    1000 radar HumanPresenceReport(PresenceInformation(Occupied))",
+            "    1045 radar HumanPresenceReport(MotionInformation(Active))",
+            "    1045 radar HumanPresenceReport(BodyMovementParameter(42))",
+            "    2000 radar error: The checksum is 0xc9, but should be 0x36.",
+            "    3010 gps   RMC time Some(2024-03-23T12:35:19Z), position Some(48.1173), Some(11.516666666666667)",
+            "    3010 gps   GGA position Some(48.1173), Some(11.516666666666667)",
+            "    4000 radar Ack(ConfigurationEnded)",
+            "    4030 radar Rejected(100)",
+            "Skipped 11 bytes of MR24HPC1 data.",
+            "Skipped 2 bytes of LD2410 data.",
+        ]);
+    }
+}
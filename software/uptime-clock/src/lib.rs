@@ -0,0 +1,40 @@
+// SPDX-FileCopyrightText: 2024 Lena Schimmel <mail@lenaschimmel.de>
+// SPDX-License-Identifier: CERN-OHL-S-2.0+
+// This file is part of besteLampe!.
+// 
+// besteLampe! is free software: you can redistribute it and/or modify it under the
+// terms of the GNU General Public License as published by the Free Software Foundation, 
+// either version 3 of the License, or (at your option) any later version.
+// 
+// besteLampe! is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; 
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. 
+// See the GNU General Public License for more details.
+// 
+// You should have received a copy of the GNU General Public License along with besteLampe!.
+// If not, see <https://www.gnu.org/licenses/>. 
+
+//! Time source for the sensor drivers and the lamp logic, so that they can be
+//! tested deterministically on the host.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+#![allow(clippy::needless_return)]
+
+/// Source of the current time in ms, e.g. since the start of the device.
+pub trait Clock {
+    fn now_ms(&self) -> u64;
+
+    /// Blocks for about `ms` while a driver waits for data, so that other tasks
+    /// can run. Without `std`, this returns immediately unless it is implemented.
+    fn sleep_ms(&self, ms: u64) {
+        #[cfg(feature = "std")]
+        std::thread::sleep(std::time::Duration::from_millis(ms));
+        #[cfg(not(feature = "std"))]
+        let _ = ms;
+    }
+}
+
+impl<F: Fn() -> u64> Clock for F {
+    fn now_ms(&self) -> u64 {
+        return self();
+    }
+}